@@ -2,15 +2,26 @@ use anyhow::{Error, Result, Context};
 use proc_macro2::{TokenStream, Span};
 use quote::quote;
 
-use syn::{parse2, Attribute, Data::Struct, DeriveInput, Fields, Generics, Ident, WhereClause};
+use syn::{parse2, Attribute, Data::{Enum, Struct}, DeriveInput, Fields, Generics, Ident, Index, Member, WhereClause};
 mod old;
 
 /// two kinds of of attributes, one is #[flat(skip)], which does not flatten
 /// that argument, the other is #[flat(exclude)], which does not add that field
 /// into the world.
+/// For enums, the fields of each variant are flattened under a path segment with the variant name,
+/// ex. /Variant/field, unit variants flatten to nothing.
 pub fn derive_flatten(input: TokenStream) -> Result<TokenStream> {
     let derive = parse2::<DeriveInput>(input)?;
-    let extracted_fields = extract_fields(&derive).context("failed to extract struct fields")?;
+    let body = match &derive.data {
+        Enum(_) => {
+            let variants = extract_variants(&derive).context("failed to extract enum variants")?;
+            flatten_enum(variants)?
+        }
+        _ => {
+            let extracted_fields = extract_fields(&derive).context("failed to extract struct fields")?;
+            flatten_struct(extracted_fields)?
+        }
+    };
     let SimpleDataStruct { 
         generics, 
         stripped_generics, 
@@ -18,39 +29,59 @@ pub fn derive_flatten(input: TokenStream) -> Result<TokenStream> {
         name: struct_name, 
     } = SimpleDataStruct::new(derive)?;
 
-    let new_fields = match extracted_fields {
+    Ok(quote!(
+        impl #generics crate::Flatten for #struct_name #stripped_generics
+        #where_clause {
+            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+                #body
+            }
+        }
+    ))
+}
+
+/// pairs each field with its name and member, ex. `a` or `0` for unnamed fields, along with its attribute
+fn field_options(fields: &SimpleStructFields) -> Result<Vec<(String, Member, FlatAttrOptions)>> {
+    match fields {
         SimpleStructFields::Named(fields) => {
-            let new_fields: Result<Vec<_>> = fields.iter().map(|(id, attrs)| {
+            fields.iter().map(|(id, attrs)| {
                 let attr_arg = compute_attributes(&attrs)?;
-                Ok((id.to_string(), attr_arg))
-            }).collect();
-            new_fields?
+                Ok((id.to_string(), Member::Named(id.clone()), attr_arg))
+            }).collect()
         }
         SimpleStructFields::Unnamed(fields) => {
-            let new_fields: Result<Vec<_>> = fields.iter().enumerate().map(|(id, attrs)| {
+            fields.iter().enumerate().map(|(id, attrs)| {
                 let attr_arg = compute_attributes(&attrs)?;
-                Ok((id.to_string(), attr_arg))
-            }).collect();
-            new_fields?
+                Ok((id.to_string(), Member::Unnamed(Index::from(id)), attr_arg))
+            }).collect()
         }
         SimpleStructFields::Unit => {
-            Vec::new()
+            Ok(Vec::new())
         }
-    };
+    }
+}
 
+/// Generates the flatten calls for each field, the fields are given as (path segment, receiver, reference, option),
+/// where the receiver is what flatten is called on, and the reference is what is pushed into world.
+/// Returns None if no field makes use of path.
+fn flatten_commands(fields: &[(String, TokenStream, TokenStream, FlatAttrOptions)]) -> Option<Vec<TokenStream>> {
+    // the last field that uses path takes it by value, every other field clones it
+    let last_used = fields.iter().rposition(|(_, _, _, opt)| *opt != FlatAttrOptions::Exclude)?;
     let mut commands = Vec::new();
-    for (name, opt) in new_fields[..new_fields.len() - 1].iter() {
-        let new_name = "/".to_string() + name;
-        let field_name = Ident::new(name, Span::call_site());
+    for (i, (name, receiver, reference, opt)) in fields.iter().enumerate() {
+        let path = if i == last_used {
+            quote!(path)
+        } else {
+            quote!(path.clone())
+        };
         let code = match opt {
             FlatAttrOptions::Include => {
                 quote!(
-                    self.#field_name.flatten(path.clone() + #new_name, world);
+                    #receiver.flatten(#path + #name, world);
                 )
             }
             FlatAttrOptions::Skip => {
                 quote!(
-                    world.push(path, self);
+                    world.push(#path + #name, #reference);
                 )
             },
             FlatAttrOptions::Exclude => {
@@ -59,39 +90,74 @@ pub fn derive_flatten(input: TokenStream) -> Result<TokenStream> {
         };
         commands.push(code);
     }
-    if new_fields.len() > 0 {
-        let (name, opt) = &new_fields[new_fields.len() - 1];
-        let new_name = "/".to_string() + name;
-        let field_name = Ident::new(name, Span::call_site());
-        let code = match opt {
-            FlatAttrOptions::Include => {
-                quote!(
-                    self.#field_name.flatten(path + #new_name, world);
-                )
-            },
-            FlatAttrOptions::Skip => {
-                quote!(
-                    world.push(path, self);
-                )
-            },
-            FlatAttrOptions::Exclude => {
-                quote!()
-            },
-        };
-        commands.push(code);
+    Some(commands)
+}
+
+fn flatten_struct(fields: SimpleStructFields) -> Result<TokenStream> {
+    let fields: Vec<_> = field_options(&fields)?.into_iter().map(|(name, member, opt)| {
+        ("/".to_string() + &name, quote!(self.#member), quote!(&mut self.#member), opt)
+    }).collect();
+
+    if let Some(commands) = flatten_commands(&fields) {
+        Ok(quote!(#(#commands)*))
+    } else {
+        // nothing to flatten, silence unused arguments
+        Ok(quote!(let _ = (path, world);))
     }
+}
 
-    Ok(quote!(
-        impl #generics crate::Flatten for #struct_name #stripped_generics
-        #where_clause {
-            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
-                #(#commands)*
+fn flatten_enum(variants: Vec<(Ident, SimpleStructFields)>) -> Result<TokenStream> {
+    if variants.is_empty() {
+        return Ok(quote!(match *self {}));
+    }
+    let mut any_used = false;
+    let mut arms = Vec::new();
+    for (variant, fields) in variants.iter() {
+        let options = field_options(fields)?;
+        // bind each field to a name that cannot clash with path or world
+        let bindings: Vec<_> = options.iter()
+            .map(|(name, _, _)| Ident::new(&format!("__flat_{}", name), Span::call_site()))
+            .collect();
+        let fields_: Vec<_> = options.iter().zip(bindings.iter()).map(|((name, _, opt), binding)| {
+            (format!("/{}/{}", variant, name), quote!(#binding), quote!(#binding), *opt)
+        }).collect();
+
+        let pattern = match fields {
+            SimpleStructFields::Named(_) => {
+                let members = options.iter().zip(bindings.iter())
+                    .filter(|((_, _, opt), _)| *opt != FlatAttrOptions::Exclude)
+                    .map(|((_, member, _), binding)| quote!(#member: #binding));
+                quote!(Self::#variant { #(#members,)* .. })
             }
+            SimpleStructFields::Unnamed(_) => {
+                let members = options.iter().zip(bindings.iter()).map(|((_, _, opt), binding)| {
+                    if *opt == FlatAttrOptions::Exclude { quote!(_) } else { quote!(#binding) }
+                });
+                quote!(Self::#variant(#(#members),*))
+            }
+            SimpleStructFields::Unit => {
+                quote!(Self::#variant)
+            }
+        };
+        let commands = flatten_commands(&fields_);
+        any_used |= commands.is_some();
+        let commands = commands.unwrap_or_default();
+        arms.push(quote!(#pattern => { #(#commands)* }));
+    }
+    let silence = if any_used {
+        quote!()
+    } else {
+        // nothing to flatten, silence unused arguments
+        quote!(let _ = (path, world);)
+    };
+    Ok(quote!(
+        match self {
+            #(#arms)*
         }
+        #silence
     ))
 }
 
-
 struct SimpleDataStruct {
     generics: Generics,
     stripped_generics: Generics,
@@ -107,25 +173,37 @@ enum SimpleStructFields {
 
 fn extract_fields(derive: &DeriveInput) -> Result<SimpleStructFields> {
     if let Struct(data) = &derive.data {
-        match &data.fields {
-            Fields::Named(fields) => {
-                let fields: Vec<_> = fields.named.iter().filter(|x| x.ident.is_some()).map(|x| {
-                    let id = x.ident.clone().unwrap();
-                    (id, x.attrs.clone())
-                }).collect();
-                Ok(SimpleStructFields::Named(fields))
-            }
-            Fields::Unnamed(fields) => {
-                let ranges: Vec<_> = fields.unnamed.iter().map(|x| x.attrs.clone()).collect();
-                Ok(SimpleStructFields::Unnamed(ranges))
-            }
-            Fields::Unit => Ok(SimpleStructFields::Unit),
-        }
+        Ok(simple_fields(&data.fields))
     } else {
         return Err(Error::msg("Expected a struct"));
     }
 }
 
+fn extract_variants(derive: &DeriveInput) -> Result<Vec<(Ident, SimpleStructFields)>> {
+    if let Enum(data) = &derive.data {
+        Ok(data.variants.iter().map(|v| (v.ident.clone(), simple_fields(&v.fields))).collect())
+    } else {
+        return Err(Error::msg("Expected an enum"));
+    }
+}
+
+fn simple_fields(fields: &Fields) -> SimpleStructFields {
+    match fields {
+        Fields::Named(fields) => {
+            let fields: Vec<_> = fields.named.iter().filter(|x| x.ident.is_some()).map(|x| {
+                let id = x.ident.clone().unwrap();
+                (id, x.attrs.clone())
+            }).collect();
+            SimpleStructFields::Named(fields)
+        }
+        Fields::Unnamed(fields) => {
+            let ranges: Vec<_> = fields.unnamed.iter().map(|x| x.attrs.clone()).collect();
+            SimpleStructFields::Unnamed(ranges)
+        }
+        Fields::Unit => SimpleStructFields::Unit,
+    }
+}
+
 impl SimpleDataStruct {
     fn new(derive: DeriveInput) -> Result<Self> {
         let stripped_generics = strip_trait_bounds(&derive.generics);
@@ -157,12 +235,13 @@ fn strip_trait_bounds(generics: &Generics) -> Generics {
 
 #[test]
 fn compute_attr_test() {
+    use FlatAttrOptions::*;
     let basic_struct = quote!(
         struct S {
             #[flat(skip)]
             a: f32,
             #[skip]
-            b: std::rc::Rc(usize),
+            b: std::rc::Rc<usize>,
             #[flat[skip]]
             c: std::collections::Hashmap<i32, String>,
             #[flat(exclude, skip)]
@@ -171,20 +250,106 @@ fn compute_attr_test() {
             f: i32,
             #[flat(exclude)]
             g: i64,
+            #[flat(exclude_me)]
+            h: i64,
+            /// doc comments are attributes too
+            i: i64,
         }
     );
 
     let deriveinput = parse2::<DeriveInput>(basic_struct).unwrap();
     let fields = extract_fields(&deriveinput).unwrap();
+    let expected = [Some(Skip), Some(Include), Some(Skip), None, None, Some(Exclude), None, Some(Include)];
     if let SimpleStructFields::Named(fields) = fields {
-        for f in fields {
-            for i in f.1 {
-                //println!("{:#?}", i);
-                println!("{:?}", compute_attr(&i));
-            }
+        assert_eq!(fields.len(), expected.len());
+        for ((id, attrs), expected) in fields.iter().zip(expected) {
+            assert_eq!(compute_attributes(attrs).ok(), expected, "on field {}", id);
         }
+    } else {
+        panic!("expected named fields");
     }
-    //println!("{:#?}", deriveinput);
+}
+
+#[test]
+fn derive_flatten_attr_test() {
+    let basic_struct = quote!(
+        struct Basic {
+            a: Vec<f32>,
+            #[flat(skip)]
+            b: HashMap<usize, String>,
+            #[flat(exclude)]
+            c: fn(usize) -> usize,
+            d: f32,
+        }
+    );
+    let expected = quote!(
+        impl crate::Flatten for Basic {
+            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+                self.a.flatten(path.clone() + "/a", world);
+                world.push(path.clone() + "/b", &mut self.b);
+                self.d.flatten(path + "/d", world);
+            }
+        }
+    );
+    let derived = derive_flatten(basic_struct).unwrap();
+    assert_eq!(derived.to_string(), expected.to_string());
+
+    let invalid = quote!(
+        struct Invalid {
+            #[flat(skip, exclude)]
+            a: f32,
+        }
+    );
+    assert!(derive_flatten(invalid).is_err());
+}
+
+#[test]
+fn derive_flatten_last_field_test() {
+    let last_skip = quote!(
+        struct LastSkip {
+            a: f32,
+            #[flat(skip)]
+            b: Vec<f32>,
+        }
+    );
+    let expected = quote!(
+        impl crate::Flatten for LastSkip {
+            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+                self.a.flatten(path.clone() + "/a", world);
+                world.push(path + "/b", &mut self.b);
+            }
+        }
+    );
+    assert_eq!(derive_flatten(last_skip).unwrap().to_string(), expected.to_string());
+
+    // path is moved into the last field that uses it
+    let last_exclude = quote!(
+        struct LastExclude {
+            a: f32,
+            #[flat(exclude)]
+            b: Vec<f32>,
+        }
+    );
+    let expected = quote!(
+        impl crate::Flatten for LastExclude {
+            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+                self.a.flatten(path + "/a", world);
+            }
+        }
+    );
+    assert_eq!(derive_flatten(last_exclude).unwrap().to_string(), expected.to_string());
+
+    let unit = quote!(
+        struct Unit;
+    );
+    let expected = quote!(
+        impl crate::Flatten for Unit {
+            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+                let _ = (path, world);
+            }
+        }
+    );
+    assert_eq!(derive_flatten(unit).unwrap().to_string(), expected.to_string());
 }
 
 #[test]
@@ -202,32 +367,41 @@ fn derive_flatten_test() {
     println!("{}", derived.unwrap());
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum FlatAttrOptions {
     Skip,
     Exclude,
     Include
 }
 
-fn compute_attr(attrs: &Attribute) -> Result<FlatAttrOptions> {
-    // first make sure that any attributes are surrounded by flat(...)
-    // if let Some(ident) = attrs.path.get_ident() {
-    //     if ident.to_string() == "flat".to_string() {
-    //         let tokens = attrs.tokens.to_string();
-    //         let has_exclude = tokens.contains("exclude");
-    //         let has_skip = tokens.contains("skip");
-    //         if has_exclude && has_skip {
-    //             return Err(Error::msg("flat(...), cannot have both exclude and skip, choose exclude to exclude a field from being included, and skip to stop that field from being flattened."));
-    //         } else if has_exclude {
-    //             return Ok(FlatAttrOptions::Exclude);
-    //         } else if has_skip {
-    //             return Ok(FlatAttrOptions::Skip);
-    //         } else {
-    //             return Err(Error::msg("no option chosen, either choose flat(exclude) to exclude a field from being inserted into world, or choose flat(skip) to prevent that field from being flattened, but it is still inserted into world."));
-    //         }
-    //     }
-    // }
-    Ok(FlatAttrOptions::Include)
+fn compute_attr(attr: &Attribute) -> Result<FlatAttrOptions> {
+    // only attributes of the form flat(...) are considered, everything else is left alone
+    if !attr.path().is_ident("flat") {
+        return Ok(FlatAttrOptions::Include);
+    }
+    let mut has_exclude = false;
+    let mut has_skip = false;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("exclude") {
+            has_exclude = true;
+            Ok(())
+        } else if meta.path.is_ident("skip") {
+            has_skip = true;
+            Ok(())
+        } else {
+            Err(meta.error("unrecognized option, expected flat(exclude) or flat(skip)"))
+        }
+    }).context("failed to parse flat(...) attribute")?;
+
+    if has_exclude && has_skip {
+        Err(Error::msg("flat(...), cannot have both exclude and skip, choose exclude to exclude a field from being included, and skip to stop that field from being flattened."))
+    } else if has_exclude {
+        Ok(FlatAttrOptions::Exclude)
+    } else if has_skip {
+        Ok(FlatAttrOptions::Skip)
+    } else {
+        Err(Error::msg("no option chosen, either choose flat(exclude) to exclude a field from being inserted into world, or choose flat(skip) to prevent that field from being flattened, but it is still inserted into world."))
+    }
 }
 
 fn compute_attributes(attrs: &[Attribute]) -> Result<FlatAttrOptions> {
@@ -249,12 +423,50 @@ fn compute_attributes(attrs: &[Attribute]) -> Result<FlatAttrOptions> {
 #[test]
 fn test_enum() {
     let a = quote!(
-        enum T {
+        enum T<F: Float> {
             A,
-            B(usize)
+            B { path: usize, #[flat(exclude)] b: F, #[flat(skip)] c: Vec<F> },
+            C(usize, #[flat(exclude)] F),
         }
     );
+    let expected = quote!(
+        impl<F: Float> crate::Flatten for T<F> {
+            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+                match self {
+                    Self::A => {}
+                    Self::B { path: __flat_path, c: __flat_c, .. } => {
+                        __flat_path.flatten(path.clone() + "/B/path", world);
+                        world.push(path + "/B/c", __flat_c);
+                    }
+                    Self::C(__flat_0, _) => {
+                        __flat_0.flatten(path + "/C/0", world);
+                    }
+                }
+            }
+        }
+    );
+    let derived = derive_flatten(a).unwrap();
+    assert_eq!(derived.to_string(), expected.to_string());
+
+    let units = quote!(
+        enum U { A, B }
+    );
+    let derived = derive_flatten(units).unwrap().to_string();
+    assert!(derived.contains(&quote!(let _ = (path, world);).to_string()));
+}
 
-    let derived = parse2::<DeriveInput>(a).unwrap();
-    println!("{:#?}", derived);
-}
\ No newline at end of file
+#[test]
+fn derive_flatten_tuple_struct_test() {
+    let tuple = quote!(
+        struct Tuple(f32, #[flat(skip)] f32);
+    );
+    let expected = quote!(
+        impl crate::Flatten for Tuple {
+            fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+                self.0.flatten(path.clone() + "/0", world);
+                world.push(path + "/1", &mut self.1);
+            }
+        }
+    );
+    assert_eq!(derive_flatten(tuple).unwrap().to_string(), expected.to_string());
+}
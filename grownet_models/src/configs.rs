@@ -1,13 +1,13 @@
 use std::collections::HashMap;
 use std::fmt::{format, Display};
 use std::ops::{AddAssign, Deref, DerefMut, Div, Index, IndexMut, Shr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{Context, Error, Result};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum Options {
     INT(isize),
     FLOAT(f64),
@@ -17,6 +17,65 @@ pub enum Options {
     CONFIG(Config),
 }
 
+/// How a numeric option should be rendered, the stored value is unaffected
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayHint {
+    /// scientific notation, ex: 1.2e-4
+    Scientific,
+    /// the value is a number of seconds, ex: 1h 1m 1s
+    Duration,
+}
+
+pub fn fmt_scientific(x: f64) -> String {
+    format!("{:e}", x)
+}
+
+/// Formats floats plainly, unless they are small enough to be hard to read
+pub fn fmt_float(x: f64) -> String {
+    if x != 0.0 && x.abs() < 1e-3 {
+        fmt_scientific(x)
+    } else {
+        format!("{}", x)
+    }
+}
+
+/// Formats a number of seconds as hours, minutes and seconds, leading zero units are omitted
+pub fn fmt_duration(secs: f64) -> String {
+    let sign = if secs < 0.0 { "-" } else { "" };
+    let secs = secs.abs().round() as u64;
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if h > 0 {
+        format!("{sign}{h}h {m}m {s}s")
+    } else if m > 0 {
+        format!("{sign}{m}m {s}s")
+    } else {
+        format!("{sign}{s}s")
+    }
+}
+
+/// Inverse of fmt_duration, also accepts a plain number of seconds
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let s = s.trim();
+    if let Ok(x) = s.parse::<f64>() {
+        return Some(x);
+    }
+    let (sign, s) = if let Some(s) = s.strip_prefix('-') { (-1.0, s) } else { (1.0, s) };
+    let mut total = 0.0;
+    for part in s.split_whitespace() {
+        let (num, scale) = if let Some(x) = part.strip_suffix('h') {
+            (x, 3600.0)
+        } else if let Some(x) = part.strip_suffix('m') {
+            (x, 60.0)
+        } else if let Some(x) = part.strip_suffix('s') {
+            (x, 1.0)
+        } else {
+            return None;
+        };
+        total += num.parse::<f64>().ok()? * scale;
+    }
+    Some(sign * total)
+}
+
 /// Wrap basetype to Options type
 macro_rules! from_overloads {
     ($otype:tt, $cast_to:tt, $opt:ident) => {
@@ -170,6 +229,34 @@ impl Options {
         std::mem::discriminant(self) == std::mem::discriminant(other)
     }
 
+    /// The name of the variant, ex: INT
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Options::INT(_) => "INT",
+            Options::FLOAT(_) => "FLOAT",
+            Options::STR(_) => "STR",
+            Options::BOOL(_) => "BOOL",
+            Options::PATH(_) => "PATH",
+            Options::CONFIG(_) => "CONFIG",
+        }
+    }
+
+    /// Formats the value according to the display hint, non-numeric variants ignore the hint
+    pub fn fmt_value(&self, hint: Option<DisplayHint>) -> String {
+        match (self, hint) {
+            (Options::INT(i), Some(DisplayHint::Scientific)) => fmt_scientific(*i as f64),
+            (Options::INT(i), Some(DisplayHint::Duration)) => fmt_duration(*i as f64),
+            (Options::FLOAT(i), Some(DisplayHint::Scientific)) => fmt_scientific(*i),
+            (Options::FLOAT(i), Some(DisplayHint::Duration)) => fmt_duration(*i),
+            (Options::FLOAT(i), None) => fmt_float(*i),
+            (Options::PATH(i), _) => i.to_str().unwrap().to_string(),
+            (Options::CONFIG(_), _) => "config".to_string(),
+            (Options::INT(i), None) => i.to_string(),
+            (Options::STR(i), _) => i.clone(),
+            (Options::BOOL(i), _) => i.to_string(),
+        }
+    }
+
     /// Not allowed the change the variant, only updates what's inside
     pub fn update(&mut self, val: &Options) -> Result<()> {
         if !self.is_same(val) {
@@ -194,22 +281,23 @@ impl Options {
         &self,
         padding: usize,
         name: Option<&str>,
+        hint: Option<DisplayHint>,
         f: &mut std::fmt::Formatter<'_>,
     ) -> std::fmt::Result {
         let pad = " ".repeat(padding);
         match self {
-            Options::INT(i) => {
+            Options::INT(_) => {
                 if let Some(name) = name {
-                    writeln!(f, "{pad}{}: {}", name, i)
+                    writeln!(f, "{pad}{}: {}", name, self.fmt_value(hint))
                 } else {
-                    writeln!(f, "{pad}i: {}", i)
+                    writeln!(f, "{pad}i: {}", self.fmt_value(hint))
                 }
             }
-            Options::FLOAT(i) => {
+            Options::FLOAT(_) => {
                 if let Some(name) = name {
-                    writeln!(f, "{pad}{}: {}", name, i)
+                    writeln!(f, "{pad}{}: {}", name, self.fmt_value(hint))
                 } else {
-                    writeln!(f, "{pad}f: {}", i)
+                    writeln!(f, "{pad}f: {}", self.fmt_value(hint))
                 }
             }
             Options::STR(i) => {
@@ -248,14 +336,16 @@ impl Options {
 
 impl Display for Options {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_(0, None, f)
+        self.display_(0, None, None, f)
     }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
 pub struct Config {
     map: HashMap<String, Options>,
     order: Vec<String>,
+    #[serde(default)]
+    hints: HashMap<String, DisplayHint>,
 }
 
 pub struct ConfigIter<'a> {
@@ -312,7 +402,7 @@ impl Config {
                 order.push(name);
             }
         }
-        Config { map, order }
+        Config { map, order, hints: HashMap::new() }
     }
 
     /// Are the keys the same between the two configs?
@@ -376,6 +466,50 @@ impl Config {
         true
     }
 
+    /// Returns the paths of all leaves of self whose values differ from, or do not exist in other,
+    /// nested keys are joined by '/', as in get
+    pub fn changed_paths(&self, other: &Config) -> Vec<String> {
+        let mut changed = Vec::new();
+        self.changed_paths_(other, "", &mut changed);
+        changed
+    }
+
+    fn changed_paths_(&self, other: &Config, prefix: &str, changed: &mut Vec<String>) {
+        for (k, v) in self.iter() {
+            let path = if prefix.is_empty() { k.to_string() } else { format!("{prefix}/{k}") };
+            match (v, other.map.get(k)) {
+                (Options::CONFIG(a), Some(Options::CONFIG(b))) => a.changed_paths_(b, &path, changed),
+                (a, Some(b)) if a == b => {}
+                _ => changed.push(path),
+            }
+        }
+    }
+
+    /// Returns (path, value in self, value in other) for every leaf whose value differs between self and other,
+    /// recursing into nested configs, nested keys are joined by '/', as in get. Keys only in self (removed in other)
+    /// have None as the value in other, and keys only in other (added) have None as the value in self
+    pub fn diff(&self, other: &Config) -> Vec<(String, Option<Options>, Option<Options>)> {
+        let mut diffs = Vec::new();
+        self.diff_(other, "", &mut diffs);
+        diffs
+    }
+
+    fn diff_(&self, other: &Config, prefix: &str, diffs: &mut Vec<(String, Option<Options>, Option<Options>)>) {
+        let path = |k: &str| if prefix.is_empty() { k.to_string() } else { format!("{prefix}/{k}") };
+        for (k, v) in self.iter() {
+            match (v, other.map.get(k)) {
+                (Options::CONFIG(a), Some(Options::CONFIG(b))) => a.diff_(b, &path(k), diffs),
+                (a, Some(b)) if a == b => {}
+                (a, b) => diffs.push((path(k), Some(a.clone()), b.cloned())),
+            }
+        }
+        for (k, v) in other.iter() {
+            if !self.map.contains_key(k) {
+                diffs.push((path(k), None, Some(v.clone())));
+            }
+        }
+    }
+
     /// Updates the current config by other by replacing all values of self with other, if the variants are the same
     /// do this recursively for configs
     pub fn update(&mut self, other: &Config) -> Result<()> {
@@ -389,16 +523,51 @@ impl Config {
         Ok(())
     }
 
+    /// Overlays overrides onto self, replacing the value of each key in overrides, nested configs are merged
+    /// recursively rather than replaced as a whole. Unlike update, keys only in overrides are appended, and
+    /// the variants do not need to match
+    pub fn merge(&mut self, overrides: &Config) {
+        for (k, v) in overrides.iter() {
+            match (self.map.get_mut(k), v) {
+                (Some(Options::CONFIG(a)), Options::CONFIG(b)) => a.merge(b),
+                (Some(a), b) => *a = b.clone(),
+                (None, b) => {
+                    self.order.push(k.to_string());
+                    self.map.insert(k.to_string(), b.clone());
+                }
+            }
+            if let Some(hint) = overrides.hint(k) {
+                self.hints.insert(k.to_string(), hint);
+            }
+        }
+    }
+
     /// Updates config at that entry, errors if key does not exist
-    /// or if the variants are not the same
+    /// or if the variants are not the same, use update_key_force to change the variant
     pub fn update_key(&mut self, key: &str, val: &Options) -> Result<()> {
         let entry = self
             .map
             .get_mut(key)
             .ok_or(Error::msg(format!("failed to retrieve key {}", key)))?;
+        if !entry.is_same(val) {
+            return Err(Error::msg(format!(
+                "cannot update key {} of variant {} with variant {}",
+                key, entry.variant_name(), val.variant_name()
+            )));
+        }
         entry.update(val).context(format!("Error on key {}", key))
     }
 
+    /// Same as update_key, but replaces the entry even if the variants are not the same
+    pub fn update_key_force(&mut self, key: &str, val: &Options) -> Result<()> {
+        let entry = self
+            .map
+            .get_mut(key)
+            .ok_or(Error::msg(format!("failed to retrieve key {}", key)))?;
+        *entry = val.clone();
+        Ok(())
+    }
+
     /// Insert key inserts a new value into the config if there isn't one
     /// already, returning error. The order is appended last
     pub fn insert(&mut self, key: &str, val: &Options) -> Result<()> {
@@ -433,6 +602,19 @@ impl Config {
         }
     }
 
+    /// Sets how the value at key should be displayed, errors if key does not exist
+    pub fn set_hint(&mut self, key: &str, hint: DisplayHint) -> Result<()> {
+        if !self.map.contains_key(key) {
+            return Err(Error::msg(format!("failed to retrieve key {}", key)));
+        }
+        self.hints.insert(key.to_string(), hint);
+        Ok(())
+    }
+
+    pub fn hint(&self, key: &str) -> Option<DisplayHint> {
+        self.hints.get(key).copied()
+    }
+
     pub fn index(&self, i: usize) -> Option<&Options> {
         if i < self.order.len() {
             Some(self.map.get(&self.order[i]).unwrap())
@@ -502,6 +684,40 @@ impl Config {
         Some(k)
     }
 
+    /// gets the value at the path, as in get, and unwraps it with f, erroring with the key and expected
+    /// variant if the path does not exist, or f does not accept the value
+    fn get_typed<'a, T>(&'a self, k: &str, expected: &str, f: impl FnOnce(&'a Options) -> Option<T>) -> Result<T> {
+        let v = self.get(k).ok_or(Error::msg(format!("failed to retrieve key {}, expected {}", k, expected)))?;
+        f(v).ok_or(Error::msg(format!(
+            "expected key {} to be of variant {}, got {}",
+            k, expected, v.variant_name()
+        )))
+    }
+
+    pub fn get_int(&self, k: &str) -> Result<isize> {
+        self.get_typed(k, "INT", |v| if let Options::INT(i) = v { Some(*i) } else { None })
+    }
+
+    pub fn get_float(&self, k: &str) -> Result<f64> {
+        self.get_typed(k, "FLOAT", |v| if let Options::FLOAT(i) = v { Some(*i) } else { None })
+    }
+
+    pub fn get_bool(&self, k: &str) -> Result<bool> {
+        self.get_typed(k, "BOOL", |v| if let Options::BOOL(i) = v { Some(*i) } else { None })
+    }
+
+    pub fn get_str(&self, k: &str) -> Result<&str> {
+        self.get_typed(k, "STR", |v| if let Options::STR(i) = v { Some(i.as_str()) } else { None })
+    }
+
+    pub fn get_path(&self, k: &str) -> Result<&Path> {
+        self.get_typed(k, "PATH", |v| if let Options::PATH(i) = v { Some(i.as_path()) } else { None })
+    }
+
+    pub fn get_config(&self, k: &str) -> Result<&Config> {
+        self.get_typed(k, "CONFIG", |v| if let Options::CONFIG(i) = v { Some(i) } else { None })
+    }
+
     /// same as get, but can panic
     pub fn uget(&self, k: &str) -> &Options {
         let mut p = k.split("/");
@@ -543,6 +759,22 @@ impl Config {
         k
     }
 
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("serializing config to json")
+    }
+
+    pub fn from_json(s: &str) -> Result<Config> {
+        serde_json::from_str(s).context("deserializing config from json")
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        serde_yaml::to_string(self).context("serializing config to yaml")
+    }
+
+    pub fn from_yaml(s: &str) -> Result<Config> {
+        serde_yaml::from_str(s).context("deserializing config from yaml")
+    }
+
     pub fn iter(&self) -> ConfigIter {
         ConfigIter {
             config: self,
@@ -559,7 +791,7 @@ impl Config {
 
     fn display_(&self, padding: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (k, v) in self.iter() {
-            v.display_(padding, Some(k), f)?;
+            v.display_(padding, Some(k), self.hint(k), f)?;
         }
         Ok(())
     }
@@ -627,3 +859,171 @@ fn config_macro_test() {
     let _k: i32 = (&_a["c"]["d"]).into();
     println!("{}", _a);
 }
+
+#[test]
+fn display_hint_test() {
+    use crate::{config, opt};
+    assert_eq!(fmt_float(0.00012), "1.2e-4");
+    assert_eq!(fmt_float(0.5), "0.5");
+    assert_eq!(fmt_duration(3661.0), "1h 1m 1s");
+    assert_eq!(fmt_duration(61.0), "1m 1s");
+    assert_eq!(parse_duration("1h 1m 1s"), Some(3661.0));
+    assert_eq!(parse_duration("12"), Some(12.0));
+    assert_eq!(parse_duration("1x"), None);
+
+    let mut c = config!(("lr", 0.00012), ("timeout", 3661));
+    c.set_hint("timeout", DisplayHint::Duration).unwrap();
+    assert!(c.set_hint("none", DisplayHint::Duration).is_err());
+    assert_eq!(c["lr"].fmt_value(c.hint("lr")), "1.2e-4");
+    assert_eq!(c["timeout"].fmt_value(c.hint("timeout")), "1h 1m 1s");
+    // stored values are unchanged
+    let timeout: isize = (&c["timeout"]).into();
+    assert_eq!(timeout, 3661);
+    assert_eq!(format!("{}", c), "lr: 1.2e-4\ntimeout: 1h 1m 1s\n");
+}
+
+#[test]
+fn changed_paths_test() {
+    use crate::{config, opt};
+    let default = config!(
+        ("lr", 0.1),
+        ("epochs", 4),
+        ("sgd", [("momentum", 0.9), ("nesterov", false)])
+    );
+    let mut config = default.clone();
+    assert!(config.changed_paths(&default).is_empty());
+
+    config.update_key("epochs", &Options::INT(5)).unwrap();
+    *config.uget_mut("sgd/nesterov") = Options::BOOL(true);
+    assert_eq!(config.changed_paths(&default), vec!["epochs".to_string(), "sgd/nesterov".to_string()]);
+
+    config.insert("extra", &Options::STR("a".into())).unwrap();
+    assert_eq!(config.changed_paths(&default).last().unwrap(), "extra");
+}
+
+#[test]
+fn diff_test() {
+    use crate::{config, opt};
+    let a = config!(
+        ("lr", 0.1),
+        ("epochs", 4),
+        ("sgd", [("momentum", 0.9), ("nesterov", false), ("dampening", 0.0)])
+    );
+    assert!(a.diff(&a).is_empty());
+
+    let b = config!(
+        ("lr", 0.1),
+        ("epochs", 5),
+        ("sgd", [("momentum", 0.9), ("nesterov", true), ("weight_decay", 0.1)]),
+        ("name", "b")
+    );
+    assert_eq!(a.diff(&b), vec![
+        ("epochs".to_string(), Some(Options::INT(4)), Some(Options::INT(5))),
+        ("sgd/nesterov".to_string(), Some(Options::BOOL(false)), Some(Options::BOOL(true))),
+        ("sgd/dampening".to_string(), Some(Options::FLOAT(0.0)), None),
+        ("sgd/weight_decay".to_string(), None, Some(Options::FLOAT(0.1))),
+        ("name".to_string(), None, Some(Options::STR("b".into()))),
+    ]);
+
+    // a change of variant is reported as a single difference
+    let c = config!(("lr", 0.1), ("epochs", 4), ("sgd", 1));
+    assert_eq!(a.diff(&c), vec![("sgd".to_string(), Some(a["sgd"].clone()), Some(Options::INT(1)))]);
+}
+
+#[test]
+fn merge_test() {
+    use crate::{config, opt};
+    let mut base = config!(
+        ("lr", 0.1),
+        ("epochs", 4),
+        ("sgd", [("momentum", 0.9), ("nesterov", false), ("schedule", [("gamma", 0.5), ("step", 10)])])
+    );
+    let overrides = config!(
+        ("epochs", "all"),
+        ("sgd", [("nesterov", true), ("schedule", [("step", 20)]), ("dampening", 0.1)]),
+        ("name", "b")
+    );
+    base.merge(&overrides);
+    let expected = config!(
+        ("lr", 0.1),
+        ("epochs", "all"),
+        ("sgd", [("momentum", 0.9), ("nesterov", true), ("schedule", [("gamma", 0.5), ("step", 20)]), ("dampening", 0.1)]),
+        ("name", "b")
+    );
+    assert_eq!(base, expected);
+
+    // a config replaces a leaf, and the other way around
+    let mut base = config!(("a", 1), ("b", [("c", 1)]));
+    base.merge(&config!(("a", [("c", 2)]), ("b", 3)));
+    assert_eq!(base, config!(("a", [("c", 2)]), ("b", 3)));
+}
+
+#[test]
+fn update_key_test() {
+    use crate::{config, opt};
+    let mut config = config!(("epochs", 4), ("sgd", [("momentum", 0.9)]));
+    config.update_key("epochs", &Options::INT(5)).unwrap();
+    assert_eq!(config["epochs"], Options::INT(5));
+    assert!(config.update_key("none", &Options::INT(5)).is_err());
+
+    let err = config.update_key("epochs", &Options::STR("5".into())).unwrap_err();
+    assert_eq!(err.to_string(), "cannot update key epochs of variant INT with variant STR");
+    assert_eq!(config["epochs"], Options::INT(5));
+    // nested configs must also keep their variants
+    let nested = Options::CONFIG(config!(("momentum", true)));
+    assert!(config.update_key("sgd", &nested).is_err());
+
+    config.update_key_force("epochs", &Options::STR("5".into())).unwrap();
+    assert_eq!(config["epochs"], Options::STR("5".into()));
+    config.update_key_force("sgd", &nested).unwrap();
+    assert_eq!(config["sgd/momentum"], Options::BOOL(true));
+    assert!(config.update_key_force("none", &Options::INT(5)).is_err());
+}
+
+#[test]
+fn typed_getters_test() {
+    use crate::{config, opt};
+    let config = config!(
+        ("epochs", 4),
+        ("lr", 0.1),
+        ("name", "baseline"),
+        ("folder", Path("runs")),
+        ("opt", [("nesterov", true), ("steps", 10)])
+    );
+    assert_eq!(config.get_int("epochs").unwrap(), 4);
+    assert_eq!(config.get_float("lr").unwrap(), 0.1);
+    assert_eq!(config.get_str("name").unwrap(), "baseline");
+    assert_eq!(config.get_path("folder").unwrap(), Path::new("runs"));
+    assert_eq!(config.get_bool("opt/nesterov").unwrap(), true);
+    assert_eq!(config.get_int("opt/steps").unwrap(), 10);
+    assert_eq!(config.get_config("opt").unwrap().get_int("steps").unwrap(), 10);
+
+    assert_eq!(config.get_int("none").unwrap_err().to_string(), "failed to retrieve key none, expected INT");
+    assert!(config.get_int("opt/none").is_err());
+    assert!(config.get_int("epochs/steps").is_err());
+    assert_eq!(config.get_float("epochs").unwrap_err().to_string(), "expected key epochs to be of variant FLOAT, got INT");
+    assert!(config.get_config("lr").is_err());
+    assert!(config.get_bool("name").is_err());
+}
+
+#[test]
+fn json_yaml_test() {
+    use crate::{config, opt};
+    let mut config = config!(
+        ("epochs", 4),
+        ("lr", 0.1),
+        ("weight_decay", 1e-7),
+        ("name", "baseline"),
+        ("folder", Path("runs/a")),
+        ("opt", [("nesterov", true), ("momentum", 0.9), ("schedule", [("steps", -10), ("gamma", 1.0)])])
+    );
+    config.set_hint("weight_decay", DisplayHint::Scientific).unwrap();
+
+    let json = config.to_json().unwrap();
+    assert_eq!(Config::from_json(&json).unwrap(), config);
+    let yaml = config.to_yaml().unwrap();
+    assert_eq!(Config::from_yaml(&yaml).unwrap(), config);
+
+    assert!(Config::from_json("{").is_err());
+    assert!(Config::from_yaml("map: 1").is_err());
+}
@@ -0,0 +1,187 @@
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, Context, bail};
+use ndarray::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The index of an ImageCache, written next to the blob once the blob is complete
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheIndex {
+    key: u64,
+    /// the [c, h, w] shape of every image
+    shape: [usize; 3],
+    labels: Vec<u32>,
+}
+
+/// Decoded images of equal shape and their labels, stored on disk as a flat f32 blob and an index,
+/// the blob is memory mapped so only the pages which are read are loaded.
+/// The cache is identified by a key, a cache on disk with a different key is rebuilt
+pub struct ImageCache {
+    data: Mmap,
+    index: CacheIndex,
+    hit: bool,
+}
+
+impl ImageCache {
+    /// memory maps the cache dir/name.bin if its index matches key, otherwise writes every image produced by
+    /// build to it first. Images must have the given [c, h, w] shape
+    pub fn load_or_build<I>(dir: &Path, name: &str, key: u64, shape: [usize; 3], build: impl FnOnce() -> Result<I>) -> Result<Self>
+    where I: Iterator<Item = Result<(Array3<f32>, u32)>> {
+        let (blob, index_path) = (dir.join(name).with_extension("bin"), dir.join(name).with_extension("json"));
+        let index = std::fs::read_to_string(&index_path).ok()
+            .and_then(|x| serde_json::from_str::<CacheIndex>(&x).ok())
+            .filter(|x| x.key == key && x.shape == shape);
+        if let Some(index) = index {
+            if let Ok(data) = Mmap::open(&blob) {
+                if data.as_f32().len() == index.labels.len() * shape.iter().product::<usize>() {
+                    return Ok(Self { data, index, hit: true });
+                }
+            }
+        }
+
+        std::fs::create_dir_all(dir).with_context(|| format!("unable to create cache folder {}", dir.display()))?;
+        // the old index is removed first, so that an interrupted build is never mistaken for a valid cache
+        let _ = std::fs::remove_file(&index_path);
+        let mut labels = Vec::new();
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(&blob)
+            .with_context(|| format!("unable to create cache {}", blob.display()))?);
+        for item in build()? {
+            let (im, label) = item?;
+            if im.shape() != shape {
+                bail!("cached images must have shape {:?}, got {:?}", shape, im.shape());
+            }
+            for x in im.iter() {
+                writer.write_all(&x.to_ne_bytes())?;
+            }
+            labels.push(label);
+        }
+        writer.flush()?;
+        drop(writer);
+        let index = CacheIndex { key, shape, labels };
+        std::fs::write(&index_path, serde_json::to_string(&index)?)?;
+        Ok(Self { data: Mmap::open(&blob)?, index, hit: false })
+    }
+
+    /// whether the cache was read from disk, rather than built
+    pub fn is_hit(&self) -> bool {
+        self.hit
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.labels.is_empty()
+    }
+
+    pub fn image(&self, i: usize) -> ArrayView3<f32> {
+        let [c, h, w] = self.index.shape;
+        let size = c * h * w;
+        ArrayView3::from_shape((c, h, w), &self.data.as_f32()[i * size..(i + 1) * size]).unwrap()
+    }
+
+    pub fn label(&self, i: usize) -> u32 {
+        self.index.labels[i]
+    }
+
+    /// the images at idx stacked to shape [b, c, h, w], and their labels
+    pub fn batch(&self, idx: &[usize]) -> (Array4<f32>, Array1<u32>) {
+        let [c, h, w] = self.index.shape;
+        let mut images = Array4::zeros((idx.len(), c, h, w));
+        for (mut im, i) in images.axis_iter_mut(Axis(0)).zip(idx) {
+            im.assign(&self.image(*i));
+        }
+        (images, idx.iter().map(|i| self.label(*i)).collect())
+    }
+}
+
+/// hashes x into a cache key, keys are only stable for a given build of the program, so a toolchain update
+/// may cause caches to be rebuilt
+pub fn cache_key(x: &impl Hash) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    x.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// hashes the path, size and modification time of each file, so that any change to the files changes the key
+pub fn files_key(files: &[PathBuf]) -> Result<u64> {
+    let stamps = files.iter().map(|path| {
+        let meta = std::fs::metadata(path).with_context(|| format!("unable to read {}", path.display()))?;
+        Ok((path, meta.len(), meta.modified().ok()))
+    }).collect::<Result<Vec<_>>>()?;
+    Ok(cache_key(&stamps))
+}
+
+/// A read only memory mapping of a file
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// the mapping is read only and owned by this struct
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn open(path: &Path) -> Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let file = std::fs::File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mmap does not accept empty mappings
+            return Ok(Self { ptr: std::ptr::null_mut(), len });
+        }
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error()).context(format!("unable to map {}", path.display()));
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn as_f32(&self) -> &[f32] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        // mappings are page aligned
+        unsafe { std::slice::from_raw_parts(self.ptr as *const f32, self.len / std::mem::size_of::<f32>()) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            unsafe { libc::munmap(self.ptr, self.len); }
+        }
+    }
+}
+
+#[test]
+fn test_image_cache() {
+    let dir = std::env::temp_dir().join("grownet_test_image_cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let images = || Ok((0..3).map(|i| Ok((Array3::from_elem((2, 2, 3), i as f32 * 0.5), i as u32))));
+
+    let cache = ImageCache::load_or_build(&dir, "test", 1, [2, 2, 3], images).unwrap();
+    assert!(!cache.is_hit());
+    assert_eq!(cache.len(), 3);
+    let (batch, labels) = cache.batch(&[2, 0]);
+    assert_eq!(batch.dim(), (2, 2, 2, 3));
+    assert!(batch.slice(s![0, .., .., ..]).iter().all(|x| *x == 1.0));
+    assert_eq!(labels, arr1(&[2, 0]));
+    drop(cache);
+
+    let cache = ImageCache::load_or_build(&dir, "test", 1, [2, 2, 3], || -> Result<std::iter::Empty<_>> { panic!("cache was rebuilt") }).unwrap();
+    assert!(cache.is_hit());
+    assert_eq!(cache.image(1), Array3::from_elem((2, 2, 3), 0.5));
+    drop(cache);
+
+    // a different key rebuilds the cache
+    let cache = ImageCache::load_or_build(&dir, "test", 2, [2, 2, 3], images).unwrap();
+    assert!(!cache.is_hit());
+    // and mismatched shapes are rejected
+    assert!(ImageCache::load_or_build(&dir, "test", 3, [1, 2, 3], images).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,5 +1,15 @@
+use std::path::{Path, PathBuf};
+
 use ndarray::prelude::*;
-use anyhow::{Result, Error};
+use anyhow::{Result, Error, Context, bail};
+use rand::{SeedableRng, rngs::StdRng, thread_rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use super::{Dataset, DatasetBuilder};
+use super::transforms::{ImClassifyDataPoint, Labels};
+
+/// each record of the binary format is a label byte followed by a 3x32x32 image in channel first order
+const RECORD_LEN: usize = 1 + 3 * 32 * 32;
 
 pub struct Cifar10 {
     train_img: Array4<u8>,
@@ -24,11 +34,29 @@ impl Cifar10 {
             test_label: Array1::from_shape_vec(10000, test_labels)? 
         })
     }
+    /// reads the binary version of the dataset from dir, which should contain data_batch_*.bin
+    /// and test_batch.bin, as extracted from cifar-10-binary.tar.gz
+    pub fn from_binary(dir: &Path) -> Result<Self> {
+        let (train_img, train_label) = read_binary(&train_files(dir)?)?;
+        let (test_img, test_label) = read_binary(&[dir.join("test_batch.bin")])?;
+        Ok(Self { train_img, train_label, test_img, test_label })
+    }
+
+    /// batches of training images normalized to [0, 1], with shape [b, 3, 32, 32], and their labels
+    pub fn iter_train_batches(&self, batch_size: usize) -> impl Iterator<Item = (Array4<f32>, Array1<u8>)> + '_ {
+        batches(&self.train_img, &self.train_label, batch_size)
+    }
+
+    /// batches of test images normalized to [0, 1], with shape [b, 3, 32, 32], and their labels
+    pub fn iter_test_batches(&self, batch_size: usize) -> impl Iterator<Item = (Array4<f32>, Array1<u8>)> + '_ {
+        batches(&self.test_img, &self.test_label, batch_size)
+    }
+
     pub fn iter_train_img(&self) -> impl Iterator<Item = ArrayView3<u8>> {
         self.train_img.axis_iter(Axis(0))
     }
     pub fn iter_train_label(&self) -> impl Iterator<Item = &u8> {
-        self.train_img.iter()
+        self.train_label.iter()
     }
     pub fn iter_test_img(&self) -> impl Iterator<Item = ArrayView3<u8>> {
         self.test_img.axis_iter(Axis(0))
@@ -36,4 +64,202 @@ impl Cifar10 {
     pub fn iter_test_label(&self) -> impl Iterator<Item = &u8> {
         self.test_label.iter()
     }
-}
\ No newline at end of file
+}
+
+/// Builds the splits of the binary version of the dataset in path, as extracted from cifar-10-binary.tar.gz
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cifar10Params {
+    pub path: String,
+    pub train_batch_size: usize,
+    pub test_batch_size: usize,
+}
+
+impl Default for Cifar10Params {
+    fn default() -> Self {
+        Self { path: "assets/ml_datasets/cifar-10-batches-bin".into(), train_batch_size: 32, test_batch_size: 32 }
+    }
+}
+
+impl DatasetBuilder for Cifar10Params {
+    type Dataset = Cifar10Split;
+    fn build_train(&self) -> Result<Cifar10Split> {
+        let (images, labels) = read_binary(&train_files(Path::new(&self.path))?)?;
+        Ok(Cifar10Split::new(images, labels, self.train_batch_size))
+    }
+
+    fn build_test(&self) -> Option<Result<Cifar10Split>> {
+        let split = read_binary(&[Path::new(&self.path).join("test_batch.bin")])
+            .map(|(images, labels)| Cifar10Split::new(images, labels, self.test_batch_size));
+        Some(split)
+    }
+}
+
+/// A split of cifar10 held in memory, batches have images with shape [b, 3, 32, 32] normalized to [0, 1]
+pub struct Cifar10Split {
+    images: Array4<u8>,
+    labels: Array1<u8>,
+    order: Vec<usize>,
+    batch_size: usize,
+    pos: usize,
+}
+
+impl Cifar10Split {
+    fn new(images: Array4<u8>, labels: Array1<u8>, batch_size: usize) -> Self {
+        let order = (0..labels.len()).collect();
+        Self { images, labels, order, batch_size: batch_size.max(1), pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+impl Dataset for Cifar10Split {
+    type DataPoint = ImClassifyDataPoint;
+
+    fn next(&mut self) -> Option<ImClassifyDataPoint> {
+        if self.pos >= self.order.len() {
+            return None;
+        }
+        let idx = &self.order[self.pos..(self.pos + self.batch_size).min(self.order.len())];
+        self.pos += idx.len();
+        Some(ImClassifyDataPoint {
+            image: self.images.select(Axis(0), idx).mapv(|x| x as f32 / 255.0),
+            label: Labels::Hard(idx.iter().map(|i| self.labels[*i] as u32).collect()),
+        })
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    fn shuffle(&mut self) {
+        self.order.shuffle(&mut thread_rng());
+        self.pos = 0;
+    }
+
+    fn shuffle_seeded(&mut self, seed: u64) {
+        self.order.sort();
+        self.order.shuffle(&mut StdRng::seed_from_u64(seed));
+        self.pos = 0;
+    }
+}
+
+/// the data_batch_*.bin files of dir, in order
+fn train_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut train_files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("unable to read cifar10 directory {}", dir.display()))?
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| {
+            let name = x.file_name().unwrap().to_string_lossy();
+            name.starts_with("data_batch_") && name.ends_with(".bin")
+        })
+        .collect();
+    if train_files.is_empty() {
+        bail!("no data_batch_*.bin files found in {}", dir.display());
+    }
+    train_files.sort();
+    Ok(train_files)
+}
+
+/// reads and concatenates the records of each file
+fn read_binary(files: &[PathBuf]) -> Result<(Array4<u8>, Array1<u8>)> {
+    let mut images = Vec::new();
+    let mut labels = Vec::new();
+    for file in files {
+        let bytes = std::fs::read(file).with_context(|| format!("unable to read cifar10 file {}", file.display()))?;
+        if bytes.len() % RECORD_LEN != 0 {
+            bail!("{} has {} bytes, which is not a multiple of the record length {}", file.display(), bytes.len(), RECORD_LEN);
+        }
+        for record in bytes.chunks_exact(RECORD_LEN) {
+            if record[0] >= 10 {
+                bail!("{} contains an invalid label {}", file.display(), record[0]);
+            }
+            labels.push(record[0]);
+            images.extend_from_slice(&record[1..]);
+        }
+    }
+    let n = labels.len();
+    Ok((Array4::from_shape_vec((n, 3, 32, 32), images)?, Array1::from_vec(labels)))
+}
+
+fn batches<'a>(images: &'a Array4<u8>, labels: &'a Array1<u8>, batch_size: usize) -> impl Iterator<Item = (Array4<f32>, Array1<u8>)> + 'a {
+    images.axis_chunks_iter(Axis(0), batch_size)
+        .zip(labels.axis_chunks_iter(Axis(0), batch_size))
+        .map(|(img, label)| (img.mapv(|x| x as f32 / 255.0), label.to_owned()))
+}
+
+#[test]
+fn test_from_binary() {
+    let dir = std::env::temp_dir().join("grownet_test_cifar10_binary");
+    std::fs::create_dir_all(&dir).unwrap();
+    let record = |label: u8, fill: u8| {
+        let mut record = vec![label];
+        record.extend(std::iter::repeat(fill).take(RECORD_LEN - 1));
+        record
+    };
+    std::fs::write(dir.join("data_batch_1.bin"), [record(3, 255), record(7, 0)].concat()).unwrap();
+    std::fs::write(dir.join("data_batch_2.bin"), record(1, 51)).unwrap();
+    // the test file is missing
+    assert!(Cifar10::from_binary(&dir).is_err());
+
+    std::fs::write(dir.join("test_batch.bin"), record(9, 0)).unwrap();
+    let cifar = Cifar10::from_binary(&dir).unwrap();
+    assert_eq!(cifar.iter_train_label().copied().collect::<Vec<_>>(), [3, 7, 1]);
+    assert_eq!(cifar.iter_test_label().copied().collect::<Vec<_>>(), [9]);
+    assert_eq!(cifar.iter_train_img().next().unwrap().dim(), (3, 32, 32));
+
+    let batches: Vec<_> = cifar.iter_train_batches(2).collect();
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].0.dim(), (2, 3, 32, 32));
+    assert_eq!(batches[1].0.dim(), (1, 3, 32, 32));
+    assert_eq!(batches[0].1, arr1(&[3, 7]));
+    assert!(batches[0].0.slice(s![0, .., .., ..]).iter().all(|x| *x == 1.0));
+    assert!(batches[1].0.iter().all(|x| (*x - 0.2).abs() < 1e-6));
+
+    // truncated records are rejected
+    std::fs::write(dir.join("test_batch.bin"), &record(9, 0)[..100]).unwrap();
+    assert!(Cifar10::from_binary(&dir).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_builder() {
+    let dir = std::env::temp_dir().join("grownet_test_cifar10_builder");
+    std::fs::create_dir_all(&dir).unwrap();
+    let record = |label: u8, fill: u8| {
+        let mut record = vec![label];
+        record.extend(std::iter::repeat(fill).take(RECORD_LEN - 1));
+        record
+    };
+    std::fs::write(dir.join("data_batch_1.bin"), [record(3, 255), record(7, 0), record(1, 51)].concat()).unwrap();
+    let params = Cifar10Params { path: dir.to_string_lossy().into(), train_batch_size: 2, test_batch_size: 4 };
+    assert!(params.build_test().unwrap().is_err());
+
+    std::fs::write(dir.join("test_batch.bin"), record(9, 0)).unwrap();
+    let mut train = params.build_train().unwrap();
+    assert_eq!(train.len(), 3);
+    let batch = train.next().unwrap();
+    assert_eq!(batch.image.dim(), (2, 3, 32, 32));
+    assert_eq!(batch.label, Labels::Hard(arr1(&[3, 7])));
+    assert!(batch.image.slice(s![0, .., .., ..]).iter().all(|x| *x == 1.0));
+    let batch = train.next().unwrap();
+    assert_eq!(batch.image.dim(), (1, 3, 32, 32));
+    assert_eq!(batch.label, Labels::Hard(arr1(&[1])));
+    assert!(batch.image.iter().all(|x| (*x - 0.2).abs() < 1e-6));
+    assert!(train.next().is_none());
+    train.reset();
+    assert_eq!(train.next().unwrap().label, Labels::Hard(arr1(&[3, 7])));
+
+    let mut test = params.build_test().unwrap().unwrap();
+    let batch = test.next().unwrap();
+    assert_eq!((batch.image.dim(), batch.label), ((1, 3, 32, 32), Labels::Hard(arr1(&[9]))));
+
+    let missing = Cifar10Params { path: dir.join("missing").to_string_lossy().into(), ..params };
+    assert!(missing.build_train().is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
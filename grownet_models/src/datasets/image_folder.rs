@@ -0,0 +1,239 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, Context, bail};
+use image::imageops::FilterType;
+use ndarray::prelude::*;
+use rand::{thread_rng, seq::SliceRandom, SeedableRng, rngs::StdRng};
+
+use super::transforms;
+use super::cache::{self, ImageCache};
+
+/// A classification dataset stored as a directory of images, where the name of each subfolder is
+/// the class of the images inside it, ex. root/cat/1.png, root/dog/2.png.
+/// Classes are labelled by their position in the sorted list of subfolder names.
+pub struct ImageFolder {
+    pub root: PathBuf,
+    /// every image is resized to [width, height]
+    pub image_size: [u32; 2],
+    pub batch_size: usize,
+    classes: Vec<String>,
+    samples: Vec<(PathBuf, u32)>,
+    order: Vec<usize>,
+    cache: Option<ImageCache>,
+}
+
+impl ImageFolder {
+    pub fn new(root: &Path, image_size: [u32; 2], batch_size: usize) -> Result<Self> {
+        let mut class_dirs: Vec<PathBuf> = std::fs::read_dir(root)
+            .with_context(|| format!("unable to read image folder {}", root.display()))?
+            .filter_map(|x| x.ok().map(|x| x.path()))
+            .filter(|x| x.is_dir())
+            .collect();
+        class_dirs.sort();
+        if class_dirs.is_empty() {
+            bail!("image folder {} has no class subfolders", root.display());
+        }
+
+        let mut classes = Vec::new();
+        let mut samples = Vec::new();
+        for (label, dir) in class_dirs.iter().enumerate() {
+            classes.push(dir.file_name().unwrap().to_string_lossy().to_string());
+            let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+                .filter_map(|x| x.ok().map(|x| x.path()))
+                .filter(|x| x.is_file() && image::ImageFormat::from_path(x).is_ok())
+                .collect();
+            files.sort();
+            samples.extend(files.into_iter().map(|x| (x, label as u32)));
+        }
+        let order = (0..samples.len()).collect();
+        Ok(Self { root: root.to_path_buf(), image_size, batch_size, classes, samples, order, cache: None })
+    }
+
+    /// decodes every image into a memory mapped cache in cache_dir, or maps the existing cache if it was built
+    /// from the same files and image_size, later batches are read from the cache instead of decoding images
+    pub fn use_cache(&mut self, cache_dir: &Path) -> Result<()> {
+        let files: Vec<PathBuf> = self.samples.iter().map(|x| x.0.clone()).collect();
+        let labels: Vec<u32> = self.samples.iter().map(|x| x.1).collect();
+        let key = cache::cache_key(&(&self.root, self.image_size, &labels, cache::files_key(&files)?));
+        let name = format!("image_folder_{}", self.root.file_name().map_or("root".into(), |x| x.to_string_lossy()));
+        let [w, h] = self.image_size;
+        self.cache = Some(ImageCache::load_or_build(cache_dir, &name, key, [3, h as usize, w as usize], || {
+            Ok(self.samples.iter().map(|(path, label)| Ok((self.load_image(path)?, *label))))
+        })?);
+        Ok(())
+    }
+
+    /// whether the cache in use was mapped from an existing cache on disk, None if no cache is used
+    pub fn cache_hit(&self) -> Option<bool> {
+        self.cache.as_ref().map(|x| x.is_hit())
+    }
+
+    /// the path and label of every image, in the order they were found
+    pub fn samples(&self) -> &[(PathBuf, u32)] {
+        &self.samples
+    }
+
+    /// the class names, indexed by label
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn shuffle(&mut self) {
+        let mut rng = thread_rng();
+        self.order.shuffle(&mut rng);
+    }
+
+    /// shuffles with a seeded rng, so that the order is reproducible
+    pub fn shuffle_seeded(&mut self, seed: u64) {
+        self.order.sort();
+        self.order.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+
+    /// decodes the image at path, resized to image_size, with shape [3, h, w] and values in [0, 1]
+    pub fn load_image(&self, path: &Path) -> Result<Array3<f32>> {
+        decode_image(path, Some(self.image_size))
+    }
+
+    /// batches of images with shape [b, 3, h, w] and their labels, in the current order, read from the cache if one is used
+    pub fn iter_batches(&self) -> impl Iterator<Item = Result<(Array4<f32>, Array1<u32>)>> + '_ {
+        self.order.chunks(self.batch_size).map(|idx| {
+            if let Some(cache) = &self.cache {
+                return Ok(cache.batch(idx));
+            }
+            let imgs = idx.iter()
+                .map(|i| self.load_image(&self.samples[*i].0))
+                .collect::<Result<Vec<_>>>()?;
+            let labels = idx.iter().map(|i| self.samples[*i].1).collect();
+            Ok((transforms::batch_im(&imgs), Array1::from_vec(labels)))
+        })
+    }
+
+    /// batches of images at their original sizes, padded with fill to the largest width and height
+    /// of each batch by transforms::collate_pad, along with the mask of the pixels of each image and the labels
+    pub fn iter_padded_batches(&self, fill: f32) -> impl Iterator<Item = Result<(Array4<f32>, Array3<bool>, Array1<u32>)>> + '_ {
+        self.order.chunks(self.batch_size).map(move |idx| {
+            let imgs = idx.iter()
+                .map(|i| decode_image(&self.samples[*i].0, None))
+                .collect::<Result<Vec<_>>>()?;
+            let labels = idx.iter().map(|i| self.samples[*i].1).collect();
+            let (batch, mask) = transforms::collate_pad(&imgs, fill);
+            Ok((batch, mask, Array1::from_vec(labels)))
+        })
+    }
+}
+
+/// decodes the image at path, optionally resized to [w, h], with shape [3, h, w] and values in [0, 1]
+pub fn decode_image(path: &Path, size: Option<[u32; 2]>) -> Result<Array3<f32>> {
+    let mut im = image::open(path)
+        .with_context(|| format!("unable to decode image {}", path.display()))?;
+    if let Some([w, h]) = size {
+        im = im.resize_exact(w, h, FilterType::Triangle);
+    }
+    Ok(transforms::from_image(im.to_rgb8(), false).mapv(|x| x as f32 / 255.0))
+}
+
+#[test]
+fn test_image_folder() {
+    let root = std::env::temp_dir().join("grownet_test_image_folder");
+    for (class, sizes) in [("dog", [(8, 6)]), ("cat", [(4, 4)])] {
+        std::fs::create_dir_all(root.join(class)).unwrap();
+        for (i, (w, h)) in sizes.iter().enumerate() {
+            let shade = if class == "cat" { 255 } else { 0 };
+            let im = image::RgbImage::from_pixel(*w, *h, image::Rgb([shade, shade, shade]));
+            im.save(root.join(class).join(format!("{}.png", i))).unwrap();
+        }
+    }
+    image::RgbImage::new(2, 2).save(root.join("cat").join("1.png")).unwrap();
+    // files that are not images are skipped
+    std::fs::write(root.join("cat").join("notes.txt"), "not an image").unwrap();
+
+    let mut dataset = ImageFolder::new(&root, [5, 3], 2).unwrap();
+    assert_eq!(dataset.classes(), ["cat", "dog"]);
+    assert_eq!(dataset.len(), 3);
+
+    let batches: Vec<_> = dataset.iter_batches().map(|x| x.unwrap()).collect();
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].0.dim(), (2, 3, 3, 5));
+    assert_eq!(batches[1].0.dim(), (1, 3, 3, 5));
+    assert_eq!(batches[0].1, arr1(&[0, 0]));
+    assert_eq!(batches[1].1, arr1(&[1]));
+    assert!(batches[0].0.slice(s![0, .., .., ..]).iter().all(|x| *x == 1.0));
+    assert!(batches[1].0.iter().all(|x| *x == 0.0));
+
+    // without resizing, the 4x4 and 2x2 cats are padded to a single 4x4 batch
+    let padded: Vec<_> = dataset.iter_padded_batches(0.5).map(|x| x.unwrap()).collect();
+    assert_eq!(padded[0].0.dim(), (2, 3, 4, 4));
+    assert_eq!(padded[0].1.iter().filter(|x| **x).count(), 16 + 4);
+    assert!(padded[0].0.slice(s![1, .., 2.., ..]).iter().all(|x| *x == 0.5));
+    assert_eq!(padded[1].0.dim(), (1, 3, 6, 8));
+    assert_eq!(padded[1].2, arr1(&[1]));
+
+    let mut other = ImageFolder::new(&root, [5, 3], 1).unwrap();
+    dataset.batch_size = 1;
+    dataset.shuffle_seeded(3);
+    other.shuffle_seeded(3);
+    for (a, b) in dataset.iter_batches().zip(other.iter_batches()) {
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.0, b.0);
+        assert_eq!(a.1, b.1);
+    }
+
+    dataset.shuffle();
+    let mut labels: Vec<u32> = dataset.iter_batches().flat_map(|x| x.unwrap().1.to_vec()).collect();
+    labels.sort();
+    assert_eq!(labels, [0, 0, 1]);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_image_folder_cache() {
+    let root = std::env::temp_dir().join("grownet_test_image_folder_cache");
+    let cache_dir = std::env::temp_dir().join("grownet_test_image_folder_cache_dir");
+    let _ = std::fs::remove_dir_all(&root);
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    for (class, shade) in [("a", 0), ("b", 100), ("b", 200)] {
+        std::fs::create_dir_all(root.join(class)).unwrap();
+        let im = image::RgbImage::from_pixel(4, 3, image::Rgb([shade, shade / 2, 255 - shade]));
+        im.save(root.join(class).join(format!("{}.png", shade))).unwrap();
+    }
+    let batches = |dataset: &ImageFolder| dataset.iter_batches().map(|x| x.unwrap()).collect::<Vec<_>>();
+
+    let mut dataset = ImageFolder::new(&root, [4, 3], 2).unwrap();
+    assert_eq!(dataset.cache_hit(), None);
+    let decoded = batches(&dataset);
+    dataset.use_cache(&cache_dir).unwrap();
+    assert_eq!(dataset.cache_hit(), Some(false));
+    assert_eq!(batches(&dataset), decoded);
+
+    // the second build maps the cache written by the first
+    let mut dataset = ImageFolder::new(&root, [4, 3], 2).unwrap();
+    dataset.use_cache(&cache_dir).unwrap();
+    assert_eq!(dataset.cache_hit(), Some(true));
+    assert_eq!(batches(&dataset), decoded);
+    dataset.shuffle_seeded(1);
+    let mut uncached = ImageFolder::new(&root, [4, 3], 2).unwrap();
+    uncached.shuffle_seeded(1);
+    assert_eq!(batches(&dataset), batches(&uncached));
+
+    // changing the params or the source folder invalidates the cache
+    let mut dataset = ImageFolder::new(&root, [2, 2], 2).unwrap();
+    dataset.use_cache(&cache_dir).unwrap();
+    assert_eq!(dataset.cache_hit(), Some(false));
+    assert_eq!(batches(&dataset)[0].0.dim(), (2, 3, 2, 2));
+    image::RgbImage::new(4, 3).save(root.join("a").join("new.png")).unwrap();
+    let mut dataset = ImageFolder::new(&root, [2, 2], 2).unwrap();
+    dataset.use_cache(&cache_dir).unwrap();
+    assert_eq!(dataset.cache_hit(), Some(false));
+    assert_eq!(dataset.iter_batches().map(|x| x.unwrap().1.len()).sum::<usize>(), 4);
+
+    std::fs::remove_dir_all(&root).unwrap();
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
@@ -4,7 +4,7 @@ use std::io::prelude::*;
 use std::path::Path;
 
 use itertools::Itertools;
-use rand::{thread_rng, seq::SliceRandom};
+use rand::{thread_rng, seq::SliceRandom, SeedableRng, rngs::StdRng};
 use anyhow::{Error, Result, Context};
 use ndarray::prelude::*;
 
@@ -113,6 +113,15 @@ impl Mnist {
         let mut rng = thread_rng();
         self.test_order.shuffle(&mut rng);
     }
+    /// shuffles the training set with a seeded rng, so that the order is reproducible
+    pub fn shuffle_train_seeded(&mut self, seed: u64) {
+        self.train_order.sort();
+        self.train_order.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+    pub fn shuffle_test_seeded(&mut self, seed: u64) {
+        self.test_order.sort();
+        self.test_order.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
 }
 
 use rand::Rng;
@@ -1,4 +1,52 @@
 mod utils;
+pub mod cache;
 pub mod mnist;
 pub mod cifar10;
-pub mod transforms;
\ No newline at end of file
+pub mod image_folder;
+pub mod prefetch;
+pub mod split;
+pub mod streaming;
+pub mod transforms;
+
+pub use prefetch::Prefetch;
+pub use split::{SplitDataset, Subset};
+pub use streaming::StreamingDataset;
+
+/// A source of data points that is iterated through in order, until reset
+pub trait Dataset {
+    type DataPoint;
+    fn next(&mut self) -> Option<Self::DataPoint>;
+    /// start iterating from the first data point again
+    fn reset(&mut self);
+    fn shuffle(&mut self);
+    /// shuffles with an rng seeded by seed, the same seed always produces the same order
+    fn shuffle_seeded(&mut self, seed: u64);
+}
+
+/// Parameters which build the train and test splits of a dataset
+pub trait DatasetBuilder {
+    type Dataset: Dataset;
+    fn build_train(&self) -> anyhow::Result<Self::Dataset>;
+    /// None if the dataset has no test split
+    fn build_test(&self) -> Option<anyhow::Result<Self::Dataset>>;
+}
+
+/// A dataset whose examples can be accessed in any order
+pub trait RandomAccess {
+    type DataPoint;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn example(&self, i: usize) -> Self::DataPoint;
+}
+
+impl<T: Clone> RandomAccess for Vec<T> {
+    type DataPoint = T;
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+    fn example(&self, i: usize) -> T {
+        self[i].clone()
+    }
+}
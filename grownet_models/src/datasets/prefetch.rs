@@ -0,0 +1,163 @@
+use std::thread::JoinHandle;
+
+use crossbeam::channel::{bounded, unbounded, select, Receiver, Sender};
+
+use super::Dataset;
+
+enum Command {
+    Reset,
+    Shuffle,
+    ShuffleSeeded(u64),
+}
+
+/// Loads data points of the wrapped dataset on a background thread, keeping up to capacity
+/// data points ready ahead of calls to next.
+/// Data points are tagged with a generation, which is incremented every time the order of the dataset is
+/// changed by reset or shuffle, so that stale data points still in the channel are skipped.
+pub struct Prefetch<D: Dataset> {
+    commands: Option<Sender<(usize, Command)>>,
+    data: Receiver<(usize, Option<D::DataPoint>)>,
+    generation: usize,
+    exhausted: bool,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<D> Prefetch<D>
+where D: Dataset + Send + 'static, D::DataPoint: Send + 'static {
+    pub fn new(dataset: D, capacity: usize) -> Self {
+        let (command_send, command_recv) = unbounded();
+        let (data_send, data_recv) = bounded(capacity);
+        let handle = std::thread::spawn(move || produce(dataset, command_recv, data_send));
+        Self { commands: Some(command_send), data: data_recv, generation: 0, exhausted: false, handle: Some(handle) }
+    }
+
+    fn send_command(&mut self, command: Command) {
+        self.generation += 1;
+        self.exhausted = false;
+        self.commands.as_ref().unwrap().send((self.generation, command)).expect("prefetch thread stopped");
+        // anything already loaded belongs to the previous generation
+        while self.data.try_recv().is_ok() {}
+    }
+}
+
+/// the producer stops once the command sender is dropped
+fn produce<D: Dataset>(mut dataset: D, commands: Receiver<(usize, Command)>, data: Sender<(usize, Option<D::DataPoint>)>) {
+    let mut generation = 0;
+    let mut exhausted = false;
+    let apply = |dataset: &mut D, (gen, command): (usize, Command)| {
+        match command {
+            Command::Reset => dataset.reset(),
+            Command::Shuffle => dataset.shuffle(),
+            Command::ShuffleSeeded(seed) => dataset.shuffle_seeded(seed),
+        }
+        gen
+    };
+    loop {
+        if exhausted {
+            // wait until the dataset is restarted
+            match commands.recv() {
+                Ok(command) => { generation = apply(&mut dataset, command); exhausted = false; }
+                Err(_) => return,
+            }
+            continue;
+        }
+        let point = dataset.next();
+        exhausted = point.is_none();
+        select! {
+            send(data, (generation, point)) -> res => if res.is_err() { return; },
+            recv(commands) -> command => match command {
+                Ok(command) => { generation = apply(&mut dataset, command); exhausted = false; }
+                Err(_) => return,
+            },
+        }
+    }
+}
+
+impl<D> Dataset for Prefetch<D>
+where D: Dataset + Send + 'static, D::DataPoint: Send + 'static {
+    type DataPoint = D::DataPoint;
+
+    fn next(&mut self) -> Option<Self::DataPoint> {
+        if self.exhausted {
+            return None;
+        }
+        loop {
+            let (generation, point) = self.data.recv().ok()?;
+            if generation == self.generation {
+                self.exhausted = point.is_none();
+                return point;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.send_command(Command::Reset);
+    }
+
+    fn shuffle(&mut self) {
+        self.send_command(Command::Shuffle);
+    }
+
+    fn shuffle_seeded(&mut self, seed: u64) {
+        self.send_command(Command::ShuffleSeeded(seed));
+    }
+}
+
+impl<D: Dataset> Drop for Prefetch<D> {
+    fn drop(&mut self) {
+        // dropping the sender stops the producer, even if it is blocked on a full channel
+        self.commands.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().expect("prefetch thread panicked");
+        }
+    }
+}
+
+#[test]
+fn test_prefetch() {
+    use std::time::{Duration, Instant};
+    struct Slow {
+        i: usize,
+        len: usize,
+    }
+    impl Dataset for Slow {
+        type DataPoint = usize;
+        fn next(&mut self) -> Option<usize> {
+            std::thread::sleep(Duration::from_millis(20));
+            if self.i < self.len {
+                self.i += 1;
+                Some(self.i - 1)
+            } else {
+                None
+            }
+        }
+        fn reset(&mut self) { self.i = 0; }
+        fn shuffle(&mut self) { self.i = 0; }
+        fn shuffle_seeded(&mut self, _seed: u64) { self.i = 0; }
+    }
+
+    let mut data = Prefetch::new(Slow { i: 0, len: 10 }, 4);
+    // loading happens while the consumer is busy, so the total time is about the larger of the two
+    let start = Instant::now();
+    let mut points = Vec::new();
+    while let Some(x) = data.next() {
+        std::thread::sleep(Duration::from_millis(20));
+        points.push(x);
+    }
+    assert!(start.elapsed() < Duration::from_millis(350), "took {:?}", start.elapsed());
+    assert_eq!(points, (0..10).collect::<Vec<_>>());
+    assert_eq!(data.next(), None);
+
+    data.reset();
+    assert_eq!(data.next(), Some(0));
+    assert_eq!(data.next(), Some(1));
+    // resetting partway through skips everything loaded beforehand
+    data.reset();
+    let points: Vec<_> = std::iter::from_fn(|| data.next()).collect();
+    assert_eq!(points, (0..10).collect::<Vec<_>>());
+
+    // dropping while the producer is blocked on a full channel does not hang
+    let mut data = Prefetch::new(Slow { i: 0, len: 100 }, 1);
+    data.next();
+    drop(data);
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use rand::{SeedableRng, rngs::StdRng, thread_rng, seq::SliceRandom};
+
+use super::{Dataset, RandomAccess};
+
+/// Deterministically partitions a dataset into a train and validation split, both splits
+/// share the underlying data, and only ever see their own examples
+pub struct SplitDataset<D> {
+    data: Arc<D>,
+    train: Vec<usize>,
+    val: Vec<usize>,
+}
+
+impl<D: RandomAccess> SplitDataset<D> {
+    /// val_fraction of the examples, rounded down, are held out for validation,
+    /// the same seed always produces the same split
+    pub fn new(data: D, val_fraction: f64, seed: u64) -> Self {
+        assert!((0.0..=1.0).contains(&val_fraction), "val_fraction must be in [0, 1]");
+        let mut indices: Vec<usize> = (0..data.len()).collect();
+        indices.shuffle(&mut StdRng::seed_from_u64(seed));
+        let n_val = (data.len() as f64 * val_fraction) as usize;
+        let train = indices.split_off(n_val);
+        Self { data: Arc::new(data), train, val: indices }
+    }
+
+    pub fn train(&self) -> Subset<D> {
+        Subset::new(self.data.clone(), self.train.clone())
+    }
+
+    pub fn val(&self) -> Subset<D> {
+        Subset::new(self.data.clone(), self.val.clone())
+    }
+}
+
+/// A view of the examples at indices of the underlying data
+pub struct Subset<D> {
+    data: Arc<D>,
+    indices: Vec<usize>,
+    pos: usize,
+}
+
+impl<D: RandomAccess> Subset<D> {
+    pub fn new(data: Arc<D>, indices: Vec<usize>) -> Self {
+        Self { data, indices, pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+impl<D: RandomAccess> Dataset for Subset<D> {
+    type DataPoint = D::DataPoint;
+
+    fn next(&mut self) -> Option<Self::DataPoint> {
+        let i = *self.indices.get(self.pos)?;
+        self.pos += 1;
+        Some(self.data.example(i))
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    /// only permutes the examples of this subset, and restarts from the beginning
+    fn shuffle(&mut self) {
+        self.indices.shuffle(&mut thread_rng());
+        self.pos = 0;
+    }
+
+    fn shuffle_seeded(&mut self, seed: u64) {
+        self.indices.sort();
+        self.indices.shuffle(&mut StdRng::seed_from_u64(seed));
+        self.pos = 0;
+    }
+}
+
+#[test]
+fn test_split_dataset() {
+    fn collect<D: Dataset<DataPoint = usize>>(data: &mut D) -> Vec<usize> {
+        let mut all = Vec::new();
+        while let Some(x) = data.next() {
+            all.push(x);
+        }
+        all
+    }
+    let data: Vec<usize> = (0..100).collect();
+    let split = SplitDataset::new(data.clone(), 0.2, 0);
+    let (mut train, mut val) = (split.train(), split.val());
+    assert_eq!((train.len(), val.len()), (80, 20));
+
+    let mut train_items = collect(&mut train);
+    let mut val_items = collect(&mut val);
+    assert!(train_items.iter().all(|x| !val_items.contains(x)));
+    let mut all = [train_items.clone(), val_items.clone()].concat();
+    all.sort();
+    assert_eq!(all, data);
+
+    // reproducible given the same seed
+    let split2 = SplitDataset::new(data.clone(), 0.2, 0);
+    assert_eq!(collect(&mut split2.val()), val_items);
+    let split3 = SplitDataset::new(data.clone(), 0.2, 1);
+    assert_ne!(collect(&mut split3.val()), val_items);
+
+    // shuffling stays within the split
+    train.shuffle();
+    val.shuffle();
+    let mut shuffled = collect(&mut train);
+    shuffled.sort();
+    train_items.sort();
+    assert_eq!(shuffled, train_items);
+    let mut shuffled = collect(&mut val);
+    shuffled.sort();
+    val_items.sort();
+    assert_eq!(shuffled, val_items);
+
+    val.reset();
+    assert_eq!(collect(&mut val).len(), 20);
+}
+
+#[test]
+fn test_shuffle_seeded() {
+    let data: Vec<usize> = (0..50).collect();
+    let (mut a, mut b) = (SplitDataset::new(data.clone(), 0.0, 0).train(), SplitDataset::new(data.clone(), 0.0, 0).train());
+    // an unseeded shuffle beforehand does not affect the seeded order
+    a.shuffle();
+    a.shuffle_seeded(7);
+    b.shuffle_seeded(7);
+    for _ in 0..10 {
+        assert_eq!(a.next(), b.next());
+    }
+    b.shuffle_seeded(8);
+    a.reset();
+    let first: Vec<_> = (0..10).map(|_| a.next().unwrap()).collect();
+    let other: Vec<_> = (0..10).map(|_| b.next().unwrap()).collect();
+    assert_ne!(first, other);
+}
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use ndarray::prelude::*;
+use rand::{SeedableRng, rngs::StdRng, thread_rng, seq::SliceRandom};
+
+use super::Dataset;
+use super::image_folder::{self, ImageFolder};
+use super::transforms::{self, ImClassifyDataPoint, Labels};
+
+/// A classification dataset which only holds the paths of its images, each batch is decoded
+/// when it is requested, so that at most one batch of images is in memory at a time
+pub struct StreamingDataset {
+    paths: Vec<(PathBuf, u32)>,
+    pub batch_size: usize,
+    /// every image is resized to [width, height], if None images keep their size and batches are padded with 0
+    pub image_size: Option<[u32; 2]>,
+    pos: usize,
+    /// the images which failed to decode since the last reset, and why
+    skipped: Vec<(PathBuf, String)>,
+    /// the most decoded images held at once, which is at most batch_size
+    max_resident: usize,
+}
+
+impl StreamingDataset {
+    pub fn new(paths: Vec<(PathBuf, u32)>, batch_size: usize, image_size: Option<[u32; 2]>) -> Self {
+        assert!(batch_size > 0, "batch_size must be positive");
+        Self { paths, batch_size, image_size, pos: 0, skipped: Vec::new(), max_resident: 0 }
+    }
+
+    /// streams the images of an image folder, with its image_size and batch_size
+    pub fn from_folder(folder: &ImageFolder) -> Self {
+        Self::new(folder.samples().to_vec(), folder.batch_size, Some(folder.image_size))
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// the paths and labels in the current order
+    pub fn paths(&self) -> &[(PathBuf, u32)] {
+        &self.paths
+    }
+
+    /// the images which failed to decode since the last reset or shuffle, with their errors
+    pub fn skipped(&self) -> &[(PathBuf, String)] {
+        &self.skipped
+    }
+}
+
+impl Dataset for StreamingDataset {
+    type DataPoint = ImClassifyDataPoint;
+
+    /// images which fail to decode are skipped, and recorded in skipped
+    fn next(&mut self) -> Option<Self::DataPoint> {
+        while self.pos < self.paths.len() {
+            let end = (self.pos + self.batch_size).min(self.paths.len());
+            let mut images = Vec::with_capacity(end - self.pos);
+            let mut labels = Vec::with_capacity(end - self.pos);
+            for (path, label) in &self.paths[self.pos..end] {
+                match image_folder::decode_image(path, self.image_size) {
+                    Ok(im) => {
+                        images.push(im);
+                        labels.push(*label);
+                        self.max_resident = self.max_resident.max(images.len());
+                    }
+                    Err(e) => self.skipped.push((path.clone(), format!("{:#}", e))),
+                }
+            }
+            self.pos = end;
+            if !images.is_empty() {
+                let (image, _) = transforms::collate_pad(&images, 0.0);
+                return Some(ImClassifyDataPoint { image, label: Labels::Hard(Array1::from_vec(labels)) });
+            }
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.skipped.clear();
+    }
+
+    fn shuffle(&mut self) {
+        self.paths.shuffle(&mut thread_rng());
+        self.reset();
+    }
+
+    fn shuffle_seeded(&mut self, seed: u64) {
+        self.paths.sort();
+        self.paths.shuffle(&mut StdRng::seed_from_u64(seed));
+        self.reset();
+    }
+}
+
+#[test]
+fn test_streaming_dataset() {
+    let root = std::env::temp_dir().join("grownet_test_streaming_dataset");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let paths: Vec<_> = (0..5u32).map(|i| {
+        let path = root.join(format!("{}.png", i));
+        image::RgbImage::from_pixel(3 + i, 2, image::Rgb([i as u8 * 50; 3])).save(&path).unwrap();
+        (path, i)
+    }).collect();
+    let labels = |x: &ImClassifyDataPoint| match &x.label {
+        Labels::Hard(labels) => labels.to_vec(),
+        _ => panic!("expected hard labels"),
+    };
+
+    let mut dataset = StreamingDataset::new(paths.clone(), 2, Some([4, 2]));
+    let batches: Vec<_> = std::iter::from_fn(|| dataset.next()).collect();
+    assert_eq!(batches.iter().map(labels).collect::<Vec<_>>(), [vec![0, 1], vec![2, 3], vec![4]]);
+    // no batch holds more than batch_size images
+    for (batch, n) in batches.iter().zip([2, 2, 1]) {
+        assert_eq!(batch.image.dim(), (n, 3, 2, 4));
+    }
+    assert!(batches[1].image.slice(s![1, .., .., ..]).iter().all(|x| (*x - 150.0 / 255.0).abs() < 1e-6));
+
+    dataset.reset();
+    assert_eq!(labels(&dataset.next().unwrap()), [0, 1]);
+    // images are only decoded when their batch is reached, so removing a later image
+    // after construction only drops that image
+    std::fs::remove_file(&paths[3].0).unwrap();
+    assert_eq!(labels(&dataset.next().unwrap()), [2]);
+    assert_eq!(labels(&dataset.next().unwrap()), [4]);
+    assert!(dataset.next().is_none());
+    assert_eq!(dataset.skipped().len(), 1);
+    assert_eq!(dataset.skipped()[0].0, paths[3].0);
+    dataset.reset();
+    assert!(dataset.skipped().is_empty());
+
+    // without resizing, each batch is padded to its largest image
+    let mut dataset = StreamingDataset::new(paths[..3].to_vec(), 2, None);
+    assert_eq!(dataset.next().unwrap().image.dim(), (2, 3, 2, 4));
+    assert_eq!(dataset.next().unwrap().image.dim(), (1, 3, 2, 5));
+
+    let mut other = StreamingDataset::new(paths[..3].to_vec(), 1, None);
+    dataset.batch_size = 1;
+    dataset.shuffle_seeded(7);
+    other.shuffle_seeded(7);
+    let order: Vec<_> = std::iter::from_fn(|| dataset.next()).flat_map(|x| labels(&x)).collect();
+    assert_eq!(order, std::iter::from_fn(|| other.next()).flat_map(|x| labels(&x)).collect::<Vec<_>>());
+    let mut sorted = order.clone();
+    sorted.sort();
+    assert_eq!(sorted, [0, 1, 2]);
+
+    // a pass over many images never holds more than one batch of decoded images
+    let many: Vec<_> = (0..20).map(|i| paths[i % 3].clone()).collect();
+    let mut dataset = StreamingDataset::new(many, 3, Some([4, 2]));
+    assert_eq!(std::iter::from_fn(|| dataset.next()).map(|x| x.image.dim().0).sum::<usize>(), 20);
+    assert_eq!(dataset.max_resident, 3);
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -3,6 +3,8 @@ use core::panic;
 use ndarray::{prelude::*, DimAdd, IntoDimension, RawData};
 use arrayfire as af;
 use image::{self, ImageBuffer};
+use serde::{Deserialize, Serialize};
+use rand::Rng;
 
 pub fn to_afarray(im: &Array4<f32>) -> af::Array<f32> {
     if im.is_standard_layout() {
@@ -43,6 +45,45 @@ pub fn batch_im(imgs: &[Array3<f32>]) -> Array4<f32> {
 }
 
 
+/// stacks images of shape [c, h, w] with possibly different sizes into a batch of shape [b, c, max h, max w],
+/// each image is placed at the top left and the remainder is filled with fill. The mask has shape [b, max h, max w]
+/// and is true at the pixels covered by each image
+pub fn collate_pad(imgs: &[Array3<f32>], fill: f32) -> (Array4<f32>, Array3<bool>) {
+    let c = imgs[0].dim().0;
+    let h = imgs.iter().map(|x| x.dim().1).max().unwrap();
+    let w = imgs.iter().map(|x| x.dim().2).max().unwrap();
+    let mut batch = Array4::from_elem((imgs.len(), c, h, w), fill);
+    let mut mask = Array3::from_elem((imgs.len(), h, w), false);
+    for (i, im) in imgs.iter().enumerate() {
+        let (ci, hi, wi) = im.dim();
+        assert!(ci == c, "batch requires that all inputs have the same number of channels");
+        batch.slice_mut(s![i, .., ..hi, ..wi]).assign(im);
+        mask.slice_mut(s![i, ..hi, ..wi]).fill(true);
+    }
+    (batch, mask)
+}
+
+#[test]
+fn test_collate_pad() {
+    let a = Array3::from_shape_fn((2, 3, 2), |(c, i, j)| (c * 6 + i * 2 + j) as f32);
+    let b = Array3::from_elem((2, 1, 4), 7.0);
+    let (batch, mask) = collate_pad(&[a.clone(), b.clone()], -1.0);
+    assert_eq!(batch.dim(), (2, 2, 3, 4));
+    assert_eq!(batch.slice(s![0, .., ..3, ..2]), a);
+    assert_eq!(batch.slice(s![1, .., ..1, ..]), b);
+    assert!(batch.slice(s![0, .., .., 2..]).iter().all(|x| *x == -1.0));
+    assert!(batch.slice(s![1, .., 1.., ..]).iter().all(|x| *x == -1.0));
+
+    assert_eq!(mask.dim(), (2, 3, 4));
+    assert_eq!(mask.slice(s![0, .., ..]), arr2(&[[true, true, false, false]; 3]));
+    assert_eq!(mask.slice(s![1, .., ..]), arr2(&[[true; 4], [false; 4], [false; 4]]));
+
+    // images of equal size are batched as is
+    let (batch, mask) = collate_pad(&[a.clone(), a.clone()], 0.0);
+    assert_eq!(batch, batch_im(&[a.clone(), a]));
+    assert!(mask.iter().all(|x| *x));
+}
+
 /// convert an array of shape [3, h, w] or [h, w, 3] to an RgbImage, panics if any other shape is given 
 pub fn to_image(im: Array3<u8>) -> image::RgbImage {
     if im.dim().0 == 3 {
@@ -79,6 +120,407 @@ pub fn from_image(im: image::RgbImage, channel_last: bool) -> Array3<u8> {
     }
 }
 
+/// A transformation applied to batches of data, such as augmentations
+pub trait Transform<In, Out> {
+    fn transform(&self, x: In) -> Out;
+}
+
+/// Resizes batches of images with shape [n, c, h, w] to [n, c, size[1], size[0]] using bilinear
+/// interpolation, sampling positions past the border are clamped to the edge pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Resize {
+    /// the target [w, h]
+    pub size: [usize; 2],
+}
+
+impl Resize {
+    pub fn new(w: usize, h: usize) -> Self {
+        Self { size: [w, h] }
+    }
+
+    /// the source positions and weights of the upper neighbour for each output position along an axis,
+    /// pixel centers are aligned, ie. the output pixel i is at (i + 0.5) * in / out - 0.5 in the input
+    fn sample_positions(input: usize, output: usize) -> Vec<(usize, usize, f32)> {
+        let scale = input as f32 / output as f32;
+        (0..output).map(|i| {
+            let x = ((i as f32 + 0.5) * scale - 0.5).clamp(0.0, (input - 1) as f32);
+            let lo = x.floor() as usize;
+            let hi = (lo + 1).min(input - 1);
+            (lo, hi, x - lo as f32)
+        }).collect()
+    }
+}
+
+impl Transform<&Array4<f32>, Array4<f32>> for Resize {
+    fn transform(&self, x: &Array4<f32>) -> Array4<f32> {
+        let (n, c, h, w) = x.dim();
+        let [out_w, out_h] = self.size;
+        let ys = Self::sample_positions(h, out_h);
+        let xs = Self::sample_positions(w, out_w);
+        Array4::from_shape_fn((n, c, out_h, out_w), |(b, ch, i, j)| {
+            let (y0, y1, fy) = ys[i];
+            let (x0, x1, fx) = xs[j];
+            let top = x[[b, ch, y0, x0]] * (1.0 - fx) + x[[b, ch, y0, x1]] * fx;
+            let bottom = x[[b, ch, y1, x0]] * (1.0 - fx) + x[[b, ch, y1, x1]] * fx;
+            top * (1.0 - fy) + bottom * fy
+        })
+    }
+}
+
+/// resizes the images of the batch, the labels are passed through unchanged
+impl Transform<ImClassifyDataPoint, ImClassifyDataPoint> for Resize {
+    fn transform(&self, x: ImClassifyDataPoint) -> ImClassifyDataPoint {
+        ImClassifyDataPoint { image: self.transform(&x.image), label: x.label }
+    }
+}
+
+/// Normalizes batches of images with shape [n, c, h, w] as (x - mu) / range, or if per channel
+/// statistics are set, each channel as (x - mean[c]) / std[c]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Normalize {
+    pub mu: f32,
+    pub range: f32,
+    /// per channel means and standard deviations for rgb images, takes precedence over mu and range
+    pub channel_stats: Option<([f32; 3], [f32; 3])>,
+}
+
+impl Normalize {
+    pub fn new(mu: f32, range: f32) -> Self {
+        Self { mu, range, channel_stats: None }
+    }
+
+    pub fn per_channel(mean: [f32; 3], std: [f32; 3]) -> Self {
+        Self { mu: 0.0, range: 1.0, channel_stats: Some((mean, std)) }
+    }
+}
+
+impl Transform<&Array4<f32>, Array4<f32>> for Normalize {
+    fn transform(&self, x: &Array4<f32>) -> Array4<f32> {
+        match &self.channel_stats {
+            Some((mean, std)) => {
+                assert!(x.dim().1 == 3, "per channel normalization expects 3 channels, got {}", x.dim().1);
+                let mut y = x.to_owned();
+                for (c, mut channel) in y.axis_iter_mut(Axis(1)).enumerate() {
+                    channel.mapv_inplace(|v| (v - mean[c]) / std[c]);
+                }
+                y
+            }
+            None => x.mapv(|v| (v - self.mu) / self.range),
+        }
+    }
+}
+
+#[test]
+fn test_normalize() {
+    let stats = |x: ArrayView3<f32>| {
+        let mean = x.mean().unwrap();
+        let std = x.mapv(|v| (v - mean).powi(2)).mean().unwrap().sqrt();
+        (mean, std)
+    };
+    // each channel has a different mean and spread
+    let x = Array4::from_shape_fn((2, 3, 4, 4), |(b, c, i, j)| {
+        (c as f32 + 1.0) * 10.0 + (c as f32 + 1.0) * ((b * 16 + i * 4 + j) as f32 - 15.5)
+    });
+    let mut mean = [0.0; 3];
+    let mut std = [0.0; 3];
+    for c in 0..3 {
+        (mean[c], std[c]) = stats(x.index_axis(Axis(1), c));
+    }
+    let y = Normalize::per_channel(mean, std).transform(&x);
+    for c in 0..3 {
+        let (m, s) = stats(y.index_axis(Axis(1), c));
+        assert!(m.abs() < 1e-4 && (s - 1.0).abs() < 1e-4, "channel {}: mean {}, std {}", c, m, s);
+    }
+
+    let y = Normalize::new(10.0, 2.0).transform(&x);
+    assert_eq!(y, x.mapv(|v| (v - 10.0) / 2.0));
+}
+
+/// Random augmentations applied independently to each image of a batch with shape [n, c, h, w]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct BasicImAugumentation {
+    /// each image is rotated by an angle sampled uniformly from [-rotate_deg, rotate_deg], 0 disables rotation
+    pub rotate_deg: f32,
+}
+
+impl Transform<&Array4<f32>, Array4<f32>> for BasicImAugumentation {
+    fn transform(&self, x: &Array4<f32>) -> Array4<f32> {
+        let mut y = x.to_owned();
+        if self.rotate_deg != 0.0 {
+            let mut rng = rand::thread_rng();
+            for mut im in y.axis_iter_mut(Axis(0)) {
+                let deg = rng.gen_range(-self.rotate_deg..=self.rotate_deg);
+                let rotated = rotate(im.view(), deg);
+                im.assign(&rotated);
+            }
+        }
+        y
+    }
+}
+
+/// rotates an image of shape [c, h, w] counter-clockwise by deg about its center, using bilinear sampling,
+/// pixels sampled from outside the image are 0
+pub fn rotate(im: ArrayView3<f32>, deg: f32) -> Array3<f32> {
+    let (_, h, w) = im.dim();
+    let (sin, cos) = deg.to_radians().sin_cos();
+    let (cy, cx) = ((h as f32 - 1.0) / 2.0, (w as f32 - 1.0) / 2.0);
+    let pixel = |c: usize, y: isize, x: isize| {
+        if y < 0 || x < 0 || y >= h as isize || x >= w as isize { 0.0 } else { im[[c, y as usize, x as usize]] }
+    };
+    Array3::from_shape_fn(im.dim(), |(c, i, j)| {
+        // inverse rotation of the output position gives the source position
+        let (dy, dx) = (i as f32 - cy, j as f32 - cx);
+        let sx = cos * dx - sin * dy + cx;
+        let sy = sin * dx + cos * dy + cy;
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = pixel(c, y0, x0) * (1.0 - fx) + pixel(c, y0, x0 + 1) * fx;
+        let bottom = pixel(c, y0 + 1, x0) * (1.0 - fx) + pixel(c, y0 + 1, x0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
+}
+
+#[test]
+fn test_rotate() {
+    let im = Array3::from_shape_fn((2, 3, 4), |(c, i, j)| (c * 12 + i * 4 + j) as f32);
+    let close = |a: &Array3<f32>, b: &Array3<f32>| a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-4);
+    assert!(close(&rotate(im.view(), 0.0), &im));
+    // rotating by 180 degrees reverses the rows and columns
+    let mut reversed = im.clone();
+    reversed.invert_axis(Axis(1));
+    reversed.invert_axis(Axis(2));
+    assert!(close(&rotate(im.view(), 180.0), &reversed));
+    assert!(close(&rotate(im.view(), -180.0), &reversed));
+
+    let batch = im.clone().insert_axis(Axis(0));
+    assert_eq!(BasicImAugumentation::default().transform(&batch), batch);
+    let rotated = BasicImAugumentation { rotate_deg: 30.0 }.transform(&batch);
+    assert_eq!(rotated.dim(), batch.dim());
+}
+
+/// Converts batches of rgb images with shape [n, 3, h, w] to grayscale images with shape [n, 1, h, w]
+/// using the luminance weights, images that already have a single channel are unchanged
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+pub struct Grayscale;
+
+impl Grayscale {
+    pub const WEIGHTS: [f32; 3] = [0.299, 0.587, 0.114];
+}
+
+impl Transform<&Array4<f32>, Array4<f32>> for Grayscale {
+    fn transform(&self, x: &Array4<f32>) -> Array4<f32> {
+        match x.dim().1 {
+            1 => x.to_owned(),
+            3 => {
+                let mut y = x.index_axis(Axis(1), 0).mapv(|v| v * Self::WEIGHTS[0]);
+                y.scaled_add(Self::WEIGHTS[1], &x.index_axis(Axis(1), 1));
+                y.scaled_add(Self::WEIGHTS[2], &x.index_axis(Axis(1), 2));
+                y.insert_axis(Axis(1))
+            }
+            c => panic!("grayscale expects 1 or 3 channels, got {}", c),
+        }
+    }
+}
+
+#[test]
+fn test_grayscale() {
+    let mut x = Array4::zeros((2, 3, 2, 2));
+    x.slice_mut(s![0, .., 0, 0]).assign(&arr1(&[1.0, 0.5, 0.25]));
+    let y = Grayscale.transform(&x);
+    assert_eq!(y.dim(), (2, 1, 2, 2));
+    assert!((y[[0, 0, 0, 0]] - (0.299 + 0.587 * 0.5 + 0.114 * 0.25)).abs() < 1e-6);
+    assert_eq!(y[[1, 0, 1, 1]], 0.0);
+    assert_eq!(Grayscale.transform(&y), y);
+}
+
+/// The labels of a batch, soft labels are per class weights with shape [n, classes]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Labels {
+    Hard(Array1<u32>),
+    Soft(Array2<f32>),
+}
+
+impl Labels {
+    /// the labels as per class weights, hard labels are one hot
+    pub fn to_soft(&self, classes: usize) -> Array2<f32> {
+        match self {
+            Labels::Hard(labels) => {
+                let mut soft = Array2::zeros((labels.len(), classes));
+                for (i, label) in labels.iter().enumerate() {
+                    soft[[i, *label as usize]] = 1.0;
+                }
+                soft
+            }
+            Labels::Soft(soft) => soft.clone(),
+        }
+    }
+}
+
+/// A batch of images with shape [n, c, h, w] and their labels
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImClassifyDataPoint {
+    pub image: Array4<f32>,
+    pub label: Labels,
+}
+
+/// samples the mixing ratio from Beta(alpha, alpha), and the image each image is mixed with
+fn sample_mix(alpha: f32, n: usize) -> (f32, Vec<usize>) {
+    use rand::seq::SliceRandom;
+    use rand_distr::{Beta, Distribution};
+    let mut rng = rand::thread_rng();
+    let lambda = Beta::new(alpha, alpha).expect("alpha must be positive").sample(&mut rng);
+    let mut perm: Vec<usize> = (0..n).collect();
+    perm.shuffle(&mut rng);
+    (lambda, perm)
+}
+
+/// label weights lambda * y_i + (1 - lambda) * y_perm[i]
+fn mix_labels(label: &Labels, classes: usize, perm: &[usize], lambda: f32) -> Labels {
+    let soft = label.to_soft(classes);
+    let mixed = Array2::from_shape_fn(soft.dim(), |(i, k)| lambda * soft[[i, k]] + (1.0 - lambda) * soft[[perm[i], k]]);
+    Labels::Soft(mixed)
+}
+
+/// Blends each image with another image of the batch, with a ratio sampled from Beta(alpha, alpha),
+/// the labels are blended with the same ratio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MixUp {
+    pub alpha: f32,
+    pub classes: usize,
+}
+
+impl MixUp {
+    /// blends image i with image perm[i], as lambda * x_i + (1 - lambda) * x_perm[i]
+    pub fn mix(&self, x: &ImClassifyDataPoint, perm: &[usize], lambda: f32) -> ImClassifyDataPoint {
+        let image = Array4::from_shape_fn(x.image.dim(), |(b, c, i, j)| {
+            lambda * x.image[[b, c, i, j]] + (1.0 - lambda) * x.image[[perm[b], c, i, j]]
+        });
+        ImClassifyDataPoint { image, label: mix_labels(&x.label, self.classes, perm, lambda) }
+    }
+}
+
+impl Transform<&ImClassifyDataPoint, ImClassifyDataPoint> for MixUp {
+    fn transform(&self, x: &ImClassifyDataPoint) -> ImClassifyDataPoint {
+        let (lambda, perm) = sample_mix(self.alpha, x.image.dim().0);
+        self.mix(x, &perm, lambda)
+    }
+}
+
+/// Pastes a random rectangle from another image of the batch into each image, the rectangle covers
+/// 1 - lambda of the area, with lambda sampled from Beta(alpha, alpha). The labels are weighted by area
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CutMix {
+    pub alpha: f32,
+    pub classes: usize,
+}
+
+impl CutMix {
+    /// pastes the rectangle [y0, y1) x [x0, x1) of image perm[i] into image i
+    pub fn cut(&self, x: &ImClassifyDataPoint, perm: &[usize], rect: [usize; 4]) -> ImClassifyDataPoint {
+        let [y0, y1, x0, x1] = rect;
+        let (_, _, h, w) = x.image.dim();
+        let mut image = x.image.clone();
+        for (b, p) in perm.iter().enumerate() {
+            image.slice_mut(s![b, .., y0..y1, x0..x1]).assign(&x.image.slice(s![*p, .., y0..y1, x0..x1]));
+        }
+        // the fraction of each image which is kept
+        let lambda = 1.0 - ((y1 - y0) * (x1 - x0)) as f32 / (h * w) as f32;
+        ImClassifyDataPoint { image, label: mix_labels(&x.label, self.classes, perm, lambda) }
+    }
+
+    /// a rectangle with area about (1 - lambda) * h * w, centered uniformly in the image and clipped to its borders
+    fn sample_rect(h: usize, w: usize, lambda: f32) -> [usize; 4] {
+        let mut rng = rand::thread_rng();
+        let ratio = (1.0 - lambda).sqrt();
+        let (cut_h, cut_w) = ((h as f32 * ratio) as usize, (w as f32 * ratio) as usize);
+        let (cy, cx) = (rng.gen_range(0..h), rng.gen_range(0..w));
+        let y0 = cy.saturating_sub(cut_h / 2);
+        let x0 = cx.saturating_sub(cut_w / 2);
+        [y0, (cy + cut_h / 2).min(h), x0, (cx + cut_w / 2).min(w)]
+    }
+}
+
+impl Transform<&ImClassifyDataPoint, ImClassifyDataPoint> for CutMix {
+    fn transform(&self, x: &ImClassifyDataPoint) -> ImClassifyDataPoint {
+        let (n, _, h, w) = x.image.dim();
+        let (lambda, perm) = sample_mix(self.alpha, n);
+        self.cut(x, &perm, Self::sample_rect(h, w, lambda))
+    }
+}
+
+#[test]
+fn test_mixup() {
+    let image = Array4::from_shape_fn((2, 1, 2, 2), |(b, ..)| b as f32);
+    let x = ImClassifyDataPoint { image, label: Labels::Hard(arr1(&[0, 2])) };
+    let mixup = MixUp { alpha: 0.2, classes: 3 };
+    let y = mixup.mix(&x, &[1, 0], 0.3);
+    assert!(y.image.slice(s![0, .., .., ..]).iter().all(|v| (v - 0.7).abs() < 1e-6));
+    assert!(y.image.slice(s![1, .., .., ..]).iter().all(|v| (v - 0.3).abs() < 1e-6));
+    let expected = arr2(&[[0.3, 0.0, 0.7], [0.7, 0.0, 0.3]]);
+    match &y.label {
+        Labels::Soft(soft) => assert!(soft.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-6)),
+        _ => panic!("expected soft labels"),
+    }
+
+    let y = mixup.transform(&x);
+    assert_eq!(y.image.dim(), x.image.dim());
+    assert!(y.label.to_soft(3).sum_axis(Axis(1)).iter().all(|v| (v - 1.0).abs() < 1e-5));
+}
+
+#[test]
+fn test_cutmix() {
+    let image = Array4::from_shape_fn((2, 1, 4, 4), |(b, ..)| b as f32);
+    let x = ImClassifyDataPoint { image, label: Labels::Hard(arr1(&[0, 1])) };
+    let cutmix = CutMix { alpha: 1.0, classes: 2 };
+    // a 2x3 patch covers 6 / 16 of the image
+    let y = cutmix.cut(&x, &[1, 0], [1, 3, 0, 3]);
+    let patched = y.image.slice(s![0, 0, .., ..]).iter().filter(|v| **v == 1.0).count();
+    assert_eq!(patched, 6);
+    let soft = y.label.to_soft(2);
+    assert!((soft[[0, 0]] - 10.0 / 16.0).abs() < 1e-6 && (soft[[0, 1]] - 6.0 / 16.0).abs() < 1e-6);
+    assert!((soft[[1, 1]] - 10.0 / 16.0).abs() < 1e-6);
+
+    // the label weights always match the pasted area
+    for _ in 0..10 {
+        let y = cutmix.transform(&x);
+        let soft = y.label.to_soft(2);
+        for b in 0..2 {
+            let kept = y.image.slice(s![b, 0, .., ..]).iter().filter(|v| **v == b as f32).count() as f32 / 16.0;
+            // images mixed with themselves keep a weight of 1
+            if soft[[b, b]] < 1.0 {
+                assert!((soft[[b, b]] - kept).abs() < 1e-6);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_resize() {
+    let im = ImClassifyDataPoint {
+        image: Array4::from_shape_vec((1, 1, 2, 2), vec![0.0, 1.0, 2.0, 3.0]).unwrap(),
+        label: Labels::Hard(arr1(&[5])),
+    };
+    let ImClassifyDataPoint { image: resized, label } = Resize::new(4, 4).transform(im);
+    assert_eq!(label, Labels::Hard(arr1(&[5])));
+    assert_eq!(resized.dim(), (1, 1, 4, 4));
+    let expected = [
+        [0.0, 0.25, 0.75, 1.0],
+        [0.5, 0.75, 1.25, 1.5],
+        [1.5, 1.75, 2.25, 2.5],
+        [2.0, 2.25, 2.75, 3.0],
+    ];
+    for i in 0..4 {
+        for j in 0..4 {
+            assert!((resized[[0, 0, i, j]] - expected[i][j]).abs() < 1e-6, "({}, {}): {}", i, j, resized[[0, 0, i, j]]);
+        }
+    }
+    // resizing to the same size is the identity, and the batch and channel axes are preserved
+    let im = Array4::from_shape_fn((2, 3, 5, 4), |(b, c, i, j)| (b * 60 + c * 20 + i * 4 + j) as f32);
+    assert_eq!(Resize::new(4, 5).transform(&im), im);
+    assert_eq!(Resize::new(7, 3).transform(&im).dim(), (2, 3, 3, 7));
+}
+
 #[test]
 fn test_af_conversion() {
     let a = Array4::zeros((4, 3, 16, 16));
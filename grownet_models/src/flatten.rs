@@ -69,6 +69,20 @@ impl<'a> World<'a> {
             .map(|x| (x.1, x.0.unwrap()))
     }
 
+    /// immutable version of query_mut_with_path
+    pub fn query_with_path<'b, T: 'static>(&'b self) -> impl Iterator<Item = (&'b str, &'b T)> + 'b {
+        self.objects.iter().zip(self.field_path.iter())
+            .filter_map(|(x, path)| x.downcast_ref::<T>().map(|x| (path.as_str(), x)))
+    }
+
+    /// same as query_mut, but only selects objects whose flattened path begins with prefix,
+    /// ex. "/pre/conv" selects the parameters of the conv layer in the pre field
+    pub fn query_path_mut<'b, T: 'static>(&'b mut self, prefix: &'b str) -> impl Iterator<Item = &'a mut T> + 'b {
+        self.query_mut_with_path::<T>()
+            .filter(move |(path, _)| path.starts_with(prefix))
+            .map(|(_, x)| x)
+    }
+
     pub fn push<T: 'static>(&mut self, push: String, a: &'a mut T) {
         let a: &mut dyn Any = a;
         let filter = self.filter;
@@ -203,4 +217,53 @@ fn test_struct1() {
         println!("{}", i);
     }
     
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_struct_last_skip() {
+    // does not implement Flatten, so it can only be pushed as is
+    #[derive(Default)]
+    struct NotFlat(u8);
+
+    #[derive(Flatten, Default)]
+    struct Test {
+        a: f32,
+        #[flat(exclude)]
+        b: f32,
+        #[flat(skip)]
+        c: NotFlat,
+    }
+
+    let mut test = Test::default();
+    test.c.0 = 3;
+    let mut world = World::from(&mut test);
+    let skipped: Vec<_> = world.query_mut_with_path::<NotFlat>().map(|(p, x)| (p.to_string(), x.0)).collect();
+    assert_eq!(skipped, vec![("/c".to_string(), 3)]);
+    let floats: Vec<_> = world.query_mut_with_path::<f32>().map(|(p, _)| p.to_string()).collect();
+    assert_eq!(floats, vec!["/a".to_string()]);
+}
+
+#[test]
+fn test_enum() {
+    #[derive(Flatten)]
+    enum Test {
+        A,
+        B { a: f32, #[flat(exclude)] b: f32 },
+        C(f32, Vec<u32>),
+    }
+
+    let mut test = Test::A;
+    let mut world = World::from(&mut test);
+    assert_eq!(world.query_mut::<f32>().count(), 0);
+
+    let mut test = Test::B { a: 1.0, b: 2.0 };
+    let mut world = World::from(&mut test);
+    let paths: Vec<_> = world.query_mut_with_path::<f32>().map(|(p, x)| (p.to_string(), *x)).collect();
+    assert_eq!(paths, vec![("/B/a".to_string(), 1.0)]);
+
+    let mut test = Test::C(3.0, vec![1]);
+    let mut world = World::from(&mut test);
+    let paths: Vec<_> = world.query_mut_with_path::<f32>().map(|(p, x)| (p.to_string(), *x)).collect();
+    assert_eq!(paths, vec![("/C/0".to_string(), 3.0)]);
+    assert_eq!(world.query_mut_with_path::<Vec<u32>>().map(|(p, _)| p.to_string()).collect::<Vec<_>>(), vec!["/C/1"]);
+}
@@ -21,7 +21,7 @@ pub mod configs;
 pub mod datasets;
 pub mod models;
 pub mod ops;
-pub use configs::{Config, Options};
+pub use configs::{Config, DisplayHint, Options};
 
 pub mod nn;
 
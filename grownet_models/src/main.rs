@@ -6,6 +6,7 @@ use arrayfire::*;
 use model_lib::Options;
 use model_lib::models::baselinev2::SimpleResnet;
 use model_lib::nn::af_ops::conv::Conv2d;
+use model_lib::nn::af_ops::initializer::Init;
 use model_lib::models::baselinev2::{run_on_main, baseline_config};
 
 fn main() {
@@ -23,7 +24,7 @@ fn main() {
         // run_on_main(&config);
         // let model = SimpleResnet::<f32>::new(10);
         let a = randn!(28, 28, 3, 1);
-        let conv = Conv2d::<f32>::new(3, 3, [3, 3], [1, 1], [1, 1], false);
+        let conv = Conv2d::<f32>::new(3, 3, [3, 3], [1, 1], [1, 1], [1, 1], 1, false, Init::default());
         let y = conv.forward2(&a);
         // let (_y, _df) = model.forward(&a);
         y.eval();
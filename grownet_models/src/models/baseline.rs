@@ -215,7 +215,7 @@ pub fn build(config: &Config) -> Result<TrainProcess> {
                             x: steps as f64, 
                             y: acc 
                         })).unwrap();
-                    sender.send(TrainRecv::STATS(RunStats { step_time: Some(avg_time / steps_per_log as f32) })).unwrap();
+                    sender.send(TrainRecv::STATS(RunStats { step_time: Some(avg_time / steps_per_log as f32), device_mem_bytes: None })).unwrap();
                     avg_time = 0.0;
                 }
 
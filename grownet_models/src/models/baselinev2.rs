@@ -38,7 +38,7 @@ impl<F: Float> SimpleResnet<F> {
         Self {
             pre: ConvBlock::new(3, 64),
             max_pool: af_ops::maxpool::MaxPool2D::new([2, 2], [2, 2]), 
-            linear: af_ops::linear::Linear::new(64, classes, true)
+            linear: af_ops::linear::Linear::new(64, classes, true, af_ops::initializer::Init::default())
         }
     }
 
@@ -55,6 +55,30 @@ impl<F: Float> SimpleResnet<F> {
         };
         (x4, df)
     }
+
+    /// Summarizes each layer with its parameter count and output shape, for an input of shape [w, h, c, n],
+    /// missing trailing dimensions are taken to be 1. Only shapes are propagated, nothing is computed.
+    pub fn summarize(&mut self, input_shape: &[usize]) -> Vec<LayerSummary> {
+        let mut dims = [1u64; 4];
+        for (d, s) in dims.iter_mut().zip(input_shape) {
+            *d = *s as u64;
+        }
+        let x0 = Dim4::new(&dims);
+        let x1 = self.pre.output_shape(x0);
+        let x2 = self.max_pool.output_shape(x1);
+        let x3 = dim4!(x2[2], x2[3]);
+        let x4 = self.linear.output_shape(x3);
+
+        let mut world = World::from(self);
+        let layers = [("pre", x1), ("max_pool", x2), ("flatten", x3), ("linear", x4)];
+        layers.into_iter().map(|(name, shape)| {
+            LayerSummary {
+                name: name.to_string(),
+                output_shape: shape.get().iter().map(|x| *x as usize).collect(),
+                params: num_params::<F>(&mut world, &format!("/{name}/")),
+            }
+        }).collect()
+    }
 }
 
 
@@ -78,7 +102,7 @@ impl<T: Float> FastResnet<T> {
             max_pool: af_ops::maxpool::MaxPool2D::new([2, 2], [2, 2]), 
             layer2: ConvLayer::new(256, 512), 
             max_pool2: af_ops::maxpool::MaxPool2D::new([3, 3], [2, 2]), 
-            linear: af_ops::linear::Linear::new(512, classes, true)
+            linear: af_ops::linear::Linear::new(512, classes, true, af_ops::initializer::Init::default())
         }
     }
 
@@ -118,7 +142,12 @@ pub fn baseline_config() -> Config {
     config!(
         ("lr", 0.008),
         ("batch_size", 8),
-        ("epochs", 10)
+        ("epochs", 10),
+        ("momentum", 0.0),
+        ("nesterov", false),
+        ("checkpoint_steps", 0),
+        ("max_checkpoints", 3),
+        ("keep_best_checkpoint", true)
     )
 }
 
@@ -126,7 +155,7 @@ use ndarray as nd;
 use image;
 use itertools::Itertools;
 
-use super::TrainProcess;
+use super::{TrainProcess, LayerSummary};
 fn transform_data<'a>(imgs: impl Iterator<Item = nd::ArrayView2<'a, u8>> + 'a, batch_size: usize) -> impl Iterator<Item = nd::Array4<f32>> + 'a {
     let pre_iter = imgs
         .map(|bk_img| {
@@ -193,6 +222,11 @@ pub fn run(config: &Config) -> Result<TrainProcess> {
     let lr: f64 = config.uget("lr").into();
     let batch_size: isize = config.uget("batch_size").into();
     let epochs: isize = config.uget("epochs").into();
+    let momentum: f64 = config.uget("momentum").into();
+    let nesterov: bool = config.uget("nesterov").into();
+    let checkpoint_steps: isize = config.uget("checkpoint_steps").into();
+    let max_checkpoints: isize = config.uget("max_checkpoints").into();
+    let keep_best: bool = config.uget("keep_best_checkpoint").into();
 
     let (command_sender, command_recv) = unbounded::<TrainSend>();
     let (log_sender, log_recv) = unbounded::<TrainRecv>();
@@ -200,6 +234,13 @@ pub fn run(config: &Config) -> Result<TrainProcess> {
     let train_log_steps: isize = config.uget("train_log_steps").into();
     let data_dir: String = config.uget("dataset_path").into();
     let dataset = mnist::Mnist::new(&data_dir)?;
+    // checkpointing is disabled if checkpoint_steps is 0
+    let mut checkpoints = if checkpoint_steps > 0 {
+        let folder: String = config.uget("checkpoint_path").into();
+        Some(super::CheckpointManager::new(folder.into(), max_checkpoints as usize)?.with_keep_best(keep_best))
+    } else {
+        None
+    };
 
     let sender = log_sender;
     let recv = command_recv;
@@ -217,12 +258,13 @@ pub fn run(config: &Config) -> Result<TrainProcess> {
         // };
 
         // world.clear();
-        let mut optim = SGDSimple { lr: lr as f32 };
+        let mut optim = SGDSimple::new(lr as f32).with_momentum(momentum as f32, nesterov);
 
         let mut steps = 0;
         let mut running_loss = 0.0;
         let mut running_acc = 0.0;
         let mut steps_since_last_log = 0;
+        let mut last_log = std::time::Instant::now();
 
         // let setup_test_iter = || {
         //     let test_iter = dataset.iter_test_img();
@@ -249,6 +291,7 @@ pub fn run(config: &Config) -> Result<TrainProcess> {
                 (transforms::to_afarray(&img), Array::new(label.as_slice().unwrap(), dim4!(label.len() as u64)))
             });
 
+            World::from(&mut model).set_training(true);
             for (img, label) in train_iter {
                 steps += 1isize;
                 let (logits, df) = model.forward(&img);
@@ -259,6 +302,7 @@ pub fn run(config: &Config) -> Result<TrainProcess> {
                 let mut world = World::new();
                 model.flatten("".to_string(), &mut world);
                 optim.update(&mut world);
+                world.zero_grad();
 
                 let mut loss_host = [0.0f32];
                 loss.host(loss_host.as_mut_slice());
@@ -267,6 +311,17 @@ pub fn run(config: &Config) -> Result<TrainProcess> {
                 running_acc += accuracy(&logits, &label.cast());
                 steps_since_last_log += 1isize;
 
+                if let Some(manager) = checkpoints.as_mut().filter(|_| steps % checkpoint_steps == 0) {
+                    let metric = (running_loss / steps_since_last_log as f32) as f64;
+                    let saved = manager.save_world(&World::from(&mut model), steps as usize)
+                        .and_then(|path| manager.record_metric(&path, metric, false))
+                        .and_then(|_| manager.remove_old_checkpoints());
+                    if let Err(e) = saved {
+                        sender.send(TrainRecv::FAILED(format!("{:#}", e))).unwrap();
+                        return;
+                    }
+                }
+
                 if steps % train_log_steps == 0 {
                     sender
                         .send(TrainRecv::PLOT(super::PlotPoint { 
@@ -285,14 +340,32 @@ pub fn run(config: &Config) -> Result<TrainProcess> {
                             x: steps as f64, 
                             y: (running_acc / steps_since_last_log as f32) as f64
                         })).unwrap();
+                    sender
+                        .send(TrainRecv::STATS(RunStats {
+                            step_time: Some(last_log.elapsed().as_secs_f32() / steps_since_last_log as f32),
+                            device_mem_bytes: Some(RunStats::device_mem()),
+                            ..Default::default()
+                        })).unwrap();
+                    last_log = std::time::Instant::now();
                     steps_since_last_log = 1;
                     running_acc = 0.0;
                     running_loss = 0.0;
                 }
 
-                if let Ok(TrainSend::KILL) = recv.try_recv() {
-                    
-                    return;
+                match recv.try_recv() {
+                    Ok(TrainSend::KILL) => { return; }
+                    Ok(TrainSend::SetLr(lr)) => {
+                        optim.lr = lr as f32;
+                        sender
+                            .send(TrainRecv::PLOT(super::PlotPoint { 
+                                title: "lr", 
+                                x_title: "step", 
+                                y_title: "lr", 
+                                x: steps as f64, 
+                                y: lr
+                            })).unwrap();
+                    }
+                    _ => {}
                 }
             }
 
@@ -323,7 +396,7 @@ pub fn run_on_main(config: &Config) {
     // let mut model = FastResnet::<f32>::new(10);
     let mut model = SimpleResnet::<f32>::new(10);
 
-    let mut optim = SGDSimple { lr: lr as f32 };
+    let mut optim = SGDSimple::new(lr as f32);
 
     let mut steps = 0;
     let mut running_loss = 0.0;
@@ -341,6 +414,7 @@ pub fn run_on_main(config: &Config) {
             (transforms::to_afarray(&img), Array::new(label.as_slice().unwrap(), dim4!(label.len() as u64)))
         });
 
+        World::from(&mut model).set_training(true);
         for (img, label) in train_iter {
             steps += 1isize;
             let (logits, df) = model.forward(&img);
@@ -351,6 +425,7 @@ pub fn run_on_main(config: &Config) {
             let mut world = World::new();
             model.flatten("".to_string(), &mut world);
             optim.update(&mut world);
+            world.zero_grad();
 
             let mut loss_host = [0.0f32];
             loss.host(loss_host.as_mut_slice());
@@ -437,4 +512,20 @@ fn test_adam_update() {
     let mut adam = Adam::new(&mut world, 0.8f32, 0.999f32);
 
     adam.update(&mut world, 0.02);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_summarize() {
+    let mut resnet = SimpleResnet::<f32>::new(10);
+    let summary = resnet.summarize(&[28, 28, 3]);
+    let names: Vec<_> = summary.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, ["pre", "max_pool", "flatten", "linear"]);
+    let shapes: Vec<_> = summary.iter().map(|x| x.output_shape.clone()).collect();
+    assert_eq!(shapes, [vec![28, 28, 64, 1], vec![14, 14, 64, 1], vec![64, 1, 1, 1], vec![10, 1, 1, 1]]);
+
+    // conv filter 3x3x3x64, instance norm gamma and beta, linear weight 64x10 and bias
+    let total: usize = summary.iter().map(|x| x.params).sum();
+    assert_eq!(total, 3 * 3 * 3 * 64 + 2 * 64 + 64 * 10 + 10);
+    let mut world = World::from(&mut resnet);
+    assert_eq!(total, num_params::<f32>(&mut world, ""));
+}
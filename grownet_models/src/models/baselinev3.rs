@@ -45,14 +45,15 @@ impl<B: Backend> Batcher<MNISTItem, MNISTBatch<B>> for MNISTBatcher<B> {
 }
 
 use burn::{
-    module::{Module, Param},
-    nn::{self, conv::Conv2dPaddingConfig, loss::CrossEntropyLoss, BatchNorm2d},
+    module::{Module, Param, RunningState},
+    nn::{self, conv::Conv2dPaddingConfig, loss::CrossEntropyLoss},
     tensor::{
         backend::{ADBackend},
     },
     train::{ClassificationOutput, TrainOutput, TrainStep, ValidStep},
 };
 use num_traits::ToPrimitive;
+use super::LayerSummary;
 
 #[derive(Module, Debug)]
 pub struct Model<B: Backend> {
@@ -66,17 +67,23 @@ pub struct Model<B: Backend> {
 }
 
 const NUM_CLASSES: usize = 10;
+const HIDDEN_SIZE: usize = 32;
 
 impl<B: Backend> Model<B> {
     pub fn new() -> Self {
-        let conv1 = ConvBlock::new([1, 8], [3, 3]); // out: [Batch,8,26,26]
-        let conv2 = ConvBlock::new([8, 16], [3, 3]); // out: [Batch,16,24x24]
-        let conv3 = ConvBlock::new([16, 24], [3, 3]); // out: [Batch,24,22x22]
+        Self::with_frozen_batchnorm(false)
+    }
+
+    /// if freeze_bn is set, the batchnorm layers use their running statistics even while training
+    pub fn with_frozen_batchnorm(freeze_bn: bool) -> Self {
+        let conv1 = ConvBlock::new([1, 8], [3, 3], freeze_bn); // out: [Batch,8,26,26]
+        let conv2 = ConvBlock::new([8, 16], [3, 3], freeze_bn); // out: [Batch,16,24x24]
+        let conv3 = ConvBlock::new([16, 24], [3, 3], freeze_bn); // out: [Batch,24,22x22]
         let hidden_size = 24 * 22 * 22;
-        let fc1 = nn::LinearConfig::new(hidden_size, 32)
+        let fc1 = nn::LinearConfig::new(hidden_size, HIDDEN_SIZE)
             .with_bias(false);
         let fc1 = nn::Linear::new(&fc1);
-        let fc2 = nn::LinearConfig::new(32, NUM_CLASSES)
+        let fc2 = nn::LinearConfig::new(HIDDEN_SIZE, NUM_CLASSES)
             .with_bias(false);
         let fc2 = nn::Linear::new(&fc2);
 
@@ -128,25 +135,33 @@ impl<B: Backend> Model<B> {
 #[derive(Module, Debug)]
 pub struct ConvBlock<B: Backend> {
     conv: Param<nn::conv::Conv2d<B>>,
-    norm: Param<BatchNorm2d<B>>,
+    norm: Param<BatchNorm<B>>,
     activation: nn::GELU,
+    channels: [usize; 2],
+    kernel_size: [usize; 2],
 }
 
 impl<B: Backend> ConvBlock<B> {
-    pub fn new(channels: [usize; 2], kernel_size: [usize; 2]) -> Self {
+    pub fn new(channels: [usize; 2], kernel_size: [usize; 2], freeze_bn: bool) -> Self {
         let conv = nn::conv::Conv2dConfig::new(channels, kernel_size)
             .with_padding(Conv2dPaddingConfig::Valid);
         let conv = nn::conv::Conv2d::new(&conv);
-        let norm = nn::BatchNorm2dConfig::new(channels[1]);
-        let norm = nn::BatchNorm2d::new(&norm);
+        let norm = BatchNorm::new(&nn::BatchNorm2dConfig::new(channels[1]), freeze_bn);
 
         Self {
             conv: Param::from(conv),
             norm: Param::from(norm),
             activation: nn::GELU::new(),
+            channels,
+            kernel_size,
         }
     }
 
+    /// the output shape for an input of shape [b, c, h, w], the convolution is unpadded
+    pub fn output_shape(&self, [batch_size, _, height, width]: [usize; 4]) -> [usize; 4] {
+        [batch_size, self.channels[1], height - self.kernel_size[0] + 1, width - self.kernel_size[1] + 1]
+    }
+
     pub fn forward(&self, input: Tensor<B, 4>) -> Tensor<B, 4> {
         let x = self.conv.forward(input);
         let x = self.norm.forward(x);
@@ -155,6 +170,94 @@ impl<B: Backend> ConvBlock<B> {
     }
 }
 
+/// Summarizes each layer of the model with its parameter count and output shape, for an input of shape [b, h, w].
+/// Only shapes are propagated, nothing is computed. The parameter counts include the batchnorm running statistics
+pub fn summarize<B: Backend>(model: &Model<B>, input_shape: &[usize]) -> Vec<LayerSummary> {
+    let [batch_size, height, width]: [usize; 3] = input_shape.try_into()
+        .expect("the input shape should be [b, h, w]");
+    let x1 = model.conv1.output_shape([batch_size, 1, height, width]);
+    let x2 = model.conv2.output_shape(x1);
+    let x3 = model.conv3.output_shape(x2);
+    let [_, channels, height, width] = x3;
+
+    let layers = [
+        ("conv1", x1.to_vec(), model.conv1.num_params()),
+        ("conv2", x2.to_vec(), model.conv2.num_params()),
+        ("conv3", x3.to_vec(), model.conv3.num_params()),
+        ("flatten", vec![batch_size, channels * height * width], 0),
+        ("fc1", vec![batch_size, HIDDEN_SIZE], model.fc1.num_params()),
+        ("fc2", vec![batch_size, NUM_CLASSES], model.fc2.num_params()),
+    ];
+    layers.into_iter().map(|(name, output_shape, params)| {
+        LayerSummary { name: name.to_string(), output_shape, params }
+    }).collect()
+}
+
+/// The same as nn::BatchNorm2d, with the same state so that checkpoints are compatible, except that
+/// when frozen the running statistics are used and left unchanged even while training,
+/// which helps when fine tuning with small batches. gamma and beta are trained either way
+#[derive(Module, Debug)]
+pub struct BatchNorm<B: Backend> {
+    gamma: Param<Tensor<B, 1>>,
+    beta: Param<Tensor<B, 1>>,
+    running_mean: Param<RunningState<Tensor<B, 1>>>,
+    running_var: Param<RunningState<Tensor<B, 1>>>,
+    momentum: f64,
+    epsilon: f64,
+    frozen: bool,
+}
+
+impl<B: Backend> BatchNorm<B> {
+    pub fn new(config: &nn::BatchNorm2dConfig, frozen: bool) -> Self {
+        Self {
+            gamma: Param::from(Tensor::ones([config.num_features])),
+            beta: Param::from(Tensor::zeros([config.num_features])),
+            running_mean: Param::from(RunningState::new(Tensor::zeros([config.num_features]))),
+            running_var: Param::from(RunningState::new(Tensor::ones([config.num_features]))),
+            momentum: config.momentum,
+            epsilon: config.epsilon,
+            frozen,
+        }
+    }
+
+    /// normalizes each channel of a [b, c, h, w] input with the batch statistics while training,
+    /// and with the running statistics during inference or when frozen
+    pub fn forward(&self, input: Tensor<B, 4>) -> Tensor<B, 4> {
+        let [batch_size, channels, height, width] = input.dims();
+        let (mean, var) = if B::ad_enabled() && !self.frozen {
+            let mean = input.clone()
+                .swap_dims(0, 1)
+                .reshape([channels, batch_size * height * width])
+                .mean_dim(1)
+                .reshape([1, channels, 1, 1]);
+            let var = input.clone()
+                .sub(mean.clone())
+                .powf(2.0)
+                .swap_dims(0, 1)
+                .reshape([channels, batch_size * height * width])
+                .mean_dim(1)
+                .reshape([1, channels, 1, 1]);
+
+            let running_mean = self.running_mean.value_sync().mul_scalar(1.0 - self.momentum)
+                .add(mean.clone().detach().mul_scalar(self.momentum).reshape([channels]));
+            let running_var = self.running_var.value_sync().mul_scalar(1.0 - self.momentum)
+                .add(var.clone().detach().mul_scalar(self.momentum).reshape([channels]));
+            self.running_mean.update(running_mean.detach());
+            self.running_var.update(running_var.detach());
+            (mean, var)
+        } else {
+            (
+                self.running_mean.val().value().reshape([1, channels, 1, 1]),
+                self.running_var.val().value().reshape([1, channels, 1, 1]),
+            )
+        };
+
+        let x = input.sub(mean).div(var.add_scalar(self.epsilon).sqrt());
+        x.mul(self.gamma.val().reshape([1, channels, 1, 1]))
+            .add(self.beta.val().reshape([1, channels, 1, 1]))
+    }
+}
+
 impl<B: ADBackend> TrainStep<MNISTBatch<B>, ClassificationOutput<B>> for Model<B> {
     fn step(&self, item: MNISTBatch<B>) -> TrainOutput<ClassificationOutput<B>> {
         let item = self.forward_classification(item);
@@ -169,8 +272,11 @@ impl<B: Backend> ValidStep<MNISTBatch<B>, ClassificationOutput<B>> for Model<B>
     }
 }
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use burn::module::{State, StateNamed};
+
 use burn::optim::decay::WeightDecayConfig;
 use burn::optim::{Adam, AdamConfig};
 use burn::{
@@ -199,11 +305,84 @@ pub struct MnistTrainingConfig {
     pub optimizer: AdamConfig,
 }
 
+/// Wraps a burn optimizer, rescaling each update by lr / base_lr, since burn does not
+/// allow changing the learning rate of an optimizer after construction
+pub struct ScheduledOptim<O> {
+    inner: O,
+    base_lr: f64,
+    lr: f64,
+}
+
+impl<O: Optimizer> ScheduledOptim<O> {
+    pub fn new(inner: O, base_lr: f64) -> Self {
+        Self { inner, base_lr, lr: base_lr }
+    }
+
+    pub fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    pub fn lr(&self) -> f64 {
+        self.lr
+    }
+}
+
+impl<O: Optimizer> Optimizer for ScheduledOptim<O> {
+    type Backend = O::Backend;
+
+    fn update_tensor<const D: usize>(
+        &mut self,
+        id: &burn::module::ParamId,
+        tensor: Tensor<Self::Backend, D>,
+        grad: Tensor<<Self::Backend as ADBackend>::InnerBackend, D>,
+    ) -> Tensor<Self::Backend, D> {
+        let updated = self.inner.update_tensor(id, tensor.clone(), grad);
+        if self.lr == self.base_lr {
+            return updated;
+        }
+        let old = tensor.inner();
+        let delta = old.clone().sub(updated.inner()).mul_scalar(self.lr / self.base_lr);
+        Tensor::from_inner(old.sub(delta))
+    }
+
+    fn register_param_state<const D: usize>(
+        &self,
+        id: &burn::module::ParamId,
+        state: &mut burn::module::StateNamed<<Self::Backend as Backend>::FloatElem>,
+    ) {
+        self.inner.register_param_state::<D>(id, state)
+    }
+
+    fn load_param_state<const D: usize>(
+        &mut self,
+        id: &burn::module::ParamId,
+        state: &burn::module::StateNamed<<Self::Backend as Backend>::FloatElem>,
+        device: &<Self::Backend as Backend>::Device,
+    ) {
+        self.inner.load_param_state::<D>(id, state, device)
+    }
+}
+
 
 use super::Config as MConfig;
 use super::{config, TrainProcess};
+use crate::nn::schedulers::LrScheduler;
 use anyhow::Result;
 
+pub type BoxedScheduler = Box<dyn CloneScheduler>;
+
+/// A scheduler which can be cloned behind a box, so that a run which is launched more than once
+/// starts every attempt with the same schedule
+pub trait CloneScheduler: LrScheduler + Send + Sync {
+    fn clone_boxed(&self) -> BoxedScheduler;
+}
+
+impl<T: LrScheduler + Clone + Send + Sync + 'static> CloneScheduler for T {
+    fn clone_boxed(&self) -> BoxedScheduler {
+        Box::new(self.clone())
+    }
+}
+
 pub fn baseline_config() -> MConfig {
     use crate::{Options, Config, opt};
     config!(
@@ -211,12 +390,37 @@ pub fn baseline_config() -> MConfig {
         ("weight_decay", 5e-5),
         ("batch_size", 4),
         ("epochs", 4),
-        ("train_log_steps", 100)
+        ("train_log_steps", 100),
+        ("checkpoint_steps", 0),
+        ("max_checkpoints", 3),
+        ("keep_best_checkpoint", true),
+        ("freeze_batchnorm", false),
+        ("eval_every", 0)
     )
 }
 
-fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig) -> Result<TrainProcess> {
-    use super::{PlotPoint, TrainRecv, TrainSend, RunStats};
+/// saves the model and optimizer state into a single file
+fn save_checkpoint<B: ADBackend, O: Optimizer<Backend = B>>(model: &Model<B>, optim: &O, path: &Path) -> Result<()>
+where B::FloatElem: serde::Serialize + serde::de::DeserializeOwned {
+    let mut state = StateNamed::new();
+    state.register_state("model", model.state());
+    state.register_state("optim", optim.state(model));
+    State::StateNamed(state).save(path.to_str().unwrap())?;
+    Ok(())
+}
+
+/// restores the model and optimizer state written by save_checkpoint
+fn load_checkpoint<B: ADBackend, O: Optimizer<Backend = B>>(model: Model<B>, optim: &mut O, state: &State<B::FloatElem>) -> Result<Model<B>> {
+    let model_state = state.get("model").ok_or(anyhow::Error::msg("checkpoint is missing the model state"))?;
+    let optim_state = state.get("optim").ok_or(anyhow::Error::msg("checkpoint is missing the optimizer state"))?;
+    let model = model.load(model_state).map_err(|e| anyhow::Error::msg(format!("unable to load model: {}", e)))?;
+    optim.load(&model, optim_state).map_err(|e| anyhow::Error::msg(format!("unable to load optimizer: {}", e)))?;
+    Ok(model)
+}
+
+fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig, mut scheduler: Option<BoxedScheduler>, resume: Option<PathBuf>) -> Result<TrainProcess>
+where B::FloatElem: serde::Serialize + serde::de::DeserializeOwned {
+    use super::{PlotPoint, LogLevel, TrainRecv, TrainSend, RunStats, CheckpointManager};
     use crossbeam::channel::unbounded;
 
     let lr: f64 = config.uget("lr").into();
@@ -224,6 +428,33 @@ fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig) -> Result<TrainProc
     let batch_size: isize = config.uget("batch_size").into();
     let epochs: isize = config.uget("epochs").into();
     let train_log_steps: isize = config.uget("train_log_steps").into();
+    let checkpoint_steps: isize = config.uget("checkpoint_steps").into();
+    let max_checkpoints: isize = config.uget("max_checkpoints").into();
+    // optional, training stops after this many steps
+    let max_steps: Option<isize> = config.get("max_steps").map(|x| x.into());
+    // the test set is evaluated every eval_every steps, disabled if 0 or missing from older configs
+    let eval_every: isize = config.get("eval_every").map_or(0, |x| x.into());
+    // off if missing from older configs
+    let freeze_bn: bool = config.get("freeze_batchnorm").map_or(false, |x| x.into());
+    // the checkpoint with the lowest val loss is never removed, off if missing from older configs
+    let keep_best: bool = config.get("keep_best_checkpoint").map_or(false, |x| x.into());
+    // checkpointing is disabled if checkpoint_steps is 0
+    let mut checkpoints = if checkpoint_steps > 0 {
+        let folder: String = config.uget("checkpoint_path").into();
+        Some(CheckpointManager::new(folder.into(), max_checkpoints as usize)?.with_keep_best(keep_best))
+    } else {
+        None
+    };
+    let resume = match resume {
+        Some(path) => {
+            let step = CheckpointManager::step_of(&path)
+                .ok_or(anyhow::Error::msg(format!("{} is not a checkpoint path", path.display())))?;
+            let state = State::<B::FloatElem>::load(path.to_str().unwrap())
+                .map_err(|e| anyhow::Error::msg(format!("unable to read checkpoint {}: {}", path.display(), e)))?;
+            Some((step, state))
+        }
+        None => None,
+    };
 
     let (command_sender, command_recv) = unbounded::<TrainSend>();
     let (log_sender, log_recv) = unbounded::<TrainRecv>();
@@ -239,11 +470,16 @@ fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig) -> Result<TrainProc
     
         let batcher_train = Arc::new(MNISTBatcher::<B>::new(device.clone()));
         let batcher_valid = Arc::new(MNISTBatcher::<B::InnerBackend>::new(device.clone()));
+        use burn::data::dataset::Dataset;
+        let train_set = Arc::new(MNISTDataset::train());
+        // the run stops at the end of the last epoch, or at max_steps if it comes first
+        let epoch_steps = (train_set.len() + batch_size as usize - 1) / batch_size as usize;
+        let total_steps = (epochs as usize * epoch_steps).min(max_steps.map_or(usize::MAX, |x| x as usize));
         let dataloader_train = DataLoaderBuilder::new(batcher_train)
             .batch_size(batch_size as usize)
             .shuffle(config.seed)
             //.num_workers(config.num_workers)
-            .build(Arc::new(MNISTDataset::train()));
+            .build(train_set);
         let dataloader_test = DataLoaderBuilder::new(batcher_valid)
             .batch_size(batch_size as usize)
             .shuffle(config.seed)
@@ -251,19 +487,38 @@ fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig) -> Result<TrainProc
             .build(Arc::new(MNISTDataset::test()));
     
         // Model
-        let mut optim = Adam::<B>::new(&config.optimizer);
-        let mut model = Model::<B>::new();
+        let mut optim = ScheduledOptim::new(Adam::<B>::new(&config.optimizer), lr);
+        let mut model = Model::<B>::with_frozen_batchnorm(freeze_bn);
     
         let mut steps = 0;
+        if let Some((step, state)) = resume {
+            match load_checkpoint(model, &mut optim, &state) {
+                Ok(loaded) => { model = loaded; }
+                Err(e) => {
+                    sender.send(TrainRecv::FAILED(e.to_string())).unwrap();
+                    return;
+                }
+            }
+            steps = step as isize;
+            sender.send(TrainRecv::LOG { level: LogLevel::Info, msg: format!("resumed from step {}", step) }).unwrap();
+        }
         let mut running_train_loss = 0.0;
         let mut running_train_acc = 0.0;
         let mut steps_since_last_log = 0;
+        let mut last_log = std::time::Instant::now();
+        // a resumed run continues within the epoch it was saved in
+        let (start_epoch, mut skip_batches) = resume_position(steps as usize, epoch_steps);
     
-        for _epoch in 0..epochs {
+        for _epoch in start_epoch as isize..epochs {
             let mut train_iter = dataloader_train.iter();
-            let mut _test_iter = dataloader_test.iter();
+            for _ in 0..std::mem::take(&mut skip_batches) {
+                train_iter.next();
+            }
     
             while let Some(item) = train_iter.next() {
+                if let Some(scheduler) = &mut scheduler {
+                    optim.set_lr(scheduler.lr(steps as usize));
+                }
                 let item = <Model<B> as TrainStep<_, _>>::step(&model, item);
                 model = optim.update_module(model, item.grads);
                 let item = item.item;
@@ -272,6 +527,18 @@ fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig) -> Result<TrainProc
                 steps += 1;
                 steps_since_last_log += 1;
 
+                // old checkpoints are removed after validation, so that the val loss of this step is recorded first
+                let mut saved = None;
+                if let Some(manager) = checkpoints.as_mut().filter(|_| steps % checkpoint_steps == 0) {
+                    let path = manager.new_path(steps as usize);
+                    if let Err(e) = save_checkpoint(&model, &optim, &path) {
+                        sender.send(TrainRecv::FAILED(format!("unable to save checkpoint: {}", e))).unwrap();
+                        return;
+                    }
+                    sender.send(TrainRecv::LOG { level: LogLevel::Info, msg: format!("saved checkpoint to {}", path.display()) }).unwrap();
+                    saved = Some(path);
+                }
+
                 if steps % train_log_steps == 0 {
                     sender
                         .send(TrainRecv::PLOT(super::PlotPoint { 
@@ -290,16 +557,68 @@ fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig) -> Result<TrainProc
                             x: steps as f64, 
                             y: (running_train_acc / steps_since_last_log as f64)
                         })).unwrap();
+                    if scheduler.is_some() {
+                        sender
+                            .send(TrainRecv::PLOT(super::PlotPoint { 
+                                title: "lr", 
+                                x_title: "step", 
+                                y_title: "lr", 
+                                x: steps as f64, 
+                                y: optim.lr()
+                            })).unwrap();
+                    }
+                    let step_time = last_log.elapsed().as_secs_f32() / steps_since_last_log as f32;
+                    sender.send(TrainRecv::STATS(RunStats { 
+                        step_time: Some(step_time), 
+                        device_mem_bytes: None, 
+                        host_mem_bytes: RunStats::host_mem(), 
+                        step: Some(steps as usize), 
+                        total_steps: Some(total_steps) 
+                    })).unwrap();
+                    last_log = std::time::Instant::now();
                     steps_since_last_log = 1;
                     running_train_acc = 0.0;
                     running_train_loss = 0.0;
                 }
 
-                if let Ok(TrainSend::KILL) = recv.try_recv() {
+                let val_loss = if eval_every > 0 && steps % eval_every == 0 {
+                    validate(&model, dataloader_test.iter(), steps as f64, &sender)
+                } else {
+                    None
+                };
+                if let (Some(manager), Some(path)) = (checkpoints.as_mut(), saved) {
+                    if let Err(e) = retain_checkpoints(manager, &path, val_loss) {
+                        sender.send(TrainRecv::FAILED(format!("{:#}", e))).unwrap();
+                        return;
+                    }
+                }
+
+                match recv.try_recv() {
+                    Ok(TrainSend::KILL) => { return; }
+                    Ok(TrainSend::SetLr(lr)) => {
+                        // a manually set lr takes precedence over the scheduler
+                        scheduler = None;
+                        optim.set_lr(lr);
+                        sender
+                            .send(TrainRecv::PLOT(super::PlotPoint { 
+                                title: "lr", 
+                                x_title: "step", 
+                                y_title: "lr", 
+                                x: steps as f64, 
+                                y: lr
+                            })).unwrap();
+                    }
+                    _ => {}
+                }
+                if max_steps.map_or(false, |max_steps| steps >= max_steps) {
                     return;
                 }
             }
-    
+
+            // mark the end of the epoch on the per step graphs
+            for title in ["train loss", "train accuracy"] {
+                sender.send(TrainRecv::MARKER { title, x: steps as f64 }).unwrap();
+            }
         }
     });
 
@@ -310,6 +629,12 @@ fn run_v2<B: ADBackend>(device: B::Device, config: &MConfig) -> Result<TrainProc
     })
 }
 
+/// the epoch that step falls in and the number of its batches which were already trained on
+fn resume_position(step: usize, epoch_steps: usize) -> (usize, usize) {
+    let epoch_steps = epoch_steps.max(1);
+    (step / epoch_steps, step % epoch_steps)
+}
+
 fn compute_accuracy<B: Backend>(input: ClassificationOutput<B>) -> f64 {
     let [batch_size, _n_classes] = input.output.dims();
 
@@ -327,19 +652,329 @@ fn compute_accuracy<B: Backend>(input: ClassificationOutput<B>) -> f64 {
     accuracy
 }
 
-pub fn run_train_loop(config: &MConfig) -> Result<TrainProcess> {
+/// runs a full pass over batches with the model in eval mode, sending the mean loss and accuracy as the 
+/// "val loss" and "val acc" plot points at step, followed by the confusion matrix of the pass. The inner model
+/// has no autodiff, so dropout is disabled, batchnorm uses its running statistics, and no parameters are updated.
+/// Returns the mean loss, or None if there were no batches
+fn validate<B: ADBackend>(
+    model: &Model<B>, 
+    batches: impl Iterator<Item = MNISTBatch<B::InnerBackend>>, 
+    step: f64, 
+    sender: &crossbeam::channel::Sender<super::TrainRecv>
+) -> Option<f64> {
+    use super::{ConfusionMatrix, PlotPoint, TrainRecv};
+    let model = model.clone().inner();
+    let (mut loss, mut acc, mut n) = (0.0, 0.0, 0);
+    let mut confusion = None;
+    for item in batches {
+        let item = <Model<B::InnerBackend> as ValidStep<_, _>>::step(&model, item);
+        loss += f64::from_elem(item.loss.to_data().value[0]);
+        let [batch_size, n_classes] = item.output.dims();
+        let labels = |data: Vec<_>| -> Vec<usize> { data.into_iter().map(|x| Into::<i64>::into(x) as usize).collect() };
+        let pred = labels(item.output.clone().argmax(1).reshape([batch_size]).to_data().value);
+        let truth = labels(item.targets.to_data().value);
+        confusion.get_or_insert_with(|| ConfusionMatrix::new(n_classes)).add_batch(&truth, &pred);
+        acc += compute_accuracy(item);
+        n += 1;
+    }
+    if n == 0 {
+        return None;
+    }
+    for (title, y_title, y) in [("val loss", "cross entropy", loss / n as f64), ("val acc", "accuracy", acc / n as f64)] {
+        sender.send(TrainRecv::PLOT(PlotPoint { title, x_title: "step", y_title, x: step, y })).unwrap();
+    }
+    if let Some(confusion) = confusion {
+        sender.send(TrainRecv::CONFUSION(confusion)).unwrap();
+    }
+    Some(loss / n as f64)
+}
+
+/// records the val loss of the checkpoint just saved at path, if it was evaluated, then removes old checkpoints,
+/// which keeps the checkpoint with the lowest recorded val loss if the manager keeps the best
+fn retain_checkpoints(manager: &mut super::CheckpointManager, path: &Path, val_loss: Option<f64>) -> Result<()> {
+    if let Some(val_loss) = val_loss {
+        manager.record_metric(path, val_loss, false)?;
+    }
+    manager.remove_old_checkpoints()
+}
+
+/// runs the baseline training loop, if resume is a checkpoint path, the model, optimizer and
+/// step count are restored from it before training
+pub fn run_train_loop(config: &MConfig, scheduler: Option<BoxedScheduler>, resume: Option<PathBuf>) -> Result<TrainProcess> {
     use burn_ndarray::NdArrayBackend;
     use burn_autodiff::ADBackendDecorator;
     let dev = burn_ndarray::NdArrayDevice::Cpu;
 
-    run_v2::<ADBackendDecorator<NdArrayBackend<f32>>>(dev, config)
+    run_v2::<ADBackendDecorator<NdArrayBackend<f32>>>(dev, config, scheduler, resume)
+}
+
+/// the summary of the model run_train_loop trains, for a single mnist image
+pub fn mnist_summary() -> Vec<LayerSummary> {
+    use burn_ndarray::NdArrayBackend;
+    summarize(&Model::<NdArrayBackend<f32>>::new(), &[1, 28, 28])
+}
+
+/// runs the baseline training loop on the current thread without a ui, passing every event to callback,
+/// returns once training finishes, fails or is stopped by the optional max_steps key of config
+pub fn run_headless(config: MConfig, callback: impl FnMut(super::TrainRecv)) -> Result<()> {
+    run_train_loop(&config, None, None)?.run_blocking(callback)
 }
 
 #[test]
 fn test_train_loop() {
     let config = baseline_config();
-    let mut handle = run_train_loop(&config).unwrap();
+    let mut handle = run_train_loop(&config, None, None).unwrap();
+    handle.send.send(super::TrainSend::KILL).unwrap();
+    handle.kill_blocking().unwrap();
+
+}
+
+#[test]
+fn test_train_loop_scheduler() {
+    use crate::nn::schedulers::LinearWarmup;
+    let config = baseline_config();
+    let mut handle = run_train_loop(&config, Some(Box::new(LinearWarmup::new(1e-4, 10))), None).unwrap();
     handle.send.send(super::TrainSend::KILL).unwrap();
     handle.kill_blocking().unwrap();
+}
+
+#[test]
+fn test_resume_train_loop() {
+    use super::{CheckpointManager, TrainRecv};
+    use crate::Options;
+    let folder = std::env::temp_dir().join("grownet_test_resume_train_loop");
+    let mut config = baseline_config();
+    config.update_key("train_log_steps", &Options::INT(1)).unwrap();
+    config.update_key("checkpoint_steps", &Options::INT(2)).unwrap();
+    config.insert("checkpoint_path", &Options::STR(folder.to_str().unwrap().into())).unwrap();
+
+    // returns the step of the first plot point received
+    fn first_step(handle: &mut TrainProcess) -> f64 {
+        loop {
+            match handle.recv.recv().unwrap() {
+                TrainRecv::PLOT(point) => { return point.x; }
+                TrainRecv::FAILED(e) => panic!("{}", e),
+                _ => {}
+            }
+        }
+    }
+
+    let mut handle = run_train_loop(&config, None, None).unwrap();
+    assert_eq!(first_step(&mut handle), 1.0);
+    while CheckpointManager::new(folder.clone(), 3).unwrap().checkpoints().unwrap().is_empty() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    handle.kill_blocking().unwrap();
+
+    let checkpoint = CheckpointManager::new(folder.clone(), 3).unwrap().checkpoints().unwrap().pop().unwrap();
+    let step = CheckpointManager::step_of(&checkpoint).unwrap();
+    assert!(step >= 2);
+    let mut handle = run_train_loop(&config, None, Some(checkpoint.clone())).unwrap();
+    assert_eq!(first_step(&mut handle), (step + 1) as f64);
+    handle.kill_blocking().unwrap();
+
+    // the resumed run only takes the remaining steps, so its progress ends at total_steps
+    config.insert("max_steps", &Options::INT(step as isize + 2)).unwrap();
+    let mut progress = Vec::new();
+    run_train_loop(&config, None, Some(checkpoint)).unwrap().run_blocking(|msg| match msg {
+        TrainRecv::STATS(s) => progress.push((s.step.unwrap(), s.total_steps.unwrap())),
+        TrainRecv::FAILED(e) => panic!("{}", e),
+        _ => {}
+    }).unwrap();
+    assert_eq!(progress, [(step + 1, step + 2), (step + 2, step + 2)]);
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_resume_position() {
+    assert_eq!(resume_position(0, 10), (0, 0));
+    assert_eq!(resume_position(7, 10), (0, 7));
+    assert_eq!(resume_position(10, 10), (1, 0));
+    assert_eq!(resume_position(25, 10), (2, 5));
+}
+
+#[test]
+fn test_set_lr() {
+    use super::TrainRecv;
+    let mut config = baseline_config();
+    config.update_key("train_log_steps", &crate::Options::INT(1)).unwrap();
+    let mut handle = run_train_loop(&config, None, None).unwrap();
+    handle.set_lr(0.5);
+    // the new lr is echoed back once the training loop receives the command
+    loop {
+        match handle.recv.recv().unwrap() {
+            TrainRecv::PLOT(point) if point.title == "lr" => {
+                assert_eq!(point.y, 0.5);
+                break;
+            }
+            TrainRecv::FAILED(e) => panic!("{}", e),
+            _ => {}
+        }
+    }
+    handle.kill_blocking().unwrap();
+
+    // the optimizer update is scaled by the new lr
+    use burn::module::ParamId;
+    type B = burn_autodiff::ADBackendDecorator<burn_ndarray::NdArrayBackend<f32>>;
+    let mut optim = ScheduledOptim::new(Adam::<B>::new(&AdamConfig::new(0.1)), 0.1);
+    let param = Tensor::<B, 1>::from_data(Data::from([1.0f32, 2.0]));
+    let grad = Tensor::<<B as ADBackend>::InnerBackend, 1>::from_data(Data::from([1.0f32, -1.0]));
+    let id = ParamId::new();
+    let step = |optim: &mut ScheduledOptim<Adam<B>>| {
+        let updated = optim.update_tensor(&id, param.clone(), grad.clone());
+        (param.clone() - updated).into_data().value
+    };
+    let full = step(&mut optim);
+    optim.set_lr(0.05);
+    let half = step(&mut optim);
+    optim.set_lr(0.0);
+    assert_eq!(step(&mut optim), vec![0.0, 0.0]);
+    // adam moves each weight by about lr on its first steps
+    for (f, h) in full.iter().zip(half.iter()) {
+        assert!((f.abs() - 0.1).abs() < 1e-3 && (h.abs() - 0.05).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn test_run_headless() {
+    use super::TrainRecv;
+    use crate::Options;
+    let mut config = baseline_config();
+    config.update_key("train_log_steps", &Options::INT(1)).unwrap();
+    config.insert("max_steps", &Options::INT(3)).unwrap();
+
+    let mut loss_steps = Vec::new();
+    let mut stats = 0;
+    run_headless(config, |msg| match msg {
+        TrainRecv::PLOT(point) if point.title == "train loss" => loss_steps.push(point.x),
+        TrainRecv::STATS(s) => {
+            assert!(s.step_time.unwrap() >= 0.0);
+            // the ndarray backend trains in host memory, not on an arrayfire device
+            assert_eq!(s.device_mem_bytes, None);
+            assert!(s.host_mem_bytes.unwrap() > 0);
+            stats += 1;
+            assert_eq!((s.step, s.total_steps), (Some(stats), Some(3)));
+        }
+        TrainRecv::FAILED(e) => panic!("{}", e),
+        _ => {}
+    }).unwrap();
+    assert_eq!(loss_steps, vec![1.0, 2.0, 3.0]);
+    assert_eq!(stats, 3);
+}
 
-}
\ No newline at end of file
+#[test]
+fn test_validate() {
+    use super::TrainRecv;
+    type B = burn_autodiff::ADBackendDecorator<burn_ndarray::NdArrayBackend<f32>>;
+    type I = burn_ndarray::NdArrayBackend<f32>;
+    let model = Model::<B>::new();
+    let state = model.state();
+    let batches = (0..2).map(|i| MNISTBatch::<I> {
+        images: Tensor::random([2, 28, 28], burn::tensor::Distribution::Standard),
+        targets: Tensor::from_data(Data::from([i as i64, 3])),
+    });
+
+    let (sender, recv) = crossbeam::channel::unbounded();
+    let val_loss = validate(&model, batches, 5.0, &sender);
+    let mut confusion = None;
+    let points: Vec<_> = recv.try_iter().filter_map(|msg| match msg {
+        TrainRecv::PLOT(point) => Some((point.title, point.x, point.y)),
+        TrainRecv::CONFUSION(matrix) => { confusion = Some(matrix); None },
+        _ => panic!("unexpected message"),
+    }).collect();
+    assert_eq!(points.len(), 2);
+    let confusion = confusion.expect("no confusion matrix sent");
+    assert_eq!((confusion.classes(), confusion.total()), (10, 4));
+    assert_eq!((0..10).map(|pred| confusion.get(3, pred)).sum::<u64>(), 2);
+    assert_eq!((points[0].0, points[0].1), ("val loss", 5.0));
+    assert_eq!(val_loss, Some(points[0].2));
+    assert!(points[0].2.is_finite() && points[0].2 > 0.0);
+    assert_eq!((points[1].0, points[1].1), ("val acc", 5.0));
+    assert!((0.0..=100.0).contains(&points[1].2));
+    // evaluating does not touch the weights
+    assert_eq!(model.state(), state);
+
+    // nothing is sent for an empty test set
+    assert_eq!(validate(&model, std::iter::empty(), 6.0, &sender), None);
+    assert!(recv.try_recv().is_err());
+}
+
+#[test]
+fn test_retain_best_checkpoint() {
+    use super::CheckpointManager;
+    let folder = std::env::temp_dir().join("grownet_test_retain_best_checkpoint");
+    let _ = std::fs::remove_dir_all(&folder);
+    let mut config = baseline_config();
+    config.update_key("max_checkpoints", &crate::Options::INT(2)).unwrap();
+    let max_checkpoints: isize = config.uget("max_checkpoints").into();
+    let keep_best: bool = config.uget("keep_best_checkpoint").into();
+    let mut manager = CheckpointManager::new(folder.clone(), max_checkpoints as usize).unwrap().with_keep_best(keep_best);
+
+    // checkpoints saved on steps without an evaluation have no val loss
+    let mut paths = Vec::new();
+    for (step, val_loss) in [(1, Some(0.9)), (2, Some(0.3)), (3, None), (4, Some(0.5)), (5, Some(0.7)), (6, None)] {
+        let path = manager.new_path(step);
+        std::fs::write(&path, b"").unwrap();
+        retain_checkpoints(&mut manager, &path, val_loss).unwrap();
+        paths.push(path);
+    }
+    // the best checkpoint at step 2 survives pruning, in addition to the 2 youngest
+    assert_eq!(manager.best_checkpoint().unwrap().as_ref(), Some(&paths[1]));
+    assert_eq!(manager.checkpoints().unwrap(), vec![paths[1].clone(), paths[4].clone(), paths[5].clone()]);
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_freeze_batchnorm() {
+    type B = burn_autodiff::ADBackendDecorator<burn_ndarray::NdArrayBackend<f32>>;
+    let batch = MNISTBatch::<B> {
+        images: Tensor::random([4, 28, 28], burn::tensor::Distribution::Standard),
+        targets: Tensor::from_data(Data::from([0, 1, 2, 3])),
+    };
+    let running_stats = |model: &Model<B>| {
+        let norm = &model.conv1.norm;
+        (norm.running_mean.value_sync().to_data(), norm.running_var.value_sync().to_data())
+    };
+    let initial = running_stats(&Model::<B>::new());
+
+    let mut losses = Vec::new();
+    for freeze_bn in [false, true] {
+        let model = Model::<B>::with_frozen_batchnorm(freeze_bn);
+        let item = <Model<B> as TrainStep<_, _>>::step(&model, batch.clone());
+        losses.push(f64::from_elem(item.item.loss.to_data().value[0]));
+        // the running statistics are only folded in when not frozen
+        assert_eq!(running_stats(&model) == initial, freeze_bn);
+        // the frozen flag survives the optimizer update
+        let mut optim = Adam::<B>::new(&AdamConfig::new(0.1));
+        let model = optim.update_module(model, item.grads);
+        assert_eq!(model.conv1.norm.frozen, freeze_bn);
+    }
+    // normalizing with the running statistics instead of the batch statistics changes the training loss
+    assert_ne!(losses[0], losses[1]);
+}
+
+#[test]
+fn test_summarize() {
+    use burn_ndarray::NdArrayBackend;
+    let model = Model::<NdArrayBackend<f32>>::new();
+    let summary = summarize(&model, &[2, 28, 28]);
+    let names: Vec<_> = summary.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, ["conv1", "conv2", "conv3", "flatten", "fc1", "fc2"]);
+    let shapes: Vec<_> = summary.iter().map(|x| x.output_shape.clone()).collect();
+    assert_eq!(shapes, [
+        vec![2, 8, 26, 26], vec![2, 16, 24, 24], vec![2, 24, 22, 22],
+        vec![2, 24 * 22 * 22], vec![2, 32], vec![2, 10]
+    ]);
+
+    // conv filter and bias, batchnorm gamma, beta and running mean and variance, linear weights without bias
+    let params: Vec<_> = summary.iter().map(|x| x.params).collect();
+    assert_eq!(params, [
+        8 * 9 + 8 + 4 * 8,
+        8 * 16 * 9 + 16 + 4 * 16,
+        16 * 24 * 9 + 24 + 4 * 24,
+        0,
+        24 * 22 * 22 * 32,
+        32 * 10
+    ]);
+    assert_eq!(params.iter().sum::<usize>(), model.num_params());
+}
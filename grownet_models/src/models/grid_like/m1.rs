@@ -0,0 +1,336 @@
+use ndarray::prelude::*;
+use ndarray_rand::{rand_distr::Normal, RandomExt};
+use rand_distr::Distribution;
+
+use crate::models::m2::{l2_norm, GlobalParams, NodeMessage};
+use super::NodeV5;
+
+
+/// A message is only worth propagating if its magnitude is at least params.underflow_epsilon
+pub fn should_propagate(msg: &NodeMessage, params: &GlobalParams) -> bool {
+    msg.mag() >= params.underflow_epsilon
+}
+
+/// A node which accumulates incoming messages, and only emits a message once the magnitude of the accumulated
+/// messages reaches its activation threshold, like m2::ComputeNode, after which the accumulator is reset.
+/// The backward pass is gated in the same way, a gradient is only pushed if the node emitted on the forward pass.
+pub struct ComputeNode {
+    w: Array2<f32>,
+    b: Array1<f32>,
+    dw: Array2<f32>,
+    db: Array1<f32>,
+    threshold: f32,
+    accum: Array1<f32>,
+    x: Option<Array1<f32>>, // the input and output of the last emission, consumed by backward
+    y: Option<Array1<f32>>,
+}
+
+impl ComputeNode {
+    /// node is the index of this node in the network, which determines its initialization
+    pub fn new(params: &GlobalParams, node: usize) -> Self {
+        let dim = params.compute_dim;
+        let mut rng = params.node_rng(node);
+        let threshold = Normal::new(params.s_mean, params.s_var).unwrap().sample(&mut rng);
+        let w = Array2::random_using((dim, dim), Normal::new(0.0, 1.0 / (dim as f32).sqrt()).unwrap(), &mut rng);
+        Self {
+            w,
+            b: Array1::zeros(dim),
+            dw: Array2::zeros((dim, dim)),
+            db: Array1::zeros(dim),
+            threshold,
+            accum: Array1::zeros(dim),
+            x: None,
+            y: None,
+        }
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// the magnitude of the messages accumulated since the last emission
+    pub fn accumulated(&self) -> f32 {
+        l2_norm(&self.accum)
+    }
+
+    /// the cosine similarity between msg and the accumulated messages, None if nothing has been accumulated
+    pub fn similarity(&self, msg: &NodeMessage) -> Option<f32> {
+        let mag = self.accumulated();
+        if mag == 0.0 || msg.mag() == 0.0 {
+            return None;
+        }
+        Some(self.accum.dot(&**msg.msg()) / (mag * msg.mag()))
+    }
+
+    pub fn zero_grad(&mut self) {
+        self.dw.fill(0.0);
+        self.db.fill(0.0);
+    }
+
+    pub fn apply_grad(&mut self, params: &GlobalParams) {
+        self.w.zip_mut_with(&self.dw, |w, dw| *w -= dw * params.lr);
+        self.b.zip_mut_with(&self.db, |b, db| *b -= db * params.lr);
+    }
+}
+
+impl<'a> NodeV5<&'a GlobalParams> for ComputeNode {
+    type F = NodeMessage;
+    type B = NodeMessage;
+
+    /// messages which underflow, or which are less similar than ctx.sim_epsilon to the accumulated messages are dropped
+    fn forward(&mut self, ctx: &'a GlobalParams, forward: NodeMessage) -> Option<NodeMessage> {
+        if !should_propagate(&forward, ctx) || self.similarity(&forward).map_or(false, |sim| sim < ctx.sim_epsilon) {
+            return None;
+        }
+        self.accum += &**forward.msg();
+        if self.accumulated() < self.threshold {
+            return None;
+        }
+        // y = relu(W x + b)
+        let x = std::mem::replace(&mut self.accum, Array1::zeros(self.b.len()));
+        let mut y = self.w.dot(&x);
+        y += &self.b;
+        y.mapv_inplace(|y| y.max(0.0));
+        self.x = Some(x);
+        self.y = Some(y.clone());
+        Some(NodeMessage::new(y.into()))
+    }
+
+    fn backward(&mut self, _ctx: &'a GlobalParams, backward: NodeMessage) -> Option<NodeMessage> {
+        let (x, y) = (self.x.take()?, self.y.take()?);
+        let mut dy = backward.msg().view().to_owned();
+        dy.zip_mut_with(&y, |dy, y| if *y <= 0.0 { *dy = 0.0; });
+        self.db += &dy;
+        self.dw += &dy.view().insert_axis(Axis(1)).dot(&x.view().insert_axis(Axis(0)));
+        let dx = self.w.t().dot(&dy);
+        Some(NodeMessage::new(dx.into()))
+    }
+}
+
+
+/// The offsets of the moore neighbourhood in D dimensions, every offset in {-1, 0, 1}^D except the origin
+pub fn neighbourhood<const D: usize>() -> Vec<[isize; D]> {
+    let mut offsets = vec![[0isize; D]];
+    for d in 0..D {
+        offsets = offsets.into_iter().flat_map(|offset| (-1..=1).map(move |k| {
+            let mut offset = offset;
+            offset[d] = k;
+            offset
+        })).collect();
+    }
+    offsets.retain(|offset| offset.iter().any(|k| *k != 0));
+    offsets
+}
+
+/// A D dimensional lattice of ComputeNodes, where each node sends its emissions to its neighbourhood.
+/// Cells on the boundary have fewer neighbours, unless the grid is toroidal, in which case it wraps around
+pub struct Grid<const D: usize> {
+    shape: [usize; D],
+    toroidal: bool,
+    nodes: Vec<ComputeNode>,
+    neighbours: Vec<Vec<usize>>,
+    inbox: Vec<Vec<NodeMessage>>, // messages to be processed at the next step
+}
+
+impl<const D: usize> Grid<D> {
+    pub fn new(shape: [usize; D], toroidal: bool, params: &GlobalParams) -> Self {
+        let len = shape.iter().product();
+        let offsets = neighbourhood::<D>();
+        let mut grid = Self { 
+            shape, toroidal, 
+            nodes: (0..len).map(|i| ComputeNode::new(params, i)).collect(), 
+            neighbours: Vec::with_capacity(len),
+            inbox: vec![Vec::new(); len]
+        };
+        for i in 0..len {
+            let coord = grid.coord(i);
+            let mut neighbours: Vec<usize> = offsets.iter().filter_map(|offset| {
+                let mut neighbour = [0; D];
+                for d in 0..D {
+                    let k = coord[d] as isize + offset[d];
+                    let n = shape[d] as isize;
+                    neighbour[d] = if toroidal { k.rem_euclid(n) as usize } 
+                        else if k < 0 || k >= n { return None; } 
+                        else { k as usize };
+                }
+                Some(grid.index(neighbour))
+            }).filter(|j| *j != i).collect();
+            // small toroidal grids can reach the same neighbour through multiple offsets
+            neighbours.sort();
+            neighbours.dedup();
+            grid.neighbours.push(neighbours);
+        }
+        grid
+    }
+
+    pub fn shape(&self) -> [usize; D] {
+        self.shape
+    }
+
+    pub fn is_toroidal(&self) -> bool {
+        self.toroidal
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// the linear index of coord, where the last dimension is contiguous
+    pub fn index(&self, coord: [usize; D]) -> usize {
+        coord.iter().zip(self.shape.iter()).fold(0, |i, (c, s)| i * s + c)
+    }
+
+    pub fn coord(&self, mut index: usize) -> [usize; D] {
+        let mut coord = [0; D];
+        for d in (0..D).rev() {
+            coord[d] = index % self.shape[d];
+            index /= self.shape[d];
+        }
+        coord
+    }
+
+    pub fn neighbours(&self, index: usize) -> &[usize] {
+        &self.neighbours[index]
+    }
+
+    pub fn node(&self, index: usize) -> &ComputeNode {
+        &self.nodes[index]
+    }
+
+    pub fn node_mut(&mut self, index: usize) -> &mut ComputeNode {
+        &mut self.nodes[index]
+    }
+
+    /// queues msg to be received by the node at index on the next step
+    pub fn send(&mut self, index: usize, msg: NodeMessage) {
+        self.inbox[index].push(msg);
+    }
+
+    /// the number of messages queued for the node at index
+    pub fn pending(&self, index: usize) -> usize {
+        self.inbox[index].len()
+    }
+
+    /// every node receives its queued messages, and the emissions are queued at each of its neighbours,
+    /// returns the number of nodes which emitted
+    pub fn step(&mut self, params: &GlobalParams) -> usize {
+        let inbox = std::mem::replace(&mut self.inbox, vec![Vec::new(); self.nodes.len()]);
+        let mut emitted = 0;
+        for (i, msgs) in inbox.into_iter().enumerate() {
+            let mut fired = false;
+            for msg in msgs {
+                if let Some(out) = self.nodes[i].forward(params, msg) {
+                    fired = true;
+                    for j in &self.neighbours[i] {
+                        self.inbox[*j].push(out.clone());
+                    }
+                }
+            }
+            emitted += fired as usize;
+        }
+        emitted
+    }
+}
+
+#[test]
+fn test_compute_node_threshold() {
+    let params = GlobalParams { compute_dim: 4, s_mean: 1.0, s_var: 0.0, ..Default::default() };
+    let mut node = ComputeNode::new(&params, 0);
+    assert_eq!(node.threshold(), 1.0);
+    let msg = |x: f32| NodeMessage::new(Array1::from_elem(4, x).into());
+
+    // nothing to push back if the node has not emitted
+    assert!(node.backward(&params, msg(1.0)).is_none());
+    // each message has magnitude 0.4, so the node fires on the third
+    assert!(node.forward(&params, msg(0.2)).is_none());
+    assert!(node.forward(&params, msg(0.2)).is_none());
+    assert!((node.accumulated() - 0.8).abs() < 1e-6);
+    let out = node.forward(&params, msg(0.2)).expect("accumulated messages are above threshold");
+    assert_eq!(out.msg().len(), 4);
+    assert!(out.msg().iter().all(|y| *y >= 0.0));
+    assert_eq!(node.accumulated(), 0.0);
+
+    let dx = node.backward(&params, msg(1.0)).expect("node emitted on the last forward");
+    assert_eq!(dx.msg().len(), 4);
+    // each emission can only be pushed back once
+    assert!(node.backward(&params, msg(1.0)).is_none());
+}
+
+#[test]
+fn test_message_gating() {
+    let params = GlobalParams { compute_dim: 4, underflow_epsilon: 0.5, sim_epsilon: 0.0, s_mean: 10.0, s_var: 0.0, ..Default::default() };
+    let msg = |x: [f32; 4]| NodeMessage::new(Array1::from_vec(x.to_vec()).into());
+    assert!(should_propagate(&msg([0.5, 0.0, 0.0, 0.0]), &params));
+    assert!(!should_propagate(&msg([0.499, 0.0, 0.0, 0.0]), &params));
+    assert!(!should_propagate(&msg([0.0; 4]), &params));
+
+    let mut node = ComputeNode::new(&params, 0);
+    // underflowing messages are not accumulated
+    node.forward(&params, msg([0.0, 0.0, 0.499, 0.0]));
+    assert_eq!(node.accumulated(), 0.0);
+    node.forward(&params, msg([1.0, 0.0, 0.0, 0.0]));
+    assert_eq!(node.accumulated(), 1.0);
+
+    // orthogonal messages have a similarity of exactly sim_epsilon, so are kept
+    assert_eq!(node.similarity(&msg([0.0, 1.0, 0.0, 0.0])), Some(0.0));
+    node.forward(&params, msg([0.0, 1.0, 0.0, 0.0]));
+    assert!((node.accumulated() - 2f32.sqrt()).abs() < 1e-6);
+    // while opposing messages are dropped
+    let opposing = msg([-1.0, -0.01, 0.0, 0.0]);
+    assert!(node.similarity(&opposing).unwrap() < 0.0);
+    node.forward(&params, opposing);
+    assert!((node.accumulated() - 2f32.sqrt()).abs() < 1e-6);
+}
+
+#[test]
+fn test_grid_neighbours() {
+    assert_eq!(neighbourhood::<1>().len(), 2);
+    assert_eq!(neighbourhood::<2>().len(), 8);
+    assert_eq!(neighbourhood::<3>().len(), 26);
+
+    let params = GlobalParams { compute_dim: 2, ..Default::default() };
+    let grid = Grid::new([3, 4], false, &params);
+    assert_eq!(grid.len(), 12);
+    assert_eq!(grid.coord(grid.index([2, 1])), [2, 1]);
+    assert_eq!(grid.neighbours(grid.index([1, 1])).len(), 8); // interior
+    assert_eq!(grid.neighbours(grid.index([0, 2])).len(), 5); // edge
+    assert_eq!(grid.neighbours(grid.index([1, 3])).len(), 5);
+    assert_eq!(grid.neighbours(grid.index([2, 3])).len(), 3); // corner
+    assert_eq!(grid.neighbours(grid.index([0, 0])), &[1, 4, 5]);
+
+    let grid = Grid::new([3, 4], true, &params);
+    for i in 0..grid.len() {
+        assert_eq!(grid.neighbours(i).len(), 8);
+    }
+    assert!(grid.neighbours(0).contains(&grid.index([2, 3])));
+    // each cell of a 2x2 torus neighbours every other cell exactly once
+    let grid = Grid::new([2, 2], true, &params);
+    assert_eq!(grid.neighbours(0), &[1, 2, 3]);
+}
+
+#[test]
+fn test_grid_step() {
+    // every message is propagated, and every node fires on receiving one
+    let params = GlobalParams { compute_dim: 2, underflow_epsilon: 0.0, s_mean: 0.0, s_var: 0.0, ..Default::default() };
+    let mut grid = Grid::new([3, 3], false, &params);
+    grid.send(0, NodeMessage::new(Array1::from_elem(2, 1.0).into()));
+    assert_eq!(grid.step(&params), 1);
+    let pending: Vec<usize> = (0..grid.len()).map(|i| grid.pending(i)).collect();
+    assert_eq!(pending, vec![0, 1, 0, 1, 1, 0, 0, 0, 0]);
+    assert_eq!(grid.step(&params), 3);
+    assert_eq!(grid.pending(0), 3);
+}
+
+#[test]
+fn test_grid_seed() {
+    let params = GlobalParams { compute_dim: 3, seed: 42, ..Default::default() };
+    let weights = |grid: &Grid<2>| -> Vec<(Array2<f32>, f32)> {
+        grid.nodes.iter().map(|node| (node.w.clone(), node.threshold)).collect()
+    };
+    let a = weights(&Grid::new([2, 3], false, &params));
+    assert_eq!(a, weights(&Grid::new([2, 3], false, &params)));
+    // nodes do not share their initialization
+    assert_ne!(a[0], a[1]);
+    let params = GlobalParams { seed: 43, ..params };
+    assert_ne!(a, weights(&Grid::new([2, 3], false, &params)));
+}
@@ -1,5 +1,6 @@
 pub mod m0;
 pub mod m0ctx;
+pub mod m1;
 
 /// Simplest computation block, a differentiable function, whose update method must strictly follow the forward method
 pub trait Node {
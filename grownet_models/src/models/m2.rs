@@ -8,7 +8,7 @@ use ndarray::Axis;
 use ndarray_rand::{rand_distr::Normal, rand_distr::Uniform, RandomExt};
 
 use num::Float;
-use rand::thread_rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rand_distr::Distribution;
 
 pub fn l2_norm<T: Float>(a: &Array1<T>) -> T {
@@ -19,18 +19,45 @@ pub fn l2_norm<T: Float>(a: &Array1<T>) -> T {
 /// is passed by reference into each forward and backward call
 #[derive(Clone)]
 pub struct GlobalParams {
-    sim_strictness: f32,
-    underflow_epsilon: f32,
-    sim_epsilon: f32,
-    lr: f32,
-    compute_dim: usize,
-
-    s_mean: f32,
-    s_var: f32,
-    b_mean: f32,
-    b_var: f32,
-
-    ro_var: f32,
+    pub sim_strictness: f32,
+    pub underflow_epsilon: f32,
+    pub sim_epsilon: f32,
+    pub lr: f32,
+    pub compute_dim: usize,
+
+    pub s_mean: f32,
+    pub s_var: f32,
+    pub b_mean: f32,
+    pub b_var: f32,
+
+    pub ro_var: f32,
+
+    pub seed: u64, // all random initialization is derived from this
+}
+
+impl Default for GlobalParams {
+    fn default() -> Self {
+        Self {
+            sim_strictness: 1.0,
+            underflow_epsilon: 1e-6,
+            sim_epsilon: -1.0, // cosine similarity is never below -1, so no message is dropped
+            lr: 1e-3,
+            compute_dim: 16,
+            s_mean: 1.0,
+            s_var: 0.1,
+            b_mean: 1.0,
+            b_var: 0.1,
+            ro_var: 0.1,
+            seed: 0,
+        }
+    }
+}
+
+impl GlobalParams {
+    /// a rng seeded by seed and node, so that each node can be initialized independently of the others
+    pub fn node_rng(&self, node: usize) -> StdRng {
+        StdRng::seed_from_u64(self.seed ^ (node as u64).wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
 }
 
 /// The main enum signifying the output state of a node, as nodes may
@@ -72,6 +99,14 @@ impl NodeMessage {
         let norm = l2_norm(&msg);
         NodeMessage { msg, mag: norm }
     }
+
+    pub fn msg(&self) -> &Message {
+        &self.msg
+    }
+
+    pub fn mag(&self) -> f32 {
+        self.mag
+    }
 }
 
 pub struct WeightedSigmoid {
@@ -139,10 +174,10 @@ pub struct ComputeInstance {
 }
 
 impl ComputeInstance {
-    pub fn new(dim: usize) -> ComputeInstance {
+    pub fn new(dim: usize, rng: &mut impl Rng) -> ComputeInstance {
         ComputeInstance {
-            w: np::Array::random((dim, dim), Normal::new(0.0, 1.0).unwrap()),
-            b: np::Array::random((dim,), Uniform::new(-1.0, 1.0)),
+            w: np::Array::random_using((dim, dim), Normal::new(0.0, 1.0).unwrap(), rng),
+            b: np::Array::random_using((dim,), Uniform::new(-1.0, 1.0), rng),
             dw: np::Array::zeros((dim, dim)),
             db: np::Array::zeros((dim,)),
             act_fn: Relu {},
@@ -181,8 +216,8 @@ impl ComputeInstance {
         self.b.zip_mut_with(&self.db, |a, b| *a -= b * params.lr);
     }
 
-    pub unsafe fn new_instance(dim: usize) -> *mut Self {
-        let compute = ComputeInstance::new(dim);
+    pub unsafe fn new_instance(dim: usize, rng: &mut impl Rng) -> *mut Self {
+        let compute = ComputeInstance::new(dim, rng);
         let mut compute = mem::ManuallyDrop::new(compute);
         &mut (*compute) as *mut ComputeInstance
     }
@@ -198,11 +233,13 @@ pub struct ComputeNode {
     pub mag: f32,
     compute: *mut ComputeInstance,
     compute_initialized: bool,
+    rng: StdRng, // the compute instance is initialized lazily
 }
 
 impl ComputeNode {
-    pub fn new(params: &GlobalParams) -> ComputeNode {
-        let mut rng = thread_rng();
+    /// node is the index of this node in the network, which determines its initialization
+    pub fn new(params: &GlobalParams, node: usize) -> ComputeNode {
+        let mut rng = params.node_rng(node);
         let mut norm = |mu, si| Normal::new(mu, si).unwrap().sample(&mut rng);
         let act_fn = WeightedSigmoid {
             s: norm(params.s_mean, params.s_var),
@@ -220,6 +257,7 @@ impl ComputeNode {
             mag: 0.0,
             compute: null::<ComputeInstance>() as *mut ComputeInstance,
             compute_initialized: false,
+            rng,
         }
     }
 
@@ -237,7 +275,7 @@ impl ComputeNode {
     fn inner_compute_forward(&mut self, msg: &Message, global_params: &GlobalParams) -> Message {
         unsafe {
             if !self.compute_initialized {
-                self.compute = ComputeInstance::new_instance(global_params.compute_dim);
+                self.compute = ComputeInstance::new_instance(global_params.compute_dim, &mut self.rng);
                 self.compute_initialized = true;
             }
             return (*self.compute).forward(&*msg).into();
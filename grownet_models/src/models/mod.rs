@@ -1,14 +1,16 @@
 use std::collections::VecDeque;
 use std::ops::DerefMut;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use crossbeam::channel::{Receiver, Sender};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::thread::{spawn, JoinHandle};
 
-use crate::{Config, config};
+use crate::{Config, config, World};
+use crate::nn::af_ops::{self, Buffer, Float, Param};
+use crate::nn::af_ops::utils::{to_host, to_device};
 // pub mod baseline;
 pub mod baselinev2;
 pub mod baselinev3;
@@ -17,9 +19,96 @@ pub mod grid_like;
 mod m1;
 mod m2;
 
-#[derive(Clone, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Default)]
 pub struct RunStats {
     pub step_time: Option<f32>,
+    /// bytes allocated by arrayfire on the device the run trains on, see device_mem
+    pub device_mem_bytes: Option<u64>,
+    /// the resident memory of the process training the run, see host_mem
+    #[serde(default)]
+    pub host_mem_bytes: Option<u64>,
+    /// the number of steps taken so far
+    #[serde(default)]
+    pub step: Option<usize>,
+    /// the number of steps the run will take, if known
+    #[serde(default)]
+    pub total_steps: Option<usize>,
+}
+
+impl RunStats {
+    /// the number of bytes currently allocated by arrayfire on the active device
+    pub fn device_mem() -> u64 {
+        let (alloc_bytes, _, _, _) = arrayfire::device_mem_info();
+        alloc_bytes as u64
+    }
+
+    /// the resident memory of this process in bytes, which is shared by every run of the process.
+    /// None if it can not be read
+    pub fn host_mem() -> Option<u64> {
+        // the second field of statm is the number of resident pages
+        let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        (page_size > 0).then(|| pages * page_size as u64)
+    }
+}
+
+/// A row of a model summary, the output shape is in the order of the model's backend,
+/// ex. [w, h, c, n] for arrayfire and [n, c, h, w] for burn
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerSummary {
+    pub name: String,
+    pub output_shape: Vec<usize>,
+    pub params: usize,
+}
+
+/// Counts of predicted against true classes over an evaluation pass,
+/// rows are indexed by the true class and columns by the predicted class
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConfusionMatrix {
+    classes: usize,
+    counts: Vec<u64>, // row major, classes x classes
+}
+
+impl ConfusionMatrix {
+    pub fn new(classes: usize) -> Self {
+        Self { classes, counts: vec![0; classes * classes] }
+    }
+
+    pub fn classes(&self) -> usize {
+        self.classes
+    }
+
+    /// the number of samples of class truth predicted as pred
+    pub fn get(&self, truth: usize, pred: usize) -> u64 {
+        self.counts[truth * self.classes + pred]
+    }
+
+    pub fn add(&mut self, truth: usize, pred: usize) {
+        assert!(truth < self.classes && pred < self.classes, "class out of range for {} classes", self.classes);
+        self.counts[truth * self.classes + pred] += 1;
+    }
+
+    /// adds each (truth, pred) pair
+    pub fn add_batch(&mut self, truth: &[usize], pred: &[usize]) {
+        assert_eq!(truth.len(), pred.len(), "mismatched number of labels and predictions");
+        for (&t, &p) in truth.iter().zip(pred) {
+            self.add(t, p);
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// each row divided by its sum, the fraction of each true class predicted as each class,
+    /// rows of classes with no samples are left as zeros
+    pub fn row_normalized(&self) -> Vec<Vec<f64>> {
+        self.counts.chunks(self.classes.max(1)).take(self.classes).map(|row| {
+            let sum = row.iter().sum::<u64>();
+            row.iter().map(|&c| if sum == 0 { 0.0 } else { c as f64 / sum as f64 }).collect()
+        }).collect()
+    }
 }
 
 #[derive(Clone)]
@@ -33,9 +122,19 @@ pub struct PlotPoint {
 
 pub enum TrainSend {
     KILL,
+    /// sets the learning rate used from the next step onwards
+    SetLr(f64),
     OTHER(usize),
 }
 
+/// The severity of a LOG message
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
 /// The reason there is a TrainRecv and a Log, with the two being nearly identical
 /// is that the TrainRecv is the direct output of the training process, which does not
 /// have information, as it does not concern itself, with various details such as model version
@@ -46,7 +145,10 @@ pub enum TrainSend {
 pub enum TrainRecv {
     PLOT(PlotPoint), // key, x, y
     FAILED(String),
+    LOG { level: LogLevel, msg: String }, // a message for the console, which does not end the run
     STATS(RunStats),
+    MARKER { title: &'static str, x: f64 }, // a vertical marker on the graphs with this title, such as an epoch boundary
+    CONFUSION(ConfusionMatrix), // the confusion matrix of the latest evaluation pass
     // CHECKPOINT(f32, std::path::PathBuf),
 }
 
@@ -67,6 +169,10 @@ impl TrainProcess {
         self.send.send(command).expect("unable to send train command");
     }
 
+    pub fn set_lr(&mut self, lr: f64) {
+        self.send_command(TrainSend::SetLr(lr));
+    }
+
     pub fn try_recv(&mut self) -> Vec<TrainRecv> {
         self.recv.try_iter().collect()
     }
@@ -79,6 +185,16 @@ impl TrainProcess {
         }
     }
 
+    /// blocks until the process exits, passing every message it sends to f
+    pub fn run_blocking(mut self, mut f: impl FnMut(TrainRecv)) -> Result<()> {
+        // the channel disconnects once the training thread exits
+        for msg in self.recv.iter() {
+            f(msg);
+        }
+        let handle = std::mem::replace(&mut self.handle, None).unwrap();
+        handle.join().map_err(|x| Error::msg(format!("thread error {:?}", x.downcast_ref::<&str>())))
+    }
+
     /// blocks until process is killed
     pub fn kill_blocking(&mut self) -> Result<()> {
         self.try_kill();
@@ -88,6 +204,87 @@ impl TrainProcess {
 }
 
 
+/// A PLOT, STATS or CONFUSION event as written to an EventLog
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum LoggedEvent {
+    Plot { title: String, x_title: String, y_title: String, x: f64, y: f64 },
+    Stats(RunStats),
+    Confusion(ConfusionMatrix),
+}
+
+impl LoggedEvent {
+    pub fn from_recv(msg: &TrainRecv) -> Option<Self> {
+        match msg {
+            TrainRecv::PLOT(p) => Some(LoggedEvent::Plot { 
+                title: p.title.into(), x_title: p.x_title.into(), y_title: p.y_title.into(), x: p.x, y: p.y 
+            }),
+            TrainRecv::STATS(stats) => Some(LoggedEvent::Stats(stats.clone())),
+            TrainRecv::CONFUSION(matrix) => Some(LoggedEvent::Confusion(matrix.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Appends the PLOT, STATS and CONFUSION events of a run to a file, one json object per line,
+/// the file is flushed every FLUSH_INTERVAL and when the log is finished
+pub struct EventLog {
+    path: PathBuf,
+    writer: std::io::BufWriter<std::fs::File>,
+    last_flush: std::time::Instant,
+}
+
+impl EventLog {
+    const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+    /// opens path for appending, creating it and its parent folders if they do not exist
+    pub fn new(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(&path)
+            .context(format!("unable to open event log {}", path.display()))?;
+        Ok(Self { path, writer: std::io::BufWriter::new(file), last_flush: std::time::Instant::now() })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// writes msg if it is a PLOT or STATS event, other events are ignored
+    pub fn log(&mut self, msg: &TrainRecv) -> Result<()> {
+        use std::io::Write;
+        if let Some(event) = LoggedEvent::from_recv(msg) {
+            serde_json::to_writer(&mut self.writer, &event)?;
+            self.writer.write_all(b"\n")?;
+        }
+        if self.last_flush.elapsed() >= Self::FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        use std::io::Write;
+        self.writer.flush()?;
+        self.last_flush = std::time::Instant::now();
+        Ok(())
+    }
+
+    /// flushes and closes the log
+    pub fn finish(mut self) -> Result<()> {
+        self.flush()
+    }
+
+    /// reads every event written to the log at path
+    pub fn read(path: &Path) -> Result<Vec<LoggedEvent>> {
+        let file = std::fs::read_to_string(path).context(format!("unable to read event log {}", path.display()))?;
+        file.lines().enumerate().map(|(i, line)| {
+            serde_json::from_str(line).context(format!("invalid event on line {}", i + 1))
+        }).collect()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CachedInfo {
     checkpoints: VecDeque<Config>,
@@ -95,18 +292,115 @@ pub struct CachedInfo {
 }
 
 
+/// The params and buffers of a world keyed by their flattened path, with their dims and values
+#[derive(Serialize, Deserialize, Default)]
+struct WorldCheckpoint {
+    f32: HashMap<String, ([u64; 4], Vec<f64>)>,
+    f64: HashMap<String, ([u64; 4], Vec<f64>)>,
+    #[serde(default)]
+    buffers_f32: HashMap<String, ([u64; 4], Vec<f64>)>,
+    #[serde(default)]
+    buffers_f64: HashMap<String, ([u64; 4], Vec<f64>)>,
+}
+
+fn params_with_path<'b, T: Float>(world: &'b World) -> impl Iterator<Item = (&'b str, &'b Param<T>)> + 'b {
+    world.query_with_path::<Param<T>>()
+        .chain(world.query_with_path::<Option<Param<T>>>().filter_map(|(path, x)| x.as_ref().map(|x| (path, x))))
+}
+
+fn save_params<T: Float>(world: &World) -> HashMap<String, ([u64; 4], Vec<f64>)> {
+    params_with_path::<T>(world)
+        .map(|(path, param)| (path.to_string(), (*param.dims().get(), to_host(&param.w))))
+        .collect()
+}
+
+fn load_params<T: Float>(world: &mut World, saved: &HashMap<String, ([u64; 4], Vec<f64>)>) -> Result<()> {
+    let mut loaded = 0;
+    let mut params: Vec<_> = world.query_mut_with_path::<Param<T>>().collect();
+    params.extend(world.query_mut_with_path::<Option<Param<T>>>().filter_map(|(path, x)| x.as_mut().map(|x| (path, x))));
+    for (path, param) in params {
+        let (dims, w) = saved.get(path).with_context(|| format!("param {} is missing from the checkpoint", path))?;
+        if dims != param.dims().get() {
+            bail!("param {} has shape {:?}, but the checkpoint has shape {:?}", path, param.dims().get(), dims);
+        }
+        param.w = to_device(w, *dims);
+        loaded += 1;
+    }
+    if loaded != saved.len() {
+        bail!("checkpoint has {} params, but the world only has {}", saved.len(), loaded);
+    }
+    Ok(())
+}
+
+fn save_buffers<T: Float>(world: &World) -> HashMap<String, ([u64; 4], Vec<f64>)> {
+    world.query_with_path::<Buffer<T>>()
+        .map(|(path, buffer)| (path.to_string(), (*buffer.dims().get(), to_host(&buffer.0))))
+        .collect()
+}
+
+fn load_buffers<T: Float>(world: &mut World, saved: &HashMap<String, ([u64; 4], Vec<f64>)>) -> Result<()> {
+    let mut loaded = 0;
+    for (path, buffer) in world.query_mut_with_path::<Buffer<T>>() {
+        let (dims, w) = saved.get(path).with_context(|| format!("buffer {} is missing from the checkpoint", path))?;
+        if dims != buffer.dims().get() {
+            bail!("buffer {} has shape {:?}, but the checkpoint has shape {:?}", path, buffer.dims().get(), dims);
+        }
+        buffer.0 = to_device(w, *dims);
+        loaded += 1;
+    }
+    if loaded != saved.len() {
+        bail!("checkpoint has {} buffers, but the world only has {}", saved.len(), loaded);
+    }
+    Ok(())
+}
+
+/// The metric recorded for a checkpoint, stored in a sidecar .meta file next to it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct CheckpointMeta {
+    metric: f64,
+    higher_is_better: bool,
+}
+
 pub struct CheckpointManager {
     pub folder: PathBuf,
     pub max_checkpoints: usize,
+    /// if true, the checkpoint with the best recorded metric is never removed
+    pub keep_best: bool,
     nver: usize
 }
 
 impl CheckpointManager {
-    pub fn new(folder: PathBuf, max_checkpoints: usize) -> Self {
+    pub fn new(folder: PathBuf, max_checkpoints: usize) -> Result<Self> {
         if !folder.exists() {
-            std::fs::create_dir(&folder).expect(&format!("failed to create folder {}", folder.display()));
+            std::fs::create_dir_all(&folder).with_context(|| format!("failed to create folder {}", folder.display()))?;
         }
-        Self { folder, max_checkpoints, nver: 0 }
+        let mut manager = Self { folder, max_checkpoints, keep_best: false, nver: 0 };
+        // continue numbering after any existing checkpoints, so that they are not overwritten
+        manager.nver = manager.checkpoints()?.iter()
+            .filter_map(|x| Self::parse_name(x))
+            .map(|x| x.0)
+            .max()
+            .unwrap_or(0);
+        Ok(manager)
+    }
+
+    /// returns the version number and step of a checkpoint path created by new_path
+    fn parse_name(path: &Path) -> Option<(usize, usize)> {
+        let stem = path.file_stem()?.to_str()?;
+        let mut parts = stem.strip_prefix("ckpt-")?.split('-');
+        let nver = parts.next()?.parse().ok()?;
+        let step = parts.next()?.parse().ok()?;
+        Some((nver, step))
+    }
+
+    /// the training step a checkpoint was saved at
+    pub fn step_of(path: &Path) -> Option<usize> {
+        Self::parse_name(path).map(|x| x.1)
+    }
+
+    pub fn with_keep_best(mut self, keep_best: bool) -> Self {
+        self.keep_best = keep_best;
+        self
     }
 
     pub fn new_path(&mut self, step: usize) -> PathBuf {
@@ -115,22 +409,94 @@ impl CheckpointManager {
     }
 
     /// returns a sorted list of checkpoints paths, full paths, youngest checkpoints first
-    pub fn checkpoints(&self) -> Vec<PathBuf> {
-        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.folder).expect(&format!("failed to read checkpoint dir {}", self.folder.display()))
+    pub fn checkpoints(&self) -> Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.folder)
+            .with_context(|| format!("failed to read checkpoint dir {}", self.folder.display()))?
             .filter(|x| x.is_ok())
             .map(|x| x.unwrap().path())
-            .filter(|x| x.is_file() && x.extension().unwrap() == "ckpt")
+            .filter(|x| x.is_file() && x.extension().is_some_and(|x| x == "ckpt"))
             .collect();
         files.sort();
-        files
+        Ok(files)
+    }
+
+    /// saves every f32 and f64 param of the world by path to a new checkpoint
+    pub fn save_world<'a>(&mut self, world: &World<'a>, step: usize) -> Result<PathBuf> {
+        let checkpoint = WorldCheckpoint { 
+            f32: save_params::<f32>(world), 
+            f64: save_params::<f64>(world),
+            buffers_f32: save_buffers::<f32>(world),
+            buffers_f64: save_buffers::<f64>(world),
+        };
+        let path = self.new_path(step);
+        let file = std::fs::File::create(&path).with_context(|| format!("failed to create checkpoint {}", path.display()))?;
+        ron::ser::to_writer(file, &checkpoint).with_context(|| format!("unable to write checkpoint {}", path.display()))?;
+        Ok(path)
     }
 
-    pub fn remove_old_checkpoints(&mut self) {
-        let mut checkpoints = self.checkpoints();
+    /// restores the params and buffers of the world from a checkpoint written by save_world, every param
+    /// must be matched by path with a param of the same shape
+    pub fn load_world<'a>(&self, path: &Path, world: &mut World<'a>) -> Result<()> {
+        let file = std::fs::File::open(path).with_context(|| format!("unable to open checkpoint {}", path.display()))?;
+        let checkpoint: WorldCheckpoint = ron::de::from_reader(file)
+            .with_context(|| format!("unable to read checkpoint {}", path.display()))?;
+        load_params::<f32>(world, &checkpoint.f32)?;
+        load_params::<f64>(world, &checkpoint.f64)?;
+        load_buffers::<f32>(world, &checkpoint.buffers_f32)?;
+        load_buffers::<f64>(world, &checkpoint.buffers_f64)?;
+        Ok(())
+    }
+
+    /// associates a metric with the checkpoint at path, used to find the best checkpoint
+    pub fn record_metric(&mut self, path: &Path, metric: f64, higher_is_better: bool) -> Result<()> {
+        let meta_path = path.with_extension("meta");
+        let file = std::fs::File::create(&meta_path).with_context(|| format!("failed to create {}", meta_path.display()))?;
+        ron::ser::to_writer(file, &CheckpointMeta { metric, higher_is_better })
+            .with_context(|| format!("unable to write checkpoint metric {}", meta_path.display()))
+    }
+
+    fn read_metric(path: &Path) -> Option<CheckpointMeta> {
+        let file = std::fs::File::open(path.with_extension("meta")).ok()?;
+        ron::de::from_reader(file).ok()
+    }
+
+    /// returns the checkpoint with the best recorded metric, if any metrics were recorded
+    pub fn best_checkpoint(&self) -> Result<Option<PathBuf>> {
+        let mut best: Option<(PathBuf, CheckpointMeta)> = None;
+        for path in self.checkpoints()? {
+            if let Some(meta) = Self::read_metric(&path) {
+                let better = match &best {
+                    None => true,
+                    Some((_, b)) if meta.higher_is_better => meta.metric > b.metric,
+                    Some((_, b)) => meta.metric < b.metric,
+                };
+                if better {
+                    best = Some((path, meta));
+                }
+            }
+        }
+        Ok(best.map(|x| x.0))
+    }
+
+    /// removes all but the max_checkpoints youngest checkpoints, along with their metrics,
+    /// if keep_best is set, the best checkpoint is retained in addition to the youngest
+    pub fn remove_old_checkpoints(&mut self) -> Result<()> {
+        let best = if self.keep_best { self.best_checkpoint()? } else { None };
+        let mut checkpoints = self.checkpoints()?;
         checkpoints.reverse();
         while self.max_checkpoints < checkpoints.len() {
-            std::fs::remove_file(checkpoints.pop().unwrap()).expect("unable to remove old checkpoints");
+            let path = checkpoints.pop().unwrap();
+            if best.as_ref() == Some(&path) {
+                continue;
+            }
+            std::fs::remove_file(&path).with_context(|| format!("unable to remove old checkpoint {}", path.display()))?;
+            let meta_path = path.with_extension("meta");
+            if meta_path.exists() {
+                std::fs::remove_file(&meta_path)
+                    .with_context(|| format!("unable to remove old checkpoint metric {}", meta_path.display()))?;
+            }
         } 
+        Ok(())
     }
 }
 
@@ -163,4 +529,173 @@ impl CheckpointManager {
 //     }
 // }
 
+#[test]
+fn test_save_load_world() {
+    use baselinev2::SimpleResnet;
+    fn weights(model: &mut SimpleResnet<f32>) -> Vec<Vec<f64>> {
+        World::from(model).query_mut::<Param<f32>>().map(|p| to_host(&p.w)).collect()
+    }
+    let folder = std::env::temp_dir().join("grownet_test_save_load_world");
+    let mut manager = CheckpointManager::new(folder.clone(), 2).unwrap();
+    let mut model = SimpleResnet::<f32>::new(10);
+    let original = weights(&mut model);
+    let path = manager.save_world(&World::from(&mut model), 0).unwrap();
+
+    for param in World::from(&mut model).query_mut::<Param<f32>>() {
+        param.w = &param.w * 2.0f32 + 1.0f32;
+    }
+    assert_ne!(weights(&mut model), original);
+    manager.load_world(&path, &mut World::from(&mut model)).unwrap();
+    assert_eq!(weights(&mut model), original);
+
+    // a model with a different number of classes has mismatched shapes
+    let mut other = SimpleResnet::<f32>::new(5);
+    let err = manager.load_world(&path, &mut World::from(&mut other)).unwrap_err();
+    assert!(err.to_string().contains("shape"));
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_save_load_buffers() {
+    use crate::nn::af_ops::batchnorm2d::BatchNorm2D;
+    let folder = std::env::temp_dir().join("grownet_test_save_load_buffers");
+    let mut manager = CheckpointManager::new(folder.clone(), 2).unwrap();
+    let mut norm = BatchNorm2D::<f32>::new(3);
+    let _ = norm.forward(&arrayfire::randn!(4, 4, 3, 2));
+    let running_mean = to_host(norm.running_mean());
+    let path = manager.save_world(&World::from(&mut norm), 0).unwrap();
+
+    let mut restored = BatchNorm2D::<f32>::new(3);
+    manager.load_world(&path, &mut World::from(&mut restored)).unwrap();
+    assert_eq!(to_host(restored.running_mean()), running_mean);
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_keep_best_checkpoint() {
+    let metrics = [0.9, 0.3, 0.5, 0.7, 0.6, 0.8];
+    for keep_best in [false, true] {
+        let folder = std::env::temp_dir().join(format!("grownet_test_keep_best_{}", keep_best));
+        let mut manager = CheckpointManager::new(folder.clone(), 2).unwrap().with_keep_best(keep_best);
+        let mut paths = Vec::new();
+        for (step, metric) in metrics.iter().enumerate() {
+            let path = manager.save_world(&World::new(), step).unwrap();
+            manager.record_metric(&path, *metric, false).unwrap();
+            manager.remove_old_checkpoints().unwrap();
+            paths.push(path);
+        }
+        let mut expected = vec![paths[4].clone(), paths[5].clone()];
+        if keep_best {
+            // the checkpoint with the lowest metric survives, even though it is the second oldest
+            assert_eq!(manager.best_checkpoint().unwrap(), Some(paths[1].clone()));
+            expected.insert(0, paths[1].clone());
+        } else {
+            assert_eq!(manager.best_checkpoint().unwrap(), Some(paths[4].clone()));
+        }
+        assert_eq!(manager.checkpoints().unwrap(), expected);
+        assert!(!paths[0].with_extension("meta").exists());
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    let folder = std::env::temp_dir().join("grownet_test_keep_best_higher");
+    let mut manager = CheckpointManager::new(folder.clone(), 1).unwrap().with_keep_best(true);
+    for (step, metric) in metrics.iter().enumerate() {
+        let path = manager.save_world(&World::new(), step).unwrap();
+        manager.record_metric(&path, *metric, true).unwrap();
+        manager.remove_old_checkpoints().unwrap();
+    }
+    let checkpoints = manager.checkpoints().unwrap();
+    assert_eq!(checkpoints.len(), 2);
+    assert_eq!(manager.best_checkpoint().unwrap().as_ref(), Some(&checkpoints[0]));
+    std::fs::remove_dir_all(&folder).unwrap();
+}
 
+#[test]
+fn test_checkpoint_errors() {
+    let folder = std::env::temp_dir().join("grownet_test_checkpoint_errors");
+    let _ = std::fs::remove_dir_all(&folder);
+    let mut manager = CheckpointManager::new(folder.clone(), 1).unwrap();
+    std::fs::remove_dir_all(&folder).unwrap();
+    // the folder was removed from under the manager, so nothing can be written or listed
+    let err = manager.save_world(&World::new(), 0).unwrap_err();
+    assert!(format!("{:#}", err).contains("failed to create checkpoint"));
+    assert!(manager.record_metric(&folder.join("ckpt-1-0.ckpt"), 0.5, false).is_err());
+    assert!(manager.remove_old_checkpoints().is_err());
+    // a file is in the way of the folder
+    std::fs::write(&folder, b"").unwrap();
+    assert!(CheckpointManager::new(folder.join("nested"), 1).is_err());
+    std::fs::remove_file(&folder).unwrap();
+}
+
+#[test]
+fn test_run_stats_serialize() {
+    let stats = RunStats { step_time: Some(0.25), device_mem_bytes: Some(3 << 30), host_mem_bytes: None, step: Some(10), total_steps: None };
+    let s = ron::to_string(&stats).unwrap();
+    let stats2: RunStats = ron::from_str(&s).unwrap();
+    assert_eq!(stats, stats2);
+
+    let stats = RunStats { device_mem_bytes: Some(RunStats::device_mem()), ..Default::default() };
+    let s = ron::to_string(&stats).unwrap();
+    assert_eq!(stats, ron::from_str(&s).unwrap());
+
+    let stats = RunStats { host_mem_bytes: RunStats::host_mem(), ..Default::default() };
+    assert!(stats.host_mem_bytes.unwrap() > 0);
+    let s = ron::to_string(&stats).unwrap();
+    assert_eq!(stats, ron::from_str(&s).unwrap());
+}
+
+#[test]
+fn test_event_log() {
+    let folder = std::env::temp_dir().join("grownet_test_event_log");
+    let _ = std::fs::remove_dir_all(&folder);
+    let path = folder.join("run").join("baseline-v0.jsonl");
+    let plot = |x: f64, y: f64| TrainRecv::PLOT(PlotPoint { title: "train loss", x_title: "step", y_title: "loss", x, y });
+    
+    let mut log = EventLog::new(path.clone()).unwrap();
+    log.log(&plot(1.0, 0.5)).unwrap();
+    log.log(&TrainRecv::STATS(RunStats { step_time: Some(0.25), ..Default::default() })).unwrap();
+    // only PLOT and STATS events are written
+    log.log(&TrainRecv::FAILED("error".into())).unwrap();
+    log.log(&TrainRecv::MARKER { title: "train loss", x: 1.0 }).unwrap();
+    log.finish().unwrap();
+
+    // a reopened log appends
+    let mut log = EventLog::new(path.clone()).unwrap();
+    log.log(&plot(2.0, 0.25)).unwrap();
+    log.finish().unwrap();
+
+    let expected_plot = |x: f64, y: f64| LoggedEvent::Plot { 
+        title: "train loss".into(), x_title: "step".into(), y_title: "loss".into(), x, y 
+    };
+    assert_eq!(EventLog::read(&path).unwrap(), vec![
+        expected_plot(1.0, 0.5),
+        LoggedEvent::Stats(RunStats { step_time: Some(0.25), ..Default::default() }),
+        expected_plot(2.0, 0.25),
+    ]);
+    let first = std::fs::read_to_string(&path).unwrap().lines().next().unwrap().to_string();
+    assert_eq!(first, r#"{"event":"Plot","title":"train loss","x_title":"step","y_title":"loss","x":1.0,"y":0.5}"#);
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_confusion_matrix() {
+    let mut matrix = ConfusionMatrix::new(3);
+    matrix.add_batch(&[0, 0, 0, 1, 1, 2, 2, 2], &[0, 0, 1, 1, 2, 2, 2, 2]);
+    matrix.add(0, 2);
+    assert_eq!(matrix.total(), 9);
+    let expected = [[2, 1, 1], [0, 1, 1], [0, 0, 3]];
+    for (i, row) in expected.iter().enumerate() {
+        for (j, &c) in row.iter().enumerate() {
+            assert_eq!(matrix.get(i, j), c, "entry ({}, {})", i, j);
+        }
+    }
+    assert_eq!(matrix.row_normalized(), vec![vec![0.5, 0.25, 0.25], vec![0.0, 0.5, 0.5], vec![0.0, 0.0, 1.0]]);
+
+    // a class without samples has a zero row
+    let mut matrix = ConfusionMatrix::new(2);
+    matrix.add(0, 1);
+    assert_eq!(matrix.row_normalized(), vec![vec![0.0, 1.0], vec![0.0, 0.0]]);
+
+    let s = serde_json::to_string(&LoggedEvent::Confusion(matrix.clone())).unwrap();
+    assert_eq!(serde_json::from_str::<LoggedEvent>(&s).unwrap(), LoggedEvent::Confusion(matrix));
+}
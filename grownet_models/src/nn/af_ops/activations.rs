@@ -19,6 +19,49 @@ pub fn relu<T: Float>(a: &Array<T>) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>
     (y, back_fn)
 }
 
+pub fn leaky_relu<T: Float>(a: &Array<T>, negative_slope: T) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>) {
+    let gate = af::ge(a, &T::zero(), true);
+    // slope is 1 where the input is positive, negative_slope elsewhere
+    let slope = af::select(
+        &af::constant(T::one(), a.dims()), &gate, &af::constant(negative_slope, a.dims()));
+    let y = af::mul(a, &slope, false);
+
+    let back_fn = move |grad: &Array<T>| {
+        af::mul(grad, &slope, false)
+    };
+
+    (y, back_fn)
+}
+
+/// Gaussian error linear unit, using the tanh approximation
+/// 0.5 * x * (1 + tanh(sqrt(2 / pi) * (x + 0.044715 * x^3))) instead of the exact erf form.
+pub fn gelu<T: Float>(a: &Array<T>) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>) {
+    let scalar = |v: f64| af::constant(T::from(v).unwrap(), dim4!(1));
+    let c = scalar((2.0 / std::f64::consts::PI).sqrt());
+    let k = scalar(0.044715);
+    let half = scalar(0.5);
+    let one = scalar(1.0);
+
+    let x2 = af::mul(a, a, false);
+    let inner = af::mul(&c, &af::add(a, &af::mul(&k, &af::mul(&x2, a, false), true), false), true);
+    let t = af::tanh(&inner);
+    let y = af::mul(&af::mul(&half, a, true), &af::add(&one, &t, true), false);
+
+    let a = a.clone();
+    let back_fn = move |grad: &Array<T>| {
+        // d/dx = 0.5 * (1 + t) + 0.5 * x * (1 - t^2) * c * (1 + 3k * x^2)
+        let dt = af::sub(&one, &af::mul(&t, &t, false), true);
+        let dinner = af::mul(&c, &af::add(&one, &af::mul(&af::mul(&scalar(3.0), &k, false), &x2, true), true), true);
+        let dx = af::add(
+            &af::mul(&half, &af::add(&one, &t, true), true),
+            &af::mul(&af::mul(&half, &a, true), &af::mul(&dt, &dinner, false), false),
+            false);
+        af::mul(grad, &dx, false)
+    };
+
+    (y, back_fn)
+}
+
 pub fn softmax<T: Float>(a: &Array<T>) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>) {
     let a: &Array<T> = &*a;
     let shifted = af::sub(a, &af::max(a, 0), true);
@@ -78,4 +121,38 @@ mod test {
         let x = randn::<f64>(dim4!(CHECKDIM));
         af_grad_check(x, None, None, None, relu);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn grad_check_leaky_relu() {
+        set_backend(Backend::CPU);
+        let x = randn::<f64>(dim4!(CHECKDIM));
+        af_grad_check(x, None, None, None, |x| leaky_relu(x, 0.1));
+    }
+
+    #[test]
+    fn grad_check_gelu() {
+        set_backend(Backend::CPU);
+        let x = randn::<f64>(dim4!(CHECKDIM));
+        af_grad_check(x, None, None, None, gelu);
+    }
+
+    #[test]
+    fn test_activation_values() {
+        set_backend(Backend::CPU);
+        let x = Array::new(&[-2.0f64, -0.5, 0.0, 1.0, 3.0], dim4!(5));
+        let mut out = [0.0f64; 5];
+
+        leaky_relu(&x, 0.1).0.host(&mut out);
+        let expected = [-0.2, -0.05, 0.0, 1.0, 3.0];
+        for (a, b) in out.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+        }
+
+        gelu(&x).0.host(&mut out);
+        // reference values of the tanh approximation
+        let expected = [-0.04540231, -0.15428599, 0.0, 0.84119199, 2.99636261];
+        for (a, b) in out.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use arrayfire::*;
+use super::Float;
+use crate::Flatten;
+
+#[derive(Clone, Flatten)]
+pub struct AvgPool2D {
+    kernel_size: [u64; 2],
+    stride: [u64; 2]
+}
+
+impl AvgPool2D {
+    pub fn new(kernel_size: [u64; 2], stride: [u64; 2]) -> Self {
+        AvgPool2D {
+            kernel_size, stride
+        }
+    }
+
+    /// The shape of the output given an input of shape [w, h, c, n], without computing anything
+    pub fn output_shape(&self, input_shape: Dim4) -> Dim4 {
+        let output_height = (input_shape[0] - self.kernel_size[0]) / self.stride[0] + 1;
+        let output_width = (input_shape[1] - self.kernel_size[1]) / self.stride[1] + 1;
+        dim4!(output_height, output_width, input_shape[2], input_shape[3])
+    }
+
+    pub fn forward<T: Float>(&self, x: &Array<T>) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>) {
+        assert!(x.dims()[0] >= self.kernel_size[0] && x.dims()[1] >= self.kernel_size[1], 
+            "image size {}x{} too small for filter {}x{}", x.dims()[0], x.dims()[1], self.kernel_size[0], self.kernel_size[1]);
+        let [kx, ky] = self.kernel_size;
+        let [sx, sy] = self.stride;
+        // [kx * ky, windows, c, n]
+        let cols = unwrap(x, kx as i64, ky as i64, sx as i64, sy as i64, 0, 0, true);
+        let output = moddims(&mean(&cols, 0), self.output_shape(x.dims()));
+
+        let cols_dims = cols.dims();
+        let input_shape = x.dims();
+        let back_fn = move |grad: &Array<T>| {
+            let window = T::from(kx * ky).unwrap();
+            let grad = moddims(grad, dim4!(1, cols_dims[1], cols_dims[2], cols_dims[3]));
+            // every element of a window receives an equal share of its gradient
+            let dcols = div(&tile(&grad, dim4!(kx * ky)), &window, true);
+            wrap(&dcols, input_shape[0] as i64, input_shape[1] as i64, kx as i64, ky as i64, sx as i64, sy as i64, 0, 0, true)
+        };
+
+        (output, back_fn)
+    }
+}
+
+/// Averages over the spatial dims, so an input of shape [w, h, c, n] is reduced to [1, 1, c, n]
+pub fn global_avg_pool<T: Float>(x: &Array<T>) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>) {
+    let dims = x.dims();
+    let output = mean(&mean(x, 0), 1);
+    let back_fn = move |grad: &Array<T>| {
+        let window = T::from(dims[0] * dims[1]).unwrap();
+        div(&tile(grad, dim4!(dims[0], dims[1])), &window, true)
+    };
+    (output, back_fn)
+}
+
+#[test]
+fn test_avgpool() {
+    set_backend(Backend::CPU);
+    let data: Vec<f32> = (0..16).map(|x| x as f32).collect();
+    let x = Array::new(&data, dim4!(4, 4));
+    let pool = AvgPool2D::new([2, 2], [2, 2]);
+    let (y, df) = pool.forward(&x);
+    assert_eq!(y.dims(), dim4!(2, 2, 1, 1));
+    assert_eq!(y.dims(), pool.output_shape(x.dims()));
+    let mut out = [0.0f32; 4];
+    y.host(&mut out);
+    assert_eq!(out, [2.5, 4.5, 10.5, 12.5]);
+
+    let dx = df(&constant(1.0f32, y.dims()));
+    assert_eq!(dx.dims(), x.dims());
+    let mut grad = [0.0f32; 16];
+    dx.host(&mut grad);
+    assert!(grad.iter().all(|&g| g == 0.25));
+
+    let (y, df) = global_avg_pool(&x);
+    assert_eq!(y.dims(), dim4!(1, 1, 1, 1));
+    let mut out = [0.0f32];
+    y.host(&mut out);
+    assert_eq!(out, [7.5]);
+    let dx = df(&constant(1.0f32, y.dims()));
+    dx.host(&mut grad);
+    assert!(grad.iter().all(|&g| g == 1.0 / 16.0));
+}
+
+#[test]
+fn gradcheck_avgpool() {
+    set_backend(Backend::CPU);
+    use super::utils::af_grad_check;
+    let x = randn::<f64>(dim4!(6, 6, 2, 1));
+    let pool = AvgPool2D::new([3, 3], [1, 1]);
+    af_grad_check(x, None, None, None, |x| pool.forward(x));
+
+    let x = randn::<f64>(dim4!(4, 4, 2, 2));
+    af_grad_check(x, None, None, None, global_avg_pool);
+}
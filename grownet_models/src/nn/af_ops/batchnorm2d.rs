@@ -1,7 +1,8 @@
 use std::rc::Rc;
 use arrayfire::*;
 use arrayfire as af;
-use super::{Float, Param, utils::{ones, zeros}};
+use super::{Buffer, Float, ModeFlag, Param, Trainable, utils::{ones, zeros}};
+use crate::{Flatten, World};
 
 fn af_batchnorm2d_forward<T: Float>(
     input: &Array<T>, 
@@ -92,47 +93,124 @@ fn nan_check<T: Float>(a: &Array<T>, msg: &str) {
     }
 }
 
+/// When set, the batchnorm layer normalizes with its stored running statistics and does not
+/// update them, even during training. Exposed through Flatten so that all norm layers of a model
+/// can be addressed at once, see `freeze_batchnorm`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FreezeStats(pub bool);
+
+impl Flatten for FreezeStats {
+    fn flatten<'a>(&'a mut self, path: String, world: &mut World<'a>) {
+        world.push(path, self);
+    }
+}
+
+/// Sets whether every batchnorm layer in the world uses frozen running statistics
+pub fn freeze_batchnorm(world: &mut World, freeze: bool) {
+    for flag in world.query_mut::<FreezeStats>() {
+        flag.0 = freeze;
+    }
+}
+
+/// Normalizes each channel of a [w, h, c, n] input. In training mode the batch statistics are used and
+/// folded into the running statistics, in eval mode (or when frozen) the running statistics are used
+/// and left untouched. The running statistics are buffers, so they are checkpointed but not optimized.
 pub struct BatchNorm2D<T: Float> {
-    mean: Array<T>,
-    variance: Array<T>,
+    running_mean: Buffer<T>,
+    running_var: Buffer<T>,
     gamma: Param<T>,
     beta: Param<T>,
+    /// the fraction of the running statistics kept on each update
     momentum: T,
     eps: T,
+    training: ModeFlag,
+    frozen: FreezeStats,
+}
+
+impl<T: Float> Flatten for BatchNorm2D<T> {
+    fn flatten<'a>(&'a mut self, path: String, world: &mut World<'a>) {
+        self.gamma.flatten(path.clone() + "/gamma", world);
+        self.beta.flatten(path.clone() + "/beta", world);
+        self.running_mean.flatten(path.clone() + "/running_mean", world);
+        self.running_var.flatten(path.clone() + "/running_var", world);
+        self.training.flatten(path.clone() + "/training", world);
+        self.frozen.flatten(path + "/frozen", world);
+    }
+}
+
+impl<T: Float> Trainable for BatchNorm2D<T> {
+    fn mode_flag(&self) -> &ModeFlag {
+        &self.training
+    }
+
+    fn mode_flag_mut(&mut self) -> &mut ModeFlag {
+        &mut self.training
+    }
 }
 
 impl<T: Float> BatchNorm2D<T> {
     pub fn new(channels: u64) -> Self {
         BatchNorm2D { 
-            mean: zeros(dim4!(1, 1, channels, 1)), 
-            variance: ones(dim4!(1, 1, channels, 1)), 
+            running_mean: Buffer(zeros(dim4!(1, 1, channels, 1))), 
+            running_var: Buffer(ones(dim4!(1, 1, channels, 1))), 
             gamma: Param::new(ones(dim4!(1, 1, channels, 1))), 
             beta: Param::new(zeros(dim4!(1, 1, channels, 1))),
             momentum: T::from(0.99).unwrap(),
-            eps: T::from(1e-5).unwrap()
+            eps: T::from(1e-5).unwrap(),
+            training: ModeFlag(true),
+            frozen: FreezeStats(false),
         }
     }
 
+    pub fn with_momentum(mut self, momentum: T) -> Self {
+        assert!(momentum >= T::zero() && momentum <= T::one(), "momentum must be in [0, 1]");
+        self.momentum = momentum;
+        self
+    }
+
+    pub fn running_mean(&self) -> &Array<T> {
+        &self.running_mean.0
+    }
+
+    pub fn running_var(&self) -> &Array<T> {
+        &self.running_var.0
+    }
+
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen.0 = frozen;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.0
+    }
+
     pub fn forward(&mut self, input: &Array<T>) -> (Array<T>, impl FnMut(&mut Self, &Array<T>) -> Array<T>) {
         let input: &Array<T> = &*input;
-        let mut flat = reorder_v2(&input, 0, 1, Some(vec![3, 2]));
-        flat = moddims(&flat, Dim4::new(&[flat.elements() as u64 / flat.dims().get()[3], flat.dims().get()[3], 1, 1]));
-        let mean = mean(&flat, 0);
-        let var = var_v2(&flat, VarianceBias::POPULATION, 0);
-        //self.mb_mean = reorder(&mean, Dim4::new(&[0, 2, 1, 3]));
-        let mb_mean = reorder_v2(&mean, 0, 2, Some(vec![1, 3]));
-        //self.mb_variance = reorder(&var, Dim4::new(&[0, 2, 1, 3]));
-        let mb_variance = reorder_v2(&var, 0, 2, Some(vec![1, 3]));
-        mb_mean.eval();
-        mb_variance.eval();
-
-        // Update the training set mean and variance using running averages
-        let vt: T = T::from(1.0).unwrap().sub(self.momentum);
-        let pt: T = self.momentum.into();
-        self.mean = mul(&pt, &self.mean, false) + &mb_mean * vt;
-        self.variance = mul(&pt, &self.variance, false) + &mb_variance * vt;
-        self.mean.eval();
-        self.variance.eval();
+        let frozen = self.frozen.0 || !self.training.0;
+        let (mb_mean, mb_variance) = if frozen {
+            // use the stored running statistics, which are left untouched
+            (self.running_mean.0.clone(), self.running_var.0.clone())
+        } else {
+            let mut flat = reorder_v2(&input, 0, 1, Some(vec![3, 2]));
+            flat = moddims(&flat, Dim4::new(&[flat.elements() as u64 / flat.dims().get()[3], flat.dims().get()[3], 1, 1]));
+            let mean = mean(&flat, 0);
+            let var = var_v2(&flat, VarianceBias::POPULATION, 0);
+            //self.mb_mean = reorder(&mean, Dim4::new(&[0, 2, 1, 3]));
+            let mb_mean = reorder_v2(&mean, 0, 2, Some(vec![1, 3]));
+            //self.mb_variance = reorder(&var, Dim4::new(&[0, 2, 1, 3]));
+            let mb_variance = reorder_v2(&var, 0, 2, Some(vec![1, 3]));
+            mb_mean.eval();
+            mb_variance.eval();
+
+            // Update the training set mean and variance using running averages
+            let vt: T = T::from(1.0).unwrap().sub(self.momentum);
+            let pt: T = self.momentum.into();
+            self.running_mean.0 = mul(&pt, &self.running_mean.0, false) + &mb_mean * vt;
+            self.running_var.0 = mul(&pt, &self.running_var.0, false) + &mb_variance * vt;
+            self.running_mean.0.eval();
+            self.running_var.0.eval();
+            (mb_mean, mb_variance)
+        };
 
         let eps: T = self.eps.into();
         let normalized_input = div(&sub(input, &mb_mean, true), &sqrt(&add(&mb_variance, &eps, true)), true);
@@ -150,6 +228,12 @@ impl<T: Float> BatchNorm2D<T> {
             s.gamma.g += dgamma;
             s.beta.g += dbeta;
 
+            if frozen {
+                // the statistics are constants, so the layer is affine in the input
+                let inv_sd = div(&T::one(), &sqrt(&add(&*mb_variance, &s.eps, true)), true);
+                return mul(dz, &mul(&s.gamma.w, &inv_sd, true), true);
+            }
+
             // Compute the derivative of the loss wrt the variance
             // c1 corresponds to: input - mb_mean
             let c1 = mul(&*normalized_input, &sqrt(&add(&*mb_variance, &s.eps, true)), true);
@@ -167,7 +251,7 @@ impl<T: Float> BatchNorm2D<T> {
             let dnormalized_input = mul(dz, &s.gamma.w, true);
     
             // Compute and return the derivative of the loss wrt the input
-            let term1 = mul(&dnormalized_input, &div(&T::one(), &sqrt(&s.variance), true), true);
+            let term1 = mul(&dnormalized_input, &div(&T::one(), &sqrt(&s.running_var.0), true), true);
             let m = T::from(normalized_input.dims()[3] as f32).unwrap();
             let term2 = mul(&dmb_variance, &mul(&T::from(2.0).unwrap().div(m), &c1, true), true);
             let term3 = div(&dmb_mean, &m, true);
@@ -225,3 +309,72 @@ fn gradcheck_batchnorm2d() {
     af_grad_check(input, None, None, None, test_dinput);    
 
 }
+
+#[test]
+fn test_freeze_batchnorm() {
+    set_backend(Backend::CPU);
+    let mut batchnorm = BatchNorm2D::<f64>::new(4);
+    let input = randn::<f64>(dim4!(3, 3, 4, 2));
+    // a training step to move the running stats away from their initial values
+    let (_, _) = batchnorm.forward(&input);
+
+    freeze_batchnorm(&mut World::from(&mut batchnorm), true);
+    assert!(batchnorm.is_frozen());
+    let mut mean = vec![0.0; 4];
+    let mut variance = vec![0.0; 4];
+    batchnorm.running_mean().host(&mut mean);
+    batchnorm.running_var().host(&mut variance);
+
+    let (x, mut f) = batchnorm.forward(&input);
+    f(&mut batchnorm, &constant(1.0, x.dims()));
+
+    let mut new_mean = vec![0.0; 4];
+    let mut new_variance = vec![0.0; 4];
+    batchnorm.running_mean().host(&mut new_mean);
+    batchnorm.running_var().host(&mut new_variance);
+    assert_eq!(mean, new_mean);
+    assert_eq!(variance, new_variance);
+}
+
+#[test]
+fn test_running_stats() {
+    set_backend(Backend::CPU);
+    let mut batchnorm = BatchNorm2D::<f64>::new(2).with_momentum(0.9);
+    let input = randn::<f64>(dim4!(4, 4, 2, 3));
+    let flat = moddims(&reorder_v2(&input, 0, 1, Some(vec![3, 2])), dim4!(48, 2));
+    let mut batch_mean = vec![0.0; 2];
+    let mut batch_var = vec![0.0; 2];
+    af::mean(&flat, 0).host(&mut batch_mean);
+    var_v2(&flat, VarianceBias::POPULATION, 0).host(&mut batch_var);
+
+    for _ in 0..3 {
+        let _ = batchnorm.forward(&input);
+    }
+    // starting from a mean of 0 and variance of 1, each update keeps 0.9 of the previous value
+    let kept = 0.9f64.powi(3);
+    let mut mean = vec![0.0; 2];
+    let mut variance = vec![0.0; 2];
+    batchnorm.running_mean().host(&mut mean);
+    batchnorm.running_var().host(&mut variance);
+    for c in 0..2 {
+        assert!((mean[c] - (1.0 - kept) * batch_mean[c]).abs() < 1e-10);
+        assert!((variance[c] - (kept + (1.0 - kept) * batch_var[c])).abs() < 1e-10);
+    }
+
+    // running stats are exposed to the world as buffers, not params
+    let mut world = World::from(&mut batchnorm);
+    assert_eq!(world.query_mut::<Buffer<f64>>().count(), 2);
+    assert_eq!(world.query_mut::<Param<f64>>().count(), 2);
+
+    batchnorm.set_training(false);
+    let other = randn::<f64>(dim4!(4, 4, 2, 3));
+    let (y1, _) = batchnorm.forward(&other);
+    let (y2, _) = batchnorm.forward(&other);
+    assert_eq!(count_all(&neq(&y1, &y2, false)).0 as usize, 0);
+    let expected = div(&sub(&other, batchnorm.running_mean(), true), &sqrt(&add(batchnorm.running_var(), &1e-5, true)), true);
+    assert!(max_all(&abs(&(y1 - expected))).0 < 1e-10);
+
+    let mut eval_mean = vec![0.0; 2];
+    batchnorm.running_mean().host(&mut eval_mean);
+    assert_eq!(mean, eval_mean);
+}
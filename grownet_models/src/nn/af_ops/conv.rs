@@ -11,52 +11,85 @@ pub struct Conv2d<T: Float> {
     bias: Option<Param<T>>,
     stride: [u64; 2],
     pad: [u64; 2],
+    dilation: [u64; 2],
+    groups: u64,
 }
 
 impl<T: Float> Conv2d<T> {
+    /// in_chan and out_chan must both be divisible by groups, each group of out_chan / groups output channels
+    /// only sees its own in_chan / groups input channels. groups == in_chan gives a depthwise convolution.
     pub fn new(
         in_chan: u64, 
         out_chan: u64, 
         kernel_size: [u64; 2], 
         stride: [u64; 2],
         padding: [u64; 2],
+        dilation: [u64; 2],
+        groups: u64,
         bias: bool,
+        weight_init: init::Init,
     ) -> Self {
+        assert!(groups > 0 && in_chan % groups == 0 && out_chan % groups == 0, 
+            "in_chan {} and out_chan {} must be divisible by groups {}", in_chan, out_chan, groups);
         let receptive_field = kernel_size[0] * kernel_size[1];
-        let fan_in = receptive_field * in_chan;
-        let fan_out = receptive_field * out_chan;
+        let fan_in = receptive_field * in_chan / groups;
+        let fan_out = receptive_field * out_chan / groups;
         let bias = if bias {
             Some(Param::new(init::Initializer::Zeros.init(dim4!(1, 1, out_chan, 1), fan_in, fan_out)))
         } else {
             None
         };
         Conv2d { 
-            filter: Param::new(init::Initializer::HeNormal.init(dim4!(kernel_size[1], kernel_size[0], in_chan, out_chan), fan_in, fan_out)), 
+            filter: Param::new(weight_init.init(dim4!(kernel_size[1], kernel_size[0], in_chan / groups, out_chan), fan_in, fan_out)), 
             bias, 
             stride: stride, 
-            pad: padding }
+            pad: padding,
+            dilation,
+            groups }
+    }
+
+    /// The shape of the output given an input of shape [w, h, c, n], without computing anything
+    pub fn output_shape(&self, input: Dim4) -> Dim4 {
+        let f = self.filter.dims();
+        // a dilated kernel covers dilation * (k - 1) + 1 pixels
+        let fw = self.dilation[1] * (f[0] - 1) + 1;
+        let fh = self.dilation[0] * (f[1] - 1) + 1;
+        let w = (input[0] + 2 * self.pad[1] - fw) / self.stride[1] + 1;
+        let h = (input[1] + 2 * self.pad[0] - fh) / self.stride[0] + 1;
+        dim4!(w, h, f[3], input[3])
     }
 
     pub fn forward(&self, x: &Array<T>) -> (Array<T>, impl Fn(&mut Self, &Array<T>) -> Array<T>) {
-        let y = af::convolve2_nn(&x, &self.filter.w, 
-            dim4!(self.stride[1], self.stride[0]), dim4!(self.pad[1], self.pad[0]), dim4!(1));
+        let in_group = x.dims()[2] / self.groups;
+        let out_group = self.filter.dims()[3] / self.groups;
+        // each group is convolved separately, with its outputs concatenated along the channel dim
+        let xs: Vec<_> = (0..self.groups).map(|g| channels(x, 2, g, in_group)).collect();
+        let ys: Vec<_> = xs.iter().enumerate().map(|(g, x)| {
+            af::convolve2_nn(x, &channels(&self.filter.w, 3, g as u64, out_group), 
+                dim4!(self.stride[1], self.stride[0]), dim4!(self.pad[1], self.pad[0]), dim4!(self.dilation[1], self.dilation[0]))
+        }).collect();
+        let y = concat(2, &ys);
         
         let y = if let Some(x) = &self.bias {
             y + &x.w  
         } else {
             y
         };
-        let y = y;
-        let y1 = y.clone();
-        let x1 = x.clone();
         let back_fn = move |s: &mut Conv2d<T>, grad: &Array<T>| {
-            let dx = af::convolve2_gradient_nn(
-                grad, &x1, &s.filter.w, &y1, 
-                dim4!(s.stride[1], s.stride[0]), dim4!(s.pad[1], s.pad[0]), dim4!(1), af::ConvGradientType::DATA);
-            let dw = af::convolve2_gradient_nn(
-                grad, &x1, &s.filter.w, &y1, 
-                dim4!(s.stride[1], s.stride[0]), dim4!(s.pad[1], s.pad[0]), dim4!(1), af::ConvGradientType::FILTER);
-            s.filter.g += dw;
+            let stride = dim4!(s.stride[1], s.stride[0]);
+            let pad = dim4!(s.pad[1], s.pad[0]);
+            let dilation = dim4!(s.dilation[1], s.dilation[0]);
+            let mut dxs = Vec::with_capacity(xs.len());
+            let mut dws = Vec::with_capacity(xs.len());
+            for (g, (x, y)) in xs.iter().zip(&ys).enumerate() {
+                let grad = channels(grad, 2, g as u64, out_group);
+                let w = channels(&s.filter.w, 3, g as u64, out_group);
+                dxs.push(af::convolve2_gradient_nn(
+                    &grad, x, &w, y, stride, pad, dilation, af::ConvGradientType::DATA));
+                dws.push(af::convolve2_gradient_nn(
+                    &grad, x, &w, y, stride, pad, dilation, af::ConvGradientType::FILTER));
+            }
+            s.filter.g += concat(3, &dws);
             if let Some(b) = &mut s.bias {
                 let reordered = af::reorder_v2(&grad, 0, 1, Some(vec![3, 2]));
                 let dim = reordered.dims();
@@ -64,14 +97,15 @@ impl<T: Float> Conv2d<T> {
                 let db = af::sum(&flattened, 0);
                 b.g += db;
             }
-            dx
+            concat(2, &dxs)
         };
         (y, back_fn)
     }
 
     pub fn forward2(&self, x: &Array<T>) -> Array<T> {
+        assert_eq!(self.groups, 1, "forward2 does not support grouped convolutions");
         let y = af::convolve2_nn(&x, &self.filter.w, 
-            dim4!(self.stride[1], self.stride[0]), dim4!(self.pad[1], self.pad[0]), dim4!(1));
+            dim4!(self.stride[1], self.stride[0]), dim4!(self.pad[1], self.pad[0]), dim4!(self.dilation[1], self.dilation[0]));
         
         // let y = if let Some(x) = &self.bias {
         //     y + &x.w  
@@ -102,6 +136,105 @@ impl<T: Float> Conv2d<T> {
     }
 }
 
+/// the ith group of n entries along dim
+fn channels<T: Float>(x: &Array<T>, dim: usize, i: u64, n: u64) -> Array<T> {
+    if x.dims()[dim] == n {
+        return x.clone();
+    }
+    let mut seqs = [af::Seq::<f64>::default(); 4];
+    seqs[dim] = af::Seq::new((i * n) as f64, ((i + 1) * n - 1) as f64, 1.0);
+    af::index(x, &seqs)
+}
+
+fn concat<T: Float>(dim: i32, xs: &[Array<T>]) -> Array<T> {
+    xs[1..].iter().fold(xs[0].clone(), |acc, x| af::join(dim, &acc, x))
+}
+
+#[test]
+fn test_dilated_conv2d() {
+    use af::*;
+    set_backend(Backend::CPU);
+    let x = randn::<f64>(dim4!(9, 9, 1, 2));
+    let w = randn::<f64>(dim4!(3, 3, 1, 1));
+    let mut conv = Conv2d::<f64>::new(1, 1, [3, 3], [1, 1], [0, 0], [2, 2], 1, false, init::Init::default());
+    conv.filter.w = w.clone();
+    let (y, _) = conv.forward(&x);
+    // a 3x3 filter with dilation 2 covers a 5x5 window
+    assert_eq!(y.dims(), dim4!(5, 5, 1, 2));
+    assert_eq!(y.dims(), conv.output_shape(x.dims()));
+
+    // reference: the equivalent 5x5 filter with zeros between the taps
+    let mut taps = [0.0f64; 9];
+    w.host(&mut taps);
+    let mut dilated = [0.0f64; 25];
+    for i in 0..3 {
+        for j in 0..3 {
+            dilated[2 * i + 10 * j] = taps[i + 3 * j];
+        }
+    }
+    let dilated = Array::new(&dilated, dim4!(5, 5, 1, 1));
+    let expected = convolve2_nn(&x, &dilated, dim4!(1, 1), dim4!(0, 0), dim4!(1, 1));
+    assert!(max_all(&abs(&(y - expected))).0 < 1e-10);
+}
+
+#[test]
+fn test_depthwise_conv2d() {
+    use af::*;
+    set_backend(Backend::CPU);
+    let x = randn::<f64>(dim4!(8, 8, 3, 2));
+    let w = randn::<f64>(dim4!(3, 3, 1, 3));
+    let mut conv = Conv2d::<f64>::new(3, 3, [3, 3], [1, 1], [1, 1], [1, 1], 3, false, init::Init::default());
+    assert_eq!(conv.filter.dims(), dim4!(3, 3, 1, 3));
+    conv.filter.w = w.clone();
+    let (y, df) = conv.forward(&x);
+    assert_eq!(y.dims(), dim4!(8, 8, 3, 2));
+
+    // reference: a dense convolution whose filter is zero between different channels
+    let mut taps = [0.0f64; 27];
+    w.host(&mut taps);
+    let mut dense = [0.0f64; 81];
+    for c in 0..3 {
+        dense[c * 9 + c * 27..c * 9 + c * 27 + 9].copy_from_slice(&taps[c * 9..(c + 1) * 9]);
+    }
+    let dense = Array::new(&dense, dim4!(3, 3, 3, 3));
+    let expected = convolve2_nn(&x, &dense, dim4!(1, 1), dim4!(1, 1), dim4!(1, 1));
+    assert!(max_all(&abs(&(&y - expected))).0 < 1e-10);
+
+    let dx = df(&mut conv, &y);
+    assert_eq!(dx.dims(), x.dims());
+    assert_eq!(conv.filter.g.dims(), w.dims());
+}
+
+#[test]
+fn gradcheck_grouped_conv2d() {
+    use super::utils::af_grad_check;
+    use af::*;
+    set_backend(Backend::CPU);
+    let x = randn::<f64>(dim4!(7, 7, 4, 1));
+    let w = randn::<f64>(dim4!(3, 3, 2, 6));
+    let conv = |x: &Array<f64>| {
+        let mut conv = Conv2d::<f64>::new(4, 6, [3, 3], [1, 1], [2, 2], [2, 2], 2, false, init::Init::default());
+        conv.filter.w = w.clone();
+        let (y, df) = conv.forward(x);
+        (y, move |grad: &Array<f64>| df(&mut conv, grad))
+    };
+    af_grad_check(x, None, None, None, conv);
+
+    // filter gradients of each group land in their own slice of the filter
+    let x = randn::<f64>(dim4!(7, 7, 4, 1));
+    let conv = |w: &Array<f64>| {
+        let mut conv = Conv2d::<f64>::new(4, 6, [3, 3], [1, 1], [1, 1], [1, 1], 2, false, init::Init::default());
+        conv.filter.w = w.clone();
+        let (y, df) = conv.forward(&x);
+        (y, move |grad: &Array<f64>| {
+            conv.filter.g = constant(0.0, conv.filter.dims());
+            df(&mut conv, grad);
+            conv.filter.g.clone()
+        })
+    };
+    af_grad_check(w, None, None, None, conv);
+}
+
 #[test]
 fn gradcheck_conv2d() {
     use super::utils::af_grad_check;
@@ -0,0 +1,94 @@
+use arrayfire::*;
+use super::{Float, ModeFlag, Trainable};
+use crate::Flatten;
+
+/// Zeros each activation with probability p during training, scaling the rest by 1 / (1 - p) so that
+/// the expected value is unchanged. In eval mode, the input is passed through as is.
+#[derive(Flatten)]
+pub struct Dropout<T: Float> {
+    #[flat(exclude)]
+    pub p: T,
+    pub training: ModeFlag,
+}
+
+impl<T: Float> Dropout<T> {
+    pub fn new(p: T) -> Self {
+        assert!(p >= T::zero() && p < T::one(), "dropout rate must be in [0, 1)");
+        Self { p, training: ModeFlag(true) }
+    }
+
+    pub fn forward(&self, x: &Array<T>) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>) {
+        let mask = if self.training.0 {
+            let keep = ge(&randu::<T>(x.dims()), &constant(self.p, x.dims()), false);
+            Some(keep.cast::<T>() / (T::one() - self.p))
+        } else {
+            None
+        };
+        let y = match &mask {
+            Some(mask) => x * mask,
+            None => x.clone(),
+        };
+        // the backward pass reuses the mask of the forward pass
+        let df = move |grad: &Array<T>| {
+            match &mask {
+                Some(mask) => grad * mask,
+                None => grad.clone(),
+            }
+        };
+        (y, df)
+    }
+}
+
+impl<T: Float> Trainable for Dropout<T> {
+    fn mode_flag(&self) -> &ModeFlag {
+        &self.training
+    }
+
+    fn mode_flag_mut(&mut self) -> &mut ModeFlag {
+        &mut self.training
+    }
+}
+
+#[test]
+fn test_dropout() {
+    set_backend(Backend::CPU);
+    let x = constant(1.0f32, dim4!(1000, 100));
+    let dropout = Dropout::new(0.3f32);
+    let (y, df) = dropout.forward(&x);
+    // kept activations are scaled by 1 / (1 - p), so the mean is preserved
+    let mean = mean_all(&y).0;
+    assert!((mean - 1.0).abs() < 0.02, "mean {}", mean);
+    let dropped = count_all(&eq(&y, &constant(0.0f32, y.dims()), false)).0 as f64 / y.elements() as f64;
+    assert!((dropped - 0.3).abs() < 0.02, "dropped {}", dropped);
+    let kept = sum_all(&y).0 / (y.elements() as f32 - count_all(&eq(&y, &constant(0.0f32, y.dims()), false)).0 as f32);
+    assert!((kept - 1.0 / 0.7).abs() < 1e-4);
+    // the gradient is masked the same way
+    assert_eq!(count_all(&neq(&df(&x), &y, false)).0 as usize, 0);
+
+    let mut dropout = dropout;
+    dropout.set_training(false);
+    let (y, df) = dropout.forward(&x);
+    assert_eq!(count_all(&neq(&y, &x, false)).0 as usize, 0);
+    assert_eq!(count_all(&neq(&df(&x), &x, false)).0 as usize, 0);
+}
+
+#[test]
+fn test_dropout_world_mode() {
+    #[derive(Flatten)]
+    struct Model {
+        a: Dropout<f32>,
+        b: Dropout<f32>,
+    }
+    set_backend(Backend::CPU);
+    let x = constant(1.0f32, dim4!(100, 100));
+    let mut model = Model { a: Dropout::new(0.5), b: Dropout::new(0.5) };
+    crate::World::from(&mut model).set_training(false);
+    assert!(!model.a.is_training() && !model.b.is_training());
+    let (y, _) = model.a.forward(&x);
+    assert_eq!(count_all(&neq(&y, &x, false)).0 as usize, 0);
+
+    crate::World::from(&mut model).set_training(true);
+    assert!(model.a.is_training() && model.b.is_training());
+    let (y, _) = model.b.forward(&x);
+    assert!(count_all(&eq(&y, &constant(0.0f32, y.dims()), false)).0 > 0);
+}
@@ -0,0 +1,64 @@
+use arrayfire::*;
+
+use super::{Param, Float, init};
+use crate::Flatten;
+
+#[derive(Flatten)]
+pub struct Embedding<T: Float> {
+    w: Param<T>,
+}
+
+impl<T: Float> Embedding<T> {
+    /// each column of the weight of shape [dim, num_embeddings] is an embedding vector
+    pub fn new(num_embeddings: u64, dim: u64) -> Self {
+        Self { 
+            w: Param::new(init::Initializer::NormalScaled(T::zero(), T::one()).init(dim4!(dim, num_embeddings), num_embeddings, dim))
+        }
+    }
+
+    /// expect indices to be [L, ...] with at most 3 dims, outputs [dim, L, ...]
+    pub fn forward(&self, indices: &Array<u32>) -> (Array<T>, impl Fn(&mut Self, &Array<T>)) {
+        let idims = indices.dims();
+        assert_eq!(idims[3], 1, "expected indices with at most 3 dims, got {}", idims);
+        let flat_idx = flat(indices);
+        let rows = lookup(&self.w.w, &flat_idx, 1);
+        let y = moddims(&rows, dim4!(self.w.dims()[0], idims[0], idims[1], idims[2]));
+
+        let num_embeddings = self.w.dims()[1];
+        let back_fn = move |s: &mut Self, grad: &Array<T>| {
+            let n = flat_idx.elements() as u64;
+            let grad = moddims(grad, dim4!(grad.dims()[0], n));
+            // one_hot[i, j] = 1 if indices[j] == i, so that repeated indices sum their gradients
+            let ids = range::<u32>(dim4!(num_embeddings, n), 0);
+            let one_hot = eq(&ids, &transpose(&flat_idx, false), true).cast::<T>();
+            s.w.g += matmul(&grad, &one_hot, MatProp::NONE, MatProp::TRANS);
+        };
+        (y, back_fn)
+    }
+}
+
+#[test]
+fn test_embedding() {
+    set_backend(Backend::CPU);
+    let mut emb = Embedding::<f32>::new(5, 3);
+    let weight: Vec<f32> = (0..15).map(|x| x as f32).collect();
+    emb.w.w = Array::new(&weight, dim4!(3, 5));
+
+    let indices = Array::new(&[4u32, 1, 4, 0], dim4!(4));
+    let (y, df) = emb.forward(&indices);
+    assert_eq!(y.dims(), dim4!(3, 4));
+    let mut out = [0.0f32; 12];
+    y.host(&mut out);
+    assert_eq!(out, [12.0, 13.0, 14.0, 3.0, 4.0, 5.0, 12.0, 13.0, 14.0, 0.0, 1.0, 2.0]);
+
+    let grad = constant(1.0f32, y.dims());
+    df(&mut emb, &grad);
+    let mut g = [0.0f32; 15];
+    emb.w.g.host(&mut g);
+    // index 4 appears twice, 2 and 3 never appear
+    assert_eq!(g, [1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 2.0, 2.0]);
+
+    let indices = Array::new(&[0u32, 1, 2, 3, 4, 0], dim4!(3, 2));
+    let (y, _) = emb.forward(&indices);
+    assert_eq!(y.dims(), dim4!(3, 3, 2));
+}
@@ -83,4 +83,79 @@ impl<T: Float> Initializer<T> {
             Initializer::Zeros => utils::zeros(dims),
         }
     }
-}
\ No newline at end of file
+}
+
+
+/// xavier (glorot) uniform initialization, with variance 2 / (fan_in + fan_out)
+pub fn xavier_uniform<T: Float>(dims: Dim4, fan_in: u64, fan_out: u64) -> Array<T> {
+    let limit = T::from(6. / (fan_in + fan_out) as f64).unwrap().sqrt();
+    utils::scaled_uniform(-limit, limit, dims)
+}
+
+/// xavier (glorot) normal initialization, with variance 2 / (fan_in + fan_out)
+pub fn xavier_normal<T: Float>(dims: Dim4, fan_in: u64, fan_out: u64) -> Array<T> {
+    let standard_deviation = T::from(2. / (fan_in + fan_out) as f64).unwrap().sqrt();
+    utils::scaled_normal(T::zero(), standard_deviation, dims)
+}
+
+/// kaiming (he) uniform initialization, with variance gain^2 / fan_in, 
+/// the gain of the nonlinearity following the layer is sqrt(2) for relu, and 1 for linear layers
+pub fn kaiming_uniform<T: Float>(dims: Dim4, fan_in: u64, gain: f64) -> Array<T> {
+    let limit = T::from(gain * (3. / fan_in as f64).sqrt()).unwrap();
+    utils::scaled_uniform(-limit, limit, dims)
+}
+
+/// kaiming (he) normal initialization, with variance gain^2 / fan_in
+pub fn kaiming_normal<T: Float>(dims: Dim4, fan_in: u64, gain: f64) -> Array<T> {
+    let standard_deviation = T::from(gain / (fan_in as f64).sqrt()).unwrap();
+    utils::scaled_normal(T::zero(), standard_deviation, dims)
+}
+
+/// The initialization scheme of the weights of Conv2d and Linear, the kaiming schemes carry the gain of the nonlinearity
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Init {
+    XavierUniform,
+    XavierNormal,
+    KaimingUniform(f64),
+    KaimingNormal(f64),
+}
+
+impl Default for Init {
+    /// kaiming normal with the gain of relu
+    fn default() -> Self {
+        Init::KaimingNormal(2f64.sqrt())
+    }
+}
+
+impl Init {
+    pub fn init<T: Float>(self, dims: Dim4, fan_in: u64, fan_out: u64) -> Array<T> {
+        match self {
+            Init::XavierUniform => xavier_uniform(dims, fan_in, fan_out),
+            Init::XavierNormal => xavier_normal(dims, fan_in, fan_out),
+            Init::KaimingUniform(gain) => kaiming_uniform(dims, fan_in, gain),
+            Init::KaimingNormal(gain) => kaiming_normal(dims, fan_in, gain),
+        }
+    }
+}
+
+#[test]
+fn test_init_variance() {
+    set_backend(Backend::CPU);
+    let (fan_in, fan_out) = (100, 300);
+    let variance = |x: Array<f64>| {
+        let x = utils::to_host(&x);
+        let mean = x.iter().sum::<f64>() / x.len() as f64;
+        x.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / x.len() as f64
+    };
+    let dims = dim4!(256, 256);
+    let xavier = 2.0 / (fan_in + fan_out) as f64;
+    let kaiming = 2.0 / fan_in as f64;
+    for (init, expected) in [
+        (Init::XavierUniform, xavier), (Init::XavierNormal, xavier),
+        (Init::KaimingUniform(2f64.sqrt()), kaiming), (Init::KaimingNormal(2f64.sqrt()), kaiming),
+        (Init::KaimingNormal(1.0), 1.0 / fan_in as f64)
+    ] {
+        let var = variance(init.init::<f64>(dims, fan_in, fan_out));
+        assert!((var - expected).abs() < 0.05 * expected, "{:?} has variance {}, expected {}", init, var, expected);
+    }
+}
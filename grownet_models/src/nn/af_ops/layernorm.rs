@@ -0,0 +1,85 @@
+use arrayfire as af;
+use af::*;
+
+use super::{Float, Param, utils::{ones, zeros}, instancenorm::instancenorm};
+use crate::Flatten;
+
+/// Normalizes over the feature dimension, so expected input shape is [features, ...]
+#[derive(Flatten)]
+pub struct LayerNorm<T: Float> {
+    gamma: Param<T>,
+    beta: Param<T>,
+    #[flat(exclude)]
+    eps: T,
+}
+
+impl<T: Float> LayerNorm<T> {
+    pub fn new(features: u64) -> Self {
+        Self { 
+            gamma: Param::new(ones(dim4!(features))), 
+            beta: Param::new(zeros(dim4!(features))),
+            eps: T::from(1e-5).unwrap(),
+        }
+    }
+
+    pub fn forward(&self, x: &Array<T>) -> (Array<T>, impl Fn(&mut Self, &Array<T>) -> Array<T>) {
+        let (y, df) = instancenorm(x, self.eps, 0);
+        let out = add(&self.beta.w, &mul(&self.gamma.w, &y, true), true);
+        let y1 = y.clone();
+        let new_df = move |s: &mut Self, grad: &Array<T>| {
+            s.beta.g += sum_except_features(grad);
+            s.gamma.g += sum_except_features(&mul(&y1, grad, true));
+            df(&mul(grad, &s.gamma.w, true))
+        };
+        (out, new_df)
+    }
+}
+
+fn sum_except_features<T: Float>(x: &Array<T>) -> Array<T> {
+    let dims = x.dims();
+    let x = moddims(x, dim4!(dims[0], dims[1] * dims[2] * dims[3]));
+    sum(&x, 1)
+}
+
+#[test]
+fn test_layernorm() {
+    set_backend(Backend::CPU);
+    let x = add(&mul(&randn::<f64>(dim4!(64, 8, 2)), &3.0, true), &5.0, true);
+    let mut norm = LayerNorm::new(64);
+    let (y, df) = norm.forward(&x);
+    assert_eq!(y.dims(), x.dims());
+
+    let mut mu = [0.0f64; 16];
+    mean(&y, 0).host(&mut mu);
+    let mut var = [0.0f64; 16];
+    var_v2(&y, VarianceBias::POPULATION, 0).host(&mut var);
+    for (m, v) in mu.iter().zip(var) {
+        assert!(m.abs() < 1e-6, "mean {}", m);
+        assert!((v - 1.0).abs() < 1e-3, "variance {}", v);
+    }
+
+    let grad = randn::<f64>(y.dims());
+    df(&mut norm, &grad);
+    assert_eq!(norm.gamma.g.dims(), dim4!(64));
+    let mut db = [0.0f64; 64];
+    norm.beta.g.host(&mut db);
+    let mut expected = [0.0f64; 64];
+    sum(&moddims(&grad, dim4!(64, 16)), 1).host(&mut expected);
+    for (a, b) in db.iter().zip(expected) {
+        assert!((a - b).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn gradcheck_layernorm() {
+    set_backend(Backend::CPU);
+    use super::utils::af_grad_check;
+    let gamma = randn::<f64>(dim4!(6));
+    let x = randn::<f64>(dim4!(6, 3));
+    af_grad_check(x, Some(1e-7), None, None, |x| {
+        let mut norm = LayerNorm::new(6);
+        norm.gamma.w = gamma.clone();
+        let (y, df) = norm.forward(x);
+        (y, move |grad: &Array<f64>| df(&mut norm, grad))
+    });
+}
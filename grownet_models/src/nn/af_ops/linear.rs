@@ -13,15 +13,20 @@ pub struct Linear<T: Float> {
 }
 
 impl<T: Float> Linear<T> {
-    pub fn new(in_dim: u64, out_dim: u64, bias: bool) -> Self {
+    pub fn new(in_dim: u64, out_dim: u64, bias: bool, weight_init: init::Init) -> Self {
         Self { 
-            w: Param::new(init::Initializer::HeNormal.init(dim4!(out_dim, in_dim), in_dim, out_dim)), 
+            w: Param::new(weight_init.init(dim4!(out_dim, in_dim), in_dim, out_dim)), 
             bias: if bias {
                 Some(Param::new(init::Initializer::Zeros.init(dim4!(out_dim), in_dim, out_dim)))
             } else { None } 
         }
     }
 
+    /// The shape of the output given an input of shape [in_dim, H, ...], without computing anything
+    pub fn output_shape(&self, input: Dim4) -> Dim4 {
+        dim4!(self.w.dims()[0], input[1], input[2], input[3])
+    }
+
     /// expect x to be [in_dim, H, ...], outputs [out_dim, H, ...]
     pub fn forward(&self, x: &Array<T>) -> (Array<T>, impl Fn(&mut Self, &Array<T>) -> Array<T>) {
         let y = matmul(&self.w.w, &x, MatProp::NONE, MatProp::NONE);
@@ -50,8 +55,56 @@ impl<T: Float> Linear<T> {
 #[test]
 fn test_linear() {
     let x = randn!(512, 4);
-    let mut lin = Linear::new(512, 10, true);
+    let mut lin = Linear::new(512, 10, true, init::Init::default());
 
     let (y, df) = lin.forward(&x);
     df(&mut lin, &y);
-}
\ No newline at end of file
+}
+
+#[test]
+fn gradcheck_linear() {
+    use super::utils::af_grad_check;
+    set_backend(Backend::CPU);
+    for bias in [false, true] {
+        let x = randn::<f64>(dim4!(4, 3));
+        let w = randn::<f64>(dim4!(5, 4));
+        let b = randn::<f64>(dim4!(5));
+        let linear = |w: &Array<f64>, b: &Array<f64>| {
+            let mut lin = Linear::<f64>::new(4, 5, bias, init::Init::default());
+            lin.w.w = w.clone();
+            if let Some(bias) = &mut lin.bias {
+                bias.w = b.clone();
+            }
+            lin
+        };
+
+        af_grad_check(x.clone(), None, None, None, |x| {
+            let mut lin = linear(&w, &b);
+            let (y, df) = lin.forward(x);
+            (y, move |grad: &Array<f64>| df(&mut lin, grad))
+        });
+
+        af_grad_check(w.clone(), None, None, None, |w| {
+            let mut lin = linear(w, &b);
+            let (y, df) = lin.forward(&x);
+            (y, move |grad: &Array<f64>| {
+                lin.w.g = constant(0.0, lin.w.dims());
+                df(&mut lin, grad);
+                lin.w.g.clone()
+            })
+        });
+
+        if bias {
+            af_grad_check(b.clone(), None, None, None, |b| {
+                let mut lin = linear(&w, b);
+                let (y, df) = lin.forward(&x);
+                (y, move |grad: &Array<f64>| {
+                    let b = lin.bias.as_mut().unwrap();
+                    b.g = constant(0.0, b.dims());
+                    df(&mut lin, grad);
+                    lin.bias.as_ref().unwrap().g.clone()
+                })
+            });
+        }
+    }
+}
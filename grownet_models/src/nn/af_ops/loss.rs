@@ -19,6 +19,32 @@ pub fn cross_entropy<T: Float>(logits: &Array<T>, gtruth: &Array<T>) -> (Array<T
     (result, df1)
 }
 
+/// cross entropy of logits of shape [classes, B] against integer labels of shape [B], returns the mean loss
+/// over the batch, and a closure computing the gradient of the mean loss w.r.t. the logits,
+/// which is (softmax(logits) - one_hot(labels)) / B. The log softmax is shifted by the max logit for stability
+pub fn cross_entropy_labels<T: Float>(logits: &Array<T>, labels: &Array<u32>) -> (T, impl Fn() -> Array<T>) {
+    let (classes, batch) = (logits.dims()[0], logits.dims()[1]);
+    let (y, _) = super::activations::log_softmax(logits);
+    let target = one_hot::<T>(labels.clone(), classes as u32);
+
+    let mut loss = [T::zero()];
+    sum(&flat(&mul(&y, &target, false)), 0).host(&mut loss);
+    let batch = T::from(batch).unwrap();
+    let df = move || (exp(&y) - &target) / batch;
+    (-loss[0] / batch, df)
+}
+
+/// the mean squared error over all elements, and a closure computing its gradient w.r.t. pred, 2 * (pred - target) / n
+pub fn mse<T: Float>(pred: &Array<T>, target: &Array<T>) -> (T, impl Fn() -> Array<T>) {
+    assert!(pred.dims() == target.dims(), "pred and target must have the same shape");
+    let diff = pred - target;
+    let n = T::from(diff.elements()).unwrap();
+    let mut loss = [T::zero()];
+    sum(&flat(&mul(&diff, &diff, false)), 0).host(&mut loss);
+    let df = move || &diff * (T::from(2.0).unwrap() / n);
+    (loss[0] / n, df)
+}
+
 /// expects a 1D array of shape [B], outputs an array of shape [classes, B]
 pub fn one_hot<T: Float>(x: Array<u32>, classes: u32) -> Array<T> {
     let mut output = super::utils::zeros::<T>(dim4!(classes as u64, x.dims()[0]));
@@ -42,4 +68,43 @@ fn test_crossentropy() {
     let gt = randn::<f64>(dim4!(8));
 
     af_grad_check(a, None, None, None, |x| { cross_entropy(x, &gt) })
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_crossentropy_labels() {
+    set_backend(Backend::CPU);
+    // the second column would overflow exp without the shift
+    let logits = Array::new(&[0.0f64, 0.0, 1000.0, 0.0], dim4!(2, 2));
+    let labels = Array::new(&[0u32, 1], dim4!(2));
+    let (loss, df) = cross_entropy_labels(&logits, &labels);
+    let expected = (2.0f64.ln() + 1000.0) / 2.0;
+    assert!((loss - expected).abs() < 1e-9, "expected {}, got {}", expected, loss);
+
+    let mut grad = [0.0f64; 4];
+    df().host(&mut grad);
+    let expected = [-0.25, 0.25, 0.5, -0.5];
+    for (g, e) in grad.iter().zip(expected.iter()) {
+        assert!((g - e).abs() < 1e-9, "expected {:?}, got {:?}", expected, grad);
+    }
+
+    // agrees with the one hot version
+    let logits = randn::<f64>(dim4!(5, 3));
+    let labels = Array::new(&[4u32, 0, 2], dim4!(3));
+    let (loss, _) = cross_entropy_labels(&logits, &labels);
+    let (reference, _) = cross_entropy(&logits, &one_hot(labels, 5));
+    let mut host = [0.0f64];
+    reference.host(&mut host);
+    assert!((loss - host[0]).abs() < 1e-9);
+}
+
+#[test]
+fn test_mse() {
+    let pred = Array::new(&[1.0f64, 2.0, 3.0, 4.0], dim4!(2, 2));
+    let target = Array::new(&[1.0f64, 0.0, 4.0, 2.0], dim4!(2, 2));
+    let (loss, df) = mse(&pred, &target);
+    // (0 + 4 + 1 + 4) / 4
+    assert!((loss - 2.25).abs() < 1e-12);
+    let mut grad = [0.0f64; 4];
+    df().host(&mut grad);
+    assert_eq!(grad, [0.0, 1.0, -0.5, 1.0]);
+}
@@ -18,6 +18,11 @@ impl MaxPool2D {
         }
     }
     
+    /// The shape of the output given an input of shape [w, h, c, n], without computing anything
+    pub fn output_shape(&self, input_shape: Dim4) -> Dim4 {
+        self.compute_output_shape(input_shape)
+    }
+
     fn compute_output_shape(&self, input_shape: Dim4) -> Dim4 {
         let output_height = ((input_shape.get()[0] - self.kernel_size[0]) as f64 / self.stride[0] as f64 + 1.).floor() as u64;
         let output_width = ((input_shape.get()[1] - self.kernel_size[0]) as f64 / self.stride[1] as f64 + 1.).floor() as u64;
@@ -0,0 +1,37 @@
+use arrayfire::*;
+
+use super::Float;
+
+/// The fraction of examples whose label is among the k highest logits, logits is [classes, batch] and labels is [batch].
+/// Ties are broken by class index, so a label tied with a lower class ranks below it
+pub fn topk_accuracy<T: Float>(logits: &Array<T>, labels: &Array<u32>, k: usize) -> f32 {
+    let dims = logits.dims();
+    let batch = dims[1];
+    let labels = moddims(labels, dim4!(1, batch));
+    let class_idx = range::<u32>(dim4!(dims[0], batch), 0);
+    let is_label = eq(&class_idx, &labels, true);
+    // the logit of the label of each example, [1, batch]
+    let label_logit = sum(&select(logits, &is_label, &constant(T::zero(), dims)), 0);
+
+    let higher = gt(logits, &label_logit, true);
+    let tied_before = and(&eq(logits, &label_logit, true), &lt(&class_idx, &labels, true), false);
+    let rank = sum(&(higher.cast::<u32>() + tied_before.cast::<u32>()), 0);
+    let correct = count_all(&lt(&rank, &constant(k as u32, rank.dims()), false)).0;
+    correct as f32 / batch as f32
+}
+
+#[test]
+fn test_topk_accuracy() {
+    set_backend(Backend::CPU);
+    // each column is an example
+    let logits = Array::new(&[
+        0.1, 0.5, 0.3, 0.2f32, // label 1 ranks first
+        0.9, 0.1, 0.2, 0.8,    // label 2 ranks third
+        0.4, 0.4, 0.4, 0.4,    // label 3 ranks last, as ties go to the lower class
+        0.7, 0.7, 0.0, 0.0,    // label 0 ranks first
+    ], dim4!(4, 4));
+    let labels = Array::new(&[1, 2, 3, 0u32], dim4!(4));
+    assert_eq!(topk_accuracy(&logits, &labels, 1), 0.5);
+    assert_eq!(topk_accuracy(&logits, &labels, 3), 0.75);
+    assert_eq!(topk_accuracy(&logits, &labels, 4), 1.0);
+}
@@ -8,13 +8,18 @@ use crate::Flatten;
 pub mod initializer;
 pub mod conv;
 pub mod linear;
+pub mod embedding;
 pub mod maxpool;
+pub mod avgpool;
 pub mod activations;
 pub mod batchnorm2d;
 pub mod instancenorm;
+pub mod layernorm;
+pub mod dropout;
 pub mod utils;
 pub mod array_ops;
 pub mod loss;
+pub mod metrics;
 pub mod sequential;
 
 use initializer as init;
@@ -39,6 +44,77 @@ impl<T: Float> Param<T> {
     }
 }
 
+/// Non-trainable state of a layer, such as running statistics. Buffers are part of the world, so they
+/// are checkpointed along with the params, but optimizers never query them.
+pub struct Buffer<T: Float>(pub Array<T>);
+
+impl<T: Float> Buffer<T> {
+    pub fn dims(&self) -> Dim4 {
+        self.0.dims()
+    }
+}
+
+impl<T: Float + 'static> Flatten for Buffer<T> {
+    fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+        world.push(path, self);
+    }
+}
+
+/// Whether a layer is in training mode, layers such as Dropout and BatchNorm2D behave differently in eval mode.
+/// Exposed through Flatten so that the mode of a whole model can be set at once, see World::set_training
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModeFlag(pub bool);
+
+impl Default for ModeFlag {
+    fn default() -> Self {
+        ModeFlag(true)
+    }
+}
+
+impl Flatten for ModeFlag {
+    fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+        world.push(path, self);
+    }
+}
+
+/// Layers whose behaviour depends on the training mode, such layers must flatten their ModeFlag
+pub trait Trainable {
+    fn mode_flag(&self) -> &ModeFlag;
+    fn mode_flag_mut(&mut self) -> &mut ModeFlag;
+
+    fn set_training(&mut self, training: bool) {
+        self.mode_flag_mut().0 = training;
+    }
+
+    fn is_training(&self) -> bool {
+        self.mode_flag().0
+    }
+}
+
+impl<'a> crate::World<'a> {
+    /// resets the gradients of all f32 and f64 params in the world, including optional params
+    pub fn zero_grad(&mut self) {
+        zero_grad_::<f32>(self);
+        zero_grad_::<f64>(self);
+    }
+
+    /// sets the mode of every Trainable layer in the world
+    pub fn set_training(&mut self, training: bool) {
+        for flag in self.query_mut::<ModeFlag>() {
+            flag.0 = training;
+        }
+    }
+}
+
+fn zero_grad_<T: Float>(world: &mut crate::World) {
+    for param in world.query_mut::<Param<T>>() {
+        param.g = af::constant(T::zero(), param.dims());
+    }
+    for param in world.query_mut::<Option<Param<T>>>().filter(|x| x.is_some()).map(|x| x.as_mut().unwrap()) {
+        param.g = af::constant(T::zero(), param.dims());
+    }
+}
+
 impl<T: Float + 'static> Flatten for Param<T> {
     fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
         world.push(path, self);
@@ -1,6 +1,6 @@
 use super::*;
 
-use std::{rc::Rc, marker::PhantomData};
+use std::{rc::Rc, marker::PhantomData, any::Any};
 
 type DF<M, F> = Rc<dyn Fn(&mut M, &Array<F>) -> Array<F>>;
 
@@ -58,4 +58,123 @@ impl<F: Float, A: Seq<F>> Seq<F> for Residual<A> {
 
         (y, Rc::new(df))
     }
-}
\ No newline at end of file
+}
+
+/// type erased backward function, which receives the layer that produced it
+pub type DynDF<F> = Box<dyn Fn(&mut dyn Any, &Array<F>) -> Array<F>>;
+
+/// Object safe version of Seq, so that layers of different types can be stored together
+pub trait Layer<F: Float>: Flatten {
+    fn forward_dyn(&self, x: &Array<F>) -> (Array<F>, DynDF<F>);
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<F: Float, L: Seq<F> + Flatten> Layer<F> for L {
+    fn forward_dyn(&self, x: &Array<F>) -> (Array<F>, DynDF<F>) {
+        let (y, df) = self.forward(x);
+        let df = move |s: &mut dyn Any, g: &Array<F>| {
+            let s = s.downcast_mut::<L>().expect("backward called with a different layer");
+            df.as_ref()(s, g)
+        };
+        (y, Box::new(df))
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A stack of layers, where each layer takes the output of the previous one, unlike Sequential, 
+/// the number and types of the layers need not be known at compile time. Flattens each layer under its index.
+pub struct DynSequential<F: Float> {
+    layers: Vec<Box<dyn Layer<F>>>,
+}
+
+impl<F: Float> DynSequential<F> {
+    pub fn new() -> Self {
+        Self { layers: Vec::new() }
+    }
+
+    pub fn add(mut self, layer: impl Layer<F> + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn forward(&self, x: &Array<F>) -> (Array<F>, impl Fn(&mut Self, &Array<F>) -> Array<F>) {
+        let mut y = x.clone();
+        let mut dfs = Vec::with_capacity(self.layers.len());
+        for layer in &self.layers {
+            let (y1, df) = layer.forward_dyn(&y);
+            y = y1;
+            dfs.push(df);
+        }
+        let back_fn = move |s: &mut Self, g: &Array<F>| {
+            let mut g = g.clone();
+            for (layer, df) in s.layers.iter_mut().zip(dfs.iter()).rev() {
+                g = df(layer.as_any_mut(), &g);
+            }
+            g
+        };
+        (y, back_fn)
+    }
+}
+
+impl<F: Float> Seq<F> for DynSequential<F> {
+    fn forward(&self, x: &Array<F>) -> (Array<F>, DF<Self, F>) {
+        let (y, df) = DynSequential::forward(self, x);
+        (y, Rc::new(df))
+    }
+}
+
+impl<F: Float> Flatten for DynSequential<F> {
+    fn flatten<'a>(&'a mut self, path: String, world: &mut crate::World<'a>) {
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            layer.flatten(format!("{}/{}", path, i), world);
+        }
+    }
+}
+
+impl<F: Float> Seq<F> for super::linear::Linear<F> {
+    fn forward(&self, x: &Array<F>) -> (Array<F>, DF<Self, F>) {
+        let (y, df) = super::linear::Linear::forward(self, x);
+        (y, Rc::new(df))
+    }
+}
+
+#[test]
+fn test_dyn_sequential() {
+    use super::linear::Linear;
+    use super::init::Init;
+    use af::*;
+    set_backend(Backend::CPU);
+    let grads = |layer: &mut dyn Flatten| -> Vec<Vec<f64>> {
+        let mut world = crate::World::new();
+        layer.flatten(String::new(), &mut world);
+        world.query_mut::<Param<f64>>().map(|p| super::utils::to_host(&p.g)).collect()
+    };
+
+    let x = randn::<f64>(dim4!(4, 3));
+    let g = randn::<f64>(dim4!(2, 3));
+    let mut lin1 = Linear::<f64>::new(4, 5, false, Init::default());
+    let mut lin2 = Linear::<f64>::new(5, 2, false, Init::default());
+    let (y1, df1) = lin1.forward(&x);
+    let (y, df2) = lin2.forward(&y1);
+    let dx = df1(&mut lin1, &df2(&mut lin2, &g));
+    let expected = [grads(&mut lin1), grads(&mut lin2)].concat();
+
+    let mut seq = DynSequential::new().add(lin1).add(lin2);
+    crate::World::from(&mut seq).zero_grad();
+    let (seq_y, df) = seq.forward(&x);
+    let seq_dx = df(&mut seq, &g);
+    assert_eq!(super::utils::to_host(&seq_y), super::utils::to_host(&y));
+    assert_eq!(super::utils::to_host(&seq_dx), super::utils::to_host(&dx));
+    assert_eq!(grads(&mut seq), expected);
+
+    let mut world = crate::World::from(&mut seq);
+    let paths: Vec<_> = world.query_mut_with_path::<Param<f64>>().map(|(p, _)| p.to_string()).collect();
+    assert_eq!(paths, vec!["/0/w", "/1/w"]);
+}
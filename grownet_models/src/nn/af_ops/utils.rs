@@ -21,6 +21,18 @@ pub fn zeros<T: Float>(dims: Dim4) -> Array<T> {
     constant(T::zero(), dims)
 }
 
+/// copies the array to the host as f64, regardless of its float type
+pub fn to_host<T: Float>(x: &Array<T>) -> Vec<f64> {
+    let mut host = vec![T::zero(); x.elements()];
+    x.host(&mut host);
+    host.into_iter().map(|x| x.to_f64().unwrap()).collect()
+}
+
+/// the inverse of to_host
+pub fn to_device<T: Float>(x: &[f64], dims: [u64; 4]) -> Array<T> {
+    let host: Vec<T> = x.iter().map(|x| T::from(*x).unwrap()).collect();
+    Array::new(&host, Dim4::new(&dims))
+}
 
 pub fn assign(a: &mut Array<f64>, i: usize, val: f64) {
     assert!(a.get_backend() == Backend::CPU);
@@ -119,6 +131,40 @@ where FB: FnMut(&Array<f64>) -> Array<f64>
 
 }
 
+/// checks the backward function returned by forward against finite differences of the loss sum(y * g),
+/// for some random g, perturbing each element of input by eps. Returns the max relative error over all 
+/// elements of input, where gradients smaller than eps are compared absolutely.
+pub fn grad_check<T, F, B>(mut forward: F, input: &Array<T>, eps: T) -> T
+where T: Float, F: FnMut(&Array<T>) -> (Array<T>, B), B: FnMut(&Array<T>) -> Array<T>
+{
+    let (y, mut backward) = forward(input);
+    let g = randn::<T>(y.dims());
+    let analytical = to_host(&backward(&g));
+    let g = to_host(&g);
+    let loss = |forward: &mut F, x: &Array<T>| -> f64 {
+        let (y, _) = forward(x);
+        to_host(&y).iter().zip(g.iter()).map(|(y, g)| y * g).sum()
+    };
+
+    let dims = input.dims();
+    let mut x = vec![T::zero(); input.elements()];
+    input.host(&mut x);
+    let (eps64, mut max_err) = (eps.to_f64().unwrap(), 0.0f64);
+    for i in 0..x.len() {
+        let orig = x[i];
+        x[i] = orig + eps;
+        let l1 = loss(&mut forward, &Array::new(&x, dims));
+        x[i] = orig - eps;
+        let l2 = loss(&mut forward, &Array::new(&x, dims));
+        x[i] = orig;
+
+        let numerical = (l1 - l2) / (2.0 * eps64);
+        let err = (numerical - analytical[i]).abs() / numerical.abs().max(analytical[i].abs()).max(eps64);
+        max_err = max_err.max(err);
+    }
+    T::from(max_err).unwrap()
+}
+
 pub fn is_close(a: &Array<f64>, b: &Array<f64>, atol: Option<f64>, rtol: Option<f64>) -> bool {
     let atol = atol.unwrap_or(1e-5);
     let rtol = rtol.unwrap_or(0.001);
@@ -243,4 +289,23 @@ mod test {
         let x = randn::<f64>(dim4!(16));
         af_grad_check(x, None, None, None, softmax);
     }
+
+    #[test]
+    fn test_grad_check() {
+        set_backend(Backend::CPU);
+        let x = randn::<f64>(dim4!(8, 4));
+        assert!(grad_check(|x| super::super::activations::relu(x), &x, 1e-6) < 1e-5);
+
+        let w = randn::<f64>(dim4!(3, 8));
+        let linear = |x: &Array<f64>| {
+            let y = matmul(&w, x, MatProp::NONE, MatProp::NONE);
+            let w = w.clone();
+            (y, move |g: &Array<f64>| matmul(&w, g, MatProp::TRANS, MatProp::NONE))
+        };
+        assert!(grad_check(linear, &x, 1e-6) < 1e-5);
+
+        // an incorrect gradient should be caught
+        let wrong = |x: &Array<f64>| (x * 3.0, |g: &Array<f64>| g * 2.0);
+        assert!(grad_check(wrong, &x, 1e-6) > 0.1);
+    }
 }
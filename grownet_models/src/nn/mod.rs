@@ -1,8 +1,9 @@
 pub mod af_ops;
 pub mod nd_ops;
 pub mod parts;
+pub mod schedulers;
 
-pub use af_ops::Param;
+pub use af_ops::{Buffer, Param};
 
 use std::any::{Any, TypeId};
 
@@ -1,16 +1,22 @@
 use super::*;
 
-/// Ctx with memory allocated in blocks, panics if an allocation is bigger than the size of a block
+/// Ctx with memory allocated in blocks, panics if an allocation is bigger than the size of a block.
+/// Blocks are kept across clears and reused in order, so the arena only grows to the peak usage
+/// between clears, use shrink_to to give memory back after a large transient allocation.
 pub struct BlockCtx<T> {
     buf: Vec<Vec<T>>,
     block_size: usize,
+    /// the block currently allocated from
+    cur: usize,
     gen: usize,
+    live: LiveRanges,
+    stats: Cell<CtxStats>,
 }
 
 impl<T: Float> BlockCtx<T> {
     pub fn new(block_size: usize) -> Self {
         let buf = Vec::new();
-        BlockCtx { buf, block_size, gen: 0 }
+        BlockCtx { buf, block_size, cur: 0, gen: 0, live: LiveRanges::default(), stats: Cell::default() }
     }
 
     unsafe fn reserve(&self, nelem: usize) -> usize {
@@ -19,16 +25,36 @@ impl<T: Float> BlockCtx<T> {
         if nelem > block_ctx.block_size {
             panic!("allocation size is bigger than the block size");
         }
-        if block_ctx.buf.len() == 0 || block_ctx.buf.last().unwrap().len() + nelem < block_ctx.buf.last().unwrap().capacity() {
+        if block_ctx.cur < block_ctx.buf.len() && block_ctx.buf[block_ctx.cur].len() + nelem > block_ctx.block_size {
+            block_ctx.cur += 1;
+        }
+        if block_ctx.cur == block_ctx.buf.len() {
             let mut new_block = Vec::new();
             new_block.reserve_exact(block_ctx.block_size);
             block_ctx.buf.push(new_block);
         }
-        let last = block_ctx.buf.last_mut().unwrap();
-        let len = last.len();
-        last.set_len(len + nelem);
+        let block = &mut block_ctx.buf[block_ctx.cur];
+        let len = block.len();
+        block.set_len(len + nelem);
+        CtxStats::record_alloc::<T>(&self.stats, nelem);
+
+        len + block_ctx.block_size * block_ctx.cur
+    }
 
-        len + block_ctx.block_size * (block_ctx.buf.len() - 1)
+    /// The number of bytes held by the arena, whether they are in use or not
+    pub fn capacity(&self) -> usize {
+        self.buf.len() * self.block_size * std::mem::size_of::<T>()
+    }
+
+    /// Frees unused blocks until the capacity is at most bytes, blocks that are in use are never freed,
+    /// so this should be called after clear
+    pub fn shrink_to(&mut self, bytes: usize) {
+        let in_use = match self.buf.get(self.cur) {
+            Some(block) if !block.is_empty() => self.cur + 1,
+            _ => self.cur,
+        };
+        let keep = bytes / (self.block_size * std::mem::size_of::<T>());
+        self.buf.truncate(keep.max(in_use));
     }
     
     /// panics if there is not enough space
@@ -62,7 +88,17 @@ impl<T: Float> BlockCtx<T> {
                 let block_ptr = block.as_ptr();
                 let offset = ptr.offset_from(block_ptr);
                 if offset >= 0 && offset < self.block_size as isize {
-                    return ArrId { dim: xs.raw_dim(), offset: offset as usize + i * self.block_size, gen: self.gen, _data: PhantomData };
+                    let offset = offset as usize + i * self.block_size;
+                    if cfg!(debug_assertions) {
+                        let (lo, hi) = view_extent(&xs);
+                        let start = offset as isize + lo;
+                        let end = offset as isize + hi;
+                        let block_start = (i * self.block_size) as isize;
+                        assert!(start >= block_start && end <= block_start + block.len() as isize, 
+                            "view over elements [{}, {}) exceeds the allocated part of block {}", start, end, i);
+                        self.live.insert(start as usize, end as usize);
+                    }
+                    return ArrId { dim: xs.raw_dim(), offset, gen: self.gen, _data: PhantomData };
                 }
             }
             panic!("view does not originate from current context");
@@ -80,6 +116,8 @@ impl<T: Float> BlockCtx<T> {
 
     pub fn from_id_mut<D: Dimension>(&self, id: ArrId<T, D>) -> ArrayViewMut<T, D> {
         assert!(id.gen == self.gen, "generation mismatch between id and ctx");
+        // the view takes over the memory of the id
+        self.live.remove(id.offset, id.offset + id.dim.size());
         let arr = unsafe {
             let ptr = self.slice_mut(id.offset, &id.dim).as_mut_ptr();
             ArrayViewMut::from_shape_ptr(id.dim.clone(), ptr)
@@ -101,6 +139,9 @@ impl<T: Float> BlockCtx<T> {
 
     pub fn clear(&mut self) {
         self.gen += 1;
+        self.cur = 0;
+        self.live.clear();
+        CtxStats::record_clear(&self.stats);
         for block in &mut self.buf {
             block.clear();
         }
@@ -114,7 +155,7 @@ impl<T: Float> ArrayCtx<T> for BlockCtx<T> {
     }
 
     fn clear(&mut self) {
-        self.buf.clear();
+        self.clear();
     }
 
     fn id<D: Dimension>(&self, xs: ArrayViewMut<T, D>) -> ArrId<T, D> {
@@ -128,4 +169,88 @@ impl<T: Float> ArrayCtx<T> for BlockCtx<T> {
     fn from_id_mut<D: Dimension>(&self, id: ArrId<T, D>) -> ArrayViewMut<T, D> {
         self.from_id_mut(id)
     }
+
+    fn stats(&self) -> CtxStats {
+        self.stats.get()
+    }
+}
+
+#[test]
+fn test_block_ctx_reuse_and_shrink() {
+    let mut ctx = BlockCtx::<f32>::new(64);
+    let block_bytes = 64 * std::mem::size_of::<f32>();
+    assert_eq!(ctx.capacity(), 0);
+    {
+        // three allocations of 40 elements do not fit in two blocks of 64
+        let xs: Vec<_> = (0..3).map(|i| { let mut x = ctx.empty(40); x.fill(i as f32); ctx.id(x) }).collect();
+        assert_eq!(ctx.capacity(), 3 * block_bytes);
+        let offsets: Vec<_> = xs.iter().map(|x| x.offset).collect();
+        assert_eq!(offsets, [0, 64, 128]);
+        for (i, x) in xs.iter().enumerate() {
+            assert!(ctx.from_id(x).iter().all(|v| *v == i as f32));
+        }
+    }
+    // small allocations are packed into the same block
+    let a = ctx.empty(10);
+    let b = ctx.empty(10);
+    let (a, b) = (ctx.id(a), ctx.id(b));
+    assert_eq!((a.offset, b.offset), (128 + 40, 128 + 50));
+
+    ctx.clear();
+    // blocks are reused after a clear
+    assert_eq!(ctx.capacity(), 3 * block_bytes);
+    let x = ctx.empty(64);
+    assert_eq!(ctx.id(x).offset, 0);
+    assert_eq!(ctx.capacity(), 3 * block_bytes);
+
+    // the block in use is never freed
+    ctx.shrink_to(0);
+    assert_eq!(ctx.capacity(), block_bytes);
+    ctx.clear();
+    ctx.shrink_to(0);
+    assert_eq!(ctx.capacity(), 0);
+
+    let x = ctx.empty(20);
+    let y = ctx.empty(50);
+    let (x, y) = (ctx.id(x), ctx.id(y));
+    assert_eq!((x.offset, y.offset), (0, 64));
+    assert_eq!(ctx.capacity(), 2 * block_bytes);
+    ctx.clear();
+    ctx.shrink_to(block_bytes + 1);
+    assert_eq!(ctx.capacity(), block_bytes);
+}
+
+#[test]
+#[should_panic(expected = "generation mismatch")]
+fn test_block_ctx_stale_id() {
+    let mut ctx = BlockCtx::<f32>::new(64);
+    let x = ctx.empty(10);
+    let id = ctx.id(x);
+    ctx.clear();
+    ctx.shrink_to(0);
+    ctx.from_id(&id);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "overlaps the live id")]
+fn test_block_ctx_overlapping_ids() {
+    let ctx = BlockCtx::<f32>::new(64);
+    let mut x = ctx.empty(10);
+    let ptr = x.as_mut_ptr();
+    let _id = ctx.id(x);
+    // a second view into the same memory
+    let y = unsafe { ArrayViewMut::from_shape_ptr(5, ptr.add(2)) };
+    ctx.id(y);
+}
+
+#[test]
+fn test_block_ctx_id_round_trip() {
+    let ctx = BlockCtx::<f32>::new(64);
+    let x = ctx.empty(10);
+    let id = ctx.id(x);
+    // consuming the id releases its memory, so the view may be turned into an id again
+    let x = ctx.from_id_mut(id);
+    let id = ctx.id(x);
+    assert_eq!(ctx.from_id(&id).len(), 10);
 }
@@ -5,13 +5,15 @@ use super::*;
 pub struct FlatCtx<T> {
     buf: Vec<T>,
     gen: usize,
+    live: LiveRanges,
+    stats: Cell<CtxStats>,
 }
 
 impl<T: Float> FlatCtx<T> {
     pub fn new(cap: usize) -> Self {
         let mut buf = Vec::new();
         buf.reserve_exact(cap);
-        FlatCtx { buf, gen: 0 }
+        FlatCtx { buf, gen: 0, live: LiveRanges::default(), stats: Cell::default() }
     }
 
     unsafe fn reserve(&self, nelem: usize) -> usize {
@@ -21,6 +23,7 @@ impl<T: Float> FlatCtx<T> {
             panic!("not enough memory")
         }
         (&mut *(&self.buf as *const Vec<T> as *mut Vec<T>)).set_len(len + nelem);
+        CtxStats::record_alloc::<T>(&self.stats, nelem);
         len
     }
     
@@ -58,6 +61,13 @@ impl<T: Float> FlatCtx<T> {
             // or alternatively, copy xs into self and return the address of that
             panic!("view is out of bounds from current buffer")
         } else {
+            if cfg!(debug_assertions) {
+                let (lo, hi) = view_extent(&xs);
+                let (start, end) = (offset + lo, offset + hi);
+                assert!(start >= 0 && end <= self.buf.len() as isize, 
+                    "view over elements [{}, {}) exceeds the allocated buffer of {} elements", start, end, self.buf.len());
+                self.live.insert(start as usize, end as usize);
+            }
             ArrId { dim: xs.raw_dim(), offset: offset as usize, gen: self.gen, _data: PhantomData }
         }
     }
@@ -73,6 +83,8 @@ impl<T: Float> FlatCtx<T> {
 
     pub fn from_id_mut<D: Dimension>(&self, id: ArrId<T, D>) -> ArrayViewMut<T, D> {
         assert!(id.gen == self.gen, "generation mismatch between id and ctx");
+        // the view takes over the memory of the id
+        self.live.remove(id.offset, id.offset + id.dim.size());
         let arr = unsafe {
             let ptr = self.buf.as_ptr().add(id.offset) as *mut T;
             ArrayViewMut::from_shape_ptr(id.dim.clone(), ptr)
@@ -93,6 +105,8 @@ impl<T: Float> FlatCtx<T> {
     pub fn clear(&mut self) {
         self.gen += 1;
         self.buf.clear();
+        self.live.clear();
+        CtxStats::record_clear(&self.stats);
     }
 }
 
@@ -103,7 +117,7 @@ impl<T: Float> ArrayCtx<T> for FlatCtx<T> {
     }
 
     fn clear(&mut self) {
-        self.buf.clear();
+        self.clear();
     }
 
     fn id<D: Dimension>(&self, xs: ArrayViewMut<T, D>) -> ArrId<T, D> {
@@ -117,4 +131,41 @@ impl<T: Float> ArrayCtx<T> for FlatCtx<T> {
     fn from_id_mut<D: Dimension>(&self, id: ArrId<T, D>) -> ArrayViewMut<T, D> {
         self.from_id_mut(id)
     }
+
+    fn stats(&self) -> CtxStats {
+        self.stats.get()
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "overlaps the live id")]
+fn test_flat_ctx_overlapping_ids() {
+    let ctx = FlatCtx::<f32>::new(64);
+    let mut x = ctx.empty((4, 4));
+    let ptr = x.as_mut_ptr();
+    let _id = ctx.id(x);
+    let y = unsafe { ArrayViewMut::from_shape_ptr((2, 2), ptr.add(10)) };
+    ctx.id(y);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "exceeds the allocated buffer")]
+fn test_flat_ctx_out_of_bounds_id() {
+    let ctx = FlatCtx::<f32>::new(64);
+    let mut x = ctx.empty(8);
+    let ptr = x.as_mut_ptr();
+    // extends past the 8 allocated elements
+    let y = unsafe { ArrayViewMut::from_shape_ptr(12, ptr) };
+    ctx.id(y);
+}
+
+#[test]
+fn test_flat_ctx_disjoint_ids() {
+    let ctx = FlatCtx::<f32>::new(64);
+    let mut x = ctx.empty(16);
+    let (a, b) = x.view_mut().split_at(Axis(0), 8);
+    let (a, b) = (ctx.id(a), ctx.id(b));
+    assert_eq!((a.offset, b.offset), (0, 8));
 }
@@ -1,3 +1,4 @@
+use std::cell::{Cell, RefCell};
 use std::marker::PhantomData;
 
 use ndarray::prelude::*;
@@ -8,10 +9,12 @@ use num::{Float, FromPrimitive};
 mod flat;
 mod block;
 mod naive;
+mod sync;
 
 pub use flat::FlatCtx;
 pub use block::BlockCtx;
 pub use naive::NaiveCtx;
+pub use sync::SyncCtx;
 
 pub trait ArrayCtx<T: Float> {
     fn empty<'a, D: Dimension, Sh: IntoDimension<Dim = D> + Clone>(&'a self, dim: Sh) -> ArrayViewMut<'a, T, D>;
@@ -42,6 +45,36 @@ pub trait ArrayCtx<T: Float> {
 
     /// Therefore, it is safe to consume id and return a mutable view, since there is no aliasing going on.
     fn from_id_mut<D: Dimension>(&self, id: ArrId<T, D>) -> ArrayViewMut<T, D>;
+
+    /// Allocation statistics since the last clear, ctxs that do not keep track return all zeros
+    fn stats(&self) -> CtxStats {
+        CtxStats::default()
+    }
+}
+
+/// Counts the memory handed out by a ctx through empty, zeros and clone.
+/// current_bytes and alloc_count are reset on clear, peak_bytes is the maximum of current_bytes over the
+/// lifetime of the ctx
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CtxStats {
+    pub peak_bytes: usize,
+    pub current_bytes: usize,
+    pub alloc_count: usize,
+}
+
+impl CtxStats {
+    fn record_alloc<T>(stats: &Cell<CtxStats>, nelem: usize) {
+        let mut s = stats.get();
+        s.current_bytes += nelem * std::mem::size_of::<T>();
+        s.peak_bytes = s.peak_bytes.max(s.current_bytes);
+        s.alloc_count += 1;
+        stats.set(s);
+    }
+
+    fn record_clear(stats: &Cell<CtxStats>) {
+        let s = stats.get();
+        stats.set(CtxStats { peak_bytes: s.peak_bytes, ..Default::default() });
+    }
 }
 
 /// Guaranteed to be unique for each view into ctx
@@ -51,3 +84,46 @@ pub struct ArrId<T, D> {
     gen: usize,
     _data: PhantomData<T>
 }
+
+/// The element ranges of the ids handed out by a ctx, used in debug builds to check that
+/// no two live ids alias each other
+#[derive(Default)]
+struct LiveRanges(RefCell<Vec<(usize, usize)>>);
+
+impl LiveRanges {
+    /// panics if [start, end) overlaps a live range
+    fn insert(&self, start: usize, end: usize) {
+        if !cfg!(debug_assertions) || start == end {
+            return;
+        }
+        let mut ranges = self.0.borrow_mut();
+        if let Some((s, e)) = ranges.iter().find(|(s, e)| start < *e && *s < end) {
+            panic!("view over elements [{}, {}) overlaps the live id over elements [{}, {})", start, end, s, e);
+        }
+        ranges.push((start, end));
+    }
+
+    fn remove(&self, start: usize, end: usize) {
+        if cfg!(debug_assertions) {
+            self.0.borrow_mut().retain(|x| *x != (start, end));
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.get_mut().clear();
+    }
+}
+
+/// The offsets of the lowest element and one past the highest element spanned by xs,
+/// relative to its first element
+fn view_extent<T, D: Dimension>(xs: &ArrayViewMut<T, D>) -> (isize, isize) {
+    if xs.is_empty() {
+        return (0, 0);
+    }
+    let (mut lo, mut hi) = (0, 0);
+    for (&d, &s) in xs.shape().iter().zip(xs.strides()) {
+        let span = (d as isize - 1) * s;
+        if span < 0 { lo += span; } else { hi += span; }
+    }
+    (lo, hi + 1)
+}
@@ -7,12 +7,13 @@ pub struct NaiveCtx<T> {
     buf: RefCell<Vec<Vec<T>>>,
     allocated: RefCell<usize>,
     gen: usize,
+    stats: Cell<CtxStats>,
 }
 
 impl<T: Float> NaiveCtx<T> {
     pub fn new() -> Self {
         let buf = Vec::new();
-        NaiveCtx { buf: RefCell::new(buf), gen: 0, allocated: RefCell::new(0) }
+        NaiveCtx { buf: RefCell::new(buf), gen: 0, allocated: RefCell::new(0), stats: Cell::default() }
     }
 
     fn reserve(&self, nelem: usize) -> usize {
@@ -24,6 +25,7 @@ impl<T: Float> NaiveCtx<T> {
         }
         blocks.push(buf);
         self.allocated.borrow_mut().add_assign(nelem);
+        CtxStats::record_alloc::<T>(&self.stats, nelem);
         blocks.len() - 1
     }
     
@@ -95,6 +97,7 @@ impl<T: Float> NaiveCtx<T> {
     pub fn clear(&mut self) {
         self.gen += 1;
         self.buf.borrow_mut().clear();
+        CtxStats::record_clear(&self.stats);
     }
 
     pub fn allocated(&self) -> usize {
@@ -109,7 +112,7 @@ impl<T: Float> ArrayCtx<T> for NaiveCtx<T> {
     }
 
     fn clear(&mut self) {
-        self.buf.borrow_mut().clear();
+        self.clear();
     }
 
     fn id<D: Dimension>(&self, xs: ArrayViewMut<T, D>) -> ArrId<T, D> {
@@ -123,4 +126,8 @@ impl<T: Float> ArrayCtx<T> for NaiveCtx<T> {
     fn from_id_mut<D: Dimension>(&self, id: ArrId<T, D>) -> ArrayViewMut<T, D> {
         self.from_id_mut(id)
     }
+
+    fn stats(&self) -> CtxStats {
+        self.stats.get()
+    }
 }
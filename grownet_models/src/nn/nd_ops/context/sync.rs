@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+use super::*;
+
+/// Shares ctxs between threads by giving each thread its own ctx, created on first use, so that ops
+/// can run in parallel without aliasing each other's memory. Each ctx is only ever touched by
+/// the thread that created it, and is cleared independently of the others.
+pub struct SyncCtx<C> {
+    ctxs: Mutex<HashMap<ThreadId, Box<RefCell<C>>>>,
+    make_ctx: Box<dyn Fn() -> C + Send + Sync>,
+}
+
+// the ctx of a thread is only accessible from that thread, through with_local
+unsafe impl<C: Send> Sync for SyncCtx<C> {}
+
+impl<T: Float + Send> SyncCtx<BlockCtx<T>> {
+    /// Each thread gets a BlockCtx with the given block size
+    pub fn blocks(block_size: usize) -> Self {
+        SyncCtx::new(move || BlockCtx::new(block_size))
+    }
+}
+
+impl<C> SyncCtx<C> {
+    pub fn new(make_ctx: impl Fn() -> C + Send + Sync + 'static) -> Self {
+        SyncCtx { ctxs: Mutex::new(HashMap::new()), make_ctx: Box::new(make_ctx) }
+    }
+
+    /// Runs f with the ctx of the current thread, views into the ctx cannot outlive f.
+    /// Panics if called again from within f.
+    pub fn with_local<R>(&self, f: impl FnOnce(&mut C) -> R) -> R {
+        let ctx: *const RefCell<C> = {
+            let mut ctxs = self.ctxs.lock().unwrap();
+            let ctx = ctxs.entry(thread::current().id()).or_insert_with(|| Box::new(RefCell::new((self.make_ctx)())));
+            &**ctx
+        };
+        // the box is never moved or dropped while self is borrowed, and no other thread uses it
+        let ctx = unsafe { &*ctx };
+        let mut ctx = ctx.try_borrow_mut().expect("the ctx of this thread is already in use");
+        f(&mut ctx)
+    }
+
+    /// The number of threads that have a ctx
+    pub fn num_ctxs(&self) -> usize {
+        self.ctxs.lock().unwrap().len()
+    }
+
+    /// Drops the ctxs of all threads, freeing their memory
+    pub fn reset(&mut self) {
+        self.ctxs.get_mut().unwrap().clear();
+    }
+
+    /// Clears the ctxs of all threads
+    pub fn clear<T: Float>(&mut self) where C: ArrayCtx<T> {
+        for ctx in self.ctxs.get_mut().unwrap().values_mut() {
+            ctx.get_mut().clear();
+        }
+    }
+}
+
+#[test]
+fn test_sync_ctx() {
+    use super::super::ops_ctx::{matmul, add};
+    let mut ctx = SyncCtx::<BlockCtx<f64>>::blocks(1024);
+    thread::scope(|s| {
+        for i in 0..4 {
+            let ctx = &ctx;
+            s.spawn(move || {
+                for round in 1..=3 {
+                    ctx.with_local(|c| {
+                        let mut a = c.empty((3, 4));
+                        a.fill(i as f64);
+                        let mut b = c.empty((4, 2));
+                        b.fill(1.0);
+                        let y = matmul(c, &a.view(), &b.view());
+                        let y = add(c, &y.view(), &y.view());
+                        assert!(y.iter().all(|x| *x == 8.0 * i as f64));
+                        // only the allocations of this thread are counted
+                        assert_eq!(c.stats().alloc_count, 4 * round);
+                    });
+                }
+                ctx.with_local(|c| c.clear());
+                ctx.with_local(|c| assert_eq!(c.stats().alloc_count, 0));
+            });
+        }
+    });
+    assert_eq!(ctx.num_ctxs(), 4);
+    ctx.clear();
+    ctx.reset();
+    assert_eq!(ctx.num_ctxs(), 0);
+}
+
+#[test]
+#[should_panic(expected = "already in use")]
+fn test_sync_ctx_reentrant() {
+    let ctx = SyncCtx::<BlockCtx<f32>>::blocks(64);
+    ctx.with_local(|_| ctx.with_local(|_| ()));
+}
@@ -73,6 +73,45 @@ pub fn div<'a, T: Float, D: Dimension, Ctx: ArrayCtx<T>>(ctx: &'a Ctx, a: &Array
 }
 
 
+/// Like uniop, but writes into out instead of allocating, panics if out and a differ in shape
+pub fn uniop_into<T, D>(out: &mut ArrayViewMut<T, D>, a: &ArrayView<T, D>, f: impl Fn(T) -> T) 
+where T: Float, D: Dimension 
+{
+    assert!(out.shape() == a.shape(), "output shape {:?} does not match input shape {:?}", out.shape(), a.shape());
+    nd::Zip::from(out).and(a)
+        .for_each(|y, a| {
+            *y = f(*a);
+        });
+}
+
+/// Like binop, but writes into out instead of allocating, panics if out and a differ in shape
+pub fn binop_into<T, D>(out: &mut ArrayViewMut<T, D>, a: &ArrayView<T, D>, b: &ArrayView<T, D>, f: impl Fn(T, T) -> T) 
+where T: Float, D: Dimension 
+{
+    assert!(out.shape() == a.shape(), "output shape {:?} does not match input shape {:?}", out.shape(), a.shape());
+    nd::Zip::from(out).and(a).and_broadcast(b)
+        .for_each(|y, a, b| {
+            *y = f(*a, *b);
+        });
+}
+
+pub fn add_into<T: Float, D: Dimension>(out: &mut ArrayViewMut<T, D>, a: &ArrayView<T, D>, b: &ArrayView<T, D>) {
+    binop_into(out, a, b, |a, b| a + b)
+}
+
+pub fn sub_into<T: Float, D: Dimension>(out: &mut ArrayViewMut<T, D>, a: &ArrayView<T, D>, b: &ArrayView<T, D>) {
+    binop_into(out, a, b, |a, b| a - b)
+}
+
+pub fn mul_into<T: Float, D: Dimension>(out: &mut ArrayViewMut<T, D>, a: &ArrayView<T, D>, b: &ArrayView<T, D>) {
+    binop_into(out, a, b, |a, b| a * b)
+}
+
+pub fn div_into<T: Float, D: Dimension>(out: &mut ArrayViewMut<T, D>, a: &ArrayView<T, D>, b: &ArrayView<T, D>) {
+    binop_into(out, a, b, |a, b| a / b)
+}
+
+
 pub fn permute<'a, T: Float, D: Dimension, Sh: IntoDimension<Dim = D> + Clone, Ctx: ArrayCtx<T>>(ctx: &'a Ctx, a: &ArrayView<T, D>, dim: Sh) -> ArrayViewMut<'a, T, D> {
     let a = a.clone().permuted_axes(dim.clone());
     let mut buf = ctx.empty(a.raw_dim());
@@ -102,6 +141,44 @@ pub fn dmatmul<'a, T: Float + 'static, Ctx: ArrayCtx<T>>(ctx: &'a Ctx, grad: &Ar
     (da, db)
 }
 
+/// Cross-correlates x [n, w, h, c] with the filter w [kw, kh, c, c_out], giving [n, ow, oh, c_out]
+pub fn conv2d<'a, T: Float + 'static, Ctx: ArrayCtx<T>>(ctx: &'a Ctx, x: &ArrayView4<T>, w: &ArrayView4<T>, stride: [usize; 2], pad: [usize; 2]) -> ArrayViewMut4<'a, T> {
+    let (n, xw, xh, c) = x.dim();
+    let (kw, kh, wc, c_out) = w.dim();
+    assert_eq!(c, wc, "input has {} channels, but the filter expects {}", c, wc);
+    let [ow, oh] = conv2d_output_size([xw, xh], [kw, kh], stride, pad);
+
+    let mut cols = ctx.empty((n * ow * oh, kw * kh * c));
+    im2col(x, [kw, kh], stride, pad, &mut cols);
+    let w = w.as_standard_layout();
+    let w = w.view().into_shape((kw * kh * c, c_out)).unwrap();
+    let mut out = ctx.empty((n * ow * oh, c_out));
+    general_mat_mul(T::one(), &cols, &w, T::zero(), &mut out);
+    out.into_shape((n, ow, oh, c_out)).unwrap()
+}
+
+/// The gradients of conv2d with respect to x and w
+pub fn dconv2d<'a, T: Float + 'static, Ctx: ArrayCtx<T>>(ctx: &'a Ctx, grad: &ArrayView4<T>, x: &ArrayView4<T>, w: &ArrayView4<T>, stride: [usize; 2], pad: [usize; 2]) -> (ArrayViewMut4<'a, T>, ArrayViewMut4<'a, T>) {
+    let (n, _, _, c) = x.dim();
+    let (kw, kh, _, c_out) = w.dim();
+    let (_, ow, oh, _) = grad.dim();
+
+    let mut cols = ctx.empty((n * ow * oh, kw * kh * c));
+    im2col(x, [kw, kh], stride, pad, &mut cols);
+    let grad = grad.as_standard_layout();
+    let grad = grad.view().into_shape((n * ow * oh, c_out)).unwrap();
+    let w2 = w.as_standard_layout();
+    let w2 = w2.view().into_shape((kw * kh * c, c_out)).unwrap();
+
+    let mut dw = ctx.empty((kw * kh * c, c_out));
+    general_mat_mul(T::one(), &cols.t(), &grad, T::zero(), &mut dw);
+    let mut dcols = ctx.empty((n * ow * oh, kw * kh * c));
+    general_mat_mul(T::one(), &grad, &w2.t(), T::zero(), &mut dcols);
+    let mut dx = ctx.zeros(x.raw_dim());
+    col2im(&dcols.view(), [kw, kh], stride, pad, &mut dx);
+    (dx, dw.into_shape(w.raw_dim()).unwrap())
+}
+
 pub fn dot_axis<'a, A: Float, D: Dimension + RemoveAxis, Ctx: ArrayCtx<A>>(ctx: &'a Ctx, x: &ArrayView<A, D>, y: &ArrayView<A, D>, axis: usize) -> ArrayViewMut<'a, A, D> {
     let mut buf = unit_axis(ctx, x.raw_dim(), axis);
     for (view_a, view_b) in x.axis_iter(Axis(axis)).zip(y.axis_iter(Axis(axis))) {
@@ -123,6 +200,35 @@ pub fn mean_axis<'a, A: Float, D: Dimension + RemoveAxis, Ctx: ArrayCtx<A>>(ctx:
     buf.mapv_into(|x| x / n)
 }
 
+/// Softmax over the slices along axis, the max of each slice is subtracted before exponentiating
+pub fn softmax_axis<'a, A: Float, D: Dimension + RemoveAxis, Ctx: ArrayCtx<A>>(ctx: &'a Ctx, x: &ArrayView<A, D>, axis: usize) -> ArrayViewMut<'a, A, D> {
+    let mut max = unit_axis(ctx, x.raw_dim(), axis);
+    max.fill(A::neg_infinity());
+    for view in x.axis_iter(Axis(axis)) {
+        max.iter_mut().zip(view.iter()).for_each(|(m, x)| { *m = m.max(*x); });
+    }
+    let mut out = ctx.empty(x.raw_dim());
+    nd::Zip::from(&mut out).and(x).and_broadcast(&max)
+        .for_each(|y, x, m| { *y = (*x - *m).exp(); });
+
+    let mut sum = unit_axis(ctx, x.raw_dim(), axis);
+    for view in out.axis_iter(Axis(axis)) {
+        sum.iter_mut().zip(view.iter()).for_each(|(s, y)| { *s = *s + *y; });
+    }
+    nd::Zip::from(&mut out).and_broadcast(&sum)
+        .for_each(|y, s| { *y = *y / *s; });
+    out
+}
+
+/// The gradient of softmax_axis given its output y, dx = y * (grad - sum(grad * y, axis))
+pub fn dsoftmax_axis<'a, A: Float, D: Dimension + RemoveAxis, Ctx: ArrayCtx<A>>(ctx: &'a Ctx, y: &ArrayView<A, D>, grad: &ArrayView<A, D>, axis: usize) -> ArrayViewMut<'a, A, D> {
+    let dot = dot_axis(ctx, y, grad, axis);
+    let mut dx = ctx.empty(y.raw_dim());
+    nd::Zip::from(&mut dx).and(y).and(grad).and_broadcast(&dot)
+        .for_each(|dx, y, g, dot| { *dx = *y * (*g - *dot); });
+    dx
+}
+
 pub fn unit_axis<'a, D: Dimension, F: Float, Ctx: ArrayCtx<F>>(ctx: &'a Ctx, mut dim: D, i: usize) -> ArrayViewMut<'a, F, D> {
     dim.slice_mut()[i] = 1;
     ctx.zeros(dim)
@@ -158,3 +264,90 @@ where
     }
     (sum_sq.mapv_into(|s| s / dof), mean)
 }
+
+#[test]
+fn test_ctx_stats() {
+    fn run<Ctx: ArrayCtx<f32>>(mut ctx: Ctx) {
+        {
+            let a = randn(&ctx, (4, 3));
+            let b = randn(&ctx, (3, 5));
+            let c = matmul(&ctx, &a.view(), &b.view());
+            let _d = add(&ctx, &c.view(), &c.view());
+        }
+        // a, b, c and d
+        let bytes = (12 + 15 + 20 + 20) * std::mem::size_of::<f32>();
+        assert_eq!(ctx.stats(), CtxStats { peak_bytes: bytes, current_bytes: bytes, alloc_count: 4 });
+
+        ctx.clear();
+        assert_eq!(ctx.stats(), CtxStats { peak_bytes: bytes, current_bytes: 0, alloc_count: 0 });
+        let _ = ctx.zeros(4);
+        assert_eq!(ctx.stats(), CtxStats { peak_bytes: bytes, current_bytes: 16, alloc_count: 1 });
+    }
+    run(FlatCtx::new(1024));
+    run(BlockCtx::new(64));
+    run(NaiveCtx::new());
+}
+
+#[test]
+fn test_binop_into() {
+    let ctx = FlatCtx::<f64>::new(1024);
+    let a = randn(&ctx, (4, 5));
+    let b = randn(&ctx, (1, 5));
+    let expected = add(&ctx, &a.view(), &b.view());
+    let mut out = ctx.empty((4, 5));
+    let allocs = ctx.stats().alloc_count;
+    add_into(&mut out, &a.view(), &b.view());
+    assert_eq!(out, expected);
+
+    // chained ops can reuse the same buffer
+    let expected = mul(&ctx, &expected.view(), &a.view());
+    let prev = out.to_owned();
+    mul_into(&mut out, &prev.view(), &a.view());
+    assert_eq!(out, expected);
+    uniop_into(&mut out, &a.view(), |x| x * 2.0);
+    assert_eq!(out, a.mapv(|x| x * 2.0));
+    assert_eq!(ctx.stats().alloc_count, allocs + 1);
+}
+
+#[test]
+#[should_panic(expected = "does not match input shape")]
+fn test_binop_into_shape_mismatch() {
+    let ctx = FlatCtx::<f64>::new(1024);
+    let a = randn(&ctx, (4, 5));
+    let mut out = ctx.empty((5, 4));
+    add_into(&mut out, &a.view(), &a.view());
+}
+
+#[test]
+fn test_conv2d() {
+    let ctx = FlatCtx::<f64>::new(4096);
+    let x = randn(&ctx, (2, 6, 5, 3));
+    let w = randn(&ctx, (3, 3, 3, 4));
+    let grad = randn(&ctx, (2, 3, 3, 4));
+    let y = conv2d(&ctx, &x.view(), &w.view(), [2, 2], [1, 1]);
+    let (dx, dw) = dconv2d(&ctx, &grad.view(), &x.view(), &w.view(), [2, 2], [1, 1]);
+
+    // matches the owned implementation
+    let expected = owned::conv2d(&x.view(), &w.view(), [2, 2], [1, 1]);
+    let (edx, edw) = owned::dconv2d(&grad.view(), &x.view(), &w.view(), [2, 2], [1, 1]);
+    assert!(isclose(&y.into_owned(), &expected));
+    assert!(isclose(&dx.into_owned(), &edx));
+    assert!(isclose(&dw.into_owned(), &edw));
+}
+
+#[test]
+fn test_softmax_axis() {
+    let ctx = FlatCtx::<f64>::new(1024);
+    let mut x = randn(&ctx, (3, 4));
+    // large values would overflow without subtracting the max
+    x.row_mut(0).mapv_inplace(|x| x + 1000.0);
+    let y = softmax_axis(&ctx, &x.view(), 1);
+    for row in y.rows() {
+        assert!((row.sum() - 1.0).abs() < 1e-12);
+    }
+    assert!(isclose(&y.to_owned(), &owned::softmax_axis(&x.view(), 1)));
+
+    let grad = randn(&ctx, (3, 4));
+    let dx = dsoftmax_axis(&ctx, &y.view(), &grad.view(), 1);
+    assert!(isclose(&dx.to_owned(), &owned::dsoftmax_axis(&y.view(), &grad.view(), 1)));
+}
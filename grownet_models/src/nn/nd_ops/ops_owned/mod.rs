@@ -1,14 +1,90 @@
+use anyhow::{bail, Result};
+
 use super::*;
 pub mod norm;
 pub use norm::*;
 
 
+/// The shape that a and b broadcast to, each axis must either be equal or 1 in one of the two shapes
+pub fn broadcast_shape<D: Dimension>(a: &D, b: &D) -> Result<D> {
+    let mut shape = a.clone();
+    for ((s, &x), &y) in shape.slice_mut().iter_mut().zip(a.slice()).zip(b.slice()) {
+        *s = if x == y || y == 1 {
+            x
+        } else if x == 1 {
+            y
+        } else {
+            bail!("shapes {:?} and {:?} cannot be broadcast together", a.slice(), b.slice());
+        };
+    }
+    Ok(shape)
+}
+
+/// Applies f elementwise after broadcasting a and b to a common shape
+pub fn binop<T: Float, D: Dimension>(a: &ArrayView<T, D>, b: &ArrayView<T, D>, f: impl Fn(T, T) -> T) -> Result<Array<T, D>> {
+    let shape = broadcast_shape(&a.raw_dim(), &b.raw_dim())?;
+    let a = a.broadcast(shape.clone()).unwrap();
+    let b = b.broadcast(shape).unwrap();
+    Ok(Zip::from(&a).and(&b).map_collect(|a, b| f(*a, *b)))
+}
+
+pub fn add<T: Float, D: Dimension>(a: &ArrayView<T, D>, b: &ArrayView<T, D>) -> Result<Array<T, D>> {
+    binop(a, b, |a, b| a + b)
+}
+
+pub fn sub<T: Float, D: Dimension>(a: &ArrayView<T, D>, b: &ArrayView<T, D>) -> Result<Array<T, D>> {
+    binop(a, b, |a, b| a - b)
+}
+
+pub fn mul<T: Float, D: Dimension>(a: &ArrayView<T, D>, b: &ArrayView<T, D>) -> Result<Array<T, D>> {
+    binop(a, b, |a, b| a * b)
+}
+
+pub fn div<T: Float, D: Dimension>(a: &ArrayView<T, D>, b: &ArrayView<T, D>) -> Result<Array<T, D>> {
+    binop(a, b, |a, b| a / b)
+}
+
+
 pub fn dmatmul<T: Float + 'static>(grad: &Array2<T>, a: &Array2<T>, b: &Array2<T>) -> (Array2<T>, Array2<T>) {
     let db = a.t().dot(grad);
     let da = grad.dot(&b.t());
     (da, db)
 }
 
+/// Cross-correlates x [n, w, h, c] with the filter w [kw, kh, c, c_out], giving [n, ow, oh, c_out]
+pub fn conv2d<T: Float + 'static>(x: &ArrayView4<T>, w: &ArrayView4<T>, stride: [usize; 2], pad: [usize; 2]) -> Array4<T> {
+    let (n, xw, xh, c) = x.dim();
+    let (kw, kh, wc, c_out) = w.dim();
+    assert_eq!(c, wc, "input has {} channels, but the filter expects {}", c, wc);
+    let [ow, oh] = conv2d_output_size([xw, xh], [kw, kh], stride, pad);
+
+    let mut cols = Array2::zeros((n * ow * oh, kw * kh * c));
+    im2col(x, [kw, kh], stride, pad, &mut cols.view_mut());
+    let w = w.as_standard_layout();
+    let w = w.view().into_shape((kw * kh * c, c_out)).unwrap();
+    cols.dot(&w).into_shape((n, ow, oh, c_out)).unwrap()
+}
+
+/// The gradients of conv2d with respect to x and w
+pub fn dconv2d<T: Float + 'static>(grad: &ArrayView4<T>, x: &ArrayView4<T>, w: &ArrayView4<T>, stride: [usize; 2], pad: [usize; 2]) -> (Array4<T>, Array4<T>) {
+    let (n, _, _, c) = x.dim();
+    let (kw, kh, _, c_out) = w.dim();
+    let (_, ow, oh, _) = grad.dim();
+
+    let mut cols = Array2::zeros((n * ow * oh, kw * kh * c));
+    im2col(x, [kw, kh], stride, pad, &mut cols.view_mut());
+    let grad = grad.as_standard_layout();
+    let grad = grad.view().into_shape((n * ow * oh, c_out)).unwrap();
+    let w2 = w.as_standard_layout();
+    let w2 = w2.view().into_shape((kw * kh * c, c_out)).unwrap();
+
+    let dw = cols.t().dot(&grad).into_shape(w.raw_dim()).unwrap();
+    let dcols = grad.dot(&w2.t());
+    let mut dx = Array4::zeros(x.raw_dim());
+    col2im(&dcols.view(), [kw, kh], stride, pad, &mut dx.view_mut());
+    (dx, dw)
+}
+
 pub fn dot_axis<A: Float, D: Dimension + RemoveAxis>(x: &ArrayView<A, D>, y: &ArrayView<A, D>, axis: usize) -> Array<A, D> {
     let mut buf = unit_axis(x.raw_dim(), axis);
     for (view_a, view_b) in x.axis_iter(Axis(axis)).zip(y.axis_iter(Axis(axis))) {
@@ -31,6 +107,29 @@ pub fn mean_axis<A: Float, D: Dimension + RemoveAxis>(x: &ArrayView<A, D>, axis:
 }
 
 
+/// Softmax over the slices along axis, the max of each slice is subtracted before exponentiating
+pub fn softmax_axis<A: Float, D: Dimension + RemoveAxis>(x: &ArrayView<A, D>, axis: usize) -> Array<A, D> {
+    let mut max = unit_axis(x.raw_dim(), axis).mapv_into(|_: A| A::neg_infinity());
+    for view in x.axis_iter(Axis(axis)) {
+        max.iter_mut().zip(view.iter()).for_each(|(m, x)| { *m = m.max(*x); });
+    }
+    let mut out = Zip::from(x).and_broadcast(&max).map_collect(|x, m| (*x - *m).exp());
+
+    let mut sum = unit_axis(x.raw_dim(), axis);
+    for view in out.axis_iter(Axis(axis)) {
+        sum.iter_mut().zip(view.iter()).for_each(|(s, y)| { *s = *s + *y; });
+    }
+    Zip::from(&mut out).and_broadcast(&sum).for_each(|y, s| { *y = *y / *s; });
+    out
+}
+
+/// The gradient of softmax_axis given its output y, dx = y * (grad - sum(grad * y, axis))
+pub fn dsoftmax_axis<A: Float, D: Dimension + RemoveAxis>(y: &ArrayView<A, D>, grad: &ArrayView<A, D>, axis: usize) -> Array<A, D> {
+    let dot = dot_axis(y, grad, axis);
+    Zip::from(y).and(grad).and_broadcast(&dot).map_collect(|y, g, dot| *y * (*g - *dot))
+}
+
+
 pub fn randn<D: Dimension, Sh: ShapeBuilder<Dim = D>>(shape: Sh) -> Array<f32, D> {
     Array::random(shape, Normal::new(0.0, 1.0).unwrap())
 }
@@ -44,3 +143,104 @@ pub fn unit_axis<D: Dimension, F: Float>(mut dim: D, i: usize) -> Array<F, D> {
     dim.slice_mut()[i] = 1;
     Array::zeros(dim)
 }
+
+#[test]
+fn test_broadcast_binops() {
+    let x = array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+    // scalar-like
+    let y = add(&x.view(), &array![[10.0]].view()).unwrap();
+    assert_eq!(y, array![[11.0, 12.0, 13.0], [14.0, 15.0, 16.0]]);
+    // row
+    let y = mul(&x.view(), &array![[1.0, 0.0, -1.0]].view()).unwrap();
+    assert_eq!(y, array![[1.0, 0.0, -3.0], [4.0, 0.0, -6.0]]);
+    // column, on either side
+    let y = sub(&array![[1.0], [2.0]].view(), &x.view()).unwrap();
+    assert_eq!(y, array![[0.0, -1.0, -2.0], [-2.0, -3.0, -4.0]]);
+    let y = div(&x.view(), &array![[1.0], [2.0]].view()).unwrap();
+    assert_eq!(y, array![[1.0, 2.0, 3.0], [2.0, 2.5, 3.0]]);
+    // both operands are broadcast
+    let y = add(&array![[1.0], [2.0]].view(), &array![[10.0, 20.0]].view()).unwrap();
+    assert_eq!(y, array![[11.0, 21.0], [12.0, 22.0]]);
+
+    let err = add(&x.view(), &array![[1.0, 2.0]].view()).unwrap_err();
+    assert!(err.to_string().contains("cannot be broadcast"));
+}
+
+#[test]
+fn test_conv2d() {
+    // x[0, i, j, 0] = 1 + 3i + j
+    let x = Array::from_shape_fn((1, 3, 3, 1), |(_, i, j, _)| (1 + 3 * i + j) as f64);
+    let mut w = Array4::zeros((2, 2, 1, 1));
+    w[[0, 0, 0, 0]] = 1.0;
+    w[[1, 1, 0, 0]] = -1.0;
+    // each output is x[i, j] - x[i + 1, j + 1]
+    let y = conv2d(&x.view(), &w.view(), [1, 1], [0, 0]);
+    assert_eq!(y, Array::from_elem((1, 2, 2, 1), -4.0));
+
+    let y = conv2d(&x.view(), &w.view(), [2, 2], [1, 1]);
+    assert_eq!(y.into_shape((2, 2)).unwrap(), array![[-1.0, -3.0], [-7.0, -4.0]]);
+
+    // channels are summed over, and each output channel has its own filter
+    let x = Array::from_shape_fn((1, 2, 2, 2), |(_, i, j, c)| (i + 2 * j + 4 * c) as f64);
+    let w = Array::from_shape_fn((1, 1, 2, 2), |(_, _, c, o)| if c == o { 1.0 } else { 10.0 });
+    let y = conv2d(&x.view(), &w.view(), [1, 1], [0, 0]);
+    assert_eq!(y[[0, 1, 1, 0]], 3.0 + 10.0 * 7.0);
+    assert_eq!(y[[0, 1, 1, 1]], 10.0 * 3.0 + 7.0);
+}
+
+#[test]
+fn gradcheck_conv2d() {
+    let x = randn64((2, 5, 4, 3));
+    let w = randn64((3, 2, 3, 2));
+    let (stride, pad) = ([2, 1], [1, 1]);
+    let y = conv2d(&x.view(), &w.view(), stride, pad);
+    let ydim = y.raw_dim();
+
+    let xdim = x.raw_dim();
+    let f = |x: &Array1<f64>| {
+        let x = x.clone().into_shape(xdim).unwrap();
+        let y = conv2d(&x.view(), &w.view(), stride, pad);
+        Array::from_iter(y.into_iter())
+    };
+    let df = |g: &Array1<f64>| {
+        let g = g.clone().into_shape(ydim).unwrap();
+        let (dx, _) = dconv2d(&g.view(), &x.view(), &w.view(), stride, pad);
+        Array::from_iter(dx.into_iter())
+    };
+    grad_check(Array::from_iter(x.iter().cloned()), f, df, None, None, None).unwrap();
+
+    let wdim = w.raw_dim();
+    let f = |w: &Array1<f64>| {
+        let w = w.clone().into_shape(wdim).unwrap();
+        let y = conv2d(&x.view(), &w.view(), stride, pad);
+        Array::from_iter(y.into_iter())
+    };
+    let df = |g: &Array1<f64>| {
+        let g = g.clone().into_shape(ydim).unwrap();
+        let (_, dw) = dconv2d(&g.view(), &x.view(), &w.view(), stride, pad);
+        Array::from_iter(dw.into_iter())
+    };
+    grad_check(Array::from_iter(w.iter().cloned()), f, df, None, None, None).unwrap();
+}
+
+#[test]
+fn test_softmax_axis() {
+    let x = randn64((4, 3));
+    for axis in 0..2 {
+        let y = softmax_axis(&x.view(), axis);
+        for lane in y.lanes(Axis(axis)) {
+            assert!((lane.sum() - 1.0).abs() < 1e-12);
+        }
+    }
+
+    let f = |x: &Array1<f64>| {
+        let x = x.clone().into_shape((4, 3)).unwrap();
+        Array::from_iter(softmax_axis(&x.view(), 1).into_iter())
+    };
+    let y = softmax_axis(&x.view(), 1);
+    let df = |g: &Array1<f64>| {
+        let g = g.clone().into_shape((4, 3)).unwrap();
+        Array::from_iter(dsoftmax_axis(&y.view(), &g.view(), 1).into_iter())
+    };
+    grad_check(Array::from_iter(x.iter().cloned()), f, df, None, None, None).unwrap();
+}
@@ -103,3 +103,63 @@ pub fn isclose<D: Dimension, A: Float>(a: &Array<A, D>, b: &Array<A, D>) -> bool
     }
     true
 }
+
+/// The output [width, height] of a convolution over an input of size [width, height]
+pub fn conv2d_output_size(input: [usize; 2], kernel: [usize; 2], stride: [usize; 2], pad: [usize; 2]) -> [usize; 2] {
+    [
+        (input[0] + 2 * pad[0] - kernel[0]) / stride[0] + 1,
+        (input[1] + 2 * pad[1] - kernel[1]) / stride[1] + 1,
+    ]
+}
+
+/// Unfolds each receptive field of x [n, w, h, c] into a row of cols [n * ow * oh, kw * kh * c],
+/// with the elements of a row ordered as [kw, kh, c]. Out of bounds elements are zero padded.
+pub fn im2col<T: Float>(x: &ArrayView4<T>, kernel: [usize; 2], stride: [usize; 2], pad: [usize; 2], cols: &mut ArrayViewMut2<T>) {
+    let (n, w, h, c) = x.dim();
+    let [ow, oh] = conv2d_output_size([w, h], kernel, stride, pad);
+    assert_eq!(cols.dim(), (n * ow * oh, kernel[0] * kernel[1] * c), "cols has the wrong shape");
+    for b in 0..n {
+        for i in 0..ow {
+            for j in 0..oh {
+                let mut row = cols.row_mut((b * ow + i) * oh + j);
+                for ki in 0..kernel[0] {
+                    for kj in 0..kernel[1] {
+                        let xi = (i * stride[0] + ki) as isize - pad[0] as isize;
+                        let xj = (j * stride[1] + kj) as isize - pad[1] as isize;
+                        let inside = xi >= 0 && xj >= 0 && (xi as usize) < w && (xj as usize) < h;
+                        for ch in 0..c {
+                            row[(ki * kernel[1] + kj) * c + ch] = if inside { x[[b, xi as usize, xj as usize, ch]] } else { T::zero() };
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The adjoint of im2col, sums each row of cols back into its receptive field in dx
+pub fn col2im<T: Float>(cols: &ArrayView2<T>, kernel: [usize; 2], stride: [usize; 2], pad: [usize; 2], dx: &mut ArrayViewMut4<T>) {
+    let (n, w, h, c) = dx.dim();
+    let [ow, oh] = conv2d_output_size([w, h], kernel, stride, pad);
+    assert_eq!(cols.dim(), (n * ow * oh, kernel[0] * kernel[1] * c), "cols has the wrong shape");
+    for b in 0..n {
+        for i in 0..ow {
+            for j in 0..oh {
+                let row = cols.row((b * ow + i) * oh + j);
+                for ki in 0..kernel[0] {
+                    for kj in 0..kernel[1] {
+                        let xi = (i * stride[0] + ki) as isize - pad[0] as isize;
+                        let xj = (j * stride[1] + kj) as isize - pad[1] as isize;
+                        if xi < 0 || xj < 0 || xi as usize >= w || xj as usize >= h {
+                            continue;
+                        }
+                        for ch in 0..c {
+                            let d = &mut dx[[b, xi as usize, xj as usize, ch]];
+                            *d = *d + row[(ki * kernel[1] + kj) * c + ch];
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,13 @@
+use std::fs::File;
+use std::path::Path;
 use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use arrayfire::*;
 use super::{af_ops, Param};
 use af_ops::Float;
+use af_ops::utils::{to_host, to_device};
 
 use crate::{Flatten, World};
 
@@ -14,11 +20,16 @@ pub struct ConvBlock<T: af_ops::Float> {
 impl<T: af_ops::Float> ConvBlock<T> {
     pub fn new(in_chan: u64, out_chan: u64) -> Self {
         Self { 
-            conv: af_ops::conv::Conv2d::new(in_chan, out_chan, [3, 3], [1, 1], [1, 1], false), 
+            conv: af_ops::conv::Conv2d::new(in_chan, out_chan, [3, 3], [1, 1], [1, 1], [1, 1], 1, false, af_ops::initializer::Init::default()), 
             instance_norm: af_ops::instancenorm::InstanceNorm2D::new(out_chan)
         }
     }
 
+    /// instance norm and relu preserve the shape of the convolution
+    pub fn output_shape(&self, input: Dim4) -> Dim4 {
+        self.conv.output_shape(input)
+    }
+
     pub fn forward(&self, x: &Array<T>) -> (Array<T>, impl Fn(&mut Self, &Array<T>) -> Array<T>) {
         let (x, f1) = self.conv.forward(x);
         let (x, f2) = self.instance_norm.forward(&x);
@@ -75,20 +86,158 @@ impl<T: Float> ConvLayer<T> {
     }
 }
 
+/// Flattens [w, h, c, n] feature maps to [w * h * c, n], so that they can be fed to a linear layer
+#[derive(Clone, Default, Flatten)]
+pub struct FlattenLayer {}
+
+impl FlattenLayer {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn output_shape(&self, input: Dim4) -> Dim4 {
+        dim4!(input[0] * input[1] * input[2], input[3])
+    }
+
+    pub fn forward<T: Float>(&self, x: &Array<T>) -> (Array<T>, impl Fn(&Array<T>) -> Array<T>) {
+        af_ops::reshape(x, self.output_shape(x.dims()))
+    }
+}
+
+/// The number of parameters in the world whose path starts with prefix
+pub fn num_params<T: Float>(world: &mut World, prefix: &str) -> usize {
+    let mut n = 0;
+    for param in world.query_path_mut::<Param<T>>(prefix) {
+        n += param.w.elements();
+    }
+    for param in world.query_path_mut::<Option<Param<T>>>(prefix) {
+        if let Some(param) = param {
+            n += param.w.elements();
+        }
+    }
+    n
+}
+
+fn sum_squares<T: Float>(x: &Array<T>) -> T {
+    let mut host = [T::zero()];
+    sum(&flat(&(x * x)), 0).host(&mut host);
+    host[0]
+}
+
+/// Scales all gradients in the world so that their global L2 norm is at most max_norm,
+/// returns the norm before clipping
+pub fn clip_grad_norm<'a, T: Float>(world: &mut World<'a>, max_norm: T) -> T {
+    let mut sq_norm = T::zero();
+    for param in world.query_mut::<Param<T>>() {
+        sq_norm = sq_norm + sum_squares(&param.g);
+    }
+    for param in world.query_mut::<Option<Param<T>>>().filter(|x| x.is_some()).map(|x| x.as_mut().unwrap()) {
+        sq_norm = sq_norm + sum_squares(&param.g);
+    }
+    let norm = sq_norm.sqrt();
+    if norm > max_norm {
+        let scale = max_norm / norm;
+        for param in world.query_mut::<Param<T>>() {
+            param.g = &param.g * scale;
+        }
+        for param in world.query_mut::<Option<Param<T>>>().filter(|x| x.is_some()).map(|x| x.as_mut().unwrap()) {
+            param.g = &param.g * scale;
+        }
+    }
+    norm
+}
+
+/// SGD with optional momentum, the velocity buffers are allocated lazily on the first update,
+/// walking the world in the same order as the updates
 pub struct SGDSimple<T: Float> {
     pub lr: T,
+    pub momentum: T,
+    pub nesterov: bool,
+    velocity: Vec<Array<T>>,
+    optional_velocity: Vec<Array<T>>,
 }
 
 impl<T: Float> SGDSimple<T> {
-    pub fn update<'a>(&mut self, world: &mut World<'a>) {
+    pub fn new(lr: T) -> Self {
+        Self { lr, momentum: T::zero(), nesterov: false, velocity: Vec::new(), optional_velocity: Vec::new() }
+    }
+
+    pub fn with_momentum(mut self, momentum: T, nesterov: bool) -> Self {
+        self.momentum = momentum;
+        self.nesterov = nesterov;
+        self
+    }
+
+    fn init_velocity<'a>(&mut self, world: &mut World<'a>) {
+        use af_ops::zeros;
         for param in world.query_mut::<Param<T>>() {
-            param.w -= &param.g * self.lr;
+            self.velocity.push(zeros(param.dims()));
+        }
+        for param in world.query_mut::<Option<Param<T>>>() {
+            if let Some(param) = param {
+                self.optional_velocity.push(zeros(param.dims()));
+            }
+        }
+    }
 
+    /// v = momentum * v + g, the step is v, or g + momentum * v for nesterov
+    pub fn update_step(param: &mut Param<T>, v: &mut Array<T>, lr: T, momentum: T, nesterov: bool) {
+        *v = &*v * momentum + &param.g;
+        if nesterov {
+            param.w -= (&param.g + &*v * momentum) * lr;
+        } else {
+            param.w -= &*v * lr;
         }
-        for param in world.query_mut::<Option<Param<T>>>().filter(|x| x.is_some()).map(|x| x.as_mut().unwrap()) {
-            param.w -= &param.g * self.lr;
+    }
+
+    pub fn update<'a>(&mut self, world: &mut World<'a>) {
+        if self.momentum == T::zero() {
+            for param in world.query_mut::<Param<T>>() {
+                param.w -= &param.g * self.lr;
+            }
+            for param in world.query_mut::<Option<Param<T>>>().filter(|x| x.is_some()).map(|x| x.as_mut().unwrap()) {
+                param.w -= &param.g * self.lr;
+            }
+            return;
+        }
+        if self.velocity.is_empty() && self.optional_velocity.is_empty() {
+            self.init_velocity(world);
         }
+        let (lr, momentum, nesterov) = (self.lr, self.momentum, self.nesterov);
+        for (param, v) in world.query_mut::<Param<T>>().zip(self.velocity.iter_mut()) {
+            Self::update_step(param, v, lr, momentum, nesterov);
+        }
+        for (param, v) in world.query_mut::<Option<Param<T>>>().filter(|x| x.is_some()).zip(self.optional_velocity.iter_mut()) {
+            let param = param.as_mut().unwrap();
+            Self::update_step(param, v, lr, momentum, nesterov);
+        }
+    }
+}
+
+/// Host side copy of the Adam moment buffers, the values are stored as f64 so that the same file
+/// format is shared between float types
+#[derive(Serialize, Deserialize)]
+struct AdamState {
+    t: u64,
+    mt_vt: Vec<([u64; 4], Vec<f64>, Vec<f64>)>,
+    optional_mt_vt: Vec<([u64; 4], Vec<f64>, Vec<f64>)>,
+}
+
+fn buffers_to_host<T: Float>(buffers: &[(Array<T>, Array<T>)]) -> Vec<([u64; 4], Vec<f64>, Vec<f64>)> {
+    buffers.iter().map(|(mt, vt)| (*mt.dims().get(), to_host(mt), to_host(vt))).collect()
+}
+
+/// Copies the saved buffers onto the device, failing if they do not line up with the current buffers
+fn buffers_to_device<T: Float>(saved: &[([u64; 4], Vec<f64>, Vec<f64>)], current: &[(Array<T>, Array<T>)]) -> Result<Vec<(Array<T>, Array<T>)>> {
+    if saved.len() != current.len() {
+        bail!("expected {} moment buffers, found {}", current.len(), saved.len());
     }
+    saved.iter().zip(current.iter()).enumerate().map(|(i, ((dims, mt, vt), (cur, _)))| {
+        if dims != cur.dims().get() {
+            bail!("moment buffer {} has shape {:?}, expected {:?}", i, dims, cur.dims().get());
+        }
+        Ok((to_device(mt, *dims), to_device(vt, *dims)))
+    }).collect()
 }
 
 pub struct Adam<T: Float> {
@@ -97,6 +246,7 @@ pub struct Adam<T: Float> {
     beta1: T,
     beta2: T,
     eps: T,
+    weight_decay: T,
     t: u64,
 }
 
@@ -116,12 +266,21 @@ impl<T: Float> Adam<T> {
             }
         }
         
-        Self { mt_vt, optional_mt_vt, beta1, beta2, eps: T::from(1e-6).unwrap(), t: 0 }
+        Self { mt_vt, optional_mt_vt, beta1, beta2, eps: T::from(1e-6).unwrap(), weight_decay: T::zero(), t: 0 }
     }
 
-    pub fn update_step(param: &mut Param<T>, mt: &mut Array<T>, vt: &mut Array<T>, lr: T, beta1: T, beta2: T, t: u64, eps: T) {
+    /// AdamW style decoupled weight decay, applied directly to the weights before the adaptive step
+    pub fn with_weight_decay(mut self, weight_decay: T) -> Self {
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    pub fn update_step(param: &mut Param<T>, mt: &mut Array<T>, vt: &mut Array<T>, lr: T, beta1: T, beta2: T, t: u64, eps: T, weight_decay: T) {
+        if weight_decay != T::zero() {
+            param.w = &param.w * (T::one() - lr * weight_decay);
+        }
         *mt = &*mt * beta1 + &param.g * (T::one() - beta1);
-        *vt = &*vt * beta2 + pow(&*vt, &T::from(2.0).unwrap(), true) * (T::one() - beta2);
+        *vt = &*vt * beta2 + pow(&param.g, &T::from(2.0).unwrap(), true) * (T::one() - beta2);
         let mhat = &*mt / (T::one() - beta1.powf(T::from(t + 1).unwrap()));
         let vhat = &*vt / (T::one() - beta2.powf(T::from(t + 1).unwrap()));
 
@@ -132,14 +291,39 @@ impl<T: Float> Adam<T> {
         let beta1 = self.beta1;
         let beta2 = self.beta2;
         for (param, (mt, vt)) in world.query_mut::<Param<T>>().zip(self.mt_vt.iter_mut()) {
-            Self::update_step(param, mt, vt, lr, beta1, beta2, self.t, self.eps);
+            Self::update_step(param, mt, vt, lr, beta1, beta2, self.t, self.eps, self.weight_decay);
         }
         for (param, (mt, vt)) in world.query_mut::<Option<Param<T>>>().filter(|x| x.is_some()).zip(self.optional_mt_vt.iter_mut()) {
             let param = param.as_mut().unwrap();
-            Self::update_step(param, mt, vt, lr, beta1, beta2, self.t, self.eps);
+            Self::update_step(param, mt, vt, lr, beta1, beta2, self.t, self.eps, self.weight_decay);
         }
         self.t += 1;
     }
+
+    /// Writes the moment buffers and step count to path
+    pub fn save_state(&self, path: &Path) -> Result<()> {
+        let state = AdamState {
+            t: self.t,
+            mt_vt: buffers_to_host(&self.mt_vt),
+            optional_mt_vt: buffers_to_host(&self.optional_mt_vt),
+        };
+        let file = File::create(path).with_context(|| format!("unable to create {}", path.display()))?;
+        ron::ser::to_writer(file, &state)?;
+        Ok(())
+    }
+
+    /// Restores the state written by save_state, the optimizer must have been constructed
+    /// from a world with the same parameter shapes
+    pub fn load_state(&mut self, path: &Path) -> Result<()> {
+        let file = File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+        let state: AdamState = ron::de::from_reader(file)?;
+        let mt_vt = buffers_to_device(&state.mt_vt, &self.mt_vt).context("params do not match saved state")?;
+        let optional_mt_vt = buffers_to_device(&state.optional_mt_vt, &self.optional_mt_vt).context("optional params do not match saved state")?;
+        self.mt_vt = mt_vt;
+        self.optional_mt_vt = optional_mt_vt;
+        self.t = state.t;
+        Ok(())
+    }
 }
 
 
@@ -150,4 +334,208 @@ fn test_convblock() {
 
     let (y, df) = resnet.forward(&x);
     let _grad = df(&mut resnet, &y);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_query_path() {
+    let mut layer = ConvLayer::<f32>::new(3, 8);
+    let mut world = World::from(&mut layer);
+    let all = world.query_mut::<Param<f32>>().count();
+    // conv filter, instance norm gamma and beta for each of the 3 conv blocks
+    assert_eq!(all, 9);
+    let block1: Vec<_> = world.query_mut_with_path::<Param<f32>>()
+        .filter(|(path, _)| path.starts_with("/block1"))
+        .map(|(path, _)| path.to_string())
+        .collect();
+    assert_eq!(block1, ["/block1/conv/filter", "/block1/instance_norm/gamma", "/block1/instance_norm/beta"]);
+    assert_eq!(world.query_path_mut::<Param<f32>>("/block1").count(), 3);
+    assert_eq!(world.query_path_mut::<Param<f32>>("/block1/conv").count(), 1);
+    assert_eq!(world.query_path_mut::<Param<f32>>("/none").count(), 0);
+}
+
+#[test]
+fn test_zero_grad() {
+    let x = randn!(8, 8, 3, 2);
+    let mut layer = ConvLayer::<f32>::new(3, 8);
+    let (y, df) = layer.forward(&x);
+    let _grad = df(&mut layer, &y);
+
+    let mut world = World::from(&mut layer);
+    let nonzero: f64 = world.query_mut::<Param<f32>>().map(|p| sum_all(&abs(&p.g)).0 as f64).sum();
+    assert!(nonzero > 0.0);
+
+    world.zero_grad();
+    for param in world.query_mut::<Param<f32>>() {
+        assert_eq!(count_all(&param.g).0, 0);
+    }
+}
+
+#[test]
+fn test_adam_reference() {
+    let mut param = Param::new(Array::new(&[1.0f64], dim4!(1)));
+    let (beta1, beta2, lr) = (0.9, 0.999, 0.1);
+    let mut adam = {
+        let mut world = World::from(&mut param);
+        Adam::new(&mut world, beta1, beta2)
+    };
+    let eps = adam.eps;
+    let grads = [0.5, -1.0, 2.0, 0.25];
+
+    // reference scalar adam
+    let (mut w, mut m, mut v) = (1.0f64, 0.0f64, 0.0f64);
+    for (t, g) in grads.iter().enumerate() {
+        m = beta1 * m + (1.0 - beta1) * g;
+        v = beta2 * v + (1.0 - beta2) * g * g;
+        let mhat = m / (1.0 - beta1.powi(t as i32 + 1));
+        let vhat = v / (1.0 - beta2.powi(t as i32 + 1));
+        w -= lr * mhat / (vhat.sqrt() + eps);
+
+        param.g = Array::new(&[*g], dim4!(1));
+        let mut world = World::from(&mut param);
+        adam.update(&mut world, lr);
+        let mut host = [0.0f64];
+        param.w.host(&mut host);
+        assert!((host[0] - w).abs() < 1e-10, "step {}: expected {}, got {}", t, w, host[0]);
+    }
+}
+
+#[test]
+fn test_adamw() {
+    fn train(weight_decay: f32) -> f32 {
+        set_seed(0);
+        let mut block = ConvBlock::<f32>::new(3, 4);
+        let x = randn!(8, 8, 3, 2);
+        let mut adam = Adam::new(&mut World::from(&mut block), 0.9, 0.999).with_weight_decay(weight_decay);
+        for _ in 0..20 {
+            let (y, df) = block.forward(&x);
+            df(&mut block, &y);
+            let mut world = World::from(&mut block);
+            adam.update(&mut world, 0.01);
+            world.zero_grad();
+        }
+        let mut world = World::from(&mut block);
+        let norm: f32 = world.query_mut::<Param<f32>>().map(|p| sum_all(&(&p.w * &p.w)).0).sum();
+        norm
+    }
+    assert!(train(0.5) < train(0.0));
+}
+
+#[test]
+fn test_sgd_momentum() {
+    let (lr, momentum) = (0.1, 0.9);
+    let grads = [0.5, -1.0, 2.0, 0.25];
+    for nesterov in [false, true] {
+        let mut param = Param::new(Array::new(&[1.0f64], dim4!(1)));
+        let mut sgd = SGDSimple::new(lr).with_momentum(momentum, nesterov);
+
+        // reference scalar sgd with momentum
+        let (mut w, mut v) = (1.0f64, 0.0f64);
+        for (t, g) in grads.iter().enumerate() {
+            v = momentum * v + g;
+            w -= if nesterov { lr * (g + momentum * v) } else { lr * v };
+
+            param.g = Array::new(&[*g], dim4!(1));
+            sgd.update(&mut World::from(&mut param));
+            let mut host = [0.0f64];
+            param.w.host(&mut host);
+            assert!((host[0] - w).abs() < 1e-12, "step {}: expected {}, got {}", t, w, host[0]);
+            sgd.velocity[0].host(&mut host);
+            assert!((host[0] - v).abs() < 1e-12);
+        }
+    }
+}
+
+#[test]
+fn test_clip_grad_norm() {
+    struct Params {
+        a: Param<f64>,
+        b: Option<Param<f64>>,
+    }
+    impl Flatten for Params {
+        fn flatten<'a>(&'a mut self, path: String, world: &mut World<'a>) {
+            self.a.flatten(path.clone() + "/a", world);
+            self.b.flatten(path + "/b", world);
+        }
+    }
+    let mut params = Params {
+        a: Param::new(Array::new(&[0.0f64, 0.0], dim4!(2))),
+        b: Some(Param::new(Array::new(&[0.0f64], dim4!(1)))),
+    };
+    // global norm is sqrt(3^2 + 4^2 + 12^2) = 13
+    params.a.g = Array::new(&[3.0, 4.0], dim4!(2));
+    params.b.as_mut().unwrap().g = Array::new(&[12.0], dim4!(1));
+
+    // under the threshold gradients are untouched
+    let norm = clip_grad_norm(&mut World::from(&mut params), 20.0f64);
+    assert!((norm - 13.0).abs() < 1e-12);
+    let mut host = [0.0f64; 2];
+    params.a.g.host(&mut host);
+    assert_eq!(host, [3.0, 4.0]);
+
+    let norm = clip_grad_norm(&mut World::from(&mut params), 6.5f64);
+    assert!((norm - 13.0).abs() < 1e-12);
+    params.a.g.host(&mut host);
+    assert!((host[0] - 1.5).abs() < 1e-12 && (host[1] - 2.0).abs() < 1e-12);
+    let mut host = [0.0f64];
+    params.b.as_ref().unwrap().g.host(&mut host);
+    assert!((host[0] - 6.0).abs() < 1e-12);
+
+    let norm = clip_grad_norm(&mut World::from(&mut params), 6.5f64);
+    assert!((norm - 6.5).abs() < 1e-12);
+}
+
+#[test]
+fn test_adam_state_round_trip() {
+    fn step(param: &mut Param<f64>, adam: &mut Adam<f64>, g: f64) {
+        param.g = Array::new(&[g, -g], dim4!(2));
+        adam.update(&mut World::from(param), 0.1);
+    }
+    let path = std::env::temp_dir().join("grownet_test_adam_state.ron");
+    let grads = [0.5, -1.0, 2.0, 0.25, 1.5, -0.5];
+    let mut param = Param::new(Array::new(&[1.0f64, 2.0], dim4!(2)));
+    let mut adam = Adam::new(&mut World::from(&mut param), 0.9, 0.999);
+    for g in &grads[..3] {
+        step(&mut param, &mut adam, *g);
+    }
+    adam.save_state(&path).unwrap();
+
+    let mut resumed = Param::new(param.w.copy());
+    let mut restored = Adam::new(&mut World::from(&mut resumed), 0.9, 0.999);
+    restored.load_state(&path).unwrap();
+    assert_eq!(restored.t, 3);
+    for g in &grads[3..] {
+        step(&mut param, &mut adam, *g);
+        step(&mut resumed, &mut restored, *g);
+    }
+    let (mut a, mut b) = ([0.0f64; 2], [0.0f64; 2]);
+    param.w.host(&mut a);
+    resumed.w.host(&mut b);
+    assert_eq!(a, b);
+
+    // an optimizer over params of a different shape is rejected
+    let mut other = Param::new(Array::new(&[1.0f64, 2.0, 3.0], dim4!(3)));
+    let mut mismatched = Adam::new(&mut World::from(&mut other), 0.9, 0.999);
+    assert!(mismatched.load_state(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_flatten_layer() {
+    set_backend(Backend::CPU);
+    let data: Vec<f32> = (0..48).map(|x| x as f32).collect();
+    let x = Array::new(&data, dim4!(2, 3, 4, 2));
+    let layer = FlattenLayer::new();
+    let (y, df) = layer.forward(&x);
+    assert_eq!(y.dims(), dim4!(24, 2));
+    assert_eq!(y.dims(), layer.output_shape(x.dims()));
+    // column major, so each column holds the features of one batch element
+    let mut out = [0.0f32; 48];
+    y.host(&mut out);
+    assert_eq!(out.to_vec(), data);
+
+    let dx = df(&y);
+    assert_eq!(dx.dims(), x.dims());
+    let mut grad = [0.0f32; 48];
+    dx.host(&mut grad);
+    assert_eq!(grad.to_vec(), data);
+}
@@ -0,0 +1,273 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Config;
+
+/// A learning rate schedule that is a function of the current step
+pub trait LrScheduler {
+    fn lr(&mut self, step: usize) -> f64;
+}
+
+/// Decays the lr by gamma every step_size steps
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StepLr {
+    pub base: f64,
+    pub step_size: usize,
+    pub gamma: f64,
+}
+
+impl StepLr {
+    pub fn new(base: f64, step_size: usize, gamma: f64) -> Self {
+        assert!(step_size > 0, "step size must be positive");
+        Self { base, step_size, gamma }
+    }
+}
+
+impl LrScheduler for StepLr {
+    fn lr(&mut self, step: usize) -> f64 {
+        self.base * self.gamma.powi((step / self.step_size) as i32)
+    }
+}
+
+/// Anneals the lr from base to min following half a cosine over t_max steps, staying at min afterwards
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CosineAnnealing {
+    pub base: f64,
+    pub min: f64,
+    pub t_max: usize,
+}
+
+impl CosineAnnealing {
+    pub fn new(base: f64, min: f64, t_max: usize) -> Self {
+        assert!(t_max > 0, "t_max must be positive");
+        Self { base, min, t_max }
+    }
+}
+
+impl LrScheduler for CosineAnnealing {
+    fn lr(&mut self, step: usize) -> f64 {
+        let ratio = step.min(self.t_max) as f64 / self.t_max as f64;
+        self.min + (self.base - self.min) * (1.0 + (PI * ratio).cos()) / 2.0
+    }
+}
+
+/// Linearly increases the lr from base / warmup_steps to base over the first warmup_steps steps,
+/// staying at base afterwards
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinearWarmup {
+    pub base: f64,
+    pub warmup_steps: usize,
+}
+
+impl LinearWarmup {
+    pub fn new(base: f64, warmup_steps: usize) -> Self {
+        Self { base, warmup_steps }
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    fn lr(&mut self, step: usize) -> f64 {
+        if step >= self.warmup_steps {
+            self.base
+        } else {
+            self.base * (step + 1) as f64 / self.warmup_steps as f64
+        }
+    }
+}
+
+/// Cosine annealing with warm restarts (SGDR), the lr is annealed from `base` to `min`
+/// over a cycle of `t0` steps, after which it resets to `base` and the cycle length
+/// is multiplied by `t_mult`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WarmRestarts {
+    pub base: f64,
+    pub min: f64,
+    pub t0: usize,
+    pub t_mult: usize,
+}
+
+impl WarmRestarts {
+    pub fn new(base: f64, min: f64, t0: usize, t_mult: usize) -> Self {
+        assert!(t0 > 0, "initial cycle length must be positive");
+        assert!(t_mult > 0, "cycle multiplier must be positive");
+        Self { base, min, t0, t_mult }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            config.uget("base").into(),
+            config.uget("min").into(),
+            config.uget("t0").into(),
+            config.uget("t_mult").into(),
+        )
+    }
+
+    pub fn config() -> Config {
+        use crate::{config, opt, Options};
+        config!(("base", 0.01), ("min", 0.0), ("t0", 1000), ("t_mult", 2))
+    }
+
+    /// returns the position within the current cycle and the length of the current cycle
+    fn cycle(&self, step: usize) -> (usize, usize) {
+        let mut t_cur = step;
+        let mut t_i = self.t0;
+        while t_cur >= t_i {
+            t_cur -= t_i;
+            t_i *= self.t_mult;
+        }
+        (t_cur, t_i)
+    }
+}
+
+impl LrScheduler for WarmRestarts {
+    fn lr(&mut self, step: usize) -> f64 {
+        let (t_cur, t_i) = self.cycle(step);
+        let ratio = t_cur as f64 / t_i as f64;
+        self.min + (self.base - self.min) * (1.0 + (PI * ratio).cos()) / 2.0
+    }
+}
+
+/// Whether a monitored metric is improving when it decreases or when it increases
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlateauMode {
+    Min,
+    Max,
+}
+
+/// Reduces the lr by `factor` once the monitored metric has not improved for `patience`
+/// consecutive observations, never going below `min_lr`. Unlike `LrScheduler`, this depends
+/// on the metric stream, so metrics are fed in with `observe` before reading `lr`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReduceOnPlateau {
+    pub factor: f64,
+    pub patience: usize,
+    pub min_lr: f64,
+    pub mode: PlateauMode,
+    lr: f64,
+    best: Option<f64>,
+    num_bad: usize,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(lr: f64, factor: f64, patience: usize, min_lr: f64, mode: PlateauMode) -> Self {
+        assert!(factor > 0.0 && factor < 1.0, "factor must be in (0, 1)");
+        Self { factor, patience, min_lr, mode, lr, best: None, num_bad: 0 }
+    }
+
+    fn is_better(&self, metric: f64, best: f64) -> bool {
+        match self.mode {
+            PlateauMode::Min => metric < best,
+            PlateauMode::Max => metric > best,
+        }
+    }
+
+    /// records a new value of the monitored metric, reducing the lr if it has plateaued
+    pub fn observe(&mut self, metric: f64) {
+        match self.best {
+            Some(best) if !self.is_better(metric, best) => {
+                self.num_bad += 1;
+            }
+            _ => {
+                self.best = Some(metric);
+                self.num_bad = 0;
+            }
+        }
+        if self.num_bad > self.patience {
+            self.lr = (self.lr * self.factor).max(self.min_lr);
+            self.num_bad = 0;
+        }
+    }
+
+    pub fn lr(&self) -> f64 {
+        self.lr
+    }
+}
+
+#[cfg(test)]
+fn is_close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-12
+}
+
+#[test]
+fn test_step_lr() {
+    let mut sched = StepLr::new(0.1, 10, 0.5);
+    assert!(is_close(sched.lr(0), 0.1));
+    assert!(is_close(sched.lr(9), 0.1));
+    assert!(is_close(sched.lr(10), 0.05));
+    assert!(is_close(sched.lr(25), 0.025));
+}
+
+#[test]
+fn test_cosine_annealing() {
+    let mut sched = CosineAnnealing::new(0.1, 0.0, 100);
+    assert!(is_close(sched.lr(0), 0.1));
+    assert!(is_close(sched.lr(50), 0.05));
+    assert!(is_close(sched.lr(100), 0.0));
+    assert!(is_close(sched.lr(200), 0.0));
+    assert!(sched.lr(25) > sched.lr(75));
+}
+
+#[test]
+fn test_linear_warmup() {
+    let mut sched = LinearWarmup::new(0.1, 10);
+    assert!(is_close(sched.lr(0), 0.01));
+    assert!(is_close(sched.lr(4), 0.05));
+    assert!(is_close(sched.lr(9), 0.1));
+    assert!(is_close(sched.lr(1000), 0.1));
+}
+
+#[test]
+fn test_warm_restarts() {
+    let mut sched = WarmRestarts::new(0.1, 0.001, 10, 2);
+    // restarts happen at 0, 10, 30, 70, with cycle lengths 10, 20, 40
+    let boundaries = [0, 10, 30, 70];
+    for &b in boundaries.iter() {
+        assert!((sched.lr(b) - 0.1).abs() < 1e-12);
+        if b > 0 {
+            // just before a restart the lr is close to min
+            assert!(sched.lr(b - 1) < 0.01);
+        }
+    }
+    for w in boundaries.windows(3) {
+        assert_eq!(w[2] - w[1], (w[1] - w[0]) * 2);
+    }
+    for (i, w) in boundaries.windows(2).enumerate() {
+        assert_eq!(sched.cycle(w[0]), (0, 10 * 2usize.pow(i as u32)));
+        assert_eq!(sched.cycle(w[1] - 1).1, w[1] - w[0]);
+    }
+
+    let s = ron::to_string(&sched).unwrap();
+    let sched2: WarmRestarts = ron::from_str(&s).unwrap();
+    assert_eq!(sched, sched2);
+}
+
+#[test]
+fn test_reduce_on_plateau() {
+    let mut sched = ReduceOnPlateau::new(0.1, 0.5, 2, 0.02, PlateauMode::Min);
+    sched.observe(1.0);
+    sched.observe(0.5);
+    assert_eq!(sched.lr(), 0.1);
+    // metric stalls, lr is held for `patience` observations then dropped
+    sched.observe(0.5);
+    sched.observe(0.6);
+    assert_eq!(sched.lr(), 0.1);
+    sched.observe(0.7);
+    assert!((sched.lr() - 0.05).abs() < 1e-12);
+    // an improvement resets the patience counter
+    sched.observe(0.4);
+    sched.observe(0.4);
+    sched.observe(0.4);
+    assert!((sched.lr() - 0.05).abs() < 1e-12);
+    for _ in 0..20 {
+        sched.observe(0.4);
+    }
+    assert_eq!(sched.lr(), 0.02);
+
+    let mut sched = ReduceOnPlateau::new(0.1, 0.5, 0, 0.0, PlateauMode::Max);
+    sched.observe(0.5);
+    sched.observe(0.6);
+    assert_eq!(sched.lr(), 0.1);
+    sched.observe(0.6);
+    assert!((sched.lr() - 0.05).abs() < 1e-12);
+}
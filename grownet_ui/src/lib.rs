@@ -16,53 +16,317 @@ use num::Num;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 const ROOT_CONFIG_PATH: &'static str = "assets/config";
+/// checkpoints of each model are saved in a subfolder named after the model
+pub const CHECKPOINT_ROOT: &'static str = "assets/checkpoints";
 
-pub use run_systems::{config_ui_adjust, config_ui_show};
+pub use run_systems::{config_ui_adjust, config_ui_adjust_diff, config_ui_show};
 
 
+/// Parameters which can be shown and edited in an egui ui
+pub trait UI {
+    fn ui(&mut self, ui: &mut egui::Ui);
+}
+
+/// What Serializer::serialize does when a path was already saved to during this session
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    Error,
+    Overwrite,
+    Skip,
+}
+
+/// The file format used by Serializer, bincode is compact, while ron and json are human readable,
+/// json requires maps to have string keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Bincode,
+    Ron,
+    Json,
+}
+
+impl Format {
+    const ALL: [Format; 3] = [Format::Bincode, Format::Ron, Format::Json];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Bincode => "bin",
+            Format::Ron => "ron",
+            Format::Json => "json",
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Format> {
+        Self::ALL.into_iter().find(|x| x.extension() == ext)
+    }
+
+    fn write<T: Serialize>(&self, writer: impl std::io::Write, x: &T) -> Result<()> {
+        match self {
+            Format::Bincode => bincode::serialize_into(writer, x)?,
+            Format::Ron => ron::ser::to_writer_pretty(writer, x, ron::ser::PrettyConfig::default())?,
+            Format::Json => serde_json::to_writer_pretty(writer, x)?,
+        }
+        Ok(())
+    }
+
+    fn read<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        Ok(match self {
+            Format::Bincode => bincode::deserialize(bytes)?,
+            Format::Ron => ron::de::from_bytes(bytes)?,
+            Format::Json => serde_json::from_slice(bytes)?,
+        })
+    }
+}
+
 #[derive(Resource)]
 pub struct Serializer {
     root_path: PathBuf, // the source folder to save all app state
     saved_paths: HashSet<PathBuf>, // path already saved
+    policy: OverwritePolicy,
+    format: Format, // the format new files are written in
 }
 
 impl Default for Serializer {
     fn default() -> Self {
-        let root_path = PathBuf::from(ROOT_CONFIG_PATH);
+        Self::new(PathBuf::from(ROOT_CONFIG_PATH))
+    }
+}
+
+impl Serializer {
+    pub fn new(root_path: PathBuf) -> Self {
         if !root_path.exists() {
             std::fs::create_dir_all(&root_path).expect("unable to setup path manager");
         }
-        Self { root_path, saved_paths: HashSet::new() }
+        Self { root_path, saved_paths: HashSet::new(), policy: OverwritePolicy::Overwrite, format: Format::Bincode }
     }
-}
 
-impl Serializer {
-    pub fn serialize<T: Serialize>(&mut self, path: &str, x: &T) {
-        let qualifed_path = self.root_path.join(path);
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// writes x to path, with the extension of the configured format
+    pub fn serialize<T: Serialize>(&mut self, path: &str, x: &T) -> Result<()> {
+        let qualifed_path = self.root_path.join(path).with_extension(self.format.extension());
         if self.saved_paths.contains(&qualifed_path) {
-            panic!("path {} already exists", qualifed_path.display());
-        } else {
-            if !qualifed_path.parent().expect(&format!("path {} does not have a parent", qualifed_path.display())).exists() {
-                std::fs::create_dir_all(qualifed_path.parent().unwrap()).expect("failed to create directory for serialize");
+            match self.policy {
+                OverwritePolicy::Error => anyhow::bail!("path {} was already saved to", qualifed_path.display()),
+                OverwritePolicy::Skip => return Ok(()),
+                OverwritePolicy::Overwrite => {}
             }
-            let train_data_writer = std::fs::File::create(&qualifed_path).unwrap();
-            println!("serializing to {}", qualifed_path.display());
-            bincode::serialize_into(train_data_writer, x).expect("unable to serialize");
-            self.saved_paths.insert(qualifed_path);
         }
+        let parent = qualifed_path.parent().context(format!("path {} does not have a parent", qualifed_path.display()))?;
+        std::fs::create_dir_all(parent).context("failed to create directory for serialize")?;
+        println!("serializing to {}", qualifed_path.display());
+        // write to a temporary sibling first, so that an interrupted write never truncates the previous file
+        let tmp_path = with_suffix(&qualifed_path, "tmp");
+        let written = std::fs::File::create(&tmp_path)
+            .context(format!("unable to create {}", tmp_path.display()))
+            .and_then(|file| {
+                let mut writer = std::io::BufWriter::new(file);
+                self.format.write(&mut writer, x).context(format!("unable to serialize to {}", qualifed_path.display()))?;
+                let file = writer.into_inner().context("unable to flush serialized data")?;
+                file.sync_all().context("unable to sync serialized data")?;
+                Ok(())
+            });
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        if qualifed_path.is_file() {
+            std::fs::copy(&qualifed_path, with_suffix(&qualifed_path, "bak"))
+                .context(format!("unable to back up {}", qualifed_path.display()))?;
+        }
+        std::fs::rename(&tmp_path, &qualifed_path)
+            .context(format!("unable to move {} to {}", tmp_path.display(), qualifed_path.display()))?;
+        self.saved_paths.insert(qualifed_path);
+        Ok(())
     }
 
+    /// the file saved at path and its format, files with the extension of the configured format
+    /// are preferred, files without an extension are assumed to be in the configured format
+    fn find(&self, path: &str) -> Option<(PathBuf, Format)> {
+        let path = self.root_path.join(path);
+        std::iter::once(self.format).chain(Format::ALL)
+            .map(|format| (path.with_extension(format.extension()), format))
+            .chain(std::iter::once((path.clone(), self.format)))
+            .find(|(path, _)| path.is_file())
+    }
+
+    /// deserializes into x from the file at path, falling back to its .bak copy if the file is corrupt,
+    /// x is left unchanged if neither can be read
     pub fn deserialize<T: DeserializeOwned>(&self, path: &str, x: &mut T) {
-        let qualifed_path = self.root_path.join(path);
-        if qualifed_path.exists() {
+        self.deserialize_with(path, x, None);
+    }
+
+    /// same as deserialize, but if decoding fails, the raw bytes are passed to migrate, 
+    /// which can transform older formats into the current one before decoding is retried
+    pub fn deserialize_with<T: DeserializeOwned>(&self, path: &str, x: &mut T, migrate: Option<&dyn Fn(&[u8]) -> Result<Vec<u8>>>) {
+        if let Some((qualifed_path, format)) = self.find(path) {
             println!("deserializing from {}", qualifed_path.display());
-            let reader = std::fs::File::open(&qualifed_path).expect("unable to open file");
-            match bincode::deserialize_from::<std::fs::File, T>(reader) {
+            let read = |path: &PathBuf| -> Result<T> {
+                let bytes = std::fs::read(path).context(format!("unable to open {}", path.display()))?;
+                match (format.read::<T>(&bytes), migrate) {
+                    (Ok(de), _) => Ok(de),
+                    (Err(e), None) => Err(e),
+                    (Err(e), Some(migrate)) => {
+                        eprintln!("migrating {} after failing to deserialize due to {e}", path.display());
+                        let migrated = migrate(&bytes).context(format!("unable to migrate {}", path.display()))?;
+                        format.read::<T>(&migrated)
+                    }
+                }
+            };
+            match read(&qualifed_path) {
                 Ok(de) => { *x = de; },
                 Err(e) => {
                     eprintln!("failed to deserializing from {} due to {e}", qualifed_path.display());
+                    let bak_path = with_suffix(&qualifed_path, "bak");
+                    if bak_path.is_file() {
+                        match read(&bak_path) {
+                            Ok(de) => { *x = de; },
+                            Err(e) => eprintln!("failed to deserializing from {} due to {e}", bak_path.display()),
+                        }
+                    }
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_overwrite_policy() {
+    let root = std::env::temp_dir().join("grownet_test_overwrite_policy");
+    let _ = std::fs::remove_dir_all(&root);
+    let read = |path: &str| -> u32 {
+        let mut x = 0;
+        Serializer::new(root.clone()).deserialize(path, &mut x);
+        x
+    };
+
+    let mut serializer = Serializer::new(root.clone());
+    serializer.serialize("overwrite/a", &1u32).unwrap();
+    serializer.serialize("overwrite/a", &2u32).unwrap();
+    assert_eq!(read("overwrite/a"), 2);
+
+    let mut serializer = Serializer::new(root.clone()).with_policy(OverwritePolicy::Skip);
+    serializer.serialize("skip", &1u32).unwrap();
+    serializer.serialize("skip", &2u32).unwrap();
+    assert_eq!(read("skip"), 1);
+
+    let mut serializer = Serializer::new(root.clone()).with_policy(OverwritePolicy::Error);
+    serializer.serialize("error", &1u32).unwrap();
+    assert!(serializer.serialize("error", &2u32).is_err());
+    assert_eq!(read("error"), 1);
+    // a path saved in another session is not a duplicate
+    let mut serializer = Serializer::new(root.clone()).with_policy(OverwritePolicy::Error);
+    serializer.serialize("error", &3u32).unwrap();
+    assert_eq!(read("error"), 3);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+/// appends .suffix to the file name of path, keeping its extension
+fn with_suffix(path: &std::path::Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[test]
+fn test_formats() {
+    use std::collections::HashMap;
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+    enum Kind { #[default] A, B(f64) }
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+    struct State { name: String, values: Vec<f64>, counts: HashMap<String, u32>, kind: Kind, path: Option<PathBuf> }
+
+    let root = std::env::temp_dir().join("grownet_test_formats");
+    let _ = std::fs::remove_dir_all(&root);
+    let state = State { 
+        name: "run".into(), values: vec![0.1, -2.5], counts: HashMap::from([("a".into(), 1)]),
+        kind: Kind::B(1e-5), path: Some("assets/a".into())
+    };
+    for format in Format::ALL {
+        let mut serializer = Serializer::new(root.clone()).with_format(format);
+        serializer.serialize(format.extension(), &state).unwrap();
+        assert!(root.join(format.extension()).with_extension(format.extension()).is_file());
+        let mut loaded = State::default();
+        serializer.deserialize(format.extension(), &mut loaded);
+        assert_eq!(loaded, state);
+        // the format is picked from the extension
+        let mut loaded = State::default();
+        Serializer::new(root.clone()).deserialize(format.extension(), &mut loaded);
+        assert_eq!(loaded, state);
+    }
+    assert_eq!(Format::from_extension("ron"), Some(Format::Ron));
+    assert_eq!(Format::from_extension("txt"), None);
+
+    // files saved before formats were added have no extension, and are in bincode
+    std::fs::write(root.join("legacy"), bincode::serialize(&state).unwrap()).unwrap();
+    let mut loaded = State::default();
+    Serializer::new(root.clone()).deserialize("legacy", &mut loaded);
+    assert_eq!(loaded, state);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_atomic_serialize() {
+    // fails halfway through serialization, after some bytes have been written
+    struct Interrupted;
+    impl Serialize for Interrupted {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            use serde::ser::{SerializeSeq, Error};
+            let mut seq = serializer.serialize_seq(Some(2))?;
+            seq.serialize_element(&vec![1.0f64; 1000])?;
+            Err(S::Error::custom("interrupted"))
+        }
+    }
+
+    let root = std::env::temp_dir().join("grownet_test_atomic_serialize");
+    let _ = std::fs::remove_dir_all(&root);
+    let mut serializer = Serializer::new(root.clone()).with_format(Format::Ron);
+    serializer.serialize("state", &vec![vec![1.0f64], vec![2.0]]).unwrap();
+    assert!(serializer.serialize("state", &Interrupted).is_err());
+    assert!(!root.join("state.ron.tmp").exists());
+    let mut loaded: Vec<Vec<f64>> = Vec::new();
+    serializer.deserialize("state", &mut loaded);
+    assert_eq!(loaded, vec![vec![1.0], vec![2.0]]);
+
+    // the previous file is kept as a backup, which is used if the file is corrupted
+    serializer.serialize("state", &vec![vec![3.0f64]]).unwrap();
+    std::fs::write(root.join("state.ron"), "[[3.0").unwrap();
+    let mut loaded: Vec<Vec<f64>> = Vec::new();
+    serializer.deserialize("state", &mut loaded);
+    assert_eq!(loaded, vec![vec![1.0], vec![2.0]]);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_deserialize_migration() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+    struct Old { name: String }
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+    struct New { name: String, steps: u32 }
+
+    let root = std::env::temp_dir().join("grownet_test_deserialize_migration");
+    let _ = std::fs::remove_dir_all(&root);
+    let mut serializer = Serializer::new(root.clone());
+    serializer.serialize("state", &Old { name: "run".into() }).unwrap();
+
+    // without a migration, the target is left unchanged
+    let mut loaded = New::default();
+    serializer.deserialize("state", &mut loaded);
+    assert_eq!(loaded, New::default());
+
+    let migrate = |bytes: &[u8]| -> Result<Vec<u8>> {
+        let old: Old = bincode::deserialize(bytes)?;
+        Ok(bincode::serialize(&New { name: old.name, steps: 0 })?)
+    };
+    serializer.deserialize_with("state", &mut loaded, Some(&migrate));
+    assert_eq!(loaded, New { name: "run".into(), steps: 0 });
+    std::fs::remove_dir_all(&root).unwrap();
+}
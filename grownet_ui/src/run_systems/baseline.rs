@@ -1,9 +1,12 @@
+use std::path::PathBuf;
+
 use bevy::prelude::*;
 use anyhow::Result;
 
 use crate::ui::train_ui::{self as ui};
 use super::run_data::{self as run, Despawn, Kill, SpawnRun};
 use super::{Config};
+use run::models::baselinev3::BoxedScheduler;
 
 pub struct BaselinePlugin;
 impl Plugin for BaselinePlugin {
@@ -17,6 +20,10 @@ impl Plugin for BaselinePlugin {
 #[derive(Component, Deref, DerefMut)]
 struct BaseTrainProcess(run::models::TrainProcess);
 
+/// the event log of a run, under the checkpoint folder
+#[derive(Component, Deref, DerefMut)]
+struct RunLog(run::models::EventLog);
+
 #[derive(Resource)]
 struct BaselineProcess {
     run_sender: run::RunSend
@@ -33,17 +40,22 @@ fn run_baseline(
     mut plots: ResMut<run::ModelPlots>,
     mut console: ResMut<run::Console>,
     mut run_stats: ResMut<run::RunStats>,
-    mut runs: Query<(Entity, &mut run::RunInfo, &mut BaseTrainProcess)>,
+    mut confusion: ResMut<run::ConfusionViewer>,
+    mut runs: Query<(Entity, &mut run::RunInfo, &mut BaseTrainProcess, &mut RunLog)>,
     run_sender: ResMut<BaselineProcess>,
 ) {
     use run::{TrainRecv};
-    for (id, info, mut train_proc) in runs.iter_mut() {
+    for (id, info, mut train_proc, mut log) in runs.iter_mut() {
         if train_proc.is_running() {
             let msgs = train_proc.try_recv();
             for msg in msgs {
+                if let Err(e) = log.log(&msg) {
+                    console.error(format!("Error {} while logging {} to {}", e, info.run_name(), log.path().display()));
+                }
                 match msg {
                     TrainRecv::PLOT(point) => {
-                        console.log(format!("Logged {}, {}: {}, {}: {}", point.title, point.x_title, point.x, point.y_title, point.y));
+                        console.info(format!("Logged {}, {}: {}, {}: {}", point.title, point.x_title, point.x, point.y_title, point.y));
+                        plots.set_tags(&info.run_name(), &info.tags);
                         plots.add_point(&run::PlotId { 
                             model: run::Models::BASELINE, 
                             run_name: info.run_name(), 
@@ -53,16 +65,26 @@ fn run_baseline(
                          }, (point.x, point.y));
                     }
                     TrainRecv::FAILED(err_msg) => {
-                        console.log(format!("Error {} while training {}", err_msg, info.run_name()));
+                        console.error(format!("Error {} while training {}", err_msg, info.run_name()));
+                        let _ = log.flush();
                         // the training run has failed => thread exited => free resources
                         despawner.send(Despawn(id));
                         let mut info = info.clone();
                         info.err_status = Some(err_msg);
                         run_sender.run_sender.send(run::RunId(run::Models::BASELINE, info, id)).expect("unable to send baseline run info");
                     },
+                    TrainRecv::LOG { level, msg } => {
+                        console.log(level, format!("{}: {}", info.run_name(), msg));
+                    }
                     TrainRecv::STATS(stats) => {
                         run_stats.update(id, stats);
                     }
+                    TrainRecv::MARKER { title, x } => {
+                        plots.add_marker(run::Models::BASELINE, &info.run_name(), title, x);
+                    }
+                    TrainRecv::CONFUSION(matrix) => {
+                        confusion.update(info.run_name(), matrix);
+                    }
                     // TrainRecv::CHECKPOINT(step, path) => {
                     //     console.log(format!("saving checkpoint for {} at step {}", info.run_name(), step));
                     //     console.log(format!("saving to {}", path.to_str().unwrap()));
@@ -71,7 +93,10 @@ fn run_baseline(
                 }
             }
         } else {
-            console.log(format!("{} finished training", info.run_name()));
+            console.info(format!("{} finished training", info.run_name()));
+            if let Err(e) = log.flush() {
+                console.error(format!("Error {} while logging {}", e, info.run_name()));
+            }
             let mut info = info.clone();
             info.err_status = None;
             run_sender.run_sender.send(run::RunId(run::Models::BASELINE, info, id)).expect("unable to send baseline run info");
@@ -81,7 +106,7 @@ fn run_baseline(
     // detects if any needs to be killed
     // not the most efficient, but there aren't that many runs
     for i in killer.iter() {
-        for (id, _, mut run) in runs.iter_mut() {
+        for (id, _, mut run, _) in runs.iter_mut() {
             if i.0 == id {
                 run.try_kill();
                 break;
@@ -90,23 +115,82 @@ fn run_baseline(
     }
 }
 
-pub fn baseline_spawn_fn(version_num: usize, mut config: Config, global_config: Config) -> (Box<dyn FnOnce(&mut Commands) -> Result<Entity> + Send + Sync>, run::RunInfo) {
+/// if checkpoint is a path, the run resumes training from that checkpoint, the run is labelled with tags.
+/// Every spawn attempt trains with a fresh clone of the scheduler
+pub fn baseline_spawn_fn(version_num: usize, config: Config, global_config: Config, tags: Vec<String>, scheduler: Option<BoxedScheduler>, checkpoint: Option<PathBuf>) -> (SpawnRun, run::RunInfo) {
+    baseline_spawn_fn_with(version_num, config, global_config, tags, scheduler, checkpoint, run::models::baselinev3::run_train_loop)
+}
+
+/// same as baseline_spawn_fn, but the training process is started by launch
+fn baseline_spawn_fn_with<F>(version_num: usize, mut config: Config, global_config: Config, tags: Vec<String>, scheduler: Option<BoxedScheduler>, checkpoint: Option<PathBuf>, mut launch: F) -> (SpawnRun, run::RunInfo)
+where F: FnMut(&Config, Option<BoxedScheduler>, Option<PathBuf>) -> Result<run::TrainProcess> + Send + Sync + 'static {
     let runinfo = run::RunInfo {
         model_class: "baseline".into(),
         version: version_num,
         dataset: "mnist".into(),
         config: config.clone(),
+        tags,
+        resume_from: checkpoint.clone(),
         ..Default::default()
     };
     config.disjoint_union(&global_config).expect("global_config and config overlap");
+    if config.get("checkpoint_path").is_none() {
+        let folder = std::path::Path::new(crate::CHECKPOINT_ROOT).join("baseline");
+        config.insert("checkpoint_path", &model_lib::Options::STR(folder.to_string_lossy().into())).unwrap();
+    }
     let run_info = runinfo.clone();
     let spawn_fn = Box::new(move |commands: &mut Commands| -> Result<Entity> {
-        let config = config;
-        run::models::baselinev3::run_train_loop(&config).map(|x| {
+        let folder: String = config.uget("checkpoint_path").into();
+        let log = run::models::EventLog::new(std::path::Path::new(&folder).join(run_info.run_name()).with_extension("jsonl"))?;
+        launch(&config, scheduler.as_ref().map(|x| x.clone_boxed()), checkpoint.clone()).map(|x| {
             let env = BaseTrainProcess(x);
-            let id = commands.spawn((run_info, env)).id();
+            let id = commands.spawn((run_info.clone(), env, RunLog(log))).id();
             id
         })
     });
     (spawn_fn, runinfo)
-}
\ No newline at end of file
+}
+
+/// The layers of the baseline model that run_train_loop trains, for a single mnist sized image
+pub fn baseline_summary() -> Vec<run::models::LayerSummary> {
+    run::models::baselinev3::mnist_summary()
+}
+
+#[test]
+fn test_spawn_retry_keeps_scheduler() {
+    use std::sync::{Arc, Mutex};
+    use bevy::ecs::system::CommandQueue;
+    use model_lib::nn::schedulers::StepLr;
+    use model_lib::Options;
+
+    let folder = std::env::temp_dir().join("grownet_test_spawn_retry_keeps_scheduler");
+    let mut config = run::models::baselinev3::baseline_config();
+    config.insert("checkpoint_path", &Options::STR(folder.to_string_lossy().into())).unwrap();
+    // the lr at step 10 given to each launch attempt, every attempt fails
+    let lrs = Arc::new(Mutex::new(Vec::new()));
+    let attempts = lrs.clone();
+    let launch = move |_: &Config, scheduler: Option<BoxedScheduler>, _: Option<PathBuf>| -> Result<run::TrainProcess> {
+        attempts.lock().unwrap().push(scheduler.map(|mut x| x.lr(10)));
+        Err(anyhow::Error::msg("device busy"))
+    };
+    let scheduler: BoxedScheduler = Box::new(StepLr::new(1.0, 5, 0.5));
+    let (mut spawn_fn, _) = baseline_spawn_fn_with(0, config, Config::new(vec![]), vec![], Some(scheduler), None, launch);
+
+    let world = World::new();
+    let mut command_queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut command_queue, &world);
+    assert!(spawn_fn(&mut commands).is_err());
+    // the retry trains with the same schedule as the first attempt
+    assert!(spawn_fn(&mut commands).is_err());
+    assert_eq!(*lrs.lock().unwrap(), [Some(0.25), Some(0.25)]);
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_baseline_summary() {
+    let summary = baseline_summary();
+    let names: Vec<_> = summary.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, ["conv1", "conv2", "conv3", "flatten", "fc1", "fc2"]);
+    assert_eq!(summary[0].output_shape, [1, 8, 26, 26]);
+    assert_eq!(summary.last().unwrap().output_shape, [1, 10]);
+}
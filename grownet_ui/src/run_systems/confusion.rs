@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use model_lib::models::ConfusionMatrix;
+
+/// Shows the latest confusion matrix of each run as a heatmap, rows are the true classes
+/// and columns the predicted classes
+#[derive(Resource, Serialize, Deserialize)]
+pub struct ConfusionViewer {
+    matrices: BTreeMap<String, ConfusionMatrix>, // run_name -> latest matrix
+    selected: Option<String>,
+    normalize: bool, // color and label cells by the fraction of their row
+    cell_size: f32,
+}
+
+impl Default for ConfusionViewer {
+    fn default() -> Self {
+        Self { matrices: BTreeMap::new(), selected: None, normalize: true, cell_size: 28.0 }
+    }
+}
+
+impl ConfusionViewer {
+    /// replaces the matrix shown for run_name, the first run added is selected
+    pub fn update(&mut self, run_name: String, matrix: ConfusionMatrix) {
+        if self.selected.is_none() {
+            self.selected = Some(run_name.clone());
+        }
+        self.matrices.insert(run_name, matrix);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if self.matrices.is_empty() {
+            ui.label("no evaluation passes yet");
+            return;
+        }
+        if !self.selected.as_ref().map_or(false, |x| self.matrices.contains_key(x)) {
+            self.selected = self.matrices.keys().next().cloned();
+        }
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("run")
+                .selected_text(self.selected.clone().unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    for name in self.matrices.keys() {
+                        ui.selectable_value(&mut self.selected, Some(name.clone()), name);
+                    }
+                });
+            ui.checkbox(&mut self.normalize, "normalize rows");
+            ui.add(egui::Slider::new(&mut self.cell_size, 12.0..=64.0).text("cell size"));
+            if ui.button("remove").clicked() {
+                if let Some(name) = self.selected.take() {
+                    self.matrices.remove(&name);
+                }
+            }
+        });
+        if let Some(matrix) = self.selected.as_ref().and_then(|x| self.matrices.get(x)) {
+            ui.label(format!("{} samples, rows are true classes, columns are predicted classes", matrix.total()));
+            heatmap_ui(matrix, self.normalize, self.cell_size, ui);
+        }
+    }
+}
+
+/// paints the matrix as a grid of cells, with the class indices along the top and left
+fn heatmap_ui(matrix: &ConfusionMatrix, normalize: bool, cell_size: f32, ui: &mut egui::Ui) {
+    let n = matrix.classes();
+    let normalized = matrix.row_normalized();
+    let max_count = (0..n).flat_map(|i| (0..n).map(move |j| (i, j))).map(|(i, j)| matrix.get(i, j)).max().unwrap_or(0).max(1);
+
+    let size = egui::Vec2::splat(cell_size * (n + 1) as f32);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let origin = response.rect.min;
+    let font = egui::FontId::monospace(cell_size * 0.35);
+    let text_color = ui.visuals().text_color();
+    let cell = |row: usize, col: usize| egui::Rect::from_min_size(
+        origin + egui::vec2(col as f32, row as f32) * cell_size,
+        egui::Vec2::splat(cell_size)
+    );
+
+    for i in 0..n {
+        painter.text(cell(0, i + 1).center(), egui::Align2::CENTER_CENTER, i, font.clone(), text_color);
+        painter.text(cell(i + 1, 0).center(), egui::Align2::CENTER_CENTER, i, font.clone(), text_color);
+    }
+    for i in 0..n {
+        for j in 0..n {
+            let count = matrix.get(i, j);
+            let t = if normalize { normalized[i][j] } else { count as f64 / max_count as f64 } as f32;
+            let rect = cell(i + 1, j + 1);
+            let fill = egui::Color32::from_rgb(
+                (255.0 * (1.0 - t)) as u8,
+                (255.0 * (1.0 - 0.7 * t)) as u8,
+                255
+            );
+            painter.rect_filled(rect.shrink(0.5), 0.0, fill);
+            let label = if normalize { format!("{:.2}", normalized[i][j]) } else { count.to_string() };
+            let color = if t > 0.5 { egui::Color32::WHITE } else { egui::Color32::BLACK };
+            painter.text(rect.center(), egui::Align2::CENTER_CENTER, label, font.clone(), color);
+        }
+    }
+
+    // show the exact entry under the pointer
+    if let Some(pos) = response.hover_pos() {
+        let rel = (pos - origin) / cell_size;
+        let (row, col) = (rel.y as usize, rel.x as usize);
+        if (1..=n).contains(&row) && (1..=n).contains(&col) {
+            let (i, j) = (row - 1, col - 1);
+            response.on_hover_text(format!("true {}, predicted {}: {} ({:.1}%)", i, j, matrix.get(i, j), 100.0 * normalized[i][j]));
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use bevy_egui::egui;
 use bevy::prelude::Component;
 
-use model_lib::{Config, Options};
+use model_lib::{Config, DisplayHint, Options};
+use model_lib::models::LayerSummary;
+use model_lib::configs::{fmt_duration, fmt_float, fmt_scientific, parse_duration};
 
 mod run_data;
 mod plots;
+mod confusion;
 pub mod baseline;
 
 pub use run_data::{
@@ -18,8 +21,11 @@ pub use run_data::{
     Kill,              // A bevy event sent from the Ui, to kill a particular run, associated with an Entity
     Despawn,           // A confirmation from the system that the run has been killed
     Spawn,             // A pair containing the runinfo and a function to spawn the necessary elements to initiate a training run
-    SpawnRun,          // A type alias for Box<dyn FnOnce(&mut Commands) -> Result<Entity> + Send + Sync>, the spawning function
+    SpawnRun,          // A type alias for Box<dyn FnMut(&mut Commands) -> Result<Entity> + Send + Sync>, the spawning function
+    RetryPolicy,       // How often and after how long a failed spawn is retried
     RunStats,          // A struct containing runtime info, such as step time and memory usage
+    parse_tags,        // Splits a comma separated list of run tags
+    tags_match,        // Whether a list of tags contains every tag of a filter
 };
 
 pub use plots::{
@@ -30,32 +36,106 @@ pub use plots::{
     PlotViewerV2
 };
 
+pub use confusion::ConfusionViewer; // A heatmap of the latest confusion matrix of each run
 
-pub fn config_ui_adjust(config: &mut Config, ui: &mut egui::Ui) {
-    for (k, v) in config.iter_mut() {
-        match v {
+
+/// Applies the display hint to a DragValue, the underlying value is unchanged
+fn hinted_drag_value<'a>(drag: egui::DragValue<'a>, hint: Option<DisplayHint>) -> egui::DragValue<'a> {
+    match hint {
+        Some(DisplayHint::Scientific) => drag.custom_formatter(|x, _| fmt_scientific(x)),
+        Some(DisplayHint::Duration) => drag
+            .custom_formatter(|x, _| fmt_duration(x))
+            .custom_parser(parse_duration),
+        None => drag,
+    }
+}
+
+/// Only entries matching the query are shown, see option_matches
+pub fn config_ui_adjust(config: &mut Config, query: &str, ui: &mut egui::Ui) {
+    config_ui_adjust_(config, None, &query.to_lowercase(), "", ui);
+}
+
+/// Same as config_ui_adjust, but values differing from default are highlighted,
+/// with the default value shown on hover
+pub fn config_ui_adjust_diff(config: &mut Config, default: &Config, query: &str, ui: &mut egui::Ui) {
+    let changed = config.changed_paths(default);
+    config_ui_adjust_(config, Some((default, &changed)), &query.to_lowercase(), "", ui);
+}
+
+/// Whether the entry at path contains the lowercase query, ignoring case, nested configs also match
+/// if any of their descendants match
+fn option_matches(path: &str, v: &Options, query: &str) -> bool {
+    if query.is_empty() || path.to_lowercase().contains(query) {
+        return true;
+    }
+    if let Options::CONFIG(c) = v {
+        c.iter().any(|(k, v)| option_matches(&format!("{path}/{k}"), v, query))
+    } else {
+        false
+    }
+}
+
+/// the color of config values which differ from the default
+const CHANGED_COLOR: egui::Color32 = egui::Color32::from_rgb(230, 180, 60);
+
+fn config_ui_adjust_(config: &mut Config, diff: Option<(&Config, &Vec<String>)>, query: &str, prefix: &str, ui: &mut egui::Ui) {
+    let hints: Vec<_> = config.iter().map(|(k, _)| config.hint(k)).collect();
+    for ((k, v), hint) in config.iter_mut().zip(hints) {
+        let path = if prefix.is_empty() { k.to_string() } else { format!("{prefix}/{k}") };
+        if !option_matches(&path, v, query) {
+            continue;
+        }
+        // the default value, if this leaf is changed
+        let changed = diff
+            .filter(|(_, changed)| changed.contains(&path))
+            .map(|(default, _)| {
+                default.get(&path).map_or("none".to_string(), |d| d.fmt_value(default.hint(&path)))
+            });
+        let label = |k: &str| {
+            if changed.is_some() {
+                egui::RichText::new(k).color(CHANGED_COLOR)
+            } else {
+                egui::RichText::new(k)
+            }
+        };
+        let response = match v {
             Options::BOOL(i) => {
-                ui.checkbox(i, k);
+                Some(ui.checkbox(i, label(k)))
             }
             Options::INT(i) => {
-                ui.horizontal(|ui| {
-                    ui.label(k);
-                    ui.add(egui::DragValue::new(i).speed(0.1));
-                });
+                Some(ui.horizontal(|ui| {
+                    ui.label(label(k));
+                    ui.add(hinted_drag_value(egui::DragValue::new(i).speed(0.1), hint));
+                }).response)
             }
             Options::FLOAT(i) => {
-                ui.horizontal(|ui| {
-                    ui.label(k);
-                    ui.add(egui::DragValue::new(i).speed(0.1));
-                });
+                Some(ui.horizontal(|ui| {
+                    ui.label(label(k));
+                    let drag = egui::DragValue::new(i).speed(0.1);
+                    let drag = if hint.is_none() {
+                        drag.custom_formatter(|x, _| fmt_float(x))
+                    } else {
+                        hinted_drag_value(drag, hint)
+                    };
+                    ui.add(drag);
+                }).response)
             }
             Options::STR(i) => {
-                ui.add(egui::TextEdit::singleline(i).hint_text(k));
+                let mut edit = egui::TextEdit::singleline(i).hint_text(k);
+                if changed.is_some() {
+                    edit = edit.text_color(CHANGED_COLOR);
+                }
+                Some(ui.add(edit))
             }
             Options::PATH(i) => {
                 let mut str = i.to_str().unwrap().to_string();
-                ui.add(egui::TextEdit::singleline(&mut str).hint_text(k));
+                let mut edit = egui::TextEdit::singleline(&mut str).hint_text(k);
+                if changed.is_some() {
+                    edit = edit.text_color(CHANGED_COLOR);
+                }
+                let response = ui.add(edit);
                 *i = str.into();
+                Some(response)
             }
             Options::CONFIG(c) => {
                 ui.horizontal(|ui| {
@@ -65,11 +145,15 @@ pub fn config_ui_adjust(config: &mut Config, ui: &mut egui::Ui) {
                         egui::CollapsingHeader::new(k)
                             .default_open(true)
                             .show(ui, |ui| {
-                                config_ui_adjust(c, ui);
+                                config_ui_adjust_(c, diff, query, &path, ui);
                             });
                     });
                 });
+                None
             }
+        };
+        if let (Some(response), Some(default)) = (response, changed) {
+            response.on_hover_text(format!("default: {default}"));
         }
     }
 }
@@ -82,11 +166,8 @@ pub fn config_ui_show(config: &Config, ui: &mut egui::Ui) {
             Options::BOOL(i) => {
                 ui.label(format!("{k}: {i}"));
             }
-            Options::INT(i) => {
-                ui.label(format!("{k}: {i}"));
-            }
-            Options::FLOAT(i) => {
-                ui.label(format!("{k}: {i}"));
+            Options::INT(_) | Options::FLOAT(_) => {
+                ui.label(format!("{k}: {}", v.fmt_value(config.hint(k))));
             }
             Options::STR(i) => {
                 ui.label(format!("{k}: {i}"));
@@ -111,7 +192,80 @@ pub fn config_ui_show(config: &Config, ui: &mut egui::Ui) {
     }
 }
 
+/// Shows the leaves which differ between a and b side by side, keys missing from one of the configs are shown as '-'
+pub fn config_diff_ui(a: &Config, b: &Config, ui: &mut egui::Ui) {
+    let diff = a.diff(b);
+    if diff.is_empty() {
+        ui.label("configs are identical");
+        return;
+    }
+    let fmt = |config: &Config, path: &str, v: &Option<Options>| {
+        v.as_ref().map_or("-".to_string(), |v| v.fmt_value(config.hint(path)))
+    };
+    egui::Grid::new("config diff").striped(true).show(ui, |ui| {
+        for (path, va, vb) in diff.iter() {
+            ui.label(egui::RichText::new(path).color(CHANGED_COLOR));
+            ui.label(fmt(a, path, va));
+            ui.label(fmt(b, path, vb));
+            ui.end_row();
+        }
+    });
+}
+
+/// Shows a model summary as a table of layers, their output shapes and parameter counts
+pub fn layer_summary_ui(summary: &[LayerSummary], ui: &mut egui::Ui) {
+    egui::Grid::new("layer summary").striped(true).show(ui, |ui| {
+        ui.label(egui::RichText::new("layer").strong());
+        ui.label(egui::RichText::new("output shape").strong());
+        ui.label(egui::RichText::new("params").strong());
+        ui.end_row();
+        for layer in summary {
+            ui.label(&layer.name);
+            ui.label(format!("{:?}", layer.output_shape));
+            ui.label(layer.params.to_string());
+            ui.end_row();
+        }
+    });
+    let total: usize = summary.iter().map(|x| x.params).sum();
+    ui.label(format!("total params: {total}"));
+}
+
 pub struct ConfigUiWrapper {
     config: Config,
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_option_matches() {
+    use model_lib::{config, opt};
+    let config = Options::CONFIG(config!(
+        ("lr", 0.1),
+        ("epochs", 4),
+        ("SGD", [("momentum", 0.9), ("nesterov", false), ("schedule", [("LR_gamma", 0.5)])])
+    ));
+    let visible = |query: &str| -> Vec<String> {
+        let mut paths = Vec::new();
+        fn walk(config: &Config, prefix: &str, query: &str, paths: &mut Vec<String>) {
+            for (k, v) in config.iter() {
+                let path = if prefix.is_empty() { k.to_string() } else { format!("{prefix}/{k}") };
+                if option_matches(&path, v, &query.to_lowercase()) {
+                    paths.push(path.clone());
+                    if let Options::CONFIG(c) = v {
+                        walk(c, &path, query, paths);
+                    }
+                }
+            }
+        }
+        if let Options::CONFIG(c) = &config {
+            walk(c, "", query, &mut paths);
+        }
+        paths
+    };
+    assert_eq!(visible("").len(), 7);
+    // the sgd and schedule groups remain since a descendant matches
+    assert_eq!(visible("lr"), vec!["lr", "SGD", "SGD/schedule", "SGD/schedule/LR_gamma"]);
+    // every entry in a matching group matches
+    assert_eq!(visible("sgd"), vec!["SGD", "SGD/momentum", "SGD/nesterov", "SGD/schedule", "SGD/schedule/LR_gamma"]);
+    assert_eq!(visible("MOM"), vec!["SGD", "SGD/momentum"]);
+    assert!(visible("none").is_empty());
+}
@@ -1,4 +1,5 @@
 use std::ops::{Range, Deref, DerefMut};
+use std::path::{Path, PathBuf};
 use std::collections::{HashMap, VecDeque, HashSet};
 
 use anyhow::{Error, Result};
@@ -32,16 +33,12 @@ struct LineStats {
 impl LineStats {
     const EPS: f64 = 1e-6;
     /// since lines are monotonic in x, we can say that two lines are equal if their lengths
-    /// are equal, and the end points are approximately equal
+    /// are equal, and the end points are within EPS of each other
     fn dirty_eq(&self, other: &LineStats) -> bool {
-        self.len == other.len && {
-            if self.last_x.is_none() {
-                true
-            } else {
-                let (x, y) = self.last_x.unwrap();
-                let (x1, y1) = self.last_x.unwrap();
-                (x - x1).abs() < Self::EPS && (y - y1).abs() < Self::EPS
-            }
+        self.len == other.len && match (self.last_x, other.last_x) {
+            (None, None) => true,
+            (Some((x, y)), Some((x1, y1))) => (x - x1).abs() < Self::EPS && (y - y1).abs() < Self::EPS,
+            _ => false
         }
     }
 }
@@ -80,6 +77,12 @@ impl PlotLine {
         self.extend_from_slice(&other[i..]);
     }
 
+    /// returns a copy of self with at most max_points points, picked by largest-triangle-three-buckets,
+    /// which keeps the end points and the visual shape of the line
+    pub fn downsample(&self, max_points: usize) -> PlotLine {
+        PlotLine(lttb(self, max_points))
+    }
+
     /// applies a sliding average window to self, with window-1 0 padding to the left
     pub fn avg_smooth(&mut self, window: usize) {
         let div = window as f64;
@@ -104,6 +107,39 @@ impl PlotLine {
     }
 }
 
+/// largest-triangle-three-buckets downsampling, the first and last points are kept, and the points in between are split into
+/// n - 2 buckets, from each the point forming the largest triangle with the previously picked point and the average of the next
+/// bucket is picked. With n < 3 there are no buckets, so only the last point, or the first and last points are kept
+fn lttb(data: &[(f64, f64)], n: usize) -> Vec<(f64, f64)> {
+    if n >= data.len() {
+        return data.to_vec();
+    }
+    match n {
+        0 => return Vec::new(),
+        1 => return vec![data[data.len() - 1]],
+        2 => return vec![data[0], data[data.len() - 1]],
+        _ => {}
+    }
+    let every = (data.len() - 2) as f64 / (n - 2) as f64;
+    let bucket = |i: usize| (i as f64 * every) as usize + 1;
+    let mut sampled = Vec::with_capacity(n);
+    sampled.push(data[0]);
+    let mut a = 0;
+    for i in 0..n - 2 {
+        let next = &data[bucket(i + 1)..bucket(i + 2).min(data.len())];
+        let avg_x = next.iter().map(|p| p.0).sum::<f64>() / next.len() as f64;
+        let avg_y = next.iter().map(|p| p.1).sum::<f64>() / next.len() as f64;
+
+        let (ax, ay) = data[a];
+        let area = |p: &(f64, f64)| ((ax - avg_x) * (p.1 - ay) - (ax - p.0) * (avg_y - ay)).abs();
+        let (start, end) = (bucket(i), bucket(i + 1));
+        a = (start..end).max_by(|j, k| area(&data[*j]).total_cmp(&area(&data[*k]))).unwrap();
+        sampled.push(data[a]);
+    }
+    sampled.push(data[data.len() - 1]);
+    sampled
+}
+
 /// Uniquely identifies a line for a particular run
 #[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Serialize, Deserialize, Default, Debug)]
 pub struct PlotId {
@@ -131,7 +167,9 @@ pub struct PlotViewerV1 {
     params: ComputeRender,
     p_cache: PlotCache,
     // some ui configuration parameters
-    local_scale: f32
+    local_scale: f32,
+    #[serde(skip)]
+    save_msg: Option<String>,
 }
 
 impl PlotViewerV1 {
@@ -148,6 +186,16 @@ impl PlotViewerV1 {
             self.params.smooth = self.params.smooth.max(1);
             ui.label("local scale");
             ui.add(egui::Slider::new(&mut self.local_scale, 0.0..=1.0));
+            if ui.button("save PNG").clicked() {
+                let folder = Path::new(crate::ROOT_CONFIG_PATH).join("exports").join(format!("{}", self.filter.model));
+                self.save_msg = Some(match self.save_png(lines, &folder) {
+                    Ok(paths) => format!("saved {} graphs to {}", paths.len(), folder.display()),
+                    Err(e) => format!("save error {}", e),
+                });
+            }
+            if let Some(msg) = &self.save_msg {
+                ui.label(msg);
+            }
         });
 
         egui::ComboBox::from_label("filter by model")
@@ -219,11 +267,20 @@ impl PlotViewerV1 {
         // step 2
         let graphs = PlotBatch::batch_by_title(pre_filter);
         // step 3
-        let need_render = graphs.into_iter().filter(|x| self.v_cache.needs_render(x));
+        let need_render: Vec<_> = graphs.into_iter().filter(|x| self.v_cache.needs_render(x)).collect();
+        self.v_cache.update(need_render.iter().flat_map(|x| x.plots.iter().map(|p| *p)));
         // step 4
-        let image_bufs = self.params.render(need_render)?;
+        let image_bufs = self.params.render(need_render.into_iter())?;
         Ok(image_bufs)
     }
+
+    /// renders every graph that passes the filter at the configured resolution, regardless of whether it
+    /// is cached, and saves each one as a png under folder
+    pub fn save_png(&self, lines: &ModelPlots, folder: &Path) -> Result<Vec<PathBuf>> {
+        let graphs = PlotBatch::batch_by_title(self.filter.filter(lines.lines.iter()));
+        let image_bufs = self.params.render(graphs.into_iter())?;
+        image_bufs.iter().map(|x| x.save_png(folder)).collect()
+    }
 }
 
 
@@ -289,17 +346,64 @@ impl<It: Iterator<Item = (f64, f64)>> Iterator for SmoothIter<It> {
     }
 }
 
+/// interpolates every line onto a common x grid, made of all the x values where the lines overlap,
+/// and returns (x, mean, std) of the lines at each point of the grid
+fn mean_std(lines: &[Vec<(f64, f64)>]) -> Vec<(f64, f64, f64)> {
+    if lines.is_empty() || lines.iter().any(|line| line.is_empty()) {
+        return Vec::new();
+    }
+    let lo = lines.iter().map(|line| line[0].0).fold(f64::NEG_INFINITY, f64::max);
+    let hi = lines.iter().map(|line| line[line.len() - 1].0).fold(f64::INFINITY, f64::min);
+    let mut xs: Vec<f64> = lines.iter()
+        .flat_map(|line| line.iter().map(|p| p.0))
+        .filter(|x| lo <= *x && *x <= hi)
+        .collect();
+    xs.sort_by(|a, b| a.total_cmp(b));
+    xs.dedup();
+
+    let n = lines.len() as f64;
+    xs.into_iter().map(|x| {
+        let ys: Vec<f64> = lines.iter().map(|line| interpolate(line, x)).collect();
+        let mean = ys.iter().sum::<f64>() / n;
+        let var = ys.iter().map(|y| (y - mean).powi(2)).sum::<f64>() / n;
+        (x, mean, var.sqrt())
+    }).collect()
+}
+
+/// linearly interpolates the value of line at x, line must be non-empty and monotonic in x
+fn interpolate(line: &[(f64, f64)], x: f64) -> f64 {
+    let i = line.partition_point(|p| p.0 < x);
+    if i == line.len() {
+        return line[i - 1].1;
+    }
+    if i == 0 || line[i].0 == x {
+        return line[i].1;
+    }
+    let ((x0, y0), (x1, y1)) = (line[i - 1], line[i]);
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
+
 #[derive(Resource, Serialize, Deserialize)]
 pub struct PlotViewerV2 {
     display_model: Models,
     display_runs: HashMap<Models, Vec<((u8, u8, u8), String, bool)>>, // (line color, run_names, display)
     display_titles: HashMap<Models, Vec<(String, bool)>>, // (title_names, display)
+    #[serde(default)]
+    log_y_titles: HashMap<Models, HashSet<String>>, // titles of graphs with a log scaled y axis
     // some ui configuration parameters
     graphs_per_row: usize,
     smooth_window: usize,
+    #[serde(default)]
+    aggregate_runs: bool, // show the mean and std across runs instead of each run
+    #[serde(default)]
+    tag_filter: String, // only runs with every one of these comma separated tags are shown
+    #[serde(skip)]
+    export_msg: Option<String>,
 }
 
 impl PlotViewerV2 {
+    const MAX_PLOT_POINTS: usize = 2000;
+
     pub fn ui(&mut self, ui: &mut egui::Ui, lines: &ModelPlots) {
         // adjust local rendering parameters, filters, etc.
         // ui to adjust which lines to show
@@ -318,8 +422,12 @@ impl PlotViewerV2 {
                 }
             });
 
+            ui.add(egui::TextEdit::singleline(&mut self.tag_filter).hint_text("filter runs by tags"));
             // pick which runs to show
             for (color, run_name, display) in cur_display_runs.iter_mut() {
+                if !lines.run_matches_tags(run_name, &self.tag_filter) {
+                    continue;
+                }
                 ui.horizontal(|ui| {
                     let color = if *display {
                         *color
@@ -337,12 +445,16 @@ impl PlotViewerV2 {
             ui.label("smooth window");
             ui.add(egui::DragValue::new(&mut self.smooth_window));
             self.smooth_window = self.smooth_window.max(1);
+            ui.checkbox(&mut self.aggregate_runs, "mean ± std across runs");
         });
 
         // now actually show the lines
         let all_lines = lines.lines.iter().filter(|(id, _)| id.model == self.display_model);
         let mut to_plot = Vec::new();
         for (pid, line) in all_lines {
+            if !lines.run_matches_tags(&pid.run_name, &self.tag_filter) {
+                continue;
+            }
             let title_idx = contains(cur_display_titles, |(title, _)| pid.title.eq(title));
             let run_idx = contains(cur_display_runs, |(_, run_name, _)| pid.run_name.eq(run_name));
             if title_idx.is_none() {
@@ -361,6 +473,18 @@ impl PlotViewerV2 {
 
         ui.ctx().request_repaint();
         let batch_by_title = PlotBatch::batch_by_title(to_plot.into_iter());
+        ui.horizontal(|ui| {
+            if ui.button("export CSV").clicked() {
+                let folder = Path::new(crate::ROOT_CONFIG_PATH).join("exports").join(format!("{}", self.display_model));
+                self.export_msg = Some(match export_csv(&batch_by_title, &folder) {
+                    Ok(paths) => format!("exported {} graphs to {}", paths.len(), folder.display()),
+                    Err(e) => format!("export error {}", e),
+                });
+            }
+            if let Some(msg) = &self.export_msg {
+                ui.label(msg);
+            }
+        });
         let available_width = ui.available_width();
         self.graphs_per_row = self.graphs_per_row.min(batch_by_title.len()).max(1);
         let graph_width = available_width / self.graphs_per_row as f32;
@@ -374,20 +498,39 @@ impl PlotViewerV2 {
                         for i in col*self.graphs_per_row..((col + 1) * self.graphs_per_row).min(batch_by_title.len()) {
                             ui.vertical(|ui| {
                                 let graph = &batch_by_title[i];
-                                ui.label(&graph.gid.0);
-                                let plot = plot::Plot::new(&graph.gid.0)
+                                let mut log_y = self.log_y(&graph.gid.0);
+                                ui.horizontal(|ui| {
+                                    ui.label(&graph.gid.0);
+                                    if ui.checkbox(&mut log_y, "log y").changed() {
+                                        self.set_log_y(&graph.gid.0, log_y);
+                                    }
+                                });
+                                let mut plot = plot::Plot::new(&graph.gid.0)
                                     .auto_bounds_x().auto_bounds_y()
                                     .allow_scroll(false)
                                     .allow_drag(false)
                                     .view_aspect(1.5)
                                     .width(graph_width);
+                                if log_y {
+                                    plot = plot.y_axis_formatter(|y, _| format!("{:.2e}", 10f64.powf(y)));
+                                }
                                 plot.show(ui, |plot_ui| {
+                                    for (pid, _) in &graph.plots {
+                                        let color = get_run_color(&pid.run_name);
+                                        for x in lines.markers(pid) {
+                                            plot_ui.vline(plot::VLine::new(*x)
+                                                .color(egui::Color32::from_rgb(color.0, color.1, color.2))
+                                                .style(plot::LineStyle::dashed_loose()));
+                                        }
+                                    }
+                                    if self.aggregate_runs && graph.plots.len() > 1 {
+                                        self.show_mean_std(plot_ui, graph);
+                                        return;
+                                    }
                                     for (pid, line) in &graph.plots {
                                         let color = get_run_color(&pid.run_name);
-                                        let smoothed_line = SmoothIter::new(
-                                            line.iter().map(|point| *point), self.smooth_window)
-                                            .map(|point| [point.0, point.1]);
-                                        let line = plot::Line::new(plot::PlotPoints::from_iter(smoothed_line))
+                                        let points = self.plot_points(&graph.gid.0, line);
+                                        let line = plot::Line::new(plot::PlotPoints::new(points))
                                             .color(egui::Color32::from_rgb(color.0, color.1, color.2))
                                             .style(plot::LineStyle::Solid);
                                         plot_ui.line(line);
@@ -405,6 +548,66 @@ impl PlotViewerV2 {
         
     }
 
+    /// whether the graph with the given title is shown with a log scaled y axis, for the current model
+    fn log_y(&self, title: &str) -> bool {
+        self.log_y_titles.get(&self.display_model).map_or(false, |x| x.contains(title))
+    }
+
+    fn set_log_y(&mut self, title: &str, log_y: bool) {
+        let titles = get_or_insert(&mut self.log_y_titles, &self.display_model, || HashSet::new());
+        if log_y {
+            titles.insert(title.to_string());
+        } else {
+            titles.remove(title);
+        }
+    }
+
+    /// log10(y) if the graph is log scaled, in which case non-positive values have no position on the plot
+    fn transform_y(&self, title: &str, y: f64) -> Option<f64> {
+        if self.log_y(title) {
+            if y > 0.0 { Some(y.log10()) } else { None }
+        } else {
+            Some(y)
+        }
+    }
+
+    /// the points of line as fed to the plot, smoothed, and if the graph is log scaled, with y replaced by log10(y),
+    /// skipping the points where y is not positive
+    fn plot_points(&self, title: &str, line: &PlotLine) -> Vec<[f64; 2]> {
+        let mut smoothed: Vec<_> = SmoothIter::new(line.iter().map(|point| *point), self.smooth_window).collect();
+        // very long lines make the plot sluggish, only the rendered copy is downsampled
+        if smoothed.len() > Self::MAX_PLOT_POINTS {
+            smoothed = lttb(&smoothed, Self::MAX_PLOT_POINTS);
+        }
+        smoothed.into_iter()
+            .filter_map(|point| Some([point.0, self.transform_y(title, point.1)?]))
+            .collect()
+    }
+
+    /// draws the mean of the smoothed lines in graph, with a shaded band of one standard deviation around it
+    fn show_mean_std(&self, plot_ui: &mut plot::PlotUi, graph: &PlotBatch) {
+        let title = &graph.gid.0;
+        let lines: Vec<Vec<(f64, f64)>> = graph.plots.iter().map(|(_, line)| {
+            SmoothIter::new(line.iter().map(|point| *point), self.smooth_window).collect()
+        }).collect();
+        let stats = mean_std(&lines);
+        let color = get_run_color(title);
+        let color = egui::Color32::from_rgb(color.0, color.1, color.2);
+
+        // the band is drawn as one quad per segment, since egui only fills convex polygons correctly
+        for (a, b) in stats.iter().tuple_windows() {
+            let corners = [(a.0, a.1 - a.2), (b.0, b.1 - b.2), (b.0, b.1 + b.2), (a.0, a.1 + a.2)];
+            let quad: Option<Vec<[f64; 2]>> = corners.iter().map(|(x, y)| Some([*x, self.transform_y(title, *y)?])).collect();
+            if let Some(quad) = quad {
+                plot_ui.polygon(plot::Polygon::new(plot::PlotPoints::new(quad)).color(color).width(0.0).fill_alpha(0.3));
+            }
+        }
+        let mean = stats.iter().filter_map(|(x, y, _)| Some([*x, self.transform_y(title, *y)?])).collect();
+        plot_ui.line(plot::Line::new(plot::PlotPoints::new(mean))
+            .color(color)
+            .style(plot::LineStyle::Solid)
+            .name("mean"));
+    }
 }
 
 impl Default for PlotViewerV2 {
@@ -413,8 +616,12 @@ impl Default for PlotViewerV2 {
             display_model: Models::BASELINE, 
             display_runs: HashMap::new(),
             display_titles: HashMap::new(),
+            log_y_titles: HashMap::new(),
             graphs_per_row: 1,
             smooth_window: 1,
+            aggregate_runs: false,
+            tag_filter: String::new(),
+            export_msg: None,
         }
     }
 }
@@ -448,6 +655,55 @@ impl<'a> PlotBatch<'a> {
             PlotBatch { gid: first.into(), plots: x }
         }).collect()
     }
+
+    /// the first column is the union of the x values of every line in the batch, followed by a
+    /// column per run, with NaN wherever a run does not have a point at that x
+    fn to_csv(&self) -> String {
+        let mut plots = self.plots.clone();
+        plots.sort_by(|a, b| a.0.run_name.cmp(&b.0.run_name));
+        let mut xs: Vec<f64> = plots.iter().flat_map(|(_, line)| line.iter().map(|p| p.0)).collect();
+        xs.sort_by(|a, b| a.total_cmp(b));
+        xs.dedup();
+
+        let mut csv = std::iter::once(self.gid.1.as_str())
+            .chain(plots.iter().map(|(id, _)| id.run_name.as_str()))
+            .join(",");
+        csv.push('\n');
+        // lines are monotonic in x, so each line only needs a cursor into its points
+        let mut cursors = vec![0; plots.len()];
+        for x in xs {
+            csv += &x.to_string();
+            for ((_, line), j) in plots.iter().zip(cursors.iter_mut()) {
+                csv.push(',');
+                if *j < line.len() && line[*j].0 == x {
+                    csv += &line[*j].1.to_string();
+                    *j += 1;
+                } else {
+                    csv += "NaN";
+                }
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+/// writes each batch to its own csv file under folder, named after the title of the graph,
+/// returns the paths of the written files
+fn export_csv(batches: &[PlotBatch], folder: &Path) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(folder)?;
+    let mut paths = Vec::new();
+    for batch in batches {
+        let path = folder.join(format!("{}.csv", file_name(&batch.gid.0)));
+        std::fs::write(&path, batch.to_csv())?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// replaces every character that is not alphanumeric, '-' or '_' in name by '_'
+fn file_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
 }
 
 /// Step 3
@@ -460,7 +716,7 @@ struct ViewCache {
 impl ViewCache {
     fn needs_render<'a>(&self, plot_batch: &PlotBatch<'a>) -> bool {
         for (pid, line) in plot_batch.plots.iter() {
-            if !self.line_info.contains_key(pid) || self.line_info.get(pid).unwrap().dirty_eq(&line.stats()) {
+            if !self.line_info.contains_key(pid) || !self.line_info.get(pid).unwrap().dirty_eq(&line.stats()) {
                 return true;
             }
         }
@@ -504,6 +760,14 @@ impl RenderedBatch {
         let texture = to_texture(&self.buf, self.res, ui)?;
         Ok(RenderedTexture { gid: gid, texture })
     }
+
+    /// writes the rendered buffer to a png under folder, named after the title of the graph
+    fn save_png(&self, folder: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(folder)?;
+        let path = folder.join(format!("{}.png", file_name(&self.gid.0)));
+        image::save_buffer(&path, &self.buf, self.res.0 as u32, self.res.1 as u32, image::ColorType::Rgb8)?;
+        Ok(path)
+    }
 }
 
 impl ComputeRender {
@@ -576,6 +840,10 @@ impl PlotCache {
 #[derive(Serialize, Deserialize, Resource, Default, Debug)]
 pub struct ModelPlots {
     lines: HashMap<PlotId, PlotLine>,
+    #[serde(default)]
+    markers: HashMap<PlotId, Vec<f64>>, // x positions of vertical markers, such as epoch boundaries
+    #[serde(default)]
+    run_tags: HashMap<String, Vec<String>>, // run_name -> tags of the run
 }
 
 impl ModelPlots {
@@ -603,6 +871,39 @@ impl ModelPlots {
         self.lines.insert(id, line);
     }
 
+    /// exports every line selected by f to csv files under folder, one file per graph
+    pub fn export_csv(&self, folder: &Path, f: impl FnMut(&PlotId) -> bool) -> Result<Vec<PathBuf>> {
+        let batches = PlotBatch::batch_by_title(self.filter(f));
+        export_csv(&batches, folder)
+    }
+
+    /// records the tags of run_name, which the tag filter of the plot viewer matches against
+    pub fn set_tags(&mut self, run_name: &str, tags: &[String]) {
+        if self.run_tags.get(run_name).map(|x| &x[..]) != Some(tags) {
+            self.run_tags.insert(run_name.to_string(), tags.to_vec());
+        }
+    }
+
+    /// whether the run has every tag of the comma separated filter, runs without recorded tags have none
+    pub fn run_matches_tags(&self, run_name: &str, filter: &str) -> bool {
+        super::tags_match(self.run_tags.get(run_name).map_or(&[], |x| &x[..]), filter)
+    }
+
+    /// adds a marker at x to every line of run_name with the given title, markers of a line are kept increasing in x,
+    /// so lines which do not exist yet do not receive the marker
+    pub fn add_marker(&mut self, model: Models, run_name: &str, title: &str, x: f64) {
+        for id in self.lines.keys().filter(|id| id.model == model && id.run_name == run_name && id.title == title) {
+            let markers = get_or_insert(&mut self.markers, id, || Vec::new());
+            if markers.last().map_or(true, |last| *last < x) {
+                markers.push(x);
+            }
+        }
+    }
+
+    pub fn markers(&self, id: &PlotId) -> &[f64] {
+        self.markers.get(id).map_or(&[], |x| x.as_slice())
+    }
+
     pub fn add_point(&mut self, id: &PlotId, point: (f64, f64)) {
         if !self.lines.contains_key(id) { // if this plot id is not in self, since changed and lines have the same set of keys
             let mut new_line = PlotLine::default();
@@ -699,13 +1000,9 @@ fn to_texture(buf: &[u8], res: (usize, usize), ui: &mut egui::Ui) -> Result<egui
         return Err(Error::msg("incorrect length of buffer for the given resolution"));
     }
 
-    let colorbuf: Vec<_> = buf.chunks_exact(3).map(|x| {
-        egui::Color32::from_rgb(x[0], x[1], x[1])
-    }).collect();
-
     let colorimage = egui::ColorImage {
         size: [res.0, res.1],
-        pixels: colorbuf
+        pixels: rgb_to_color32(buf)
     };
 
     let handle = ui.ctx().load_texture(
@@ -714,6 +1011,13 @@ fn to_texture(buf: &[u8], res: (usize, usize), ui: &mut egui::Ui) -> Result<egui
     Ok(handle)
 }
 
+/// converts a buffer of packed rgb triples to colors
+fn rgb_to_color32(buf: &[u8]) -> Vec<egui::Color32> {
+    buf.chunks_exact(3).map(|x| {
+        egui::Color32::from_rgb(x[0], x[1], x[2])
+    }).collect()
+}
+
 pub fn wider_range(a: Range<f64>, b: Range<f64>) -> Range<f64> {
     a.start.min(b.start)..a.end.max(b.end)
 }
@@ -725,24 +1029,40 @@ pub fn compute_bounds<'a>(lines: impl Iterator<Item = (f64, f64)>) -> (Range<f64
     })
 }
 
+/// trailing average over the last window_size points, the first window_size - 1 points
+/// average over the points seen so far
 fn smooth_window(data: &[(f64, f64)], window_size: usize) -> Vec<(f64, f64)> {
-    let mut vec = Vec::new();
-    let div = window_size as f64;
-    vec.reserve_exact(data.len());
-    let mut sum = 0.0;
-    for i in 0..(data.len().min(window_size - 1)) {
-        sum += data[i].1;
-        vec.push((data[i].0, sum / (i + 1) as f64));
+    if window_size <= 1 {
+        return data.to_vec();
     }
-    
-    for j in (data.len().min(window_size - 1))..data.len() {
-        sum += data[j].1;
-        vec.push((data[j].0, sum / div));
-        sum -= data[j + 1 - window_size].1;
+    let mut vec = Vec::with_capacity(data.len());
+    let mut sum = 0.0;
+    for (j, &(x, y)) in data.iter().enumerate() {
+        sum += y;
+        if j >= window_size {
+            sum -= data[j - window_size].1;
+        }
+        vec.push((x, sum / (j + 1).min(window_size) as f64));
     }
     vec
 }
 
+#[test]
+fn test_smooth_window() {
+    assert!(smooth_window(&[], 3).is_empty());
+    assert!(smooth_window(&[], 0).is_empty());
+    assert_eq!(smooth_window(&[(0.0, 2.0)], 4), vec![(0.0, 2.0)]);
+    
+    let data = [(0.0, 1.0), (1.0, 3.0), (2.0, 5.0), (3.0, 7.0)];
+    // window sizes of 0 and 1 leave the data unchanged
+    assert_eq!(smooth_window(&data, 0), data.to_vec());
+    assert_eq!(smooth_window(&data, 1), data.to_vec());
+    // shorter than the window
+    assert_eq!(smooth_window(&data[..2], 5), vec![(0.0, 1.0), (1.0, 2.0)]);
+    assert_eq!(smooth_window(&data, 2), vec![(0.0, 1.0), (1.0, 2.0), (2.0, 4.0), (3.0, 6.0)]);
+    assert_eq!(smooth_window(&data, 3), vec![(0.0, 1.0), (1.0, 2.0), (2.0, 3.0), (3.0, 5.0)]);
+}
+
 #[test]
 fn test_render() {
     let mut plots = ModelPlots::default();
@@ -756,4 +1076,183 @@ fn test_render() {
     //println!("{:?}", plots);
     let mut render = PlotViewerV1::default();
     render.compute(&plots).expect("failed to render plots");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_export_csv() {
+    let mut plots = ModelPlots::default();
+    let id = |run: &str, title: &str| PlotId { model: Models::BASELINE, run_name: run.into(), 
+        title: title.into(), x_title: "steps".into(), y_title: "loss".into() };
+    
+    for i in 0..3 {
+        plots.add_point(&id("run b", "train loss"), (i as f64 * 2.0, i as f64));
+        plots.add_point(&id("run a", "train loss"), (i as f64, 0.5 * i as f64));
+    }
+    plots.add_point(&id("run a", "val/loss"), (1.0, 2.0));
+
+    let folder = std::env::temp_dir().join("grownet_test_export_csv");
+    let _ = std::fs::remove_dir_all(&folder);
+    let mut paths = plots.export_csv(&folder, |_| true).expect("failed to export csv");
+    paths.sort();
+    assert_eq!(paths, vec![folder.join("train_loss.csv"), folder.join("val_loss.csv")]);
+
+    let train = std::fs::read_to_string(&paths[0]).unwrap();
+    assert_eq!(train, "steps,run a,run b\n0,0,0\n1,0.5,NaN\n2,1,1\n4,NaN,2\n");
+    let val = std::fs::read_to_string(&paths[1]).unwrap();
+    assert_eq!(val, "steps,run a\n1,2\n");
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_save_png() {
+    let mut plots = ModelPlots::default();
+    let test_id = PlotId { model: Models::BASELINE, run_name: "baselinev1".into(), 
+        title: "test loss".into(), x_title: "steps".into(), y_title: "loss".into()  };
+    for i in 0..100 {
+        plots.add_point(&test_id, (i as f64, (i as f64).cos()));
+    }
+
+    let folder = std::env::temp_dir().join("grownet_test_save_png");
+    let _ = std::fs::remove_dir_all(&folder);
+    let render = PlotViewerV1::default();
+    let paths = render.save_png(&plots, &folder).expect("failed to save png");
+    assert_eq!(paths, vec![folder.join("test_loss.png")]);
+    
+    assert!(std::fs::metadata(&paths[0]).unwrap().len() > 0);
+    let im = image::open(&paths[0]).unwrap();
+    let res = ComputeRender::default().res;
+    assert_eq!((im.width() as usize, im.height() as usize), res);
+    std::fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn test_log_y_points() {
+    let mut viewer = PlotViewerV2::default();
+    let line = PlotLine(vec![(0.0, 100.0), (1.0, 0.0), (2.0, -1.0), (3.0, 10.0)]);
+    assert_eq!(viewer.plot_points("loss", &line), vec![[0.0, 100.0], [1.0, 0.0], [2.0, -1.0], [3.0, 10.0]]);
+
+    viewer.set_log_y("loss", true);
+    assert!(viewer.log_y("loss") && !viewer.log_y("accuracy"));
+    // non-positive points are skipped
+    assert_eq!(viewer.plot_points("loss", &line), vec![[0.0, 2.0], [3.0, 1.0]]);
+    assert_eq!(viewer.plot_points("accuracy", &line).len(), 4);
+
+    // the toggle is kept through serialization
+    let saved = ron::to_string(&viewer).unwrap();
+    let mut viewer: PlotViewerV2 = ron::from_str(&saved).unwrap();
+    assert!(viewer.log_y("loss"));
+    viewer.set_log_y("loss", false);
+    assert_eq!(viewer.plot_points("loss", &line).len(), 4);
+}
+
+#[test]
+fn test_mean_std() {
+    let a = vec![(0.0, 1.0), (1.0, 2.0), (2.0, 3.0)];
+    let b = vec![(1.0, 4.0), (2.0, 6.0), (3.0, 6.0)];
+    // only the overlapping x values are kept
+    assert_eq!(mean_std(&[a.clone(), b]), vec![(1.0, 3.0, 1.0), (2.0, 4.5, 1.5)]);
+
+    // b is interpolated at x = 1
+    let b = vec![(0.0, 0.0), (2.0, 4.0)];
+    let c = vec![(0.0, 2.0), (1.0, 2.0), (2.0, 2.0)];
+    assert_eq!(mean_std(&[b, c]), vec![(0.0, 1.0, 1.0), (1.0, 2.0, 0.0), (2.0, 3.0, 1.0)]);
+
+    assert_eq!(mean_std(&[a.clone()]), vec![(0.0, 1.0, 0.0), (1.0, 2.0, 0.0), (2.0, 3.0, 0.0)]);
+    assert!(mean_std(&[a, vec![]]).is_empty());
+    assert!(mean_std(&[]).is_empty());
+}
+
+#[test]
+fn test_rgb_to_color32() {
+    let buf = [255, 0, 0, 0, 255, 0, 0, 0, 255, 10, 20, 30];
+    let colors = rgb_to_color32(&buf);
+    assert_eq!(colors, vec![
+        egui::Color32::from_rgb(255, 0, 0),
+        egui::Color32::from_rgb(0, 255, 0),
+        egui::Color32::from_rgb(0, 0, 255),
+        egui::Color32::from_rgb(10, 20, 30),
+    ]);
+    for (c, x) in colors.iter().zip(buf.chunks_exact(3)) {
+        assert_eq!([c.r(), c.g(), c.b()], [x[0], x[1], x[2]]);
+    }
+}
+
+#[test]
+fn test_dirty_eq() {
+    let a = PlotLine(vec![(0.0, 1.0), (1.0, 2.0)]);
+    let b = PlotLine(vec![(0.0, 1.0), (1.0, 2.5)]);
+    assert!(a.stats().dirty_eq(&a.stats()));
+    assert!(!a.stats().dirty_eq(&b.stats()));
+    assert!(!b.stats().dirty_eq(&a.stats()));
+    assert!(!a.stats().dirty_eq(&PlotLine(vec![(0.0, 1.0)]).stats()));
+    assert!(PlotLine::default().stats().dirty_eq(&PlotLine::default().stats()));
+
+    // the view cache only re-renders graphs whose lines changed
+    let mut plots = ModelPlots::default();
+    let test_id = PlotId { model: Models::BASELINE, run_name: "baselinev1".into(), 
+        title: "test loss".into(), x_title: "steps".into(), y_title: "loss".into()  };
+    plots.insert(test_id.clone(), a);
+    let mut render = PlotViewerV1::default();
+    assert_eq!(render.compute(&plots).unwrap().len(), 1);
+    assert_eq!(render.compute(&plots).unwrap().len(), 0);
+    plots.insert(test_id, b);
+    assert_eq!(render.compute(&plots).unwrap().len(), 1);
+}
+
+#[test]
+fn test_markers() {
+    let mut plots = ModelPlots::default();
+    let id = |run: &str, title: &str| PlotId { model: Models::BASELINE, run_name: run.into(), 
+        title: title.into(), x_title: "step".into(), y_title: "loss".into() };
+    for run in ["a", "b"] {
+        for title in ["train loss", "lr"] {
+            plots.add_point(&id(run, title), (0.0, 1.0));
+        }
+    }
+
+    plots.add_marker(Models::BASELINE, "a", "train loss", 10.0);
+    plots.add_marker(Models::BASELINE, "a", "train loss", 20.0);
+    // markers must be increasing
+    plots.add_marker(Models::BASELINE, "a", "train loss", 15.0);
+    // there is no such line
+    plots.add_marker(Models::BASELINE, "a", "test loss", 10.0);
+    assert_eq!(plots.markers(&id("a", "train loss")), &[10.0, 20.0]);
+    assert!(plots.markers(&id("a", "lr")).is_empty());
+    assert!(plots.markers(&id("b", "train loss")).is_empty());
+    assert!(plots.markers(&id("a", "test loss")).is_empty());
+
+    let saved = bincode::serialize(&plots).unwrap();
+    let plots: ModelPlots = bincode::deserialize(&saved).unwrap();
+    assert_eq!(plots.markers(&id("a", "train loss")), &[10.0, 20.0]);
+}
+
+#[test]
+fn test_downsample() {
+    let line = PlotLine((0..10000).map(|i| (i as f64, (i as f64 / 500.0).sin())).collect());
+    let sampled = line.downsample(500);
+    assert_eq!(sampled.len(), 500);
+    assert_eq!(line.len(), 10000);
+    assert_eq!(sampled[0], line[0]);
+    assert_eq!(sampled[499], line[9999]);
+    assert!(sampled.iter().tuple_windows().all(|(a, b)| a.0 < b.0));
+    // the peaks of the sine are kept
+    let max = sampled.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    assert!(max > 0.999);
+
+    assert_eq!(line.downsample(20000).len(), 10000);
+    let short = PlotLine(vec![(0.0, 1.0), (1.0, 2.0)]);
+    assert_eq!(short.downsample(500).0, short.0);
+
+    let viewer = PlotViewerV2::default();
+    assert_eq!(viewer.plot_points("sine", &line).len(), PlotViewerV2::MAX_PLOT_POINTS);
+}
+
+#[test]
+fn test_downsample_few_points() {
+    let line = PlotLine(vec![(0.0, 1.0), (1.0, 3.0), (2.0, 2.0), (3.0, 5.0)]);
+    assert!(line.downsample(0).is_empty());
+    assert_eq!(line.downsample(1).0, [(3.0, 5.0)]);
+    assert_eq!(line.downsample(2).0, [(0.0, 1.0), (3.0, 5.0)]);
+    assert_eq!(line.downsample(3).len(), 3);
+    assert!(PlotLine(Vec::new()).downsample(1).is_empty());
+}
@@ -11,9 +11,9 @@ use plotters::style::Color;
 use serde::{Deserialize, Serialize};
 
 pub use model_lib::{models, Config};
-pub use models::{TrainProcess, TrainRecv, TrainSend, PlotPoint};
+pub use models::{LogLevel, TrainProcess, TrainRecv, TrainSend, PlotPoint};
 pub use crate::ui::OperatingState;
-pub use super::{ModelPlots, PlotId, PlotViewerV1, PlotViewerV2};
+pub use super::{ConfusionViewer, ModelPlots, PlotId, PlotViewerV1, PlotViewerV2};
 
 use crate::{ops, Serializer};
 
@@ -32,6 +32,7 @@ impl Plugin for RunDataPlugin {
             // .insert_resource(PlotViewerV1::default())
             .insert_resource(PlotViewerV2::default())
             .insert_resource(ModelPlots::default())
+            .insert_resource(ConfusionViewer::default())
             .insert_resource(Console::default())
             .insert_resource(RunStats::default())
             .add_startup_system(setup_run_data)
@@ -46,12 +47,14 @@ fn setup_run_data(
     // mut plot_viewer: ResMut<PlotViewerV1>,
     mut plot_viewer2: ResMut<PlotViewerV2>,
     mut console: ResMut<Console>,
+    mut confusion: ResMut<ConfusionViewer>,
     serializer: Res<Serializer>
 ) {
     serializer.deserialize("model_plots", &mut *plots);
     serializer.deserialize("model_console", &mut *console);
     // serializer.deserialize("plot_viewer", &mut *plot_viewer);
     serializer.deserialize("plot_viewer2", &mut *plot_viewer2);
+    serializer.deserialize("confusion_viewer", &mut *confusion);
 }
 
 /// write run data to disk
@@ -60,12 +63,14 @@ fn save_run_data(
     // plot_viewer: Res<PlotViewerV1>,
     plot_viewer2: Res<PlotViewerV2>,
     console: Res<Console>,
+    confusion: Res<ConfusionViewer>,
     mut serializer: ResMut<Serializer>
 ) {
-    serializer.serialize("model_plots", &*plots);
-    serializer.serialize("model_console", &*console);
+    serializer.serialize("model_plots", &*plots).unwrap_or_else(|e| eprintln!("{:#}", e));
+    serializer.serialize("model_console", &*console).unwrap_or_else(|e| eprintln!("{:#}", e));
     // serializer.serialize("plot_viewer", &*plot_viewer);
-    serializer.serialize("plot_viewer2", &*plot_viewer2);
+    serializer.serialize("plot_viewer2", &*plot_viewer2).unwrap_or_else(|e| eprintln!("{:#}", e));
+    serializer.serialize("confusion_viewer", &*confusion).unwrap_or_else(|e| eprintln!("{:#}", e));
 }
 
 /// Enum of all the model variants
@@ -108,6 +113,10 @@ pub struct RunInfo {
     pub comments: String,
     pub dataset: String,
     pub err_status: Option<String>, // True is returned successfully, false if Killed mid-run
+    #[serde(default)]
+    pub tags: Vec<String>,   // user labels to group runs by, ex. "ablation-lr"
+    #[serde(default)]
+    pub resume_from: Option<std::path::PathBuf>, // the checkpoint the run resumes training from
     // pub checkpoints: Vec<(f32, std::path::PathBuf)>, // (step, path)
 }
 
@@ -116,6 +125,15 @@ impl RunInfo {
         format!("{}-v{}", self.model_class, self.version)
     }
 
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|x| x == tag)
+    }
+
+    /// whether the run has every tag in filter, see tags_match
+    pub fn matches_tags(&self, filter: &str) -> bool {
+        tags_match(&self.tags, filter)
+    }
+
     // pub fn add_checkpoint(&mut self, step: f32, path: std::path::PathBuf) {
     //     self.checkpoints.push((step, path));
     // }
@@ -144,6 +162,9 @@ impl RunInfo {
             //         }
             //     });
             // });
+            if self.tags.len() > 0 {
+                ui.label(format!("tags: {}", self.tags.join(", ")));
+            }
             if self.err_status.is_some() {
                 ui.label(format!("error status: {:?}", self.err_status));
             }
@@ -157,30 +178,91 @@ impl RunInfo {
     }
 }
 
+/// splits a comma separated list of tags, ignoring surrounding whitespace, empty and repeated tags
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    let mut parsed: Vec<String> = Vec::new();
+    for tag in tags.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        if !parsed.iter().any(|x| x == tag) {
+            parsed.push(tag.to_string());
+        }
+    }
+    parsed
+}
+
+/// whether tags contains every tag of the comma separated filter, an empty filter matches everything
+pub fn tags_match(tags: &[String], filter: &str) -> bool {
+    parse_tags(filter).iter().all(|tag| tags.contains(tag))
+}
+
 /// Tracking performance, memory usage, etc.
 #[derive(Resource, Default)]
 pub struct RunStats {
-    runs: HashMap<Entity, models::RunStats>
+    runs: HashMap<Entity, models::RunStats>,
+    step_times: HashMap<Entity, VecDeque<f32>>, // the most recent step times of each run
 }
 
 impl RunStats {
+    /// the number of step times averaged for the time remaining
+    const STEP_TIME_WINDOW: usize = 10;
+
     pub fn has_stat(&self, id: Entity) -> bool {
         self.runs.contains_key(&id)
     }
 
     pub fn update(&mut self, id: Entity, stats: models::RunStats) {
+        if let Some(step_time) = stats.step_time {
+            let times = self.step_times.entry(id).or_default();
+            times.push_back(step_time);
+            if times.len() > Self::STEP_TIME_WINDOW {
+                times.pop_front();
+            }
+        }
         self.runs.insert(id, stats);
     }
 
+    /// the mean of the recent step times of the run
+    pub fn mean_step_time(&self, id: Entity) -> Option<f32> {
+        let times = self.step_times.get(&id).filter(|x| x.len() > 0)?;
+        Some(times.iter().sum::<f32>() / times.len() as f32)
+    }
+
+    /// a progress bar with the estimated time remaining if the total number of steps is known,
+    /// otherwise the number of steps per second
+    pub fn show_progress(&self, id: Entity, ui: &mut egui::Ui) {
+        let (Some(stat), Some(step_time)) = (self.runs.get(&id), self.mean_step_time(id)) else { return; };
+        match (stat.step, stat.total_steps) {
+            (Some(step), Some(total)) if total > 0 => {
+                let text = match eta(step_time, step, total) {
+                    Some(secs) => format!("{}/{}, {} left", step, total, model_lib::configs::fmt_duration(secs)),
+                    None => format!("{}/{}", step, total),
+                };
+                ui.add(egui::ProgressBar::new(step.min(total) as f32 / total as f32).text(text));
+            }
+            _ if step_time > 0.0 => { ui.label(format!("{:.2} steps/s", 1.0 / step_time)); }
+            _ => {}
+        }
+    }
+
     pub fn show_basic_stats(&self, id: Entity, ui: &mut egui::Ui) {
         if let Some(stat) = self.runs.get(&id) {
             if let Some(step_time) = stat.step_time {
                 ui.label(format!("step time {:.5}s", step_time));
             }
+            if let Some(mem) = stat.device_mem_bytes {
+                ui.label(format!("device memory {:.1}MB", mem as f64 / (1024.0 * 1024.0)));
+            }
+            if let Some(mem) = stat.host_mem_bytes {
+                ui.label(format!("host memory {:.1}MB", mem as f64 / (1024.0 * 1024.0)));
+            }
         }
     }
 }
 
+/// the seconds until step reaches total_steps, taking step_time seconds per step, None once the run is done
+pub fn eta(step_time: f32, step: usize, total_steps: usize) -> Option<f64> {
+    (step < total_steps).then(|| (total_steps - step) as f64 * step_time as f64)
+}
+
 /// Since each run is identified with an Entity, sending a Kill event for a particular entity
 /// should kill it. Listeners for each run type should listen for this event, and kill their
 /// respective runs when this event is heard.
@@ -192,15 +274,72 @@ pub struct Kill(pub Entity);
 #[derive(Deref)]
 pub struct Despawn(pub Entity);
 
-pub type SpawnRun = Box<dyn FnOnce(&mut Commands) -> Result<Entity> + Send + Sync>;
+/// Spawns the run, may be called again to retry after an error
+pub type SpawnRun = Box<dyn FnMut(&mut Commands) -> Result<Entity> + Send + Sync>;
 /// A wrapper with all of the required information to spawn a new run
-pub struct Spawn(pub RunInfo, pub SpawnRun);
+pub struct Spawn {
+    pub info: RunInfo,
+    pub spawn_fn: SpawnRun,
+    pub priority: i32, // queued runs with a higher priority are spawned first
+    pub retry: Option<RetryPolicy>, // without a policy, the run is dropped on the first spawn error
+    pub retries: usize, // number of failed spawn attempts which were retried
+    pub next_attempt: Option<std::time::Instant>, // the run is not spawned before this time
+    pub last_err: Option<String>,
+}
+
+impl Spawn {
+    pub fn new(info: RunInfo, spawn_fn: SpawnRun) -> Self {
+        Self { info, spawn_fn, priority: 0, retry: None, retries: 0, next_attempt: None, last_err: None }
+    }
+
+    pub fn is_ready(&self, now: std::time::Instant) -> bool {
+        self.next_attempt.map_or(true, |t| t <= now)
+    }
+
+    /// records a failed spawn attempt at now, returns false if the run should not be retried
+    pub fn failed(&mut self, err: String, now: std::time::Instant) -> bool {
+        self.last_err = Some(err);
+        match self.retry {
+            Some(policy) if self.retries < policy.max_retries => {
+                self.next_attempt = Some(now + policy.delay(self.retries));
+                self.retries += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Retries failed spawns up to max_retries times, waiting delay * factor^i before the i-th retry
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    pub delay: std::time::Duration,
+    pub factor: f64,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize, delay: std::time::Duration) -> Self {
+        Self { max_retries, delay, factor: 2.0 }
+    }
+
+    /// the wait before the retry following `retries` previous retries
+    pub fn delay(&self, retries: usize) -> std::time::Duration {
+        self.delay.mul_f64(self.factor.powi(retries as i32))
+    }
+}
 
 
 #[derive(Resource, Serialize, Deserialize)]
 pub struct Console {
-    pub console_msgs: VecDeque<String>,
+    pub console_msgs: VecDeque<(LogLevel, String)>,
     pub max_console_msgs: usize,
+    #[serde(default = "Console::all_levels")]
+    pub shown_levels: Vec<LogLevel>, // messages of other levels are hidden
+    #[serde(default)]
+    pub filter: String,              // only messages containing the filter are shown, ignoring case
+    #[serde(default)]
+    pub highlight: bool,             // highlight messages containing the filter, rather than hiding the others
 }
 
 impl Console {
@@ -208,19 +347,97 @@ impl Console {
         Console {
             console_msgs: VecDeque::new(),
             max_console_msgs: n_logs,
+            shown_levels: Self::all_levels(),
+            filter: String::new(),
+            highlight: false,
         }
     }
 
-    pub fn log(&mut self, msg: String) {
-        self.console_msgs.push_front(msg);
+    fn all_levels() -> Vec<LogLevel> {
+        vec![LogLevel::Info, LogLevel::Warn, LogLevel::Error]
+    }
+
+    pub fn log(&mut self, level: LogLevel, msg: String) {
+        self.console_msgs.push_front((level, msg));
         if self.console_msgs.len() > self.max_console_msgs {
             self.console_msgs.pop_back();
         }
     }
 
-    pub fn console_ui(&self, ui: &mut egui::Ui) {
+    /// sets the maximum number of stored messages, dropping the oldest messages over the limit
+    pub fn set_max_msgs(&mut self, n: usize) {
+        self.max_console_msgs = n;
+        self.console_msgs.truncate(n);
+    }
+
+    pub fn info(&mut self, msg: String) {
+        self.log(LogLevel::Info, msg);
+    }
+
+    pub fn warn(&mut self, msg: String) {
+        self.log(LogLevel::Warn, msg);
+    }
+
+    pub fn error(&mut self, msg: String) {
+        self.log(LogLevel::Error, msg);
+    }
+
+    /// whether a message contains the filter, ignoring case
+    pub fn matches(&self, msg: &str) -> bool {
+        msg.to_lowercase().contains(&self.filter.to_lowercase())
+    }
+
+    /// whether a message passes the level and text filters, in highlight mode only the level filter applies
+    pub fn is_shown(&self, level: LogLevel, msg: &str) -> bool {
+        self.shown_levels.contains(&level) && (self.highlight || self.matches(msg))
+    }
+
+    /// the messages passing the filters, newest first
+    pub fn shown_msgs(&self) -> impl Iterator<Item = &(LogLevel, String)> {
+        self.console_msgs.iter().filter(|(level, msg)| self.is_shown(*level, msg))
+    }
+
+    /// the messages passing the level and text filters, oldest first, one per line
+    pub fn copy_text(&self) -> String {
+        self.console_msgs.iter().rev()
+            .filter(|(level, msg)| self.shown_levels.contains(level) && self.matches(msg))
+            .map(|(_, msg)| msg.as_str())
+            .join("\n")
+    }
+
+    pub fn console_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for level in Self::all_levels() {
+                let mut shown = self.shown_levels.contains(&level);
+                if ui.checkbox(&mut shown, format!("{:?}", level)).changed() {
+                    self.shown_levels.retain(|x| *x != level);
+                    if shown {
+                        self.shown_levels.push(level);
+                    }
+                }
+            }
+            ui.add(egui::TextEdit::singleline(&mut self.filter).hint_text("search"));
+            ui.checkbox(&mut self.highlight, "highlight");
+            if ui.button("copy all").clicked() {
+                ui.output().copied_text = self.copy_text();
+            }
+            let mut max_msgs = self.max_console_msgs;
+            if ui.add(egui::DragValue::new(&mut max_msgs).clamp_range(1..=100000).prefix("max lines: ")).changed() {
+                self.set_max_msgs(max_msgs);
+            }
+        });
         egui::ScrollArea::vertical().show(ui, |ui| {
-            for text in &self.console_msgs {
+            let highlight = self.highlight && !self.filter.is_empty();
+            for (level, text) in self.shown_msgs() {
+                let mut text = egui::RichText::new(text);
+                match level {
+                    LogLevel::Info => {}
+                    LogLevel::Warn => { text = text.color(egui::Color32::YELLOW); }
+                    LogLevel::Error => { text = text.color(egui::Color32::RED); }
+                }
+                if highlight && self.matches(text.text()) {
+                    text = text.background_color(ui.visuals().selection.bg_fill);
+                }
                 ui.label(text);
             }
         });
@@ -229,9 +446,104 @@ impl Console {
 
 impl Default for Console {
     fn default() -> Self {
-        Self {
-            console_msgs: VecDeque::new(),
-            max_console_msgs: 50,
-        }
+        Self::new(50)
+    }
+}
+
+#[test]
+fn test_filter_runs_by_tag() {
+    let run = |version: usize, tags: &str| RunInfo { model_class: "baseline".into(), version, tags: parse_tags(tags), ..Default::default() };
+    let runs = vec![run(0, "baseline"), run(1, "ablation-lr, baseline"), run(2, " ablation-lr ,,ablation-lr"), run(3, "")];
+    assert_eq!(runs[2].tags, ["ablation-lr"]);
+
+    let versions = |pred: &dyn Fn(&RunInfo) -> bool| runs.iter().filter(|x| pred(x)).map(|x| x.version).collect::<Vec<_>>();
+    assert_eq!(versions(&|x| x.has_tag("ablation-lr")), [1, 2]);
+    assert_eq!(versions(&|x| x.matches_tags("baseline")), [0, 1]);
+    assert_eq!(versions(&|x| x.matches_tags("baseline, ablation-lr")), [1]);
+    assert_eq!(versions(&|x| x.matches_tags(" ")), [0, 1, 2, 3]);
+    assert_eq!(versions(&|x| x.matches_tags("other")), Vec::<usize>::new());
+
+    // tags are saved with the run, and runs saved before tags existed load without any
+    let saved = ron::to_string(&runs[1]).unwrap();
+    assert_eq!(ron::from_str::<RunInfo>(&saved).unwrap().tags, ["ablation-lr", "baseline"]);
+    let old = saved.replace(",tags:[\"ablation-lr\",\"baseline\"]", "");
+    assert_ne!(old, saved);
+    assert!(ron::from_str::<RunInfo>(&old).unwrap().tags.is_empty());
+}
+
+#[test]
+fn test_eta() {
+    let id = Entity::from_raw(0);
+    let mut stats = RunStats::default();
+    let stat = |step: usize, step_time: f32| models::RunStats { step_time: Some(step_time), step: Some(step), total_steps: Some(100), ..Default::default() };
+    assert_eq!(stats.mean_step_time(id), None);
+    for (i, step_time) in [0.5, 1.5, 1.0].into_iter().enumerate() {
+        stats.update(id, stat(10 * (i + 1), step_time));
+    }
+    assert_eq!(stats.mean_step_time(id), Some(1.0));
+    assert_eq!(eta(stats.mean_step_time(id).unwrap(), 30, 100), Some(70.0));
+
+    // only the most recent step times are averaged
+    for i in 0..RunStats::STEP_TIME_WINDOW {
+        stats.update(id, stat(40 + i, 0.25));
+    }
+    assert_eq!(stats.mean_step_time(id), Some(0.25));
+    assert_eq!(eta(0.25, 60, 100), Some(10.0));
+    assert_eq!(eta(0.25, 100, 100), None);
+    // stats without step times do not change the average
+    stats.update(id, models::RunStats::default());
+    assert_eq!(stats.mean_step_time(id), Some(0.25));
+}
+
+#[test]
+fn test_console_filter() {
+    let mut console = Console::new(10);
+    console.info("Logged train loss".into());
+    console.warn("loss is increasing".into());
+    console.error("Error out of memory while training baseline-v0".into());
+    console.info("baseline-v0 finished training".into());
+    let shown = |console: &Console| console.shown_msgs().map(|x| x.1.clone()).collect::<Vec<_>>();
+    assert_eq!(shown(&console).len(), 4);
+
+    console.shown_levels = vec![LogLevel::Warn, LogLevel::Error];
+    assert_eq!(shown(&console), ["Error out of memory while training baseline-v0", "loss is increasing"]);
+
+    console.shown_levels = Console::all_levels();
+    console.filter = "BASELINE".into();
+    assert_eq!(shown(&console), ["baseline-v0 finished training", "Error out of memory while training baseline-v0"]);
+    console.shown_levels = vec![LogLevel::Info];
+    assert_eq!(shown(&console), ["baseline-v0 finished training"]);
+    console.shown_levels.clear();
+    assert!(shown(&console).is_empty());
+}
+
+#[test]
+fn test_console_search() {
+    let mut console = Console::new(3);
+    for i in 0..5 {
+        console.info(format!("step {}", i));
     }
+    // the oldest messages are dropped
+    assert_eq!(console.console_msgs.iter().map(|x| x.1.as_str()).collect::<Vec<_>>(), ["step 4", "step 3", "step 2"]);
+    console.set_max_msgs(2);
+    assert_eq!(console.console_msgs.iter().map(|x| x.1.as_str()).collect::<Vec<_>>(), ["step 4", "step 3"]);
+    console.set_max_msgs(4);
+    console.error("Error at step 5".into());
+    console.info("finished".into());
+    assert_eq!(console.console_msgs.len(), 4);
+    console.info("done".into());
+    assert_eq!(console.console_msgs.back().unwrap().1, "step 4");
+
+    console.filter = "STEP".into();
+    assert!(console.is_shown(LogLevel::Error, "Error at step 5"));
+    assert!(!console.is_shown(LogLevel::Info, "finished"));
+    assert_eq!(console.copy_text(), "step 4\nError at step 5");
+    // highlighting keeps the other messages, but copying still only takes the matches
+    console.highlight = true;
+    assert!(console.is_shown(LogLevel::Info, "finished"));
+    assert_eq!(console.shown_msgs().count(), 4);
+    assert_eq!(console.copy_text(), "step 4\nError at step 5");
+    console.shown_levels = vec![LogLevel::Info];
+    assert!(!console.is_shown(LogLevel::Error, "Error at step 5"));
+    assert_eq!(console.copy_text(), "step 4");
 }
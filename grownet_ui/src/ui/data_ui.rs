@@ -1,14 +1,16 @@
-use std::sync::Mutex;
+use std::path::Path;
 
-use anyhow::{Context, Error, Result};
+use anyhow::{Error, Result};
 use bevy::prelude::*;
 use bevy_egui::egui;
-use itertools::Itertools;
-use ndarray::{Array, Array3, Ix4};
+use ndarray::prelude::*;
+use rand::{SeedableRng, rngs::StdRng, thread_rng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
 
-use super::{AppState, OperatingState, UIParams};
-use crate::{Configure, UI, CONFIG_PATH};
-use model_lib::datasets::{data, Dataset, DatasetBuilder};
+use super::OperatingState;
+use crate::{Serializer, UI};
+use model_lib::datasets::{self as data, Dataset, DatasetBuilder};
+use model_lib::datasets::transforms::{ImClassifyDataPoint, Labels, Resize};
 
 pub struct DatasetUIPlugin;
 impl Plugin for DatasetUIPlugin {
@@ -18,68 +20,72 @@ impl Plugin for DatasetUIPlugin {
     }
 }
 
-fn setup_dataset_ui(mut commands: Commands) {
-    use model_lib::datasets as data;
-
+fn setup_dataset_ui(mut commands: Commands, serializer: Res<Serializer>) {
     let mut dataset_ui = DatasetUI::default();
+    dataset_ui.push_viewer(ClassificationViewer::new("assets/ml_datasets", build_mnist), "mnist");
+    dataset_ui.push_viewer(ClassificationViewer::new("assets/ml_datasets/cifar-10-batches-bin", build_cifar10), "cifar10");
+    dataset_ui.push_viewer(ClassificationViewer::new("assets/ml_datasets/images", build_image_folder), "image folder");
 
-    let cifar10 = data::cifar::Cifar10Params::default();
-    let cifar_viewer = ClassificationViewer::new(cifar10);
-    dataset_ui.push_viewer(cifar_viewer, "cifar10");
-
-    let mnist = data::mnist::MnistParams::default();
-    let mnist_viewer = ClassificationViewer::new(mnist);
-    dataset_ui.push_viewer(mnist_viewer, "mnist");
-
-    // load configurations from disk
-    let root_path: std::path::PathBuf = CONFIG_PATH.into();
-    let config_file = root_path.join("data_ui_config").with_extension("ron");
-
-    if config_file.exists() {
-        eprintln!("loading from config file {}", config_file.to_str().unwrap());
-        let config: String =
-            ron::from_str(&std::fs::read_to_string(&config_file).unwrap()).unwrap();
-        if let Err(e) = dataset_ui.load_config(&config) {
-            eprintln!("failed to load data config\n{}", e);
-        }
-    }
-
+    let mut params = DatasetUIParams::default();
+    serializer.deserialize("dataset_ui", &mut params);
+    dataset_ui.load_params(params);
     commands.insert_resource(dataset_ui);
 }
 
-fn save_dataset_ui(dataset_params: Res<DatasetUI>) {
-    eprintln!("saving data_ui params");
-    let data_ui_config = dataset_params.config();
-    eprintln!("data ui params {}", data_ui_config);
-
-    let root_path: std::path::PathBuf = CONFIG_PATH.into();
-    if !root_path.exists() {
-        std::fs::create_dir_all(&root_path).unwrap();
-    }
-
-    let config_file = root_path.join("data_ui_config").with_extension("ron");
-
-    let serialized = ron::to_string(&data_ui_config).unwrap();
-    std::fs::write(&config_file, serialized).unwrap();
+fn save_dataset_ui(dataset_ui: Res<DatasetUI>, mut serializer: ResMut<Serializer>) {
+    serializer.serialize("dataset_ui", &dataset_ui.params()).unwrap_or_else(|e| eprintln!("{:#}", e));
 }
 
+/// `viewers` and `names` are parallel vectors kept in insertion order, so the dataset
+/// selector renders in a stable order across launches
 #[derive(Default, Resource)]
 pub struct DatasetUI {
     cur_active: usize,
-    viewers: Vec<Box<dyn Viewer>>,
+    viewers: Vec<ClassificationViewer>,
     names: Vec<&'static str>,
 }
 
+/// The persisted part of DatasetUI, viewers are matched by name
+#[derive(Default, Serialize, Deserialize)]
+pub struct DatasetUIParams {
+    active: Option<String>,
+    viewers: Vec<(String, ViewerParams)>,
+}
+
 impl DatasetUI {
-    pub fn push_viewer<T: Viewer + 'static>(&mut self, viewer: T, name: &'static str) {
-        self.viewers.push(Box::new(viewer));
+    pub fn push_viewer(&mut self, viewer: ClassificationViewer, name: &'static str) {
+        self.viewers.push(viewer);
         self.names.push(name);
     }
-}
 
-impl UI for DatasetUI {
-    fn ui(&mut self, ui: &mut egui::Ui) {
-        //ui.spacing_mut().interact_size.y = ui.available_size().y;
+    /// the dataset names, in the order they are shown in the selector
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.names.iter().copied()
+    }
+
+    pub fn params(&self) -> DatasetUIParams {
+        DatasetUIParams {
+            active: self.names.get(self.cur_active).map(|x| x.to_string()),
+            viewers: self.names().map(|x| x.to_string()).zip(self.viewers.iter().map(|x| x.params.clone())).collect(),
+        }
+    }
+
+    /// params of datasets which are not in the selector are ignored
+    pub fn load_params(&mut self, params: DatasetUIParams) {
+        for (name, viewer_params) in params.viewers {
+            if let Some(i) = self.names.iter().position(|x| *x == name) {
+                self.viewers[i].params = viewer_params;
+            }
+        }
+        if let Some(i) = params.active.and_then(|name| self.names.iter().position(|x| *x == name)) {
+            self.cur_active = i;
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if self.viewers.is_empty() {
+            return;
+        }
         ui.horizontal(|ui| {
             // Select which dataset to use
             let last_active = self.cur_active;
@@ -101,251 +107,372 @@ impl UI for DatasetUI {
     }
 }
 
-impl Configure for DatasetUI {
-    fn config(&self) -> String {
-        let viewers: Vec<_> = self.viewers.iter().map(|v| v.config()).collect_vec();
-        ron::to_string(&viewers).unwrap()
-    }
-
-    fn load_config(&mut self, config: &str) -> Result<()> {
-        let config: Vec<String> = ron::from_str(config)?;
-        for (i, s) in config.iter().enumerate() {
-            self.viewers[i].load_config(s)?;
-        }
-        Ok(())
+impl UI for Resize {
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.size[0]).clamp_range(1..=4096).prefix("width: "));
+            ui.add(egui::DragValue::new(&mut self.size[1]).clamp_range(1..=4096).prefix("height: "));
+        });
     }
 }
 
-pub trait Viewer: Configure + UI {
-    fn drop_dataset(&mut self);
+/// A dataset of image batches with shape [b, c, h, w] in [0, 1]
+pub type BoxedDataset = Box<dyn Dataset<DataPoint = ImClassifyDataPoint> + Send + Sync>;
+/// Builds the train set, and the test set if there is one, of the dataset at a path with a batch size
+pub type BuildDataset = fn(&Path, usize) -> Result<(BoxedDataset, Option<BoxedDataset>)>;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewerParams {
+    pub path: String,
+    pub batch_size: usize,
+    pub im_scale: f32,
 }
 
-/// Viewer for the Classification dataset type
-pub struct ClassificationViewer<D: DatasetBuilder> {
-    train_data: Option<Mutex<D::Dataset>>,
-    test_data: Option<Mutex<D::Dataset>>,
-    params: D,
+/// Viewer for image classification datasets
+pub struct ClassificationViewer {
+    params: ViewerParams,
+    build: BuildDataset,
+    train_data: Option<BoxedDataset>,
+    test_data: Option<BoxedDataset>,
+    load_err: Option<String>, // the datasets are not rebuilt until reset after an error
     train_texture: Option<Vec<egui::TextureHandle>>,
     test_texture: Option<Vec<egui::TextureHandle>>,
-    im_scale: f32,
+    next_train: bool,
+    next_test: bool,
 }
 
-unsafe impl<D: DatasetBuilder> Send for ClassificationViewer<D> {}
-unsafe impl<D: DatasetBuilder> Sync for ClassificationViewer<D> {}
-
-impl<D, B> ClassificationViewer<B>
-where
-    D: Dataset<DataPoint = data::ImClassify>,
-    B: DatasetBuilder<Dataset = D>,
-{
-    pub fn new(builder: B) -> Self {
+impl ClassificationViewer {
+    pub fn new(path: &str, build: BuildDataset) -> Self {
         Self {
+            params: ViewerParams { path: path.into(), batch_size: 4, im_scale: 4.0 },
+            build,
             train_data: None,
             test_data: None,
-            params: builder,
+            load_err: None,
             train_texture: None,
             test_texture: None,
-            im_scale: 1.0,
+            next_train: false,
+            next_test: false,
         }
     }
 
-    fn load_texture(data: &mut Mutex<D>, ui: &mut egui::Ui) -> Vec<egui::TextureHandle> {
-        use ndarray::Axis;
-        let data = &mut *data.lock().unwrap();
-        let data_point = if let Some(x) = data.next() {
-            x
-        } else {
-            data.reset();
-            data.next().unwrap()
+    pub fn drop_dataset(&mut self) {
+        self.train_data = None;
+        self.test_data = None;
+        self.train_texture = None;
+        self.test_texture = None;
+        self.load_err = None;
+    }
+
+    /// Loads the next data point into textures, reusing the existing texture handles when the
+    /// number of images and their sizes are unchanged, only allocating on first use or size change
+    fn load_texture(data: &mut BoxedDataset, textures: &mut Option<Vec<egui::TextureHandle>>, ctx: &egui::Context) -> Result<()> {
+        let data_point = match data.next() {
+            Some(x) => x,
+            None => {
+                data.reset();
+                match data.next() {
+                    Some(x) => x,
+                    None => return Ok(()),
+                }
+            }
         };
+        let images = data_point.image.axis_iter(Axis(0)).map(to_color_image).collect::<Result<Vec<_>>>()?;
 
-        let batch_size = data_point.image.image.dim().0;
-
-        let mut pixels: Vec<Vec<_>> = (0..batch_size)
-            .map(|batch| {
-                data_point
-                    .image
-                    .image
-                    .index_axis(Axis(0), batch)
-                    .as_slice()
-                    .unwrap()
-                    .chunks_exact(3)
-                    .map(|x| {
-                        egui::Color32::from_rgb(
-                            (x[0] * 255.0) as u8,
-                            (x[1] * 255.0) as u8,
-                            (x[2] * 255.0) as u8,
-                        )
-                    })
-                    .collect()
-            })
-            .collect();
-
-        let size = data_point.image.size();
-        let handles = (0..batch_size)
-            .map(|_| {
-                let color_image = egui::ColorImage {
-                    size,
-                    pixels: pixels.pop().unwrap(),
-                };
-                ui.ctx()
-                    .load_texture("im sample", color_image, egui::TextureOptions::NEAREST)
-            })
-            .collect();
-        handles
+        match textures {
+            Some(handles) if reuse_textures(handles, &images) => {
+                for (handle, image) in handles.iter_mut().zip(images) {
+                    handle.set(image, egui::TextureOptions::NEAREST);
+                }
+            }
+            _ => {
+                let handles = images
+                    .into_iter()
+                    .map(|image| ctx.load_texture("im sample", image, egui::TextureOptions::NEAREST))
+                    .collect();
+                *textures = Some(handles);
+            }
+        }
+        Ok(())
     }
 
-    fn loading_logic(&mut self, ui: &mut egui::Ui) {
-        let err_ui = |err: Error, ui: &mut egui::Ui| -> Option<D> {
-            ui.label(format!("Error loading dataset {}", err.to_string()));
-            None
-        };
+    fn loading_logic(&mut self, ctx: &egui::Context) {
         // load the datasets if not loaded already
-        if let None = self.train_data {
-            let train_data = self
-                .params
-                .build_train()
-                .map_or_else(|e| err_ui(e, ui), |x| Some(x));
-            self.train_data = train_data.map(|x| Mutex::new(x));
-        }
-        if let None = self.test_data {
-            if let Some(x) = self.params.build_test() {
-                let test_data = x.map_or_else(|e| err_ui(e, ui), |x| Some(x));
-                self.test_data = test_data.map(|x| Mutex::new(x));
+        if self.train_data.is_none() && self.load_err.is_none() {
+            match (self.build)(Path::new(&self.params.path), self.params.batch_size.max(1)) {
+                Ok((train, test)) => {
+                    self.train_data = Some(train);
+                    self.test_data = test;
+                }
+                Err(e) => self.load_err = Some(format!("{:#}", e)),
             }
         }
 
-        // load a data point if not loaded already
+        // load a data point if not loaded already, or if the next one was requested
+        let mut loaded = Ok(());
         if let Some(data) = &mut self.train_data {
-            if let None = self.train_texture {
-                self.train_texture = Some(Self::load_texture(data, ui));
+            if self.train_texture.is_none() || self.next_train {
+                loaded = Self::load_texture(data, &mut self.train_texture, ctx);
+                self.next_train = false;
             }
         }
         if let Some(data) = &mut self.test_data {
-            if let None = self.test_texture {
-                self.test_texture = Some(Self::load_texture(data, ui));
+            if loaded.is_ok() && (self.test_texture.is_none() || self.next_test) {
+                loaded = Self::load_texture(data, &mut self.test_texture, ctx);
+                self.next_test = false;
             }
         }
-    }
-
-    fn display_im_if_any(&self, textures: &Option<Vec<egui::TextureHandle>>, ui: &mut egui::Ui) {
-        let im_scale = self.im_scale;
-        if let Some(images) = &textures {
-            for im in images {
-                let mut size = im.size_vec2();
-                size *= im_scale;
-                ui.image(im, size);
-            }
+        // images which can not be shown are reported like a failed load, until reset
+        if let Err(e) = loaded {
+            self.load_err = Some(format!("{:#}", e));
+            self.train_data = None;
+            self.test_data = None;
         }
     }
-}
 
-impl<D, B> UI for ClassificationViewer<B>
-where
-    D: Dataset<DataPoint = data::ImClassify>,
-    B: DatasetBuilder<Dataset = D> + UI,
-{
-    fn ui(&mut self, ui: &mut egui::Ui) {
-        ui.vertical(|ui| {
-            self.params.ui(ui);
-            self.loading_logic(ui);
-        });
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
         ui.vertical(|ui| {
-            egui::containers::panel::SidePanel::right("test images").show(ui.ctx(), |ui| {
-                ui.vertical(|ui| {
-                    egui::containers::ScrollArea::vertical()
-                        .id_source("test images")
-                        .show(ui, |ui| {
-                            self.display_im_if_any(&self.test_texture, ui);
-                            if let Some(_) = &self.test_texture {
-                                if ui.button("next test").clicked() {
-                                    self.test_texture = None;
-                                }
-                            }
-                            if let Some(data) = &mut self.test_data {
-                                if ui.button("shuffle test").clicked() {
-                                    (*data.lock().unwrap()).shuffle();
-                                }
-                            }
-                            if ui.button("reset test").clicked() {
-                                self.test_data = None;
-                                self.test_texture = None;
-                            }
-                        });
-                    //egui::CollapsingHeader::new("test images")
-                    //    .default_open(true)
-                    //    .show(ui, |ui| {
-                    //    self.display_im_if_any(&self.test_texture, ui);
-                    //});
-                });
+            ui.horizontal(|ui| {
+                ui.label("path");
+                ui.text_edit_singleline(&mut self.params.path);
+                ui.add(egui::DragValue::new(&mut self.params.batch_size).clamp_range(1..=64).prefix("batch size: "));
+                if ui.button("reload").clicked() {
+                    self.drop_dataset();
+                }
             });
+            ui.add(egui::Slider::new(&mut self.params.im_scale, 0.1..=10.0).text("image scale"));
+            self.loading_logic(ui.ctx());
+            if let Some(err) = &self.load_err {
+                ui.label(egui::RichText::new(format!("Error loading dataset {}", err)).color(egui::Color32::RED));
+            }
 
-            egui::containers::panel::SidePanel::right("train images").show(ui.ctx(), |ui| {
-                ui.vertical(|ui| {
-                    egui::containers::ScrollArea::vertical()
-                        .id_source("train images")
-                        .show(ui, |ui| {
-                            self.display_im_if_any(&self.train_texture, ui);
-                            if let Some(_) = &self.train_texture {
-                                if ui.button("next train").clicked() {
-                                    self.train_texture = None;
-                                }
-                            }
-                            if let Some(data) = &mut self.train_data {
-                                if ui.button("shuffle train").clicked() {
-                                    (*data.lock().unwrap()).shuffle();
-                                }
-                            }
-                            if ui.button("reset train").clicked() {
-                                self.train_data = None;
-                                self.train_texture = None;
-                            }
-                        });
-
-                    //egui::CollapsingHeader::new("train images")
-                    //    .default_open(true)
-                    //    .show(ui, |ui| {
-                    //    self.display_im_if_any(&self.train_texture, ui);
-                    //});
-                });
+            let im_scale = self.params.im_scale;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                if let Some(data) = &mut self.train_data {
+                    images_ui("train", data, &self.train_texture, &mut self.next_train, im_scale, ui);
+                }
+                if let Some(data) = &mut self.test_data {
+                    images_ui("test", data, &self.test_texture, &mut self.next_test, im_scale, ui);
+                }
             });
+        });
+    }
+}
 
-            ui.label("image scale");
-            ui.add(egui::Slider::new(&mut self.im_scale, 0.1..=10.0));
+/// the buttons to advance or shuffle a dataset, and its current images
+fn images_ui(name: &str, data: &mut BoxedDataset, textures: &Option<Vec<egui::TextureHandle>>, next: &mut bool, im_scale: f32, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.label(format!("{} images", name));
+        if ui.button(format!("next {}", name)).clicked() {
+            *next = true;
+        }
+        if ui.button(format!("shuffle {}", name)).clicked() {
+            data.shuffle();
+            *next = true;
+        }
+    });
+    if let Some(images) = textures {
+        ui.horizontal_wrapped(|ui| {
+            for im in images {
+                ui.image(im, im.size_vec2() * im_scale);
+            }
         });
     }
 }
 
-impl<D, B> Configure for ClassificationViewer<B>
-where
-    D: Dataset<DataPoint = data::ImClassify>,
-    B: DatasetBuilder<Dataset = D>,
-{
-    fn config(&self) -> String {
-        let self_params = ron::to_string(&self.im_scale).unwrap();
-        let data_params = self.params.config();
-        ron::to_string(&(self_params, data_params)).unwrap()
+/// Can the existing texture handles be updated in place with the new images?
+fn reuse_textures(handles: &[egui::TextureHandle], images: &[egui::ColorImage]) -> bool {
+    handles.len() == images.len()
+        && handles.iter().zip(images).all(|(h, im)| h.size() == im.size)
+}
+
+/// converts an image with shape [c, h, w] and values in [0, 1], single channel images are shown in grayscale
+fn to_color_image(image: ArrayView3<f32>) -> Result<egui::ColorImage> {
+    let (channels, h, w) = image.dim();
+    let byte = |x: f32| (x.clamp(0.0, 1.0) * 255.0) as u8;
+    let pixels = match channels {
+        // grayscale, the single channel is used for each of r, g and b
+        1 => image.iter().map(|x| {
+            let v = byte(*x);
+            egui::Color32::from_rgb(v, v, v)
+        }).collect(),
+        // rgb, the alpha channel of rgba images is dropped
+        3 | 4 => (0..h).flat_map(|y| (0..w).map(move |x| (y, x))).map(|(y, x)| {
+            egui::Color32::from_rgb(byte(image[[0, y, x]]), byte(image[[1, y, x]]), byte(image[[2, y, x]]))
+        }).collect(),
+        c => return Err(Error::msg(format!("cannot display images with {} channels", c))),
+    };
+    Ok(egui::ColorImage { size: [w, h], pixels })
+}
+
+/// Images stored as bytes with shape [n, c, h, w], each batch is converted to [0, 1] when it is requested
+struct InMemoryImages {
+    images: Array4<u8>,
+    labels: Array1<u8>,
+    order: Vec<usize>,
+    batch_size: usize,
+    pos: usize,
+}
+
+impl InMemoryImages {
+    fn new(images: Array4<u8>, labels: Array1<u8>, batch_size: usize) -> Self {
+        let order = (0..images.dim().0).collect();
+        Self { images, labels, order, batch_size, pos: 0 }
     }
+}
 
-    fn load_config(&mut self, config: &str) -> Result<()> {
-        let (self_params, data_params): (String, String) =
-            ron::from_str(config).context("Classification Viewer")?;
-        let scale: f32 = ron::from_str(&self_params).context("Classification Viewer")?;
-        self.params
-            .load_config(&data_params)
-            .context("Classification viewer dataset parameters")?;
-        self.im_scale = scale;
-        Ok(())
+impl Dataset for InMemoryImages {
+    type DataPoint = ImClassifyDataPoint;
+
+    fn next(&mut self) -> Option<Self::DataPoint> {
+        if self.pos >= self.order.len() {
+            return None;
+        }
+        let idx = &self.order[self.pos..(self.pos + self.batch_size).min(self.order.len())];
+        self.pos += idx.len();
+        Some(ImClassifyDataPoint {
+            image: self.images.select(Axis(0), idx).mapv(|x| x as f32 / 255.0),
+            label: Labels::Hard(idx.iter().map(|i| self.labels[*i] as u32).collect()),
+        })
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    fn shuffle(&mut self) {
+        self.order.shuffle(&mut thread_rng());
+        self.pos = 0;
+    }
+
+    fn shuffle_seeded(&mut self, seed: u64) {
+        self.order.sort();
+        self.order.shuffle(&mut StdRng::seed_from_u64(seed));
+        self.pos = 0;
     }
 }
 
-impl<D, B> Viewer for ClassificationViewer<B>
-where
-    D: Dataset<DataPoint = data::ImClassify>,
-    B: DatasetBuilder<Dataset = D> + UI,
-{
-    fn drop_dataset(&mut self) {
-        self.train_data = None;
-        self.test_data = None;
+/// stacks images with the same shape along a new first axis
+fn stack<'a, D: Dimension>(images: impl Iterator<Item = ArrayView<'a, u8, D>>) -> Result<Array<u8, D::Larger>> {
+    let images: Vec<_> = images.collect();
+    ndarray::stack(Axis(0), &images).map_err(Error::msg)
+}
+
+fn build_mnist(path: &Path, batch_size: usize) -> Result<(BoxedDataset, Option<BoxedDataset>)> {
+    let mnist = data::mnist::Mnist::new(&path.to_string_lossy())?;
+    // mnist images are single channel
+    let train = stack(mnist.iter_train_img())?.insert_axis(Axis(1));
+    let test = stack(mnist.iter_test_img())?.insert_axis(Axis(1));
+    Ok((
+        Box::new(InMemoryImages::new(train, mnist.iter_train_label().copied().collect(), batch_size)),
+        Some(Box::new(InMemoryImages::new(test, mnist.iter_test_label().copied().collect(), batch_size))),
+    ))
+}
+
+fn build_cifar10(path: &Path, batch_size: usize) -> Result<(BoxedDataset, Option<BoxedDataset>)> {
+    let params = data::cifar10::Cifar10Params {
+        path: path.to_string_lossy().into(),
+        train_batch_size: batch_size,
+        test_batch_size: batch_size,
+    };
+    let test = params.build_test().transpose()?;
+    Ok((Box::new(params.build_train()?), test.map(|x| Box::new(x) as BoxedDataset)))
+}
+
+/// a folder with a subfolder of images per class, images are shown at their own size
+fn build_image_folder(path: &Path, batch_size: usize) -> Result<(BoxedDataset, Option<BoxedDataset>)> {
+    let folder = data::image_folder::ImageFolder::new(path, [1, 1], batch_size)?;
+    Ok((Box::new(data::StreamingDataset::new(folder.samples().to_vec(), batch_size, None)), None))
+}
+
+#[cfg(test)]
+fn build_test_images(_path: &Path, batch_size: usize) -> Result<(BoxedDataset, Option<BoxedDataset>)> {
+    let images = Array4::from_shape_fn((5, 3, 2, 4), |(i, c, y, x)| (i * 40 + c * 10 + y * 4 + x) as u8);
+    let labels = (0..5).collect();
+    Ok((Box::new(InMemoryImages::new(images, labels, batch_size)), None))
+}
+
+#[test]
+fn test_dataset_selector_order() {
+    let names = ["mnist", "cifar10", "image folder", "another"];
+    let mut dataset_ui = DatasetUI::default();
+    for name in names {
+        dataset_ui.push_viewer(ClassificationViewer::new("", build_test_images), name);
+    }
+    for _ in 0..3 {
+        assert_eq!(dataset_ui.names().collect::<Vec<_>>(), names);
+    }
+
+    // params are restored by name, and unknown datasets are ignored
+    dataset_ui.viewers[2].params.batch_size = 7;
+    dataset_ui.cur_active = 2;
+    let mut params = dataset_ui.params();
+    assert_eq!(params.viewers.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(), names);
+    params.viewers.reverse();
+    params.viewers.push(("removed".into(), params.viewers[0].1.clone()));
+    let mut restored = DatasetUI::default();
+    for name in names {
+        restored.push_viewer(ClassificationViewer::new("", build_test_images), name);
+    }
+    restored.load_params(params);
+    assert_eq!(restored.names().collect::<Vec<_>>(), names);
+    assert_eq!(restored.cur_active, 2);
+    assert_eq!(restored.viewers.iter().map(|x| x.params.batch_size).collect::<Vec<_>>(), [4, 4, 7, 4]);
+}
+
+#[test]
+fn test_texture_reuse() {
+    let ctx = egui::Context::default();
+    let mut viewer = ClassificationViewer::new("", build_test_images);
+    viewer.params.batch_size = 2;
+    let ids = |viewer: &ClassificationViewer| viewer.train_texture.as_ref().unwrap().iter().map(|x| x.id()).collect::<Vec<_>>();
+
+    viewer.loading_logic(&ctx);
+    let first = ids(&viewer);
+    assert_eq!(first.len(), 2);
+    // the second batch has the same shape, so its images are uploaded into the same textures
+    viewer.next_train = true;
+    viewer.loading_logic(&ctx);
+    assert_eq!(ids(&viewer), first);
+    assert!(!viewer.next_train);
+    // the last batch holds a single image, so new textures are allocated
+    viewer.next_train = true;
+    viewer.loading_logic(&ctx);
+    let last = ids(&viewer);
+    assert_eq!(last.len(), 1);
+    assert!(!first.contains(&last[0]));
+    // without a request for the next batch, nothing is reloaded
+    viewer.loading_logic(&ctx);
+    assert_eq!(ids(&viewer), last);
+}
+
+#[test]
+fn test_to_color_image() {
+    let rgb = Array3::from_shape_vec((3, 1, 2), vec![1.0, 0.0, 0.5, 1.0, 0.0, 0.25]).unwrap();
+    let im = to_color_image(rgb.view()).unwrap();
+    assert_eq!(im.size, [2, 1]);
+    assert_eq!(im.pixels, [egui::Color32::from_rgb(255, 127, 0), egui::Color32::from_rgb(0, 255, 63)]);
+
+    let gray = Array3::from_shape_vec((1, 2, 1), vec![0.5, 1.0]).unwrap();
+    let im = to_color_image(gray.view()).unwrap();
+    assert_eq!(im.size, [1, 2]);
+    assert_eq!(im.pixels, [egui::Color32::from_rgb(127, 127, 127), egui::Color32::from_rgb(255, 255, 255)]);
+
+    // the alpha channel is dropped
+    let rgba = Array3::from_shape_vec((4, 1, 2), vec![1.0, 0.0, 0.5, 1.0, 0.0, 0.25, 0.0, 1.0]).unwrap();
+    assert_eq!(to_color_image(rgba.view()).unwrap().pixels, to_color_image(rgb.view()).unwrap().pixels);
+
+    let two_channels = Array3::<f32>::zeros((2, 1, 2));
+    assert!(to_color_image(two_channels.view()).is_err());
+
+    // the viewer shows an error instead of panicking, and does not rebuild the dataset until reset
+    fn build_two_channel_images(_path: &Path, batch_size: usize) -> Result<(BoxedDataset, Option<BoxedDataset>)> {
+        Ok((Box::new(InMemoryImages::new(Array4::zeros((2, 2, 1, 2)), (0..2).collect(), batch_size)), None))
     }
+    let ctx = egui::Context::default();
+    let mut viewer = ClassificationViewer::new("", build_two_channel_images);
+    viewer.loading_logic(&ctx);
+    assert!(viewer.load_err.as_ref().unwrap().contains("2 channels"));
+    viewer.loading_logic(&ctx);
+    assert!(viewer.train_data.is_none() && viewer.train_texture.is_none());
 }
@@ -16,7 +16,7 @@ use bincode;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use crate::Serializer;
 
-// pub mod data_ui;
+pub mod data_ui;
 pub mod train_ui;
 
 /// The ui plugin, the entry point for the ui
@@ -29,7 +29,7 @@ impl Plugin for UIPlugin {
             .add_startup_system_to_stage(StartupStage::Startup, setup_ui)
             .add_state(AppState::Models)
             .add_state(OperatingState::Active)
-            // .add_plugin(data_ui::DatasetUIPlugin)
+            .add_plugin(data_ui::DatasetUIPlugin)
             .add_plugin(train_ui::TrainUIPlugin)
             .add_system_set(SystemSet::on_update(AppState::Menu).with_system(menu_ui))
             .add_system_set(SystemSet::on_update(OperatingState::Active).with_system(should_cleanup))
@@ -42,7 +42,7 @@ impl Plugin for UIPlugin {
 fn menu_ui(
     mut egui_context: ResMut<EguiContext>,
     mut params: ResMut<UIParams>,
-    // mut dataset_state: ResMut<data_ui::DatasetUI>,
+    mut dataset_state: ResMut<data_ui::DatasetUI>,
     mut app_state: ResMut<State<AppState>>,
     op_state: ResMut<State<OperatingState>>,
 ) {
@@ -56,7 +56,7 @@ fn menu_ui(
             OpenPanel::Models => {
                 app_state.set(AppState::Models).unwrap();
             }
-            // OpenPanel::Datasets => dataset_state.ui(ui),
+            OpenPanel::Datasets => dataset_state.ui(ui),
             OpenPanel::Misc => params.update_misc(ui, op_state), // force kill option
             OpenPanel::Trainer => {
                 // stupid hack, as if open_panel is ever Trainer, then the training menu system will get stuck trying to go back
@@ -74,7 +74,7 @@ fn handle_pane_options(ui: &mut egui::Ui, panel: &mut OpenPanel) {
         // The three possible states for the ui to be in,
         // selecting "Train" switches to the Trainer app state
         ui.selectable_value(panel, OpenPanel::Models, "Models");
-        // ui.selectable_value(panel, OpenPanel::Datasets, "Datasets");
+        ui.selectable_value(panel, OpenPanel::Datasets, "Datasets");
         ui.selectable_value(panel, OpenPanel::Misc, "Misc");
         ui.selectable_value(panel, OpenPanel::Trainer, "Train Environment");
     });
@@ -92,7 +92,7 @@ fn save_ui(
     params: Res<UIParams>,
     mut serializer: ResMut<Serializer>
 ) {
-    serializer.serialize("ui_config", &*params);
+    serializer.serialize("ui_config", &*params).unwrap_or_else(|e| eprintln!("{:#}", e));
 }
 
 /// cleanup when user tries to close the window
@@ -124,6 +124,8 @@ pub struct UIParams {
     open_panel: OpenPanel,
     pub run_queue_max_active: usize,
     pub run_queue_num_errs: usize,
+    #[serde(default)]
+    pub run_queue_spawn_retries: usize, // times a failed launch is retried, 0 disables retries
 }
 
 
@@ -187,6 +189,9 @@ impl UIParams {
         ui.label("run queue maximum number of error messages");
         ui.add(egui::Slider::new(&mut self.run_queue_num_errs, 1..=100));
 
+        ui.label("run queue retries of failed launches");
+        ui.add(egui::Slider::new(&mut self.run_queue_spawn_retries, 0..=10));
+
         // emergency kill switch, in case some processes are unable to be killed
         if ui.button("force kill").clicked() {
             state.set(OperatingState::Close).unwrap();
@@ -201,6 +206,7 @@ impl Default for UIParams {
             font_delta: 4.0,
             run_queue_max_active: 1,
             run_queue_num_errs: 5,
+            run_queue_spawn_retries: 0,
         }
     }
 }
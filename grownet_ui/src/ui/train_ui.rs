@@ -13,7 +13,7 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use model_lib::models::{self, TrainRecv};
 use model_lib::Config;
 
-use crate::{ops, config_ui_adjust};
+use crate::{ops, config_ui_adjust, config_ui_adjust_diff};
 use crate::run_systems::{self as run, config_ui_show, ModelPlots, PlotViewerV1, PlotViewerV2};
 use run::{Models, Despawn, Kill, Spawn, SpawnRun};
 use super::{Serializer, AppState, OperatingState, OpenPanel, UIParams, handle_pane_options};
@@ -105,6 +105,9 @@ fn train_menu_ui(
                     // The config environments of each specific model type
                     match train_ui.model {
                         run::Models::BASELINE => {
+                            if !train_ui.baseline.has_summary() {
+                                train_ui.baseline.set_summary(run::baseline::baseline_summary());
+                            }
                             needed_width = train_ui.baseline.ui(ui).width();
                         }
                     }
@@ -115,13 +118,15 @@ fn train_menu_ui(
                         // entry point for launching training
                         // only launch things if the operating state is active
                         if *op_state.current() == OperatingState::Active && ui.button("Launch Training").clicked() {
+                            let retry = (params.run_queue_spawn_retries > 0)
+                                .then(|| run::RetryPolicy::new(params.run_queue_spawn_retries, std::time::Duration::from_secs(1)));
                             match train_ui.model {
                                 run::Models::BASELINE => {
                                     let (spawn_fn, runinfo) = 
-                                        run::baseline::baseline_spawn_fn(train_ui.baseline.version_num as usize, train_ui.baseline.get_config(), train_ui.baseline.get_global_config());
+                                        run::baseline::baseline_spawn_fn(train_ui.baseline.version_num as usize, train_ui.baseline.get_config(), train_ui.baseline.get_global_config(), train_ui.baseline.get_tags(), None, train_ui.baseline.take_resume());
                                     //app_state.set(AppState::Trainer).unwrap();
                                     train_ui.baseline.version_num += 1;
-                                    run_queue.add_run(runinfo, spawn_fn);
+                                    run_queue.add_run(runinfo, spawn_fn, retry);
                                 }
                             }
                         }
@@ -162,7 +167,8 @@ fn train_env_ui(
     killer: EventWriter<Kill>,
     // mut viewer: ResMut<PlotViewerV1>,
     // plots: Res<ModelPlots>, 
-    console: Res<run::Console>,    
+    mut console: ResMut<run::Console>,    
+    mut confusion: ResMut<run::ConfusionViewer>,
 ) {
     egui::Window::new("train").show(egui_context.ctx_mut(), |ui| {
         // make it so that going back to menu does not suspend current training progress
@@ -180,6 +186,10 @@ fn train_env_ui(
                 console.console_ui(ui);
             });
 
+            ui.collapsing("confusion matrix", |ui| {
+                confusion.ui(ui);
+            });
+
             // ui.separator();
             // ui.heading("plots");
             // viewer.ui(ui, &*plots);
@@ -196,7 +206,7 @@ fn cleanup_queue(
     mut killed: EventReader<Despawn>,
     mut app_state: ResMut<State<OperatingState>>
 ) {
-    queue.queued_runs.clear();
+    // queued runs are not spawned outside of the active state, and are kept to be saved on close
     for i in queue.active_runs.iter() {
         killer.send(Kill(i.1));
     }
@@ -230,41 +240,37 @@ fn run_queue(
         }
     }
     // spawn new things
-    for _ in 0..(params.run_queue_max_active - queue.active_runs.len()) {
-        if let Some(x) = queue.queued_runs.pop_front() {
-            let (info, spawn_fn) = (x.0, x.1);
-            let id = spawn_fn(&mut commands);
-            match id {
-                Ok(id) => { queue.active_runs.push_back((info, id)); },
-                Err(msg) => {
-                    queue.spawn_errors.push_back(msg.to_string());
-                    if queue.spawn_errors.len() >= params.run_queue_num_errs {
-                        queue.spawn_errors.pop_front();
-                    }
-                },
-            }
-        } else {
-            break;
-        }
-    }
+    queue.spawn_ready(&mut commands, params.run_queue_max_active, params.run_queue_num_errs, std::time::Instant::now());
 }
 
-/// possibly load any training state from disk
+/// possibly load any training state from disk, including the runs left queued when the app was closed
 /// Startup System
 fn setup_train_ui(
     mut train_ui: ResMut<TrainingUI>,
+    mut queue: ResMut<RunQueue>,
     serializer: Res<Serializer>
 ) {
     serializer.deserialize("train_ui", &mut *train_ui);
+    let mut saved: Vec<SavedRun> = Vec::new();
+    serializer.deserialize("run_queue", &mut saved);
+    let global_config = train_ui.baseline.get_global_config();
+    queue.restore(saved, |info| match info.model_class.as_str() {
+        "baseline" => Some(run::baseline::baseline_spawn_fn(
+            info.version, info.config.clone(), global_config.clone(), info.tags.clone(), None, info.resume_from.clone()
+        ).0),
+        _ => None,
+    });
 }
 
-/// write train state to disk
+/// write train state to disk, active runs are not saved with the queue
 /// Shutdown system
 fn save_train_ui(
     train_ui: Res<TrainingUI>,
+    queue: Res<RunQueue>,
     mut serializer: ResMut<Serializer>
 ) {
-    serializer.serialize("train_ui", &*train_ui)
+    serializer.serialize("train_ui", &*train_ui).unwrap_or_else(|e| eprintln!("{:#}", e));
+    serializer.serialize("run_queue", &queue.saved_runs()).unwrap_or_else(|e| eprintln!("{:#}", e));
 }
 
 
@@ -305,9 +311,21 @@ pub struct ConfigEnviron {
     saved_runs: CheckedList<run::RunInfo>,
     version_num: u32,
     global_config: Config,
-    // checkpoint configs
-    // checkpoint_folder: PathBuf,
-    // num_kept_checkpoints: u32,
+    // comma separated tags given to launched runs
+    #[serde(default)]
+    tags: String,
+    #[serde(skip)]
+    summary: Option<Vec<models::LayerSummary>>,
+    // the checkpoint to resume the next launched run from
+    #[serde(skip)]
+    resume_from: Option<PathBuf>,
+    #[serde(skip)]
+    checkpoints: Vec<PathBuf>,
+    // only config entries containing the query are shown
+    #[serde(skip)]
+    query: String,
+    #[serde(skip)]
+    history: ConfigHistory,
 }
 
 impl ConfigEnviron {
@@ -323,9 +341,52 @@ impl ConfigEnviron {
             // saved_configs: CheckedList { header: name.to_string() + " saved configs", deletion: true, ..default() },
             saved_runs: CheckedList { title: name.to_string() + " saved runs", default_open: false, deletion: true, ..default()},
             version_num: 0,
-            global_config
-            // num_kept_checkpoints: 3,
-            // checkpoint_folder,
+            global_config,
+            tags: String::new(),
+            summary: None,
+            resume_from: None,
+            checkpoints: Vec::new(),
+            query: String::new(),
+            history: ConfigHistory::default(),
+        }
+    }
+
+    pub fn checkpoint_folder(&self) -> PathBuf {
+        PathBuf::from(crate::CHECKPOINT_ROOT).join(&self.name)
+    }
+
+    /// the checkpoint selected to resume from, clearing the selection
+    pub fn take_resume(&mut self) -> Option<PathBuf> {
+        self.resume_from.take()
+    }
+
+    fn resume_ui(&mut self, ui: &mut egui::Ui) {
+        let selected = match &self.resume_from {
+            Some(path) => path.file_stem().unwrap().to_string_lossy().to_string(),
+            None => "new run".to_string(),
+        };
+        let response = egui::ComboBox::from_label("resume from")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.resume_from, None, "new run");
+                for path in self.checkpoints.iter() {
+                    let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                    ui.selectable_value(&mut self.resume_from, Some(path.clone()), name);
+                }
+            });
+        // only read the checkpoint folder when the menu is opened
+        if response.response.clicked() {
+            let folder = self.checkpoint_folder();
+            // an unreadable folder lists no checkpoints
+            self.checkpoints = if folder.exists() {
+                let mut checkpoints = models::CheckpointManager::new(folder, 0)
+                    .and_then(|x| x.checkpoints())
+                    .unwrap_or_default();
+                checkpoints.reverse();
+                checkpoints
+            } else {
+                Vec::new()
+            };
         }
     }
 
@@ -337,6 +398,19 @@ impl ConfigEnviron {
         self.global_config.clone()
     }
 
+    /// the tags given to the next launched run
+    pub fn get_tags(&self) -> Vec<String> {
+        run::parse_tags(&self.tags)
+    }
+
+    pub fn has_summary(&self) -> bool {
+        self.summary.is_some()
+    }
+
+    pub fn set_summary(&mut self, summary: Vec<models::LayerSummary>) {
+        self.summary = Some(summary);
+    }
+
     pub fn add_run(&mut self, run: run::RunInfo) {
         self.saved_runs.add(run);
     }
@@ -345,41 +419,80 @@ impl ConfigEnviron {
         self.saved_runs.get_checked().or_else(|| self.saved_runs.get_latest())
     }
 
+    /// reverts the last edit to the local config, returns false if there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        self.history.undo(&mut self.config)
+    }
+
+    /// reapplies the last undone edit, returns false if there is nothing to redo
+    pub fn redo(&mut self) -> bool {
+        self.history.redo(&mut self.config)
+    }
+
+    /// sets the maximum number of edits which can be undone
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history.set_depth(depth);
+    }
+
     pub fn ui(&mut self, ui: &mut egui::Ui) -> egui::Rect {
         let response = ui.group(|ui| {
             egui::ScrollArea::vertical().id_source("global config").show(ui, |ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.query).hint_text("filter config"));
                 ui.label(egui::RichText::new("global config").heading().underline());
                 ui.separator();
-                config_ui_adjust(&mut self.global_config, ui);
+                config_ui_adjust(&mut self.global_config, &self.query, ui);
                 ui.separator();
                 ui.label(egui::RichText::new("local config").heading().underline());
                 ui.separator();
                 ui.horizontal(|ui| {
-                    // reset current config logic
-                    if !self.saved_runs.is_checked() {
-                        if ui.button("reset local config").clicked() {
-                            self.config.update(&self.default).unwrap();
-                        }
-                    } else {
-                        // something is checked, default to past config
-                        let checked = self.saved_runs.get_checked_num().unwrap();
-                        if ui
-                            .button(format!("reset local with past config {}", checked))
-                            .clicked()
-                        {
-                            if let Some(a) = self.saved_runs.get_checked() {
-                                self.config.update(&a.config).unwrap();
+                    if ui.add_enabled(self.history.can_undo(), egui::Button::new("undo")).clicked() {
+                        self.undo();
+                    }
+                    if ui.add_enabled(self.history.can_redo(), egui::Button::new("redo")).clicked() {
+                        self.redo();
+                    }
+                });
+                // changes made while dragging a value count as a single edit
+                let dragging = ui.input().pointer.any_down();
+                self.history.edit(&mut self.config, dragging, |config| {
+                    ui.horizontal(|ui| {
+                        // reset current config logic
+                        if !self.saved_runs.is_checked() {
+                            if ui.button("reset local config").clicked() {
+                                config.update(&self.default).unwrap();
+                            }
+                        } else {
+                            // something is checked, default to past config
+                            let checked = self.saved_runs.get_checked_num().unwrap();
+                            if ui
+                                .button(format!("reset local with past config {}", checked))
+                                .clicked()
+                            {
+                                if let Some(a) = self.saved_runs.get_checked() {
+                                    config.update(&a.config).unwrap();
+                                }
                             }
                         }
-                    }
+                    });
+                    
+                    config_ui_adjust_diff(config, &self.default, &self.query, ui);
                 });
-                
-                config_ui_adjust(&mut self.config, ui);
                 ui.separator();
+                self.resume_ui(ui);
+                ui.horizontal(|ui| {
+                    ui.label("tags");
+                    ui.add(egui::TextEdit::singleline(&mut self.tags).hint_text("comma separated, ex. ablation-lr"));
+                });
                 
                 ui.collapsing("past configs", |ui| {
+                    self.saved_runs.compare_ui(ui, |ui, a, b| { run::config_diff_ui(&a.config, &b.config, ui); });
                     self.saved_runs.ui(ui, |ui, run| { run.show_basic(ui); });
                 });
+                if let Some(summary) = &self.summary {
+                    ui.collapsing("model summary", |ui| {
+                        run::layer_summary_ui(summary, ui);
+                    });
+                }
             });
         });
         response.response.rect
@@ -393,6 +506,85 @@ impl ConfigEnviron {
     }
 }
 
+/// A bounded history of config snapshots, each taken before an edit, supporting undo and redo
+struct ConfigHistory {
+    undo: VecDeque<Config>,
+    redo: Vec<Config>,
+    depth: usize,    // maximum number of snapshots kept for undo
+    in_edit: bool,   // whether the last edit can be merged into
+}
+
+impl Default for ConfigHistory {
+    fn default() -> Self {
+        Self::new(32)
+    }
+}
+
+impl ConfigHistory {
+    fn new(depth: usize) -> Self {
+        Self { undo: VecDeque::new(), redo: Vec::new(), depth, in_edit: false }
+    }
+
+    fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+    }
+
+    fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// applies f to config, recording the previous state if f changed it, which clears the redo stack.
+    /// If merge is true, a change following a previously merged change is recorded as part of the same edit
+    fn edit(&mut self, config: &mut Config, merge: bool, f: impl FnOnce(&mut Config)) {
+        let before = config.clone();
+        f(config);
+        if *config != before {
+            if !(merge && self.in_edit) {
+                self.push(before);
+            }
+            self.in_edit = true;
+        }
+        if !merge {
+            self.in_edit = false;
+        }
+    }
+
+    fn push(&mut self, before: Config) {
+        self.redo.clear();
+        self.undo.push_back(before);
+        if self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+
+    fn undo(&mut self, config: &mut Config) -> bool {
+        self.in_edit = false;
+        if let Some(prev) = self.undo.pop_back() {
+            self.redo.push(std::mem::replace(config, prev));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn redo(&mut self, config: &mut Config) -> bool {
+        self.in_edit = false;
+        if let Some(next) = self.redo.pop() {
+            self.undo.push_back(std::mem::replace(config, next));
+            true
+        } else {
+            false
+        }
+    }
+}
+
 /// A wrapper struct owning a list of values, providing a ui method which allows insertion and deletion from that list
 #[derive(Serialize, Deserialize, Default)]
 struct CheckedList<T> {
@@ -401,7 +593,9 @@ struct CheckedList<T> {
     is_open: VecDeque<bool>, // the collapsing header is open
     default_open: bool,      // whether each new addition is open on default
     deletion: bool,          // support deletion
-    checked: Option<usize>   // current checked position
+    checked: Option<usize>,  // current checked position
+    #[serde(default)]
+    compared: Option<usize>  // position compared against the checked position
 }
 
 impl<T> CheckedList<T> {
@@ -426,6 +620,15 @@ impl<T> CheckedList<T> {
         self.is_open.push_front(self.default_open);
     }
 
+    /// shows the checked and compared items through f, if both are selected
+    pub fn compare_ui(&self, ui: &mut egui::Ui, f: impl FnOnce(&mut egui::Ui, &T, &T)) {
+        if let (Some(a), Some(b)) = (self.checked, self.compared) {
+            ui.collapsing(format!("compare {} with {}", a, b), |ui| {
+                f(ui, &self.saved[a], &self.saved[b]);
+            });
+        }
+    }
+
     pub fn ui(&mut self, ui: &mut egui::Ui, mut f: impl FnMut(&mut egui::Ui, &T)) {
             // use pub_runs as dummy display
             let mut i = 0;
@@ -433,16 +636,23 @@ impl<T> CheckedList<T> {
                 // allow checked to be negative so it becomes possible for no
                 // option to be checked
                 let mut cur_check = self.checked.is_some() && i == self.checked.unwrap();
+                let mut cur_compare = self.compared == Some(i);
                 let mut removed_run = false;
                 // heading for each collapsing header
                 ui.horizontal(|ui| {
                     ui.checkbox(&mut cur_check, format!("{}", i));
+                    ui.checkbox(&mut cur_compare, "compare");
                     if self.deletion && ui.button("delete").clicked() {
                         self.saved.remove(i);
                         self.is_open.remove(i);
                         if cur_check {
                             self.checked = None;
                         }
+                        self.compared = match self.compared {
+                            Some(j) if j == i => None,
+                            Some(j) if j > i => Some(j - 1),
+                            j => j
+                        };
                         removed_run = true;
                     }                    
                 });
@@ -463,6 +673,11 @@ impl<T> CheckedList<T> {
                 } else if checked {
                     self.checked = None;
                 }
+                if cur_compare {
+                    self.compared = Some(i);
+                } else if self.compared == Some(i) {
+                    self.compared = None;
+                }
                 i += 1;
             }
     }
@@ -476,11 +691,74 @@ pub struct RunQueue {
     queued_runs: VecDeque<Spawn>,
     active_runs: VecDeque<(run::RunInfo, Entity)>,
     spawn_errors: VecDeque<String>,
+    tag_filter: String, // only runs with every one of these comma separated tags are shown
+}
+
+/// A queued run as written to disk, without its spawn function, which is rebuilt from the run info
+#[derive(Serialize, Deserialize, Clone)]
+struct SavedRun {
+    info: run::RunInfo,
+    priority: i32,
+    retry: Option<run::RetryPolicy>,
 }
 
 impl RunQueue {
-    fn add_run(&mut self, info: run::RunInfo, run_fn: SpawnRun) {
-        self.queued_runs.push_back(Spawn(info, run_fn));
+    /// the queued runs, in order
+    fn saved_runs(&self) -> Vec<SavedRun> {
+        self.queued_runs.iter()
+            .map(|x| SavedRun { info: x.info.clone(), priority: x.priority, retry: x.retry })
+            .collect()
+    }
+
+    /// queues each saved run with the spawn function built by spawn_fn, runs for which it returns None are dropped
+    fn restore(&mut self, saved: Vec<SavedRun>, mut spawn_fn: impl FnMut(&run::RunInfo) -> Option<SpawnRun>) {
+        for x in saved {
+            match spawn_fn(&x.info) {
+                Some(f) => self.queued_runs.push_back(Spawn { priority: x.priority, retry: x.retry, ..Spawn::new(x.info, f) }),
+                None => eprintln!("unable to restore queued run {}", x.info.run_name()),
+            }
+        }
+    }
+
+    fn add_run(&mut self, info: run::RunInfo, run_fn: SpawnRun, retry: Option<run::RetryPolicy>) {
+        self.queued_runs.push_back(Spawn { retry, ..Spawn::new(info, run_fn) });
+    }
+
+    /// removes the queued run with the highest priority, of those the earliest queued,
+    /// ignoring runs waiting to retry after now
+    fn pop_next(&mut self, now: std::time::Instant) -> Option<Spawn> {
+        let mut best: Option<usize> = None;
+        for (i, x) in self.queued_runs.iter().enumerate() {
+            if x.is_ready(now) && best.map_or(true, |j| x.priority > self.queued_runs[j].priority) {
+                best = Some(i);
+            }
+        }
+        self.queued_runs.remove(best?)
+    }
+
+    /// spawns queued runs which are ready at now until there are max_active active runs. Runs which fail
+    /// to spawn are queued again if their retry policy allows, otherwise their error is recorded,
+    /// keeping fewer than max_errs errors
+    fn spawn_ready(&mut self, commands: &mut Commands, max_active: usize, max_errs: usize, now: std::time::Instant) {
+        for _ in 0..max_active.saturating_sub(self.active_runs.len()) {
+            if let Some(mut x) = self.pop_next(now) {
+                match (x.spawn_fn)(commands) {
+                    Ok(id) => { self.active_runs.push_back((x.info, id)); },
+                    Err(msg) => {
+                        if x.failed(msg.to_string(), now) {
+                            self.queued_runs.push_back(x);
+                            continue;
+                        }
+                        self.spawn_errors.push_back(msg.to_string());
+                        if self.spawn_errors.len() >= max_errs {
+                            self.spawn_errors.pop_front();
+                        }
+                    },
+                }
+            } else {
+                break;
+            }
+        }
     }
 
     fn ui(&mut self, ui: &mut egui::Ui, mut kill: EventWriter<Kill>, stats: &run::RunStats) {
@@ -498,16 +776,36 @@ impl RunQueue {
                 }
                 //ui.separator();
             }
+            ui.add(egui::TextEdit::singleline(&mut self.tag_filter).hint_text("filter runs by tags"));
             // show a list of queued runs, with option to remove a run
             ui.label("queued runs");
             let mut i = 0;
             while i < self.queued_runs.len() {
+                if !self.queued_runs[i].info.matches_tags(&self.tag_filter) {
+                    i += 1;
+                    continue;
+                }
                 if ui.button("remove").clicked() {
                     self.queued_runs.remove(i);
                     continue;
                 }
-                ui.collapsing(self.queued_runs[i].0.run_name(), |ui| {
-                    self.queued_runs[i].0.show_basic(ui);
+                let run = &mut self.queued_runs[i];
+                ui.horizontal(|ui| {
+                    ui.label(format!("priority {}", run.priority));
+                    if ui.small_button("+").clicked() {
+                        run.priority += 1;
+                    }
+                    if ui.small_button("-").clicked() {
+                        run.priority -= 1;
+                    }
+                });
+                if let (Some(policy), Some(next)) = (run.retry, run.next_attempt) {
+                    let wait = next.saturating_duration_since(std::time::Instant::now());
+                    ui.label(format!("retry {}/{}, next attempt in {:.1}s", run.retries, policy.max_retries, wait.as_secs_f32()))
+                        .on_hover_text(run.last_err.clone().unwrap_or_default());
+                }
+                ui.collapsing(run.info.run_name(), |ui| {
+                    run.info.show_basic(ui);
                 });
                 i += 1;
             }
@@ -515,11 +813,15 @@ impl RunQueue {
             ui.label("active runs");
             for i in 0..self.active_runs.len() {
                 let cur_run = &mut self.active_runs[i];
+                if !cur_run.0.matches_tags(&self.tag_filter) {
+                    continue;
+                }
                 ui.horizontal(|ui| {
                     if ui.button("kill").clicked() {
                         kill.send(Kill(cur_run.1));
                     }
                     ui.vertical(|ui| {
+                        stats.show_progress(cur_run.1, ui);
                         ui.collapsing(cur_run.0.run_name(), |ui| {
                             if stats.has_stat(cur_run.1) {
                                 ui.vertical(|ui| {
@@ -535,3 +837,136 @@ impl RunQueue {
     }
 }
 
+#[test]
+fn test_config_history() {
+    use model_lib::{config, opt, Options};
+    let mut env = ConfigEnviron::new("test", config!(("a", 0), ("b", 0.0)), config!());
+    let set = |env: &mut ConfigEnviron, k: &str, v: Options| {
+        env.history.edit(&mut env.config, false, |config| config.update_key(k, &v).unwrap());
+    };
+    let state = |env: &ConfigEnviron| -> (isize, f64) { ((&env.config["a"]).into(), (&env.config["b"]).into()) };
+    
+    assert!(!env.undo() && !env.redo());
+    set(&mut env, "a", Options::INT(1));
+    set(&mut env, "b", Options::FLOAT(1.0));
+    set(&mut env, "a", Options::INT(2));
+    // an edit which changes nothing is not recorded
+    set(&mut env, "a", Options::INT(2));
+    assert_eq!(state(&env), (2, 1.0));
+
+    assert!(env.undo());
+    assert_eq!(state(&env), (1, 1.0));
+    assert!(env.undo());
+    assert_eq!(state(&env), (1, 0.0));
+    assert!(env.redo());
+    assert_eq!(state(&env), (1, 1.0));
+
+    // a new edit clears the redo stack
+    set(&mut env, "a", Options::INT(3));
+    assert!(!env.redo());
+    assert_eq!(state(&env), (3, 1.0));
+    assert!(env.undo() && env.undo() && env.undo());
+    assert_eq!(state(&env), (0, 0.0));
+    assert!(!env.undo());
+
+    // merged edits undo together
+    env.history.edit(&mut env.config, true, |config| config.update_key("a", &Options::INT(5)).unwrap());
+    env.history.edit(&mut env.config, true, |config| config.update_key("a", &Options::INT(6)).unwrap());
+    env.history.edit(&mut env.config, false, |_| {});
+    assert_eq!(state(&env), (6, 0.0));
+    assert!(env.undo());
+    assert_eq!(state(&env), (0, 0.0));
+
+    // the history is bounded
+    env.set_history_depth(2);
+    for i in 1..=4 {
+        set(&mut env, "a", Options::INT(i));
+    }
+    assert!(env.undo() && env.undo() && !env.undo());
+    assert_eq!(state(&env), (2, 0.0));
+}
+
+#[test]
+fn test_run_queue_priority() {
+    let mut queue = RunQueue::default();
+    let runs = [(0, 0), (1, 2), (2, 0), (3, 5), (4, 2), (5, -1)];
+    for (version, priority) in runs {
+        let info = run::RunInfo { version, ..Default::default() };
+        let spawn_fn: SpawnRun = Box::new(|_: &mut Commands| -> Result<Entity> { Err(Error::msg("not spawned")) });
+        queue.add_run(info, spawn_fn, None);
+        queue.queued_runs.back_mut().unwrap().priority = priority;
+    }
+    // bumping the last run puts it ahead of the runs of equal priority queued before it
+    queue.queued_runs[5].priority = 3;
+    let now = std::time::Instant::now();
+    let order: Vec<_> = std::iter::from_fn(|| queue.pop_next(now)).map(|x| x.info.version).collect();
+    assert_eq!(order, [3, 5, 1, 4, 0, 2]);
+    assert!(queue.pop_next(now).is_none());
+}
+
+#[test]
+fn test_run_queue_retry() {
+    use std::time::{Duration, Instant};
+    use bevy::ecs::system::CommandQueue;
+    let world = World::new();
+    let mut command_queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut command_queue, &world);
+    let mut queue = RunQueue::default();
+
+    // fails twice, then spawns
+    let attempts = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = attempts.clone();
+    let spawn_fn: SpawnRun = Box::new(move |commands: &mut Commands| -> Result<Entity> {
+        if counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 {
+            return Err(Error::msg("device busy"));
+        }
+        Ok(commands.spawn_empty().id())
+    });
+    let policy = run::RetryPolicy::new(3, Duration::from_secs(1));
+    queue.add_run(run::RunInfo::default(), spawn_fn, Some(policy));
+
+    let start = Instant::now();
+    queue.spawn_ready(&mut commands, 1, 5, start);
+    assert_eq!((queue.queued_runs.len(), queue.queued_runs[0].retries), (1, 1));
+    assert_eq!(queue.queued_runs[0].last_err.as_deref(), Some("device busy"));
+    // the run waits for its backoff
+    queue.spawn_ready(&mut commands, 1, 5, start + Duration::from_millis(500));
+    assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 1);
+    queue.spawn_ready(&mut commands, 1, 5, start + Duration::from_secs(1));
+    assert_eq!(queue.queued_runs[0].retries, 2);
+    // the second delay is doubled
+    assert_eq!(queue.queued_runs[0].next_attempt, Some(start + Duration::from_secs(3)));
+    queue.spawn_ready(&mut commands, 1, 5, start + Duration::from_secs(3));
+    assert!(queue.queued_runs.is_empty() && queue.spawn_errors.is_empty());
+    assert_eq!(queue.active_runs.len(), 1);
+    assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+    // without a policy the error is recorded immediately
+    let spawn_fn: SpawnRun = Box::new(|_: &mut Commands| -> Result<Entity> { Err(Error::msg("no retry")) });
+    queue.add_run(run::RunInfo::default(), spawn_fn, None);
+    queue.spawn_ready(&mut commands, 2, 5, start);
+    assert!(queue.queued_runs.is_empty());
+    assert_eq!(queue.spawn_errors, ["no retry"]);
+}
+
+#[test]
+fn test_run_queue_restore() {
+    use model_lib::{config, opt, Options};
+    let unused = || -> SpawnRun { Box::new(|_: &mut Commands| -> Result<Entity> { Err(Error::msg("not spawned")) }) };
+    let mut queue = RunQueue::default();
+    for (version, model_class, lr) in [(0, "baseline", 0.1), (1, "unknown", 0.2), (2, "baseline", 0.3)] {
+        let info = run::RunInfo { version, model_class: model_class.into(), config: config!(("lr", lr)), ..Default::default() };
+        queue.add_run(info, unused(), Some(run::RetryPolicy::new(2, std::time::Duration::from_secs(1))));
+    }
+    queue.queued_runs[2].priority = 4;
+    // active runs are not saved
+    queue.active_runs.push_back((run::RunInfo { version: 3, ..Default::default() }, Entity::from_raw(0)));
+
+    let saved = ron::to_string(&queue.saved_runs()).unwrap();
+    let mut restored = RunQueue::default();
+    restored.restore(ron::from_str(&saved).unwrap(), |info| (info.model_class == "baseline").then(unused));
+    assert!(restored.active_runs.is_empty());
+    let runs: Vec<_> = restored.queued_runs.iter().map(|x| (x.info.version, x.priority, x.retry.map(|r| r.max_retries))).collect();
+    assert_eq!(runs, [(0, 0, Some(2)), (2, 4, Some(2))]);
+    assert_eq!(restored.queued_runs[1].info.config["lr"], Options::FLOAT(0.3));
+}